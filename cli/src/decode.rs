@@ -0,0 +1,339 @@
+use crate::Messages;
+use protodef_codegen::{
+    interpreter::{Interpreter, Span},
+    lowering::LoweringOptions,
+};
+use protodef_core::Value;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    error::Error,
+    fmt::Write,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Decode {
+    /// The `protocol.json` file.
+    #[structopt(short, long, parse(from_os_str))]
+    protocol: PathBuf,
+    /// The fully qualified name of the type to decode (e.g.
+    /// `play.toClient.packet`).
+    #[structopt(short, long = "type")]
+    ty: String,
+    /// How to print the decoded value (`tree` or `json`).
+    #[structopt(long, default_value = "tree")]
+    format: Format,
+    /// A file containing the raw bytes, or the bytes written as hex.
+    input: String,
+}
+
+impl Decode {
+    pub(crate) fn run(&self, messages: Messages) -> Result<(), Box<dyn Error>> {
+        let bytes = read_input(&self.input)?;
+        let compilation_unit = crate::lower(
+            &self.protocol,
+            &LoweringOptions::default(),
+            messages,
+        )?;
+        let interpreter = Interpreter::new(&compilation_unit);
+
+        let (value, spans, rest) = interpreter
+            .decode_with_spans(&self.ty, &bytes)
+            .map_err(|e| {
+                messages.decode_error(&e);
+                crate::problems_found(1)
+            })?;
+        let offsets = Offsets::new(&spans, 0..bytes.len() - rest.len());
+
+        match self.format {
+            Format::Tree => print!("{}", tree(&self.ty, &value, &offsets)),
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&annotated(&value, &offsets))?
+            ),
+        }
+
+        if !rest.is_empty() {
+            eprintln!("Warning: {} trailing bytes weren't decoded", rest.len());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Tree,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(Format::Tree),
+            "json" => Ok(Format::Json),
+            other => Err(format!(
+                "Unknown format \"{}\", expected \"tree\" or \"json\"",
+                other
+            )),
+        }
+    }
+}
+
+/// Read bytes from a file, falling back to parsing the input as hex.
+fn read_input(input: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let path = Path::new(input);
+
+    if path.is_file() {
+        return std::fs::read(path).map_err(|e| {
+            format!("Unable to read \"{}\": {}", path.display(), e).into()
+        });
+    }
+
+    parse_hex(input).ok_or_else(|| {
+        format!("\"{}\" is neither a file nor a valid hex string", input).into()
+    })
+}
+
+/// Parse a hex string like `0x02 05 48 65`, ignoring whitespace.
+pub(crate) fn parse_hex(src: &str) -> Option<Vec<u8>> {
+    let src = src.trim();
+    let src = src.strip_prefix("0x").unwrap_or(src);
+    let digits: Vec<u32> = src
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16))
+        .collect::<Option<_>>()?;
+
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+
+    Some(
+        digits
+            .chunks(2)
+            .map(|pair| (pair[0] * 16 + pair[1]) as u8)
+            .collect(),
+    )
+}
+
+/// A lookup table for the bytes each field was decoded from.
+struct Offsets {
+    root: Range<usize>,
+    spans: HashMap<Vec<String>, Range<usize>>,
+}
+
+impl Offsets {
+    fn new(spans: &[Span], root: Range<usize>) -> Self {
+        let spans = spans
+            .iter()
+            .map(|span| (span.path.clone(), span.range.clone()))
+            .collect();
+
+        Offsets { root, spans }
+    }
+
+    /// Get the bytes a field was decoded from, falling back to its parent's
+    /// bytes for things like bitfields which don't take up whole bytes.
+    fn get(&self, path: &[String]) -> Range<usize> {
+        let mut path = path;
+
+        loop {
+            if path.is_empty() {
+                return self.root.clone();
+            }
+            if let Some(range) = self.spans.get(path) {
+                return range.clone();
+            }
+            path = &path[..path.len() - 1];
+        }
+    }
+}
+
+/// Render a value as an indented tree, with the byte range each field came
+/// from.
+fn tree(name: &str, value: &Value, offsets: &Offsets) -> String {
+    let mut buffer = String::new();
+    write_tree(&mut buffer, name, value, &mut Vec::new(), offsets);
+    buffer
+}
+
+fn write_tree(
+    buffer: &mut String,
+    name: &str,
+    value: &Value,
+    path: &mut Vec<String>,
+    offsets: &Offsets,
+) {
+    let range = offsets.get(path);
+    let indent = "  ".repeat(path.len());
+
+    let children: Vec<(String, &Value)> = match value {
+        Value::Map(fields) => fields
+            .iter()
+            .map(|(name, value)| (name.clone(), value))
+            .collect(),
+        Value::List(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| (i.to_string(), item))
+            .collect(),
+        _ => {
+            let _ = writeln!(
+                buffer,
+                "{}{}: {} [{}..{}]",
+                indent, name, value, range.start, range.end
+            );
+            return;
+        },
+    };
+
+    let _ = writeln!(
+        buffer,
+        "{}{} [{}..{}]",
+        indent, name, range.start, range.end
+    );
+
+    for (child_name, child) in children {
+        path.push(child_name.clone());
+        write_tree(buffer, &child_name, child, path, offsets);
+        path.pop();
+    }
+}
+
+/// Convert a value to JSON, where every field is an object containing its
+/// value and the range of bytes it was decoded from.
+fn annotated(value: &Value, offsets: &Offsets) -> serde_json::Value {
+    annotate(value, &mut Vec::new(), offsets)
+}
+
+fn annotate(
+    value: &Value,
+    path: &mut Vec<String>,
+    offsets: &Offsets,
+) -> serde_json::Value {
+    let range = offsets.get(path);
+
+    let value = match value {
+        Value::Map(fields) => {
+            let mut object = serde_json::Map::new();
+
+            for (name, field) in fields {
+                path.push(name.clone());
+                object.insert(name.clone(), annotate(field, path, offsets));
+                path.pop();
+            }
+
+            serde_json::Value::Object(object)
+        },
+        Value::List(items) => {
+            let mut annotated_items = Vec::new();
+
+            for (i, item) in items.iter().enumerate() {
+                path.push(i.to_string());
+                annotated_items.push(annotate(item, path, offsets));
+                path.pop();
+            }
+
+            serde_json::Value::Array(annotated_items)
+        },
+        other => to_json(other),
+    };
+
+    json!({
+        "offset": range.start,
+        "length": range.end - range.start,
+        "value": value,
+    })
+}
+
+/// Convert a value to plain JSON, using the same representation
+/// [`Interpreter::encode()`] accepts.
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Bool(b) => json!(b),
+        Value::Integer(i) => {
+            if let Ok(small) = i64::try_from(*i) {
+                json!(small)
+            } else if let Ok(unsigned) = u64::try_from(*i) {
+                json!(unsigned)
+            } else {
+                json!(i.to_string())
+            }
+        },
+        Value::Float(f) => json!(f),
+        Value::String(s) => json!(s),
+        Value::Bytes(bytes) => json!(bytes),
+        Value::List(items) => items.iter().map(to_json).collect(),
+        Value::Map(fields) => serde_json::Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_strings() {
+        assert_eq!(parse_hex("0x0205"), Some(vec![0x02, 0x05]));
+        assert_eq!(
+            parse_hex(" ca fe\nBA BE "),
+            Some(vec![0xca, 0xfe, 0xba, 0xbe])
+        );
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("xyz1"), None);
+    }
+
+    #[test]
+    fn render_a_tree_with_offsets() {
+        let value = Value::Map(vec![
+            (String::from("name"), Value::from("chat")),
+            (
+                String::from("params"),
+                Value::Map(vec![
+                    (String::from("message"), Value::from("Hi")),
+                    (
+                        String::from("flags"),
+                        Value::from(vec![Value::from(true)]),
+                    ),
+                ]),
+            ),
+        ]);
+        let span = |path: &[&str], range| Span {
+            path: path.iter().map(|s| s.to_string()).collect(),
+            range,
+        };
+        let spans = vec![
+            span(&["name"], 0..1),
+            span(&["params", "message"], 1..4),
+            span(&["params", "flags", "0"], 5..6),
+            span(&["params", "flags"], 4..6),
+            span(&["params"], 1..6),
+        ];
+        let offsets = Offsets::new(&spans, 0..6);
+
+        let got = tree("packet", &value, &offsets);
+
+        let should_be = "packet [0..6]
+  name: \"chat\" [0..1]
+  params [1..6]
+    message: \"Hi\" [1..4]
+    flags [4..6]
+      0: true [5..6]
+";
+        assert_eq!(got, should_be);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::Messages;
+use protodef_codegen::diff::{self, Change};
+use std::{error::Error, path::PathBuf, str::FromStr};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Diff {
+    /// The old `protocol.json` file.
+    #[structopt(parse(from_os_str))]
+    old: PathBuf,
+    /// The new `protocol.json` file.
+    #[structopt(parse(from_os_str))]
+    new: PathBuf,
+    /// How to print the changes (`human` or `json`).
+    #[structopt(long, default_value = "human")]
+    format: Format,
+}
+
+impl Diff {
+    pub(crate) fn run(&self, messages: Messages) -> Result<(), Box<dyn Error>> {
+        let old = crate::parse(&self.old, messages)?;
+        let new = crate::parse(&self.new, messages)?;
+
+        let changes = diff::diff(&old, &new);
+
+        match self.format {
+            Format::Human => print!("{}", human(&changes)),
+            Format::Json => {
+                let changes: Vec<_> =
+                    changes.iter().map(Change::to_json).collect();
+                println!("{}", serde_json::to_string_pretty(&changes)?);
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Human,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            other => Err(format!(
+                "Unknown format \"{}\", expected \"human\" or \"json\"",
+                other
+            )),
+        }
+    }
+}
+
+fn human(changes: &[Change]) -> String {
+    if changes.is_empty() {
+        return String::from("No changes\n");
+    }
+
+    changes
+        .iter()
+        .map(|change| format!("{}\n", change))
+        .collect()
+}
@@ -0,0 +1,118 @@
+use crate::Messages;
+use protodef_codegen::{interpreter::Interpreter, lowering::LoweringOptions};
+use std::{
+    error::Error,
+    io::{Read, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub(crate) struct Encode {
+    /// The `protocol.json` file.
+    #[structopt(short, long, parse(from_os_str))]
+    protocol: PathBuf,
+    /// The fully qualified name of the type to encode (e.g.
+    /// `play.toServer.packet`).
+    #[structopt(short, long = "type")]
+    ty: String,
+    /// How to write the encoded bytes (`hex` or `raw`).
+    #[structopt(long, default_value = "hex")]
+    format: Format,
+    /// Where to write the encoded bytes (defaults to stdout).
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// A JSON file containing the value to encode (defaults to stdin).
+    #[structopt(parse(from_os_str))]
+    input: Option<PathBuf>,
+}
+
+impl Encode {
+    pub(crate) fn run(&self, messages: Messages) -> Result<(), Box<dyn Error>> {
+        let value = self.read_value()?;
+        let compilation_unit = crate::lower(
+            &self.protocol,
+            &LoweringOptions::default(),
+            messages,
+        )?;
+        let interpreter = Interpreter::new(&compilation_unit);
+
+        let bytes = interpreter.encode(&self.ty, &value).map_err(|e| {
+            messages.encode_error(&e);
+            crate::problems_found(1)
+        })?;
+
+        let output = match self.format {
+            Format::Hex => {
+                let mut hex = to_hex(&bytes);
+                hex.push('\n');
+                hex.into_bytes()
+            },
+            Format::Raw => bytes,
+        };
+
+        match &self.output {
+            Some(path) => std::fs::write(path, output).map_err(|e| {
+                format!("Unable to write to \"{}\": {}", path.display(), e)
+            })?,
+            None => std::io::stdout().write_all(&output)?,
+        }
+
+        Ok(())
+    }
+
+    fn read_value(&self) -> Result<serde_json::Value, Box<dyn Error>> {
+        match &self.input {
+            Some(path) => crate::read_json(path),
+            None => {
+                let mut src = String::new();
+                std::io::stdin().read_to_string(&mut src)?;
+                serde_json::from_str(&src).map_err(|e| {
+                    format!("Unable to parse stdin as JSON: {}", e).into()
+                })
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Hex,
+    Raw,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Format::Hex),
+            "raw" => Ok(Format::Raw),
+            other => Err(format!(
+                "Unknown format \"{}\", expected \"hex\" or \"raw\"",
+                other
+            )),
+        }
+    }
+}
+
+/// Write bytes as hex, in a form the `decode` command accepts.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips_through_decode() {
+        let bytes = vec![0x02, 0x05, b'H', 0xff];
+
+        let hex = to_hex(&bytes);
+
+        assert_eq!(hex, "020548ff");
+        assert_eq!(crate::decode::parse_hex(&hex), Some(bytes));
+    }
+}
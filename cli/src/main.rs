@@ -1,24 +1,542 @@
-use std::error::Error;
+mod decode;
+mod diff;
+mod encode;
+
+use crate::{decode::Decode, diff::Diff, encode::Encode};
+use protodef_codegen::{
+    backend::{self, CodegenOptions},
+    interpreter::{DecodeError, EncodeError},
+    lowering::{self, CompilationUnit, Diagnostics, LoweringOptions},
+    report::Report,
+    syntax::{self, ParseError, Protocol},
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use structopt::StructOpt;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<_> = std::env::args().collect();
+    let Args {
+        message_format,
+        command,
+    } = Args::from_args();
+    let messages = Messages {
+        format: message_format,
+    };
+
+    match command {
+        Command::Generate(generate) => generate.run(messages),
+        Command::Check {
+            input,
+            native_type,
+            next_state,
+        } => check(
+            &input,
+            &codegen_options(&native_type, &next_state),
+            messages,
+        ),
+        Command::DumpAst { input } => {
+            let protocol = parse(&input, messages)?;
+            println!("{:#?}", protocol);
+            Ok(())
+        },
+        Command::DumpHir { input } => {
+            let compilation_unit =
+                lower(&input, &LoweringOptions::default(), messages)?;
+            println!("{:#?}", compilation_unit);
+            Ok(())
+        },
+        Command::Decode(decode) => decode.run(messages),
+        Command::Encode(encode) => encode.run(messages),
+        Command::Diff(diff) => diff.run(messages),
+    }
+}
+
+/// Work with ProtoDef protocol specifications.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "protodef")]
+struct Args {
+    /// How to print problems found in a `protocol.json` file (`human` or
+    /// `json`).
+    ///
+    /// With `json`, each problem is printed to stdout as a JSON object on
+    /// its own line.
+    #[structopt(long, default_value = "human", global = true)]
+    message_format: MessageFormat,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Generate Rust code for a protocol.
+    Generate(Generate),
+    /// Check a protocol for errors without generating any code.
+    Check {
+        /// The `protocol.json` file.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+        /// Use your own Rust type for a native type which `protodef_core`
+        /// doesn't provide (e.g. `--native-type nbt=crate::nbt::Nbt`).
+        #[structopt(
+            long,
+            parse(try_from_str = parse_native_type),
+            number_of_values = 1
+        )]
+        native_type: Vec<(String, String)>,
+        /// The state a packet's `nextState` field switches to for a particular
+        /// value (e.g. `--next-state 2=login`).
+        #[structopt(
+            long,
+            parse(try_from_str = parse_next_state),
+            number_of_values = 1
+        )]
+        next_state: Vec<(i64, String)>,
+    },
+    /// Print the parsed `protocol.json` file.
+    DumpAst {
+        /// The `protocol.json` file.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Print the protocol's types after lowering.
+    DumpHir {
+        /// The `protocol.json` file.
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Decode some bytes using the types from a protocol.
+    Decode(Decode),
+    /// Encode a JSON value using the types from a protocol.
+    Encode(Encode),
+    /// Show what changed between two versions of a protocol.
+    Diff(Diff),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum MessageFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            other => Err(format!(
+                "Unknown message format \"{}\", expected \"human\" or \"json\"",
+                other
+            )),
+        }
+    }
+}
+
+/// Prints the problems found in a `protocol.json` file.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Messages {
+    format: MessageFormat,
+}
+
+impl Messages {
+    fn parse_errors(&self, file: &Path, src: &str, errors: &[ParseError]) {
+        for error in errors {
+            match self.format {
+                MessageFormat::Human => eprintln!("Error: {}", error),
+                MessageFormat::Json => {
+                    self.emit(Report::from(error).located(file, src))
+                },
+            }
+        }
+    }
+
+    fn diagnostics(&self, file: &Path, src: &str, diagnostics: &Diagnostics) {
+        for diagnostic in diagnostics.all_diagnostics() {
+            match self.format {
+                MessageFormat::Human => eprint!("Error: {}", diagnostic),
+                MessageFormat::Json => {
+                    self.emit(Report::from(diagnostic).located(file, src))
+                },
+            }
+        }
+    }
+
+    fn invalid_json(&self, file: &Path, error: &serde_json::Error) {
+        match self.format {
+            MessageFormat::Human => eprintln!(
+                "Error: Unable to parse \"{}\": {}",
+                file.display(),
+                error
+            ),
+            MessageFormat::Json => {
+                let mut report = Report::from(error);
+                report.file = Some(file.to_path_buf());
+                self.emit(report);
+            },
+        }
+    }
+
+    fn decode_error(&self, error: &DecodeError) {
+        match self.format {
+            MessageFormat::Human => eprintln!("Error: {}", error),
+            MessageFormat::Json => self.emit(Report::from(error)),
+        }
+    }
+
+    fn encode_error(&self, error: &EncodeError) {
+        match self.format {
+            MessageFormat::Human => eprintln!(
+                "Error: Invalid value at \"{}\": {}",
+                error.json_path(),
+                error.kind
+            ),
+            MessageFormat::Json => self.emit(Report::from(error)),
+        }
+    }
+
+    fn emit(&self, report: Report) {
+        println!("{}", report.to_json());
+    }
+}
+
+#[derive(Debug, StructOpt)]
+struct Generate {
+    /// The `protocol.json` file.
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// Where to write the generated code (defaults to stdout).
+    ///
+    /// If this is a directory, the code is written to a file named after
+    /// the input (e.g. `protocol.json` becomes `protocol.rs`).
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+    /// Give structurally identical anonymous types a single shared type.
+    #[structopt(long)]
+    deduplicate_types: bool,
+    /// Use your own Rust type for a native type which `protodef_core`
+    /// doesn't provide (e.g. `--native-type nbt=crate::nbt::Nbt`).
+    #[structopt(
+        long,
+        parse(try_from_str = parse_native_type),
+        number_of_values = 1
+    )]
+    native_type: Vec<(String, String)>,
+    /// The state a packet's `nextState` field switches to for a particular
+    /// value (e.g. `--next-state 2=login`).
+    #[structopt(
+        long,
+        parse(try_from_str = parse_next_state),
+        number_of_values = 1
+    )]
+    next_state: Vec<(i64, String)>,
+}
+
+impl Generate {
+    fn run(&self, messages: Messages) -> Result<(), Box<dyn Error>> {
+        let options = LoweringOptions {
+            deduplicate_types: self.deduplicate_types,
+        };
+        let (compilation_unit, src) =
+            lower_with_source(&self.input, &options, messages)?;
+        let tokens = backend::generate_rust_with_options(
+            &compilation_unit,
+            &codegen_options(&self.native_type, &self.next_state),
+        )
+        .map_err(|diags| {
+            messages.diagnostics(&self.input, &src, &diags);
+            problems_found(diags.all_diagnostics().len())
+        })?;
+
+        let formatted =
+            backend::rustfmt(&tokens).unwrap_or_else(|_| tokens.to_string());
+
+        match &self.output {
+            Some(output) => {
+                let path = output_path(&self.input, output);
+
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&path, formatted).map_err(|e| {
+                    format!("Unable to write to \"{}\": {}", path.display(), e)
+                })?;
+            },
+            None => println!("{}", formatted),
+        }
+
+        Ok(())
+    }
+}
+
+fn codegen_options(
+    native_types: &[(String, String)],
+    next_states: &[(i64, String)],
+) -> CodegenOptions {
+    let options = native_types
+        .iter()
+        .fold(CodegenOptions::new(), |options, (name, path)| {
+            options.native_type(name, path)
+        });
+
+    next_states.iter().fold(options, |options, (value, state)| {
+        options.next_state(*value, state)
+    })
+}
+
+/// Parse a `--next-state` argument (e.g. `2=login`).
+fn parse_next_state(s: &str) -> Result<(i64, String), String> {
+    let error = || {
+        format!(
+            "Expected \"VALUE=STATE\" (e.g. \"2=login\"), found \"{}\"",
+            s
+        )
+    };
+    let ix = s.find('=').ok_or_else(error)?;
+    let value = s[..ix].parse().map_err(|_| error())?;
+
+    Ok((value, s[ix + 1..].to_string()))
+}
+
+/// Parse a `--native-type` argument (e.g. `nbt=crate::nbt::Nbt`).
+fn parse_native_type(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(ix) => Ok((s[..ix].to_string(), s[ix + 1..].to_string())),
+        None => Err(format!(
+            "Expected \"NAME=PATH\" (e.g. \"nbt=crate::nbt::Nbt\"), found \"{}\"",
+            s
+        )),
+    }
+}
+
+/// Figure out which file to write generated code to.
+fn output_path(input: &Path, output: &Path) -> PathBuf {
+    if output.is_dir() {
+        let stem = input.file_stem().unwrap_or_else(|| "protocol".as_ref());
+        output.join(stem).with_extension("rs")
+    } else {
+        output.to_path_buf()
+    }
+}
+
+/// Run every stage of code generation except writing the code, reporting
+/// any problems found along the way.
+fn check(
+    input: &Path,
+    options: &CodegenOptions,
+    messages: Messages,
+) -> Result<(), Box<dyn Error>> {
+    let src = read_to_string(input)?;
+    let json = parse_json(input, &src, messages)?;
+    let (parsed, errors) = syntax::parse_with_recovery(&json);
+
+    messages.parse_errors(input, &src, &errors);
+
+    // parse errors leave holes in the protocol, which would make lowering and
+    // the backend report problems that aren't really there
+    let diagnostics = if !errors.is_empty() {
+        Diagnostics::default()
+    } else {
+        match lowering::lower(&parsed) {
+            Ok(compilation_unit) => match backend::generate_rust_with_options(
+                &compilation_unit,
+                options,
+            ) {
+                Ok(_) => Diagnostics::default(),
+                Err(diags) => diags,
+            },
+            Err(diags) => diags,
+        }
+    };
+
+    messages.diagnostics(input, &src, &diagnostics);
+
+    let problems = errors.len() + diagnostics.all_diagnostics().len();
+
+    if problems == 0 {
+        eprintln!("No problems found in \"{}\"", input.display());
+        Ok(())
+    } else {
+        Err(problems_found(problems))
+    }
+}
+
+fn problems_found(count: usize) -> Box<dyn Error> {
+    format!("{} problems found", count).into()
+}
+
+fn read_to_string(path: &Path) -> Result<String, Box<dyn Error>> {
+    std::fs::read_to_string(path).map_err(|e| {
+        format!("Unable to read \"{}\": {}", path.display(), e).into()
+    })
+}
+
+/// Parse a `protocol.json` file's contents as JSON, reporting any syntax
+/// errors.
+fn parse_json(
+    path: &Path,
+    src: &str,
+    messages: Messages,
+) -> Result<serde_json::Value, Box<dyn Error>> {
+    serde_json::from_str(src).map_err(|e| {
+        messages.invalid_json(path, &e);
+        problems_found(1)
+    })
+}
+
+fn read_json(path: &Path) -> Result<serde_json::Value, Box<dyn Error>> {
+    let src = read_to_string(path)?;
+    let json = serde_json::from_str(&src).map_err(|e| {
+        format!("Unable to parse \"{}\": {}", path.display(), e)
+    })?;
+
+    Ok(json)
+}
+
+/// Parse a `protocol.json` file, also returning the file's contents so
+/// later problems can be located.
+fn parse_with_source(
+    path: &Path,
+    messages: Messages,
+) -> Result<(Protocol, String), Box<dyn Error>> {
+    let src = read_to_string(path)?;
+    let json = parse_json(path, &src, messages)?;
+    let (parsed, errors) = syntax::parse_with_recovery(&json);
+
+    if !errors.is_empty() {
+        messages.parse_errors(path, &src, &errors);
+        return Err(format!("{} parse errors found", errors.len()).into());
+    }
+
+    Ok((parsed, src))
+}
+
+fn parse(path: &Path, messages: Messages) -> Result<Protocol, Box<dyn Error>> {
+    parse_with_source(path, messages).map(|(parsed, _)| parsed)
+}
+
+fn lower_with_source(
+    path: &Path,
+    options: &LoweringOptions,
+    messages: Messages,
+) -> Result<(CompilationUnit, String), Box<dyn Error>> {
+    let (parsed, src) = parse_with_source(path, messages)?;
 
-    if args.len() != 2 {
-        return Err(format!("Usage: {} <filename>", args[0]).into());
+    match lowering::lower_with_options(&parsed, options) {
+        Ok(compilation_unit) => Ok((compilation_unit, src)),
+        Err(diags) => {
+            messages.diagnostics(path, &src, &diags);
+            Err(problems_found(diags.all_diagnostics().len()))
+        },
     }
-    let filename = &args[1];
+}
+
+fn lower(
+    path: &Path,
+    options: &LoweringOptions,
+    messages: Messages,
+) -> Result<CompilationUnit, Box<dyn Error>> {
+    lower_with_source(path, options, messages)
+        .map(|(compilation_unit, _)| compilation_unit)
+}
 
-    let src = std::fs::read_to_string(&filename)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let json = serde_json::from_str(&src)?;
-    let parsed = protodef_codegen::syntax::parse(&json)?;
-    let analysed = protodef_codegen::lowering::lower(&parsed)?;
-    let tokens = protodef_codegen::backend::generate_rust(&analysed);
+    #[test]
+    fn generate_into_a_directory() {
+        let dir = std::env::temp_dir();
+
+        let got = output_path(Path::new("fixtures/protocol.json"), &dir);
+
+        assert_eq!(got, dir.join("protocol.rs"));
+    }
 
-    let formatted = protodef_codegen::backend::rustfmt(&tokens)
-        .unwrap_or_else(|_| tokens.to_string());
+    #[test]
+    fn generate_into_a_file() {
+        let output = Path::new("this/does/not/exist/generated.rs");
 
-    println!("{}", formatted);
+        let got = output_path(Path::new("protocol.json"), output);
 
-    Ok(())
+        assert_eq!(got, output);
+    }
+
+    #[test]
+    fn parse_subcommands() {
+        let args = Args::from_iter(&[
+            "protodef",
+            "generate",
+            "protocol.json",
+            "--output",
+            "src/generated.rs",
+            "--deduplicate-types",
+            "--native-type",
+            "nbt=crate::nbt::Nbt",
+            "--next-state",
+            "2=login",
+        ]);
+
+        assert_eq!(args.message_format, MessageFormat::Human);
+        match args.command {
+            Command::Generate(Generate {
+                input,
+                output,
+                deduplicate_types,
+                native_type,
+                next_state,
+            }) => {
+                assert_eq!(input, Path::new("protocol.json"));
+                assert_eq!(output.unwrap(), Path::new("src/generated.rs"));
+                assert!(deduplicate_types);
+                assert_eq!(
+                    native_type,
+                    vec![(
+                        String::from("nbt"),
+                        String::from("crate::nbt::Nbt")
+                    )]
+                );
+                assert_eq!(next_state, vec![(2, String::from("login"))]);
+            },
+            other => panic!("Unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn message_format_can_go_after_the_subcommand() {
+        let args = Args::from_iter(&[
+            "protodef",
+            "check",
+            "protocol.json",
+            "--message-format=json",
+        ]);
+
+        assert_eq!(args.message_format, MessageFormat::Json);
+    }
+
+    #[test]
+    fn parse_errors_dont_cause_bogus_diagnostics() {
+        // "b" refers to "a", which gets dropped because it can't be parsed
+        let doc = r#"{
+            "types": {
+                "varint": "native",
+                "a": ["not_a_function", {}],
+                "b": "a"
+            }
+        }"#;
+        let input = std::env::temp_dir()
+            .join(format!("protodef-check-{}.json", std::process::id()));
+        std::fs::write(&input, doc).unwrap();
+        let messages = Messages {
+            format: MessageFormat::Json,
+        };
+
+        let got = check(&input, &CodegenOptions::new(), messages);
+
+        std::fs::remove_file(&input).unwrap();
+        assert_eq!(got.unwrap_err().to_string(), "1 problems found");
+    }
 }
@@ -1,151 +1,2989 @@
 //! Code generation.
 
-use crate::lowering::{CompilationUnit, Type, TypeId};
-use proc_macro2::{Ident, Span, TokenStream};
+mod naming;
+mod options;
+pub mod serialization;
+
+pub use options::{CodegenOptions, StringType};
+
+use crate::lowering::{
+    BitFields, Buffer, CompilationUnit, Count, Diagnostic, Diagnostics, Enum,
+    Field, LengthPrefixedString, Mapper, Struct, Type, TypeId,
+};
+use indexmap::{IndexMap, IndexSet};
+use proc_macro2::{Ident, Literal, TokenStream};
 use quote::quote;
+use serialization::{
+    BitFieldInfo, Encoding, FieldInfo, Fields, MappingInfo, PacketInfo, Paths,
+    StateInfo, Transition, VariantInfo,
+};
 use std::{
     collections::{HashMap, HashSet},
-    io::{self, Read, Write},
-    process::{Child, Command, Stdio},
+    io::{self, Write},
+    process::{Command, Stdio},
 };
 
 /// Generate Rust bindings for the types in a particular [`CompilationUnit`].
-pub fn generate_rust(compilation_unit: &CompilationUnit) -> TokenStream {
-    let names = generate_names(compilation_unit);
-    let mut tokens = Vec::new();
+///
+/// Type names are converted to `UpperCamelCase` and field names to
+/// `snake_case`, with a [`Diagnostic::NameCollision`] being emitted when two
+/// names from the `protocol.json` would map to the same Rust identifier.
+pub fn generate_rust(
+    compilation_unit: &CompilationUnit,
+) -> Result<TokenStream, Diagnostics> {
+    generate_rust_with_options(compilation_unit, &CodegenOptions::default())
+}
 
-    let required_imports =
-        native_types_we_need_to_import(&names, compilation_unit);
+/// Generate Rust bindings for the types in a particular [`CompilationUnit`],
+/// using [`CodegenOptions`] to control what is generated.
+///
+/// Types from a namespace (e.g. `play.toClient`) are placed in nested
+/// modules (e.g. `play::to_client`).
+///
+/// When a namespace's `packet` type is a `mapper` containing each packet's
+/// id followed by a `switch` on it, it becomes a `Packet` enum with a variant
+/// for each packet, plus `packet_id()`, `name()`, and `deserialize_by_id()`
+/// methods. If those namespaces are the `toClient` and `toServer` halves of a
+/// connection's states (e.g. `play.toClient`), a top-level `State` enum is
+/// generated along with `ToClientPacket` and `ToServerPacket` enums which
+/// decode the packets for the current state (see
+/// `protodef_core::Connection`).
+pub fn generate_rust_with_options(
+    compilation_unit: &CompilationUnit,
+    options: &CodegenOptions,
+) -> Result<TokenStream, Diagnostics> {
+    let mut diagnostics = Diagnostics::default();
+    let settings = Settings::from_options(options, &mut diagnostics);
+    let modules = module_paths(compilation_unit);
+    let names =
+        generate_names(compilation_unit, &modules, options, &mut diagnostics);
+    check_field_names(compilation_unit, &names, &mut diagnostics);
+    check_variant_names(compilation_unit, &names, &mut diagnostics);
+    check_bitfields(compilation_unit, &names, &mut diagnostics);
+    let emitted =
+        types_to_emit(compilation_unit, &modules, options, &mut diagnostics);
+    let next_states =
+        next_states(compilation_unit, &emitted, options, &mut diagnostics);
+    let native_imports = native_imports(
+        compilation_unit,
+        &names,
+        &emitted,
+        &settings,
+        &mut diagnostics,
+    );
 
-    if !required_imports.is_empty() {
-        let using_statements = quote! {
-            use ::protodef_core::native::{ #(#required_imports),* };
-        };
-        tokens.push(using_statements);
+    if !diagnostics.all_diagnostics().is_empty() {
+        return Err(diagnostics);
+    }
+
+    let generator = Generator {
+        compilation_unit,
+        borrowed: types_which_borrow(compilation_unit, settings.string_type),
+        switch_fields: fields_used_by_switches(compilation_unit),
+        switch_key_types: switch_key_types(compilation_unit, &names),
+        anonymous: anonymous_structs(compilation_unit),
+        next_states,
+        names,
+        modules,
+        settings,
     };
+    let mut root = Module::default();
+    root.items.push(native_imports);
+
+    for (id, ty) in &compilation_unit.types {
+        if emitted.contains(id) {
+            let definition = generator.generate_type_definition(*id, ty);
+            root.insert(&generator.modules[id], definition);
+        }
+    }
+
+    root.items.push(generator.generate_states(&emitted));
+
+    Ok(root.into_tokens(&generator.settings.visibility))
+}
+
+/// The parsed form of any [`CodegenOptions`] which get inserted into the
+/// generated code.
+#[derive(Debug, Clone)]
+struct Settings {
+    derives: Vec<TokenStream>,
+    paths: Paths,
+    visibility: TokenStream,
+    string_type: StringType,
+    serde: bool,
+    native_types: IndexMap<String, TokenStream>,
+}
+
+impl Settings {
+    fn from_options(
+        options: &CodegenOptions,
+        diagnostics: &mut Diagnostics,
+    ) -> Self {
+        let mut parse = |option: &str, value: &str| match value.parse() {
+            Ok(tokens) => tokens,
+            Err(_) => {
+                diagnostics.push(Diagnostic::InvalidOption {
+                    option: option.to_string(),
+                    value: value.to_string(),
+                });
+                TokenStream::new()
+            },
+        };
+
+        let derives = options
+            .derives
+            .iter()
+            .map(|derive| parse("derive", derive))
+            .collect();
+        let runtime_crate = parse("runtime_crate", &options.runtime_crate);
+        let visibility = parse("visibility", &options.visibility);
+        let native_types = options
+            .native_types
+            .iter()
+            .map(|(name, path)| (name.clone(), parse("native_type", path)))
+            .collect();
+
+        Settings {
+            derives,
+            paths: if options.no_std {
+                Paths::no_std(runtime_crate)
+            } else {
+                Paths::std(runtime_crate)
+            },
+            visibility,
+            string_type: options.string_type,
+            serde: options.serde,
+            native_types,
+        }
+    }
+}
+
+/// The items to be generated in a module, plus its child modules.
+#[derive(Debug, Default)]
+struct Module {
+    items: Vec<TokenStream>,
+    children: IndexMap<String, Module>,
+}
+
+impl Module {
+    fn insert(&mut self, path: &[String], item: TokenStream) {
+        match path.split_first() {
+            Some((first, rest)) => self
+                .children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, item),
+            None => self.items.push(item),
+        }
+    }
+
+    fn into_tokens(self, visibility: &TokenStream) -> TokenStream {
+        let Module { items, children } = self;
+        let children = children.into_iter().map(|(name, module)| {
+            let name = naming::field_name(&name);
+            let contents = module.into_tokens(visibility);
+            quote! {
+                #visibility mod #name { #contents }
+            }
+        });
+
+        quote! {
+            #( #items )*
+            #( #children )*
+        }
+    }
+}
+
+/// Split a fully qualified name (e.g. `play.toClient.packet`) into its
+/// namespace and name.
+fn split_qualified_name(name: &str) -> (Vec<String>, &str) {
+    let mut segments: Vec<&str> = name.split('.').collect();
+    let name = segments.pop().unwrap_or_default();
+
+    (segments.into_iter().map(String::from).collect(), name)
+}
+
+/// Figure out which namespace each type is defined in.
+fn module_paths(
+    compilation_unit: &CompilationUnit,
+) -> HashMap<TypeId, Vec<String>> {
+    let mut modules = HashMap::new();
+
+    for (name, id) in &compilation_unit.named_types {
+        modules
+            .entry(*id)
+            .or_insert_with(|| split_qualified_name(name).0);
+    }
+
+    for (id, hint) in &compilation_unit.name_hints {
+        modules
+            .entry(*id)
+            .or_insert_with(|| split_qualified_name(hint).0);
+    }
+
+    for (id, ty) in &compilation_unit.types {
+        // natives are always imported into the top-level module
+        if matches!(ty, Type::Native) {
+            modules.insert(*id, Vec::new());
+        } else {
+            modules.entry(*id).or_default();
+        }
+    }
+
+    modules
+}
+
+fn types_to_emit(
+    compilation_unit: &CompilationUnit,
+    modules: &HashMap<TypeId, Vec<String>>,
+    options: &CodegenOptions,
+    diagnostics: &mut Diagnostics,
+) -> IndexSet<TypeId> {
+    if options.emit_everything() {
+        return compilation_unit.types.keys().copied().collect();
+    }
+
+    let mut to_visit = Vec::new();
+
+    for name in &options.types {
+        match compilation_unit.named_types.get(name) {
+            Some(id) => to_visit.push(*id),
+            None => diagnostics.push(Diagnostic::MissingName {
+                name: name.clone(),
+                used_by: None,
+            }),
+        }
+    }
+
+    for namespace in &options.namespaces {
+        let namespace: Vec<&str> = namespace.split('.').collect();
+        let in_namespace = compilation_unit.types.keys().filter(|id| {
+            let module = &modules[id];
+            module.len() >= namespace.len()
+                && module.iter().zip(&namespace).all(|(l, r)| l == r)
+        });
+        let len_before = to_visit.len();
+        to_visit.extend(in_namespace);
+
+        if to_visit.len() == len_before {
+            diagnostics.push(Diagnostic::MissingName {
+                name: namespace.join("."),
+                used_by: None,
+            });
+        }
+    }
+
+    // make sure we also emit everything the requested types depend on
+    let mut emitted = IndexSet::new();
+
+    while let Some(id) = to_visit.pop() {
+        if emitted.insert(id) {
+            to_visit.extend(member_types(&compilation_unit.types[&id]));
+        }
+    }
 
-    let type_definitions = compilation_unit
+    compilation_unit
         .types
-        .iter()
-        .map(|(id, ty)| generate_type_definition(*id, ty, &names));
+        .keys()
+        .filter(|id| emitted.contains(*id))
+        .copied()
+        .collect()
+}
+
+/// The natives provided by the runtime crate's `native` module.
+const RUNTIME_NATIVES: &[&str] = &[
+    "I8",
+    "I16",
+    "I32",
+    "I64",
+    "U8",
+    "U16",
+    "U32",
+    "U64",
+    "F32",
+    "F64",
+    "Bool",
+    "Void",
+    "Varint",
+    "Uuid",
+    "RestBuffer",
+];
+
+/// Bring the natives used by the emitted types into scope, either from the
+/// runtime crate or from the path given by
+/// [`CodegenOptions::native_type()`].
+fn native_imports(
+    compilation_unit: &CompilationUnit,
+    names: &HashMap<TypeId, Ident>,
+    emitted: &IndexSet<TypeId>,
+    settings: &Settings,
+    diagnostics: &mut Diagnostics,
+) -> TokenStream {
+    for name in settings.native_types.keys() {
+        let is_native = compilation_unit
+            .named_types
+            .get(name)
+            .is_some_and(|id| compilation_unit.types[id] == Type::Native);
+
+        if !is_native {
+            diagnostics.push(Diagnostic::MissingName {
+                name: name.clone(),
+                used_by: None,
+            });
+        }
+    }
 
-    tokens.extend(type_definitions);
+    let mut from_runtime = Vec::new();
+    let mut from_elsewhere = Vec::new();
 
-    tokens.into_iter().collect()
+    for id in native_types_we_need_to_import(names, compilation_unit, emitted) {
+        let name = &names[&id];
+        let protocol_name = compilation_unit
+            .named_types
+            .iter()
+            .find(|(_, named)| **named == id)
+            .map(|(protocol_name, _)| protocol_name.as_str());
+
+        match protocol_name.and_then(|n| settings.native_types.get(n)) {
+            Some(path) => from_elsewhere.push(quote!(use #path as #name;)),
+            None if RUNTIME_NATIVES.iter().any(|n| name == n) => {
+                from_runtime.push(name)
+            },
+            None => diagnostics.push(Diagnostic::UnknownNative {
+                name: protocol_name
+                    .or_else(|| {
+                        compilation_unit.name_hints.get(&id).map(String::as_str)
+                    })
+                    .unwrap_or_default()
+                    .to_string(),
+            }),
+        }
+    }
+
+    let runtime = &settings.paths.runtime;
+    let from_runtime = if from_runtime.is_empty() {
+        TokenStream::new()
+    } else {
+        quote!(use #runtime::native::{ #(#from_runtime),* };)
+    };
+
+    quote! {
+        #from_runtime
+        #( #from_elsewhere )*
+    }
 }
 
-fn native_types_we_need_to_import<'n>(
-    names: &'n HashMap<TypeId, Ident>,
+fn native_types_we_need_to_import(
+    names: &HashMap<TypeId, Ident>,
     compilation_unit: &CompilationUnit,
-) -> Vec<&'n Ident> {
-    let mut ids = HashSet::new();
+    emitted: &IndexSet<TypeId>,
+) -> Vec<TypeId> {
+    let mut ids = IndexSet::new();
+
+    for (id, ty) in &compilation_unit.types {
+        if !emitted.contains(id) {
+            continue;
+        }
 
-    for ty in compilation_unit.types.values() {
         for member_type_id in member_types(ty) {
             let member_type = &compilation_unit.types[&member_type_id];
+            // a switch's void variants are unit variants, so they never
+            // mention the type
+            let unit_variant =
+                matches!(ty, Type::Enum(_)) && names[&member_type_id] == "Void";
 
-            if matches!(member_type, Type::Native) {
+            if matches!(member_type, Type::Native) && !unit_variant {
                 ids.insert(member_type_id);
             }
         }
     }
 
-    ids.into_iter().map(|id| &names[&id]).collect()
+    ids.into_iter().collect()
 }
 
 fn member_types(ty: &Type) -> Vec<TypeId> {
     match ty {
         Type::Native => Vec::new(),
         Type::Struct(s) => s.fields.iter().map(|f| f.ty).collect(),
-        Type::Enum(e) => e.variants.iter().map(|_| todo!()).collect(),
+        Type::Enum(e) => {
+            e.variants.iter().map(|v| v.ty).chain(e.default).collect()
+        },
         Type::LengthPrefixedString(s) => vec![s.count_type],
         Type::BitFields(_) => Vec::new(),
+        Type::Mapper(m) => vec![m.ty],
+        Type::Array(a) => count_types(&a.count).chain(Some(a.item)).collect(),
+        Type::Buffer(b) => count_types(&b.count).collect(),
+        Type::Option(inner) => vec![*inner],
+    }
+}
+
+/// Find every type which borrows from its input, either because it is a
+/// borrowed string or because it contains something which borrows.
+fn types_which_borrow(
+    compilation_unit: &CompilationUnit,
+    string_type: StringType,
+) -> HashSet<TypeId> {
+    let mut borrowed = HashSet::new();
+
+    if string_type == StringType::Owned {
+        return borrowed;
+    }
+
+    // keep going until we reach a fixed point, so types which (indirectly)
+    // refer to each other are handled correctly
+    loop {
+        let len_before = borrowed.len();
+
+        for (id, ty) in &compilation_unit.types {
+            let borrows = matches!(ty, Type::LengthPrefixedString(_))
+                || member_types(ty).iter().any(|m| borrowed.contains(m));
+
+            if borrows {
+                borrowed.insert(*id);
+            }
+        }
+
+        if borrowed.len() == len_before {
+            return borrowed;
+        }
+    }
+}
+
+/// Find every field a `switch` compares against or an array uses as its
+/// `count`, so they can be recorded in the `Context` for nested types to use
+/// (e.g. `../action`).
+///
+/// Paths are resolved relative to the struct containing the `switch` or
+/// array, so a field is only recorded by the structs it could refer to
+/// rather than by every struct with a field of the same name. Parameters
+/// (e.g. `$version`) are supplied by the caller, so they are skipped.
+fn fields_used_by_switches(
+    compilation_unit: &CompilationUnit,
+) -> HashSet<(TypeId, String)> {
+    let anonymous = anonymous_structs(compilation_unit);
+    // the structs each struct may be nested inside
+    let mut parents: HashMap<TypeId, HashSet<TypeId>> = HashMap::new();
+    // the paths used by each struct's switches and arrays
+    let mut paths: Vec<(TypeId, &str)> = Vec::new();
+
+    for (id, ty) in &compilation_unit.types {
+        match ty {
+            Type::Struct(s) if !anonymous.contains(id) => {
+                let mut visited = HashSet::new();
+
+                for field in &s.fields {
+                    visit_field(
+                        compilation_unit,
+                        *id,
+                        field,
+                        &mut visited,
+                        &mut parents,
+                        &mut paths,
+                    );
+                }
+            },
+            _ => {},
+        }
+    }
+
+    let mut fields = HashSet::new();
+
+    for (owner, path) in paths {
+        if path.starts_with('$') {
+            continue;
+        }
+
+        let segments: Vec<&str> = path.split('/').collect();
+        let ups = segments.iter().take_while(|s| **s == "..").count();
+        let (name, nested) = match segments[ups..].split_last() {
+            Some(split) => split,
+            None => continue,
+        };
+
+        let mut scopes: HashSet<TypeId> = std::iter::once(owner).collect();
+
+        for _ in 0..ups {
+            scopes = scopes
+                .iter()
+                .filter_map(|scope| parents.get(scope))
+                .flatten()
+                .copied()
+                .collect();
+        }
+
+        for segment in nested {
+            scopes = scopes
+                .iter()
+                .filter_map(|scope| find_field(compilation_unit, *scope, segment))
+                .map(|(_, field)| field.ty)
+                .filter(|ty| {
+                    matches!(compilation_unit.types[ty], Type::Struct(_))
+                })
+                .collect();
+        }
+
+        for scope in scopes {
+            if let Some((declared_in, _)) =
+                find_field(compilation_unit, scope, name)
+            {
+                fields.insert((declared_in, String::from(*name)));
+            }
+        }
     }
+
+    fields
+}
+
+/// Look for the `switch`es and arrays used by a struct's field, and the
+/// structs nested inside it, without going inside those structs.
+fn visit_field<'cu>(
+    compilation_unit: &'cu CompilationUnit,
+    owner: TypeId,
+    field: &'cu Field,
+    visited: &mut HashSet<TypeId>,
+    parents: &mut HashMap<TypeId, HashSet<TypeId>>,
+    paths: &mut Vec<(TypeId, &'cu str)>,
+) {
+    // an anonymous struct's fields are part of its parent
+    if field.anonymous {
+        if let Type::Struct(s) = &compilation_unit.types[&field.ty] {
+            for field in &s.fields {
+                visit_field(
+                    compilation_unit,
+                    owner,
+                    field,
+                    visited,
+                    parents,
+                    paths,
+                );
+            }
+            return;
+        }
+    }
+
+    let mut pending = vec![field.ty];
+
+    while let Some(ty) = pending.pop() {
+        if !visited.insert(ty) {
+            continue;
+        }
+
+        match &compilation_unit.types[&ty] {
+            Type::Struct(_) => {
+                parents.entry(ty).or_default().insert(owner);
+            },
+            Type::Enum(e) => {
+                paths.push((owner, e.compare_to.as_str()));
+                pending.extend(e.variants.iter().map(|v| v.ty));
+                pending.extend(e.default);
+            },
+            Type::Array(a) => {
+                if let Count::Field(path) = &a.count {
+                    paths.push((owner, path.as_str()));
+                }
+                pending.push(a.item);
+            },
+            Type::Buffer(Buffer {
+                count: Count::Field(path),
+            }) => paths.push((owner, path.as_str())),
+            Type::Option(inner) => pending.push(*inner),
+            _ => {},
+        }
+    }
+}
+
+/// Find a struct's field by name, looking inside anonymous fields, and the
+/// struct which declares it.
+fn find_field<'cu>(
+    compilation_unit: &'cu CompilationUnit,
+    id: TypeId,
+    name: &str,
+) -> Option<(TypeId, &'cu Field)> {
+    let s = match &compilation_unit.types[&id] {
+        Type::Struct(s) => s,
+        _ => return None,
+    };
+
+    s.fields.iter().find_map(|field| {
+        if field.name == name {
+            Some((id, field))
+        } else if field.anonymous {
+            find_field(compilation_unit, field.ty, name)
+        } else {
+            None
+        }
+    })
+}
+
+/// Find the structs which are used as anonymous fields.
+fn anonymous_structs(compilation_unit: &CompilationUnit) -> HashSet<TypeId> {
+    compilation_unit
+        .types
+        .values()
+        .filter_map(|ty| match ty {
+            Type::Struct(s) => Some(s),
+            _ => None,
+        })
+        .flat_map(|s| &s.fields)
+        .filter(|f| f.anonymous)
+        .filter(|f| matches!(compilation_unit.types[&f.ty], Type::Struct(_)))
+        .map(|f| f.ty)
+        .collect()
+}
+
+/// Find the types which can be used as a `SwitchKey`.
+fn switch_key_types(
+    compilation_unit: &CompilationUnit,
+    names: &HashMap<TypeId, Ident>,
+) -> HashSet<TypeId> {
+    const NATIVES: &[&str] = &[
+        "I8", "I16", "I32", "I64", "U8", "U16", "U32", "U64", "Bool", "Varint",
+    ];
+
+    compilation_unit
+        .types
+        .iter()
+        .filter(|(id, ty)| match ty {
+            Type::Native => NATIVES.contains(&names[id].to_string().as_str()),
+            Type::LengthPrefixedString(_) | Type::Mapper(_) => true,
+            _ => false,
+        })
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+/// Check the states a `nextState` field can switch to (see
+/// [`CodegenOptions::next_state()`]), making sure they were given for every
+/// `nextState` which isn't a `mapper`.
+fn next_states(
+    compilation_unit: &CompilationUnit,
+    emitted: &IndexSet<TypeId>,
+    options: &CodegenOptions,
+    diagnostics: &mut Diagnostics,
+) -> IndexMap<i64, String> {
+    // the namespaces containing toClient or toServer packets
+    let mut states: Vec<&str> = Vec::new();
+
+    for (name, id) in &compilation_unit.named_types {
+        let segments: Vec<&str> = name.split('.').collect();
+
+        if segments.len() != 3
+            || !matches!(segments[1], "toClient" | "toServer")
+        {
+            continue;
+        }
+
+        if !states.contains(&segments[0]) {
+            states.push(segments[0]);
+        }
+
+        let needs_next_states = match &compilation_unit.types[id] {
+            Type::Struct(s) => s.fields.iter().any(|f| {
+                f.name == "nextState"
+                    && !matches!(compilation_unit.types[&f.ty], Type::Mapper(_))
+            }),
+            _ => false,
+        };
+
+        if needs_next_states
+            && emitted.contains(id)
+            && options.next_states.is_empty()
+        {
+            diagnostics.push(Diagnostic::MissingNextStates {
+                packet: name.clone(),
+            });
+        }
+    }
+
+    for state in options.next_states.values() {
+        if !states.contains(&state.as_str()) {
+            diagnostics.push(Diagnostic::InvalidOption {
+                option: String::from("next_state"),
+                value: state.clone(),
+            });
+        }
+    }
+
+    options.next_states.clone()
+}
+
+fn count_types(count: &Count) -> impl Iterator<Item = TypeId> {
+    match count {
+        Count::Prefixed(ty) => Some(*ty),
+        Count::Fixed(_) | Count::Field(_) => None,
+    }
+    .into_iter()
 }
 
 fn generate_names(
     compilation_unit: &CompilationUnit,
+    modules: &HashMap<TypeId, Vec<String>>,
+    options: &CodegenOptions,
+    diagnostics: &mut Diagnostics,
 ) -> HashMap<TypeId, Ident> {
     let mut names = HashMap::new();
+    // names are only unique within a module
+    let mut original_names: IndexMap<(Vec<String>, String), Vec<String>> =
+        IndexMap::new();
+
+    for (protocol_name, rust_name) in &options.type_names {
+        let id =
+            compilation_unit.named_types.get(protocol_name).or_else(|| {
+                compilation_unit
+                    .name_hints
+                    .iter()
+                    .find(|(_, hint)| *hint == protocol_name)
+                    .map(|(id, _)| id)
+            });
+
+        match (id, naming::parse_ident(rust_name)) {
+            (Some(id), Some(ident)) => {
+                original_names
+                    .entry((modules[id].clone(), ident.to_string()))
+                    .or_default()
+                    .push(protocol_name.clone());
+                names.insert(*id, ident);
+            },
+            (None, _) => diagnostics.push(Diagnostic::MissingName {
+                name: protocol_name.clone(),
+                used_by: None,
+            }),
+            (_, None) => diagnostics.push(Diagnostic::InvalidOption {
+                option: String::from("type_name"),
+                value: rust_name.clone(),
+            }),
+        }
+    }
+
+    for (name, id) in &compilation_unit.named_types {
+        if names.contains_key(id) {
+            continue;
+        }
+
+        let ident = naming::type_name(split_qualified_name(name).1);
+        original_names
+            .entry((modules[id].clone(), ident.to_string()))
+            .or_default()
+            .push(name.clone());
+        names.insert(*id, ident);
+    }
 
+    // Anonymous types are named after the place they were declared, using a
+    // numeric suffix to disambiguate. Types are visited in declaration order
+    // so the same protocol will always generate the same names.
     for id in compilation_unit.types.keys() {
-        if let Some(real_name) = compilation_unit
-            .named_types
-            .iter()
-            .find(|(_, v)| *v == id)
-            .map(|(name, _)| name)
+        if names.contains_key(id) {
+            continue;
+        }
+
+        let hint = compilation_unit
+            .name_hints
+            .get(id)
+            .map(String::as_str)
+            .unwrap_or("anonymous");
+        let base = naming::type_name(split_qualified_name(hint).1);
+        let module = &modules[id];
+        let mut ident = base.clone();
+        let mut suffix = 1;
+
+        while original_names.contains_key(&(module.clone(), ident.to_string()))
         {
-            let ident = Ident::new(real_name, Span::call_site());
-            names.insert(*id, ident);
-        } else {
-            // generate a synthetic name
-            let name = id.unique_name();
-            let ident = Ident::new(&name, Span::call_site());
-            names.insert(*id, ident);
+            suffix += 1;
+            ident = Ident::new(&format!("{}{}", base, suffix), base.span());
         }
+
+        original_names.insert(
+            (module.clone(), ident.to_string()),
+            vec![hint.to_string()],
+        );
+        names.insert(*id, ident);
     }
 
+    let original_names = original_names
+        .into_iter()
+        .map(|((_, ident), names)| (ident, names));
+    report_collisions(original_names, diagnostics);
+
     names
 }
 
-fn generate_type_definition(
-    id: TypeId,
-    ty: &crate::lowering::Type,
+/// Bitfields are decoded by packing their fields into a `u64`, so they must
+/// be a whole number of bytes and fit in 64 bits.
+fn check_bitfields(
+    compilation_unit: &CompilationUnit,
     names: &HashMap<TypeId, Ident>,
-) -> TokenStream {
-    match ty {
-        crate::lowering::Type::Native => TokenStream::new(),
-        crate::lowering::Type::Struct(s) => {
-            generate_struct_definition(id, s, names)
-        },
-        crate::lowering::Type::Enum(_) => todo!(),
-        crate::lowering::Type::LengthPrefixedString(_) => {
-            let name = &names[&id];
-            quote! {
-                #[derive(Debug, Clone, PartialEq)]
-                pub struct #name(pub String);
+    diagnostics: &mut Diagnostics,
+) {
+    for (id, ty) in &compilation_unit.types {
+        if let Type::BitFields(b) = ty {
+            let bits: usize = b.fields.iter().map(|f| f.size).sum();
+
+            if bits % 8 != 0 || bits > 64 {
+                diagnostics.push(Diagnostic::UnsupportedBitFields {
+                    name: names[id].to_string(),
+                    bits,
+                });
             }
-        },
-        crate::lowering::Type::BitFields(_) => todo!(),
+        }
     }
 }
 
-fn generate_struct_definition(
-    id: TypeId,
-    s: &crate::lowering::Struct,
+fn check_field_names(
+    compilation_unit: &CompilationUnit,
     names: &HashMap<TypeId, Ident>,
-) -> TokenStream {
-    let name = &names[&id];
-    let fields = s.fields.iter().map(|f| {
-        let name = Ident::new(&f.name, Span::call_site());
-        let type_name = &names[&f.ty];
+    diagnostics: &mut Diagnostics,
+) {
+    for (id, ty) in &compilation_unit.types {
+        if let Type::Struct(Struct { fields }) = ty {
+            let mut original_names: IndexMap<String, Vec<String>> =
+                IndexMap::new();
 
-        quote! { pub #name: #type_name, }
-    });
+            for field in fields {
+                let ident = naming::field_name(&field.name);
+                original_names
+                    .entry(ident.to_string())
+                    .or_default()
+                    .push(format!("{}.{}", names[id], field.name));
+            }
 
-    quote! {
-        #[derive(Debug, Clone, PartialEq)]
-        pub struct #name {
-            #( #fields )*
+            report_collisions(original_names, diagnostics);
         }
     }
 }
 
-/// Use `rustfmt` to correctly format some Rust code.
-pub fn rustfmt(tokens: &TokenStream) -> io::Result<String> {
-    let Child { stdin, stdout, .. } = Command::new("rustfmt")
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
+fn check_variant_names(
+    compilation_unit: &CompilationUnit,
+    names: &HashMap<TypeId, Ident>,
+    diagnostics: &mut Diagnostics,
+) {
+    for (id, ty) in &compilation_unit.types {
+        let variants: Vec<(Ident, String)> = match ty {
+            Type::Enum(e) => e
+                .variants
+                .iter()
+                .map(|v| (variant_name(&v.key), v.key.clone()))
+                .chain(e.default.map(|_| {
+                    (naming::type_name("default"), String::from("(default)"))
+                }))
+                .collect(),
+            Type::Mapper(m) => m
+                .mappings
+                .iter()
+                .map(|(_, label)| (naming::type_name(label), label.clone()))
+                .collect(),
+            _ => continue,
+        };
+        let mut original_names: IndexMap<String, Vec<String>> = IndexMap::new();
 
-    let mut stdin = stdin.unwrap();
-    write!(stdin, "{}", tokens)?;
-    stdin.flush()?;
-    drop(stdin);
+        for (ident, key) in variants {
+            original_names
+                .entry(ident.to_string())
+                .or_default()
+                .push(format!("{}.{}", names[id], key));
+        }
+
+        report_collisions(original_names, diagnostics);
+    }
+}
 
-    let mut stdout = stdout.unwrap();
-    let mut formatted = String::new();
-    stdout.read_to_string(&mut formatted)?;
+fn report_collisions(
+    original_names: impl IntoIterator<Item = (String, Vec<String>)>,
+    diagnostics: &mut Diagnostics,
+) {
+    for (ident, names) in original_names {
+        if names.len() > 1 {
+            diagnostics.push(Diagnostic::NameCollision { ident, names });
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Generator<'a> {
+    compilation_unit: &'a CompilationUnit,
+    names: HashMap<TypeId, Ident>,
+    /// Types which borrow from the buffer they are deserialized from, and
+    /// therefore need a `'de` lifetime.
+    borrowed: HashSet<TypeId>,
+    /// The fields which a `switch` compares against, and the structs they
+    /// belong to.
+    switch_fields: HashSet<(TypeId, String)>,
+    /// Types which implement `AsSwitchKey`.
+    switch_key_types: HashSet<TypeId>,
+    /// Structs used as `"anon": true` fields, which share their parent's
+    /// `Context` scope.
+    anonymous: HashSet<TypeId>,
+    /// The state each value of a `nextState` field switches to, unless the
+    /// field is a `mapper`.
+    next_states: IndexMap<i64, String>,
+    modules: HashMap<TypeId, Vec<String>>,
+    settings: Settings,
+}
+
+impl<'a> Generator<'a> {
+    /// Get the path used to refer to a type from inside a particular module.
+    fn type_path(&self, from: &[String], id: TypeId) -> TokenStream {
+        let name = &self.names[&id];
+        let module = &self.modules[&id];
+        let lifetime = self.lifetime(id);
+
+        if module.as_slice() == from {
+            quote!(#name #lifetime)
+        } else {
+            let supers = from.iter().map(|_| quote!(super::));
+            let path = module.iter().map(|segment| naming::field_name(segment));
+            quote!(#(#supers)* #(#path::)* #name #lifetime)
+        }
+    }
+
+    fn borrows(&self, id: TypeId) -> bool { self.borrowed.contains(&id) }
+
+    /// Should a struct's field be recorded in the `Context` while
+    /// deserializing?
+    fn records(&self, owner: TypeId, name: &str, ty: TypeId) -> bool {
+        self.switch_fields.contains(&(owner, String::from(name)))
+            && self.switch_key_types.contains(&ty)
+    }
+
+    /// The fields of an anonymous struct which need to be recorded in its
+    /// parent's `Context`, because they are treated as part of the parent.
+    fn merged_fields(&self, ty: TypeId) -> Vec<(String, TokenStream)> {
+        let s = match &self.compilation_unit.types[&ty] {
+            Type::Struct(s) => s,
+            _ => return Vec::new(),
+        };
+        let mut merged = Vec::new();
+
+        for field in &s.fields {
+            let member = naming::field_name(&field.name);
 
-    Ok(formatted)
+            if self.records(ty, &field.name, field.ty) {
+                merged.push((member.to_string(), quote!(.#member)));
+            }
+
+            if field.anonymous {
+                merged.extend(
+                    self.merged_fields(field.ty)
+                        .into_iter()
+                        .map(|(name, path)| (name, quote!(.#member #path))),
+                );
+            }
+        }
+
+        merged
+    }
+
+    /// The generic parameters needed when referring to a type.
+    fn lifetime(&self, id: TypeId) -> TokenStream {
+        if self.borrows(id) {
+            quote!(<'de>)
+        } else {
+            TokenStream::new()
+        }
+    }
+
+    fn derives(&self, id: TypeId) -> TokenStream {
+        self.derives_for(self.borrows(id))
+    }
+
+    fn derives_for(&self, borrows: bool) -> TokenStream {
+        let extra = &self.settings.derives;
+        let serde = match (self.settings.serde, borrows) {
+            (false, _) => TokenStream::new(),
+            (true, false) => {
+                quote!(, ::serde::Serialize, ::serde::Deserialize)
+            },
+            // serde reserves the 'de lifetime for Deserialize impls
+            (true, true) => quote!(, ::serde::Serialize),
+        };
+
+        quote! {
+            #[derive(Debug, Clone, PartialEq #(, #extra)* #serde)]
+        }
+    }
+
+    /// A `#[serde(...)]` attribute, if serde support is enabled.
+    fn serde_attribute(&self, args: TokenStream) -> TokenStream {
+        if self.settings.serde {
+            quote!(#[serde(#args)])
+        } else {
+            TokenStream::new()
+        }
+    }
+
+    /// Make sure serde uses the name from the `protocol.json` for something
+    /// which was renamed to be a valid Rust identifier.
+    fn serde_rename(&self, ident: &Ident, original: &str) -> TokenStream {
+        if ident.to_string().trim_start_matches("r#") == original {
+            TokenStream::new()
+        } else {
+            self.serde_attribute(quote!(rename = #original))
+        }
+    }
+
+    fn is_void(&self, id: TypeId) -> bool {
+        matches!(self.compilation_unit.types[&id], Type::Native)
+            && self.names[&id] == "Void"
+    }
+
+    /// How a field of a particular type is encoded.
+    fn encoding(&self, id: TypeId) -> Encoding {
+        match &self.compilation_unit.types[&id] {
+            Type::Enum(e) => Encoding::Switch {
+                compare_to: compare_to_path(&e.compare_to),
+            },
+            _ => Encoding::Default,
+        }
+    }
+
+    fn generate_type_definition(&self, id: TypeId, ty: &Type) -> TokenStream {
+        match ty {
+            Type::Native => TokenStream::new(),
+            Type::Struct(s) => match self.packet_dispatch(id, s) {
+                Some(dispatch) => self.generate_packet_definition(id, dispatch),
+                None => self.generate_struct_definition(id, s),
+            },
+            Type::Enum(e) => self.generate_switch_definition(id, e),
+            Type::LengthPrefixedString(s) => {
+                self.generate_length_prefixed_string(id, s)
+            },
+            Type::BitFields(b) => self.generate_bitfields_definition(id, b),
+            Type::Mapper(m) => self.generate_mapper_definition(id, m),
+            Type::Array(a) => {
+                self.generate_array_definition(id, Some(a.item), &a.count)
+            },
+            Type::Buffer(b) => {
+                self.generate_array_definition(id, None, &b.count)
+            },
+            Type::Option(inner) => self.generate_option_definition(id, *inner),
+        }
+    }
+
+    fn generate_struct_definition(
+        &self,
+        id: TypeId,
+        s: &Struct,
+    ) -> TokenStream {
+        let name = &self.names[&id];
+        let module = &self.modules[&id];
+        let vis = &self.settings.visibility;
+        let fields: Vec<_> = s
+            .fields
+            .iter()
+            .map(|f| {
+                let name = naming::field_name(&f.name);
+
+                FieldInfo {
+                    member: quote!(#name),
+                    name: name.to_string(),
+                    ty: self.type_path(module, f.ty),
+                    encoding: self.encoding(f.ty),
+                    context: self.records(id, &f.name, f.ty),
+                    merged: if f.anonymous {
+                        self.merged_fields(f.ty)
+                    } else {
+                        Vec::new()
+                    },
+                }
+            })
+            .collect();
+        let field_definitions =
+            fields.iter().zip(&s.fields).map(|(f, field)| {
+                let FieldInfo { member, ty, .. } = f;
+                let rename = self.serde_rename(
+                    &naming::field_name(&field.name),
+                    &field.name,
+                );
+                quote! { #rename #vis #member: #ty, }
+            });
+        let derives = self.derives(id);
+        let impls = serialization::struct_impls(
+            &self.settings.paths,
+            name,
+            self.borrows(id),
+            self.anonymous.contains(&id),
+            &Fields::Named(fields.clone()),
+        );
+        let lifetime = self.lifetime(id);
+
+        quote! {
+            #derives
+            #vis struct #name #lifetime {
+                #( #field_definitions )*
+            }
+
+            #impls
+        }
+    }
+
+    fn generate_length_prefixed_string(
+        &self,
+        id: TypeId,
+        s: &LengthPrefixedString,
+    ) -> TokenStream {
+        let name = &self.names[&id];
+        let alloc = &self.settings.paths.alloc;
+        let ty = match self.settings.string_type {
+            StringType::Owned => quote!(#alloc::string::String),
+            StringType::Borrowed => quote!(&'de str),
+            StringType::Cow => quote!(#alloc::borrow::Cow<'de, str>),
+        };
+        let encoding = Encoding::LengthPrefixed {
+            count: self.type_path(&self.modules[&id], s.count_type),
+        };
+        let definition = self.generate_newtype(id, ty, encoding);
+        let lifetime = self.lifetime(id);
+        let runtime = &self.settings.paths.runtime;
+
+        quote! {
+            #definition
+
+            impl #lifetime #runtime::AsSwitchKey for #name #lifetime {
+                fn as_switch_key(&self) -> #runtime::SwitchKey<'_> {
+                    #runtime::AsSwitchKey::as_switch_key(&self.0)
+                }
+            }
+        }
+    }
+
+    /// Generate a newtype around a `Vec` for an `array` of `item`s, or a
+    /// `buffer` of bytes when there is no `item`.
+    fn generate_array_definition(
+        &self,
+        id: TypeId,
+        item: Option<TypeId>,
+        count: &Count,
+    ) -> TokenStream {
+        let module = &self.modules[&id];
+        let item = match item {
+            Some(item) => self.type_path(module, item),
+            None => quote!(u8),
+        };
+        let alloc = &self.settings.paths.alloc;
+        let encoding = match count {
+            Count::Prefixed(ty) => Encoding::LengthPrefixed {
+                count: self.type_path(module, *ty),
+            },
+            Count::Fixed(length) => Encoding::Fixed { length: *length },
+            Count::Field(path) => Encoding::Counted {
+                count: compare_to_path(path),
+            },
+        };
+
+        self.generate_newtype(id, quote!(#alloc::vec::Vec<#item>), encoding)
+    }
+
+    /// An `option` is a newtype around an `Option`.
+    fn generate_option_definition(
+        &self,
+        id: TypeId,
+        inner: TypeId,
+    ) -> TokenStream {
+        let core = &self.settings.paths.core;
+        let inner = self.type_path(&self.modules[&id], inner);
+
+        self.generate_newtype(
+            id,
+            quote!(#core::option::Option<#inner>),
+            Encoding::Default,
+        )
+    }
+
+    fn generate_newtype(
+        &self,
+        id: TypeId,
+        ty: TokenStream,
+        encoding: Encoding,
+    ) -> TokenStream {
+        let name = &self.names[&id];
+        let derives = self.derives(id);
+        let transparent = self.serde_attribute(quote!(transparent));
+        let vis = &self.settings.visibility;
+        let field = FieldInfo {
+            member: quote!(0),
+            name: String::from("0"),
+            ty: ty.clone(),
+            encoding,
+            context: false,
+            merged: Vec::new(),
+        };
+        let impls = serialization::struct_impls(
+            &self.settings.paths,
+            name,
+            self.borrows(id),
+            false,
+            &Fields::Unnamed(vec![field]),
+        );
+        let lifetime = self.lifetime(id);
+
+        quote! {
+            #derives
+            #transparent
+            #vis struct #name #lifetime (#vis #ty);
+
+            #impls
+        }
+    }
+
+    fn generate_switch_definition(&self, id: TypeId, e: &Enum) -> TokenStream {
+        let name = &self.names[&id];
+        let module = &self.modules[&id];
+        let vis = &self.settings.visibility;
+        let runtime = &self.settings.paths.runtime;
+        let mut variants = Vec::new();
+        let mut definitions = Vec::new();
+
+        let cases = e.variants.iter().map(|v| (Some(&v.key), v.ty));
+        let default = e.default.map(|ty| (None, ty));
+
+        for (key, ty) in cases.chain(default) {
+            let variant_name = match key {
+                Some(key) => variant_name(key),
+                None => naming::type_name("default"),
+            };
+            let rename = match key {
+                Some(key) => self.serde_attribute(quote!(rename = #key)),
+                None => TokenStream::new(),
+            };
+            let fields = if self.is_void(ty) {
+                definitions.push(quote!(#rename #variant_name,));
+                Fields::Unit
+            } else {
+                let ty_path = self.type_path(module, ty);
+                definitions.push(quote!(#rename #variant_name(#ty_path),));
+                Fields::Unnamed(vec![FieldInfo {
+                    member: quote!(0),
+                    name: String::from("0"),
+                    ty: ty_path,
+                    encoding: self.encoding(ty),
+                    context: false,
+                    merged: Vec::new(),
+                }])
+            };
+
+            variants.push(VariantInfo {
+                name: variant_name,
+                key: key
+                    .map(|key| serialization::switch_key_pattern(runtime, key)),
+                fields,
+            });
+        }
+
+        let derives = self.derives(id);
+        let tag = self.switch_tag(e);
+        let impls = serialization::switch_impls(
+            &self.settings.paths,
+            name,
+            self.borrows(id),
+            &variants,
+        );
+        let lifetime = self.lifetime(id);
+
+        quote! {
+            #derives
+            #tag
+            #vis enum #name #lifetime {
+                #( #definitions )*
+            }
+
+            #impls
+        }
+    }
+
+    /// The `#[serde(...)]` attribute for a `switch`, which is tagged with the
+    /// name of the field it compares against.
+    fn switch_tag(&self, e: &Enum) -> TokenStream {
+        let tag = e.compare_to.rsplit('/').next().unwrap_or_default();
+        let tag = tag.trim_start_matches('$');
+        let variants = e.variants.iter().map(|v| v.ty).chain(e.default);
+
+        self.serde_tag(tag, "value", variants)
+    }
+
+    /// Tag an enum with a field, putting each variant's fields alongside the
+    /// tag (e.g. `{ "name": "chat", "message": "Hi" }`).
+    ///
+    /// Internally tagged enums can only contain objects, so the variant's
+    /// value goes in a separate `content` field if any variant contains
+    /// something else.
+    fn serde_tag(
+        &self,
+        tag: &str,
+        content: &str,
+        variants: impl IntoIterator<Item = TypeId>,
+    ) -> TokenStream {
+        let only_objects = variants.into_iter().all(|ty| {
+            self.is_void(ty)
+                || matches!(
+                    self.compilation_unit.types[&ty],
+                    Type::Struct(_) | Type::BitFields(_)
+                )
+        });
+
+        if only_objects {
+            self.serde_attribute(quote!(tag = #tag))
+        } else {
+            self.serde_attribute(quote!(tag = #tag, content = #content))
+        }
+    }
+
+    fn generate_mapper_definition(
+        &self,
+        id: TypeId,
+        m: &Mapper,
+    ) -> TokenStream {
+        let name = &self.names[&id];
+        let vis = &self.settings.visibility;
+        let raw = self.type_path(&self.modules[&id], m.ty);
+        let variants = self.mappings(&self.modules[&id], m);
+        let definitions = variants.iter().map(|v| {
+            let rename = self.serde_attribute({
+                let label = &v.label;
+                quote!(rename = #label)
+            });
+            let variant = &v.name;
+            quote!(#rename #variant,)
+        });
+        let derives = self.derives(id);
+        let impls = serialization::mapper_impls(
+            &self.settings.paths,
+            name,
+            &raw,
+            &variants,
+        );
+
+        quote! {
+            #derives
+            #vis enum #name {
+                #( #definitions )*
+            }
+
+            #impls
+        }
+    }
+
+    /// The variants of a `mapper`, as seen from a particular module.
+    fn mappings(&self, module: &[String], m: &Mapper) -> Vec<MappingInfo> {
+        let raw = self.type_path(module, m.ty);
+        let is_varint = self.names[&m.ty] == "Varint";
+
+        m.mappings
+            .iter()
+            .map(|(value, label)| {
+                let literal = Literal::i64_unsuffixed(*value);
+
+                MappingInfo {
+                    name: naming::type_name(label),
+                    value: *value,
+                    // the other natives are type aliases for integers
+                    raw: if is_varint {
+                        quote!(#raw(#literal))
+                    } else {
+                        quote!(#literal)
+                    },
+                    label: label.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Check whether a struct is a namespace's `packet` type, a `mapper`
+    /// containing the packet's id followed by a `switch` on the packet's
+    /// name.
+    fn packet_dispatch<'s>(
+        &'s self,
+        id: TypeId,
+        s: &'s Struct,
+    ) -> Option<PacketDispatch<'s>> {
+        let is_packet =
+            self.compilation_unit.named_types.iter().any(|(name, ty)| {
+                *ty == id && split_qualified_name(name).1 == "packet"
+            });
+
+        match (is_packet, s.fields.as_slice()) {
+            (true, [tag, params]) => {
+                let types = &self.compilation_unit.types;
+
+                match (&types[&tag.ty], &types[&params.ty]) {
+                    (Type::Mapper(mapper), Type::Enum(switch))
+                        if switch.compare_to == tag.name =>
+                    {
+                        Some(PacketDispatch {
+                            tag,
+                            mapper,
+                            params,
+                            switch,
+                        })
+                    },
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    /// Find the body of each packet in a namespace's `packet` type.
+    ///
+    /// Packets without a body can't be decoded anyway, so they are skipped.
+    fn packet_bodies(
+        &self,
+        module: &[String],
+        dispatch: PacketDispatch<'_>,
+    ) -> Vec<(MappingInfo, TypeId)> {
+        let PacketDispatch { mapper, switch, .. } = dispatch;
+
+        self.mappings(module, mapper)
+            .into_iter()
+            .filter_map(|mapping| {
+                let body = switch
+                    .variants
+                    .iter()
+                    .find(|v| v.key == mapping.label)
+                    .map(|v| v.ty)
+                    .or(switch.default)?;
+
+                Some((mapping, body))
+            })
+            .collect()
+    }
+
+    /// Generate a `State` enum for the namespaces a connection moves between
+    /// (e.g. `handshaking` and `play`), plus `ToClientPacket` and
+    /// `ToServerPacket` enums for decoding the packets sent in the current
+    /// state.
+    ///
+    /// This only applies to namespaces containing `toClient` and `toServer`
+    /// namespaces with a `packet` type (see [`Generator::packet_dispatch()`]).
+    fn generate_states(&self, emitted: &IndexSet<TypeId>) -> TokenStream {
+        let mut namespaces: IndexMap<&str, [Option<TypeId>; 2]> =
+            IndexMap::new();
+
+        for id in emitted {
+            let is_packet = match &self.compilation_unit.types[id] {
+                Type::Struct(s) => self.packet_dispatch(*id, s).is_some(),
+                _ => false,
+            };
+
+            if let (true, [state, direction]) =
+                (is_packet, self.modules[id].as_slice())
+            {
+                let index = match direction.as_str() {
+                    "toClient" => 0,
+                    "toServer" => 1,
+                    _ => continue,
+                };
+                namespaces.entry(state).or_default()[index] = Some(*id);
+            }
+        }
+
+        if namespaces.is_empty() {
+            return TokenStream::new();
+        }
+
+        let vis = &self.settings.visibility;
+        let state = naming::type_name("state");
+        let variants = namespaces.keys().map(|label| {
+            let variant = naming::type_name(label);
+            let rename = self.serde_rename(&variant, label);
+            quote!(#rename #variant,)
+        });
+        let extra = &self.settings.derives;
+        let serde = if self.settings.serde {
+            quote!(, ::serde::Serialize, ::serde::Deserialize)
+        } else {
+            TokenStream::new()
+        };
+        let states = |direction: usize| -> Vec<StateInfo> {
+            namespaces
+                .iter()
+                .map(|(label, packets)| StateInfo {
+                    name: naming::type_name(label),
+                    label: label.to_string(),
+                    packets: packets[direction]
+                        .map(|id| self.type_path(&[], id)),
+                    transitions: packets[direction]
+                        .map(|id| self.transitions(id, &namespaces))
+                        .unwrap_or_default(),
+                })
+                .collect()
+        };
+        let state_impls = serialization::state_impls(
+            &self.settings.paths,
+            &state,
+            &states(0),
+        );
+        let to_client = self.generate_stateful_packets(
+            &namespaces,
+            "ToClient",
+            &state,
+            &states(0),
+        );
+        let to_server = self.generate_stateful_packets(
+            &namespaces,
+            "ToServer",
+            &state,
+            &states(1),
+        );
+
+        quote! {
+            #[derive(Debug, Clone, Copy, PartialEq #(, #extra)* #serde)]
+            #vis enum #state {
+                #( #variants )*
+            }
+
+            #state_impls
+            #to_client
+            #to_server
+        }
+    }
+
+    /// Generate the enum containing every packet which can be sent in a
+    /// particular direction, across all states.
+    fn generate_stateful_packets(
+        &self,
+        namespaces: &IndexMap<&str, [Option<TypeId>; 2]>,
+        direction: &str,
+        state: &Ident,
+        states: &[StateInfo],
+    ) -> TokenStream {
+        if states.iter().all(|s| s.packets.is_none()) {
+            return TokenStream::new();
+        }
+
+        let name = naming::type_name(&format!("{}_packet", direction));
+        let direction = naming::type_name(direction);
+        let vis = &self.settings.visibility;
+        let index = if direction == "ToClient" { 0 } else { 1 };
+        let borrows = namespaces
+            .values()
+            .filter_map(|packets| packets[index])
+            .any(|id| self.borrows(id));
+        let variants = states.iter().filter_map(|s| {
+            let variant = &s.name;
+            let rename = self.serde_rename(variant, &s.label);
+            let packets = s.packets.as_ref()?;
+            Some(quote!(#rename #variant(#packets),))
+        });
+        let derives = self.derives_for(borrows);
+        let impls = serialization::stateful_impls(
+            &self.settings.paths,
+            &name,
+            borrows,
+            &direction,
+            state,
+            states,
+        );
+        let lifetime = if borrows {
+            quote!(<'de>)
+        } else {
+            TokenStream::new()
+        };
+
+        quote! {
+            #derives
+            #vis enum #name #lifetime {
+                #( #variants )*
+            }
+
+            #impls
+        }
+    }
+
+    /// Find the packets in a namespace's `packet` type which switch to a new
+    /// state, because their body has a `nextState` field, and the value of
+    /// that field for each of the `states` it can switch to.
+    fn transitions(
+        &self,
+        id: TypeId,
+        states: &IndexMap<&str, [Option<TypeId>; 2]>,
+    ) -> Vec<Transition> {
+        let dispatch = match &self.compilation_unit.types[&id] {
+            Type::Struct(s) => self.packet_dispatch(id, s),
+            _ => None,
+        };
+        let dispatch = match dispatch {
+            Some(dispatch) => dispatch,
+            None => return Vec::new(),
+        };
+        let name = &self.names[&id];
+        let module = self.modules[&id].iter().map(|s| naming::field_name(s));
+        let path = quote!(#(#module::)* #name);
+
+        self.packet_bodies(&self.modules[&id], dispatch)
+            .into_iter()
+            .filter_map(|(mapping, body)| {
+                let field = match &self.compilation_unit.types[&body] {
+                    Type::Struct(s) => s.fields.iter().find(|f| {
+                        f.name == "nextState"
+                            && self.switch_key_types.contains(&f.ty)
+                    })?,
+                    _ => return None,
+                };
+                let variant = &mapping.name;
+
+                Some(Transition {
+                    packet: quote!(#path::#variant),
+                    field: naming::field_name(&field.name),
+                    next_states: self.next_state_arms(field.ty, states),
+                })
+            })
+            .collect()
+    }
+
+    /// The `SwitchKey` pattern a `nextState` field of type `ty` matches for
+    /// each of the `states` it can switch to.
+    fn next_state_arms(
+        &self,
+        ty: TypeId,
+        states: &IndexMap<&str, [Option<TypeId>; 2]>,
+    ) -> Vec<(TokenStream, Ident)> {
+        let runtime = &self.settings.paths.runtime;
+
+        match &self.compilation_unit.types[&ty] {
+            Type::Mapper(mapper) => mapper
+                .mappings
+                .iter()
+                .filter(|(_, label)| states.contains_key(label.as_str()))
+                .map(|(_, label)| {
+                    let pattern = quote!(#runtime::SwitchKey::String(#label));
+                    (pattern, naming::type_name(label))
+                })
+                .collect(),
+            _ => self
+                .next_states
+                .iter()
+                .filter(|(_, state)| states.contains_key(state.as_str()))
+                .map(|(value, state)| {
+                    let value = Literal::i64_unsuffixed(*value);
+                    let pattern = quote!(#runtime::SwitchKey::Integer(#value));
+                    (pattern, naming::type_name(state))
+                })
+                .collect(),
+        }
+    }
+
+    /// Generate a namespace's `packet` type as an enum with a variant for
+    /// each packet, instead of a struct containing the `mapper` and
+    /// `switch`.
+    fn generate_packet_definition(
+        &self,
+        id: TypeId,
+        dispatch: PacketDispatch<'_>,
+    ) -> TokenStream {
+        let PacketDispatch {
+            tag,
+            mapper,
+            params,
+            ..
+        } = dispatch;
+        let name = &self.names[&id];
+        let module = &self.modules[&id];
+        let vis = &self.settings.visibility;
+        let mut definitions = Vec::new();
+        let mut packets = Vec::new();
+
+        for (mapping, body) in self.packet_bodies(module, dispatch) {
+            let variant = &mapping.name;
+            let rename = self.serde_attribute({
+                let label = &mapping.label;
+                quote!(rename = #label)
+            });
+
+            let fields = if self.is_void(body) {
+                definitions.push(quote!(#rename #variant,));
+                Fields::Unit
+            } else {
+                let ty = self.type_path(module, body);
+                definitions.push(quote!(#rename #variant(#ty),));
+                Fields::Unnamed(vec![FieldInfo {
+                    member: quote!(0),
+                    name: String::from("0"),
+                    ty,
+                    encoding: self.encoding(body),
+                    context: false,
+                    merged: Vec::new(),
+                }])
+            };
+
+            packets.push(PacketInfo { mapping, fields });
+        }
+
+        let derives = self.derives(id);
+        let serde_tag = self.serde_tag(
+            &tag.name,
+            &params.name,
+            self.packet_bodies(module, dispatch)
+                .into_iter()
+                .map(|(_, body)| body),
+        );
+        let impls = serialization::packet_impls(
+            &self.settings.paths,
+            name,
+            self.borrows(id),
+            &self.type_path(module, mapper.ty),
+            &naming::field_name(&tag.name).to_string(),
+            &packets,
+        );
+        let lifetime = self.lifetime(id);
+
+        quote! {
+            #derives
+            #serde_tag
+            #vis enum #name #lifetime {
+                #( #definitions )*
+            }
+
+            #impls
+        }
+    }
+
+    fn generate_bitfields_definition(
+        &self,
+        id: TypeId,
+        b: &BitFields,
+    ) -> TokenStream {
+        let name = &self.names[&id];
+        let vis = &self.settings.visibility;
+        let fields: Vec<_> = b
+            .fields
+            .iter()
+            .map(|f| BitFieldInfo {
+                member: naming::field_name(&f.name),
+                ty: bitfield_type(f.size, f.signed),
+                size: f.size,
+                signed: f.signed,
+            })
+            .collect();
+        let definitions = fields.iter().zip(&b.fields).map(|(f, field)| {
+            let BitFieldInfo { member, ty, .. } = f;
+            let rename = self.serde_rename(member, &field.name);
+            quote!(#rename #vis #member: #ty,)
+        });
+        let derives = self.derives(id);
+        let impls =
+            serialization::bitfield_impls(&self.settings.paths, name, &fields);
+
+        quote! {
+            #derives
+            #vis struct #name {
+                #( #definitions )*
+            }
+
+            #impls
+        }
+    }
+}
+
+/// The pieces of a namespace's `packet` type (see
+/// [`Generator::packet_dispatch()`]).
+#[derive(Debug, Clone, Copy)]
+struct PacketDispatch<'a> {
+    /// The field containing the packet's id.
+    tag: &'a Field,
+    mapper: &'a Mapper,
+    /// The field containing the packet's body.
+    params: &'a Field,
+    switch: &'a Enum,
+}
+
+/// Convert a `compareTo` path from the `protocol.json` to the field names
+/// used in generated code (e.g. `../entityId` becomes `../entity_id`).
+fn compare_to_path(compare_to: &str) -> String {
+    compare_to
+        .split('/')
+        .map(|segment| {
+            if segment == ".." || segment.starts_with('$') {
+                segment.to_string()
+            } else {
+                naming::field_name(segment).to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The name of the variant a `switch` uses for a particular key.
+fn variant_name(key: &str) -> Ident {
+    if key.starts_with(char::is_alphabetic) {
+        naming::type_name(key)
+    } else if let Some(negative) = key.strip_prefix('-') {
+        // e.g. "-1" becomes CaseNeg1, so it doesn't collide with "1"
+        naming::type_name(&format!("case_neg_{}", negative))
+    } else {
+        // e.g. "0" becomes Case0
+        naming::type_name(&format!("case_{}", key))
+    }
+}
+
+/// The smallest integer which can hold a bitfield's value.
+fn bitfield_type(size: usize, signed: bool) -> TokenStream {
+    match (size, signed) {
+        (0..=8, false) => quote!(u8),
+        (0..=8, true) => quote!(i8),
+        (9..=16, false) => quote!(u16),
+        (9..=16, true) => quote!(i16),
+        (17..=32, false) => quote!(u32),
+        (17..=32, true) => quote!(i32),
+        (_, false) => quote!(u64),
+        (_, true) => quote!(i64),
+    }
+}
+
+/// Use `rustfmt` to correctly format some Rust code.
+pub fn rustfmt(tokens: &TokenStream) -> io::Result<String> {
+    let mut child = Command::new("rustfmt")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    write!(stdin, "{}", tokens)?;
+    stdin.flush()?;
+    drop(stdin);
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!(
+            "rustfmt failed ({}): {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lower(doc: serde_json::Value) -> CompilationUnit {
+        let protocol = crate::syntax::parse(&doc).unwrap();
+
+        crate::lowering::lower(&protocol).unwrap()
+    }
+
+    #[test]
+    fn detect_colliding_variant_names() {
+        let compilation_unit = lower(json!({
+            "types": {
+                "i32": "native",
+                "void": "native",
+                "thing": [
+                    "switch",
+                    {
+                        "compareTo": "kind",
+                        "fields": { "1": "i32", "-1": "i32", "default": "i32" },
+                        "default": "void"
+                    }
+                ],
+                "kind": [
+                    "mapper",
+                    { "type": "i32", "mappings": { "0": "a_b", "1": "aB" } }
+                ],
+            }
+        }));
+
+        let got = generate_rust(&compilation_unit).unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[
+                Diagnostic::NameCollision {
+                    ident: "Default".into(),
+                    names: vec![
+                        "Thing.default".into(),
+                        "Thing.(default)".into()
+                    ],
+                },
+                Diagnostic::NameCollision {
+                    ident: "AB".into(),
+                    names: vec!["Kind.a_b".into(), "Kind.aB".into()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn negative_switch_keys_get_their_own_variants() {
+        assert_eq!(variant_name("1"), "Case1");
+        assert_eq!(variant_name("-1"), "CaseNeg1");
+        assert_eq!(variant_name("play"), "Play");
+    }
+
+    #[test]
+    fn detect_colliding_type_and_field_names() {
+        let compilation_unit = lower(json!({
+            "types": {
+                "i32": "native",
+                "packet_ping": [
+                    "container",
+                    [
+                        { "name": "itemCount", "type": "i32" },
+                        { "name": "item_count", "type": "i32" },
+                    ]
+                ],
+                "PacketPing": ["container", []],
+            }
+        }));
+
+        let got = generate_rust(&compilation_unit).unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[
+                Diagnostic::NameCollision {
+                    ident: "PacketPing".into(),
+                    names: vec!["packet_ping".into(), "PacketPing".into()],
+                },
+                Diagnostic::NameCollision {
+                    ident: "item_count".into(),
+                    names: vec![
+                        "PacketPing.itemCount".into(),
+                        "PacketPing.item_count".into()
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn keywords_are_escaped() {
+        let compilation_unit = lower(json!({
+            "types": {
+                "varint": "native",
+                "packet_use_entity": [
+                    "container",
+                    [{ "name": "type", "type": "varint" }]
+                ],
+            }
+        }));
+
+        let got = generate_rust(&compilation_unit).unwrap().to_string();
+
+        assert!(got.contains("pub struct PacketUseEntity"));
+        assert!(got.contains("pub r#type : Varint"));
+    }
+
+    #[test]
+    fn anonymous_types_are_named_after_their_context() {
+        let compilation_unit = lower(json!({
+            "types": {
+                "i32": "native",
+                "entity": [
+                    "container",
+                    [{
+                        "name": "metadata_item",
+                        "type": ["container", [{ "name": "x", "type": "i32" }]]
+                    }]
+                ],
+                "entity_metadata": [
+                    "container",
+                    [{
+                        "name": "item",
+                        "type": ["container", [{ "name": "y", "type": "i32" }]]
+                    }]
+                ],
+                "slot": [
+                    "container",
+                    [{
+                        "name": "data",
+                        "type": ["container", [{ "name": "z", "type": "i32" }]]
+                    }]
+                ],
+                "slot_data": ["container", [{ "name": "w", "type": "i32" }]],
+            }
+        }));
+
+        let got = generate_rust(&compilation_unit).unwrap().to_string();
+
+        assert!(got.contains("pub struct EntityMetadataItem {"));
+        assert!(got.contains("pub struct EntityMetadataItem2 {"));
+        assert!(got.contains("pub struct SlotData2 {"));
+        assert!(got.contains("pub struct SlotData {"));
+    }
+
+    fn namespaced_protocol() -> CompilationUnit {
+        let doc = json!({
+            "types": {
+                "varint": "native",
+                "i64": "native",
+                "string": ["pstring", { "countType": "varint" }],
+                "unused": ["container", [{ "name": "x", "type": "i64" }]],
+            },
+            "handshaking": {
+                "toServer": {
+                    "types": {
+                        "packet_set_protocol": [
+                            "container",
+                            [
+                                { "name": "protocolVersion", "type": "varint" },
+                                { "name": "serverHost", "type": "string" },
+                            ]
+                        ],
+                    }
+                }
+            },
+            "status": {
+                "toClient": {
+                    "types": {
+                        "packet_ping": [
+                            "container",
+                            [{ "name": "time", "type": "i64" }]
+                        ],
+                    }
+                }
+            }
+        });
+
+        lower(doc)
+    }
+
+    fn named_struct_impls(
+        paths: &Paths,
+        name: &str,
+        fields: &[(&str, TokenStream)],
+    ) -> TokenStream {
+        let fields = fields
+            .iter()
+            .map(|(name, ty)| {
+                let member = naming::field_name(name);
+                FieldInfo {
+                    member: quote!(#member),
+                    name: name.to_string(),
+                    ty: ty.clone(),
+                    encoding: Encoding::Default,
+                    context: false,
+                    merged: Vec::new(),
+                }
+            })
+            .collect();
+
+        serialization::struct_impls(
+            paths,
+            &naming::type_name(name),
+            false,
+            false,
+            &Fields::Named(fields),
+        )
+    }
+
+    fn string_impls(paths: &Paths, count: TokenStream) -> TokenStream {
+        let alloc = &paths.alloc;
+        let field = FieldInfo {
+            member: quote!(0),
+            name: String::from("0"),
+            ty: quote!(#alloc::string::String),
+            encoding: Encoding::LengthPrefixed { count },
+            context: false,
+            merged: Vec::new(),
+        };
+
+        let runtime = &paths.runtime;
+        let impls = serialization::struct_impls(
+            paths,
+            &naming::type_name("String"),
+            false,
+            false,
+            &Fields::Unnamed(vec![field]),
+        );
+
+        quote! {
+            #impls
+
+            impl #runtime::AsSwitchKey for String {
+                fn as_switch_key(&self) -> #runtime::SwitchKey<'_> {
+                    #runtime::AsSwitchKey::as_switch_key(&self.0)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn namespaces_become_modules() {
+        let compilation_unit = namespaced_protocol();
+
+        let got = generate_rust(&compilation_unit).unwrap().to_string();
+
+        let paths = Paths::std(quote!(::protodef_core));
+        let string_impls = string_impls(&paths, quote!(Varint));
+        let unused_impls =
+            named_struct_impls(&paths, "Unused", &[("x", quote!(I64))]);
+        let set_protocol_impls = named_struct_impls(
+            &paths,
+            "PacketSetProtocol",
+            &[
+                ("protocol_version", quote!(super::super::Varint)),
+                ("server_host", quote!(super::super::String)),
+            ],
+        );
+        let ping_impls = named_struct_impls(
+            &paths,
+            "PacketPing",
+            &[("time", quote!(super::super::I64))],
+        );
+        let should_be = quote! {
+            use ::protodef_core::native::{Varint, I64};
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct String(pub ::std::string::String);
+            #string_impls
+            #[derive(Debug, Clone, PartialEq)]
+            pub struct Unused {
+                pub x: I64,
+            }
+            #unused_impls
+            pub mod handshaking {
+                pub mod to_server {
+                    #[derive(Debug, Clone, PartialEq)]
+                    pub struct PacketSetProtocol {
+                        pub protocol_version: super::super::Varint,
+                        pub server_host: super::super::String,
+                    }
+                    #set_protocol_impls
+                }
+            }
+            pub mod status {
+                pub mod to_client {
+                    #[derive(Debug, Clone, PartialEq)]
+                    pub struct PacketPing {
+                        pub time: super::super::I64,
+                    }
+                    #ping_impls
+                }
+            }
+        };
+        assert_eq!(got, should_be.to_string());
+    }
+
+    #[test]
+    fn customise_the_generated_code() {
+        let compilation_unit = namespaced_protocol();
+        let options = CodegenOptions::new()
+            .derive("Eq")
+            .derive("Hash")
+            .runtime_crate("crate::runtime")
+            .visibility("pub(crate)")
+            .type_name("handshaking.toServer.packet_set_protocol", "Handshake")
+            .include_namespace("handshaking");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let paths = Paths::std(quote!(crate::runtime));
+        let string_impls = string_impls(&paths, quote!(Varint));
+        let handshake_impls = named_struct_impls(
+            &paths,
+            "Handshake",
+            &[
+                ("protocol_version", quote!(super::super::Varint)),
+                ("server_host", quote!(super::super::String)),
+            ],
+        );
+        let should_be = quote! {
+            use crate::runtime::native::{Varint};
+            #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+            pub(crate) struct String(pub(crate) ::std::string::String);
+            #string_impls
+            pub(crate) mod handshaking {
+                pub(crate) mod to_server {
+                    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+                    pub(crate) struct Handshake {
+                        pub(crate) protocol_version: super::super::Varint,
+                        pub(crate) server_host: super::super::String,
+                    }
+                    #handshake_impls
+                }
+            }
+        };
+        assert_eq!(got, should_be.to_string());
+    }
+
+    #[test]
+    fn borrowed_strings_add_a_lifetime_to_everything_containing_them() {
+        let compilation_unit = namespaced_protocol();
+        let options = CodegenOptions::new().string_type(StringType::Borrowed);
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let string = quote!(
+            pub struct String<'de>(pub &'de str);
+        )
+        .to_string();
+        assert!(got.contains(&string));
+        let set_protocol = quote! {
+            pub struct PacketSetProtocol<'de> {
+                pub protocol_version: super::super::Varint,
+                pub server_host: super::super::String<'de>,
+            }
+        };
+        assert!(got.contains(&set_protocol.to_string()));
+        let impl_deserialize = quote! {
+            impl<'de> ::protodef_core::Deserialize<'de> for PacketSetProtocol<'de>
+        };
+        assert!(got.contains(&impl_deserialize.to_string()));
+        // types without strings are left alone
+        assert!(got.contains("pub struct PacketPing {"));
+        assert!(got.contains("pub struct Unused {"));
+    }
+
+    #[test]
+    fn strings_can_be_copy_on_write() {
+        let compilation_unit = namespaced_protocol();
+        let options = CodegenOptions::new().string_type(StringType::Cow);
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let string = quote! {
+            pub struct String<'de>(pub ::std::borrow::Cow<'de, str>);
+        };
+        assert!(got.contains(&string.to_string()));
+    }
+
+    #[test]
+    fn no_std_code_only_uses_core_and_alloc() {
+        let compilation_unit = namespaced_protocol();
+        let options = CodegenOptions::new().no_std(true);
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let string = quote!(
+            pub struct String(pub ::alloc::string::String);
+        );
+        assert!(got.contains(&string.to_string()));
+        assert!(got.contains(&quote!(::core::result::Result).to_string()));
+        assert!(!got.contains("std"));
+        assert!(!got.contains("ReadFrom"));
+    }
+
+    #[test]
+    fn only_emit_the_requested_types() {
+        let compilation_unit = namespaced_protocol();
+        let options =
+            CodegenOptions::new().include_type("status.toClient.packet_ping");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        assert!(got.contains("pub struct PacketPing"));
+        assert!(!got.contains("PacketSetProtocol"));
+        assert!(!got.contains("Unused"));
+        assert!(!got.contains("pub struct String"));
+    }
+
+    #[test]
+    fn fields_used_by_switches_are_recorded_in_the_context() {
+        let doc = json!({
+            "types": {
+                "varint": "native",
+                "i64": "native",
+                "packet": [
+                    "container",
+                    [
+                        { "name": "action", "type": "varint" },
+                        { "name": "time", "type": "i64" },
+                        {
+                            "name": "body",
+                            "type": [
+                                "container",
+                                [{
+                                    "name": "data",
+                                    "type": [
+                                        "switch",
+                                        {
+                                            "compareTo": "../action",
+                                            "fields": { "0": "i64" },
+                                        }
+                                    ]
+                                }]
+                            ]
+                        },
+                    ]
+                ],
+                // has a field with the same name, but no switch refers to it
+                "unrelated": [
+                    "container",
+                    [{ "name": "action", "type": "varint" }]
+                ],
+            }
+        });
+        let compilation_unit = lower(doc);
+        let options = CodegenOptions::new()
+            .include_type("packet")
+            .include_type("unrelated");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let (packet, unrelated) = got.split_at(got.find("Unrelated").unwrap());
+        let insert_action = r#"scope . insert ("action" , & field_action)"#;
+        assert!(packet.contains(insert_action));
+        assert!(!packet.contains(r#"scope . insert ("time""#));
+        assert!(!unrelated.contains(insert_action));
+    }
+
+    #[test]
+    fn serde_representations() {
+        let doc = json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "void": "native",
+                "kind": [
+                    "mapper",
+                    {
+                        "type": "varint",
+                        "mappings": { "0x00": "chat", "0x01": "move" },
+                    }
+                ],
+                "position": [
+                    "bitfield",
+                    [
+                        { "name": "x", "size": 12, "signed": true },
+                        { "name": "y", "size": 4, "signed": false },
+                    ]
+                ],
+                "packet": [
+                    "container",
+                    [
+                        { "name": "kind", "type": "kind" },
+                        {
+                            "name": "body",
+                            "type": [
+                                "switch",
+                                {
+                                    "compareTo": "kind",
+                                    "fields": {
+                                        "chat": "u8",
+                                        "move": "position",
+                                    },
+                                    "default": "void",
+                                }
+                            ]
+                        },
+                    ]
+                ],
+            }
+        });
+        let compilation_unit = lower(doc);
+        let options = CodegenOptions::new().serde(true);
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let derives = quote!(::serde::Serialize, ::serde::Deserialize);
+        assert!(got.contains(&derives.to_string()));
+        assert!(got.contains(&quote!(#[serde(rename = "chat")]).to_string()));
+        assert!(got.contains(
+            &quote!(#[serde(tag = "kind", content = "value")]).to_string()
+        ));
+        assert!(got.contains(&quote!(SwitchKey::String("move")).to_string()));
+        assert!(got.contains(&quote!(pub x: i16).to_string()));
+        assert!(got.contains(&quote!(pub y: u8).to_string()));
+    }
+
+    #[test]
+    fn borrowed_types_only_derive_serialize() {
+        let compilation_unit = namespaced_protocol();
+        let options = CodegenOptions::new()
+            .serde(true)
+            .string_type(StringType::Borrowed);
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let set_protocol = quote! {
+            #[derive(Debug, Clone, PartialEq, ::serde::Serialize)]
+            pub struct PacketSetProtocol<'de>
+        };
+        assert!(got.contains(&set_protocol.to_string()), "{}", got);
+        let ping = quote! {
+            #[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+            pub struct PacketPing
+        };
+        assert!(got.contains(&ping.to_string()), "{}", got);
+    }
+
+    #[test]
+    fn packets_are_dispatched_by_id() {
+        let doc = json!({
+            "types": {
+                "varint": "native",
+                "i64": "native",
+                "void": "native",
+            },
+            "status": {
+                "toClient": {
+                    "types": {
+                        "packet_ping": [
+                            "container",
+                            [{ "name": "time", "type": "i64" }]
+                        ],
+                        "packet": [
+                            "container",
+                            [
+                                {
+                                    "name": "name",
+                                    "type": [
+                                        "mapper",
+                                        {
+                                            "type": "varint",
+                                            "mappings": {
+                                                "0x00": "server_info",
+                                                "0x01": "ping",
+                                            },
+                                        }
+                                    ]
+                                },
+                                {
+                                    "name": "params",
+                                    "type": [
+                                        "switch",
+                                        {
+                                            "compareTo": "name",
+                                            "fields": {
+                                                "server_info": "void",
+                                                "ping": "packet_ping",
+                                            },
+                                        }
+                                    ]
+                                },
+                            ]
+                        ],
+                    }
+                }
+            }
+        });
+        let compilation_unit = lower(doc);
+        let options = CodegenOptions::new().serde(true);
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        // every packet is an object, so the fields go alongside the name
+        let packet = quote! {
+            #[serde(tag = "name")]
+            pub enum Packet {
+                #[serde(rename = "server_info")]
+                ServerInfo,
+                #[serde(rename = "ping")]
+                Ping(PacketPing),
+            }
+        };
+        assert!(got.contains(&packet.to_string()));
+        let packet_id = quote! {
+            match *self {
+                Packet::ServerInfo { .. } => 0,
+                Packet::Ping { .. } => 1,
+            }
+        };
+        assert!(got.contains(&packet_id.to_string()));
+        assert!(got.contains(r#"scope . insert ("name" , "ping")"#));
+        assert!(!got.contains("pub struct Packet {"));
+    }
+
+    #[test]
+    fn connection_states_are_generated_from_the_namespaces() {
+        let packet = |mappings: serde_json::Value,
+                      fields: serde_json::Value| {
+            json!([
+                "container",
+                [
+                    {
+                        "name": "name",
+                        "type": [
+                            "mapper",
+                            { "type": "varint", "mappings": mappings }
+                        ]
+                    },
+                    {
+                        "name": "params",
+                        "type": [
+                            "switch",
+                            { "compareTo": "name", "fields": fields }
+                        ]
+                    },
+                ]
+            ])
+        };
+        let doc = json!({
+            "types": {
+                "varint": "native",
+                "i64": "native",
+            },
+            "handshaking": {
+                "toServer": {
+                    "types": {
+                        "packet_set_protocol": [
+                            "container",
+                            [{ "name": "nextState", "type": "varint" }]
+                        ],
+                        "packet": packet(
+                            json!({ "0x00": "set_protocol" }),
+                            json!({ "set_protocol": "packet_set_protocol" }),
+                        ),
+                    }
+                }
+            },
+            "status": {
+                "toClient": {
+                    "types": {
+                        "packet_ping": [
+                            "container",
+                            [{ "name": "time", "type": "i64" }]
+                        ],
+                        "packet": packet(
+                            json!({ "0x01": "ping" }),
+                            json!({ "ping": "packet_ping" }),
+                        ),
+                    }
+                }
+            }
+        });
+        let compilation_unit = lower(doc);
+
+        let options = CodegenOptions::new().next_state(1, "status");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let state = quote! {
+            pub enum State {
+                Handshaking,
+                Status,
+            }
+        };
+        assert!(got.contains(&state.to_string()));
+        let states = quote! {
+            const STATES: &'static [Self] = &[State::Handshaking, State::Status];
+        };
+        assert!(got.contains(&states.to_string()));
+        let to_server = quote! {
+            pub enum ToServerPacket {
+                Handshaking(handshaking::to_server::Packet),
+            }
+        };
+        assert!(got.contains(&to_server.to_string()));
+        let transition = quote! {
+            ToServerPacket::Handshaking(
+                handshaking::to_server::Packet::SetProtocol(packet)
+            ) =>
+        };
+        assert!(got.contains(&transition.to_string()));
+        assert!(got.contains("as_switch_key (& packet . next_state)"));
+        let next_states = quote! {
+            ::protodef_core::SwitchKey::Integer(1) => ::std::result::Result::Ok(
+                ::std::option::Option::Some(State::Status),
+            ),
+            key => ::std::result::Result::Err(
+                ::protodef_core::DeserializeError::unknown_next_state(key),
+            ),
+        };
+        assert!(got.contains(&next_states.to_string()));
+        let no_packets = quote! {
+            State::Status => ::std::result::Result::Err(
+                ::protodef_core::DeserializeError::NoPacketsInState { state: "status" },
+            ),
+        };
+        assert!(got.contains(&no_packets.to_string()));
+        assert!(got.contains("pub enum ToClientPacket {"));
+    }
+
+    /// A protocol where the handshake's `nextState` has the given type.
+    fn handshake_protocol(next_state: serde_json::Value) -> serde_json::Value {
+        let packet = |name: &str| {
+            json!([
+                "container",
+                [
+                    {
+                        "name": "name",
+                        "type": [
+                            "mapper",
+                            { "type": "varint", "mappings": { "0x00": name } }
+                        ]
+                    },
+                    {
+                        "name": "params",
+                        "type": [
+                            "switch",
+                            {
+                                "compareTo": "name",
+                                "fields": { name: format!("packet_{}", name) }
+                            }
+                        ]
+                    },
+                ]
+            ])
+        };
+
+        json!({
+            "types": { "varint": "native" },
+            "handshaking": {
+                "toServer": {
+                    "types": {
+                        "packet_set_protocol": [
+                            "container",
+                            [{ "name": "nextState", "type": next_state }]
+                        ],
+                        "packet": packet("set_protocol"),
+                    }
+                }
+            },
+            "status": {
+                "toServer": {
+                    "types": {
+                        "packet_ping": ["container", []],
+                        "packet": packet("ping"),
+                    }
+                }
+            },
+            "login": {
+                "toServer": {
+                    "types": {
+                        "packet_login_start": ["container", []],
+                        "packet": packet("login_start"),
+                    }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn next_states_can_be_given_explicitly() {
+        let compilation_unit = lower(handshake_protocol(json!("varint")));
+        let options = CodegenOptions::new()
+            .next_state(1, "status")
+            .next_state(2, "login")
+            .next_state(3, "login");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        let next_states = quote! {
+            ::protodef_core::SwitchKey::Integer(1) => ::std::result::Result::Ok(
+                ::std::option::Option::Some(State::Status),
+            ),
+            ::protodef_core::SwitchKey::Integer(2) => ::std::result::Result::Ok(
+                ::std::option::Option::Some(State::Login),
+            ),
+            ::protodef_core::SwitchKey::Integer(3) => ::std::result::Result::Ok(
+                ::std::option::Option::Some(State::Login),
+            ),
+            key => ::std::result::Result::Err(
+                ::protodef_core::DeserializeError::unknown_next_state(key),
+            ),
+        };
+        assert!(got.contains(&next_states.to_string()));
+    }
+
+    #[test]
+    fn mapped_next_states_use_the_state_names() {
+        let next_state = json!([
+            "mapper",
+            {
+                "type": "varint",
+                "mappings": { "1": "status", "2": "login", "3": "transfer" }
+            }
+        ]);
+        let compilation_unit = lower(handshake_protocol(next_state));
+
+        let got = generate_rust(&compilation_unit).unwrap().to_string();
+
+        // "transfer" isn't a state, so it is rejected like any other value
+        let next_states = quote! {
+            ::protodef_core::SwitchKey::String("status") => ::std::result::Result::Ok(
+                ::std::option::Option::Some(State::Status),
+            ),
+            ::protodef_core::SwitchKey::String("login") => ::std::result::Result::Ok(
+                ::std::option::Option::Some(State::Login),
+            ),
+            key => ::std::result::Result::Err(
+                ::protodef_core::DeserializeError::unknown_next_state(key),
+            ),
+        };
+        assert!(got.contains(&next_states.to_string()));
+    }
+
+    #[test]
+    fn numeric_next_states_must_be_given() {
+        let compilation_unit = lower(handshake_protocol(json!("varint")));
+
+        let got = generate_rust(&compilation_unit).unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[Diagnostic::MissingNextStates {
+                packet: String::from(
+                    "handshaking.toServer.packet_set_protocol"
+                ),
+            }]
+        );
+    }
+
+    #[test]
+    fn next_states_must_be_namespaces() {
+        let compilation_unit = lower(handshake_protocol(json!("varint")));
+        let options = CodegenOptions::new().next_state(1, "lobby");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[Diagnostic::InvalidOption {
+                option: String::from("next_state"),
+                value: String::from("lobby"),
+            }]
+        );
+    }
+
+    #[test]
+    fn bitfields_must_fit_in_a_u64() {
+        let doc = json!({
+            "types": {
+                "huge": [
+                    "bitfield",
+                    [
+                        { "name": "a", "size": 64, "signed": false },
+                        { "name": "b", "size": 8, "signed": false },
+                    ]
+                ],
+            }
+        });
+        let compilation_unit = lower(doc);
+
+        let got = generate_rust(&compilation_unit).unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[Diagnostic::UnsupportedBitFields {
+                name: "Huge".into(),
+                bits: 72,
+            }]
+        );
+    }
+
+    #[test]
+    fn bitfields_only_write_their_own_bytes() {
+        let doc = json!({
+            "types": {
+                "position": [
+                    "bitfield",
+                    [
+                        { "name": "x", "size": 26, "signed": true },
+                        { "name": "z", "size": 26, "signed": true },
+                        { "name": "y", "size": 12, "signed": true },
+                    ]
+                ],
+                "flags": [
+                    "bitfield",
+                    [
+                        { "name": "a", "size": 4, "signed": false },
+                        { "name": "b", "size": 12, "signed": false },
+                    ]
+                ],
+            }
+        });
+        let compilation_unit = lower(doc);
+
+        let got = generate_rust(&compilation_unit).unwrap().to_string();
+
+        // the offset is a single literal, so a 64-bit bitfield doesn't
+        // trip clippy::eq_op with "8 - 8"
+        assert!(got.contains("& self . to_bits () [0 ..]"));
+        assert!(got.contains("& self . to_bits () [6 ..]"));
+    }
+
+    #[test]
+    fn arrays_buffers_and_options_wrap_a_vec_or_option() {
+        let compilation_unit = lower(json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "map": [
+                    "container",
+                    [
+                        { "name": "columns", "type": "u8" },
+                        {
+                            "name": "cells",
+                            "type": ["array", { "count": "columns", "type": "varint" }]
+                        },
+                        {
+                            "name": "position",
+                            "type": ["array", { "count": 2, "type": "varint" }]
+                        },
+                        {
+                            "name": "data",
+                            "type": ["buffer", { "countType": "varint" }]
+                        },
+                        { "name": "scale", "type": ["option", "u8"] },
+                    ]
+                ],
+            }
+        }));
+
+        let got = generate_rust(&compilation_unit).unwrap().to_string();
+
+        let definitions = [
+            quote!(
+                pub struct MapCells(pub ::std::vec::Vec<Varint>);
+            ),
+            quote!(
+                pub struct MapPosition(pub ::std::vec::Vec<Varint>);
+            ),
+            quote!(
+                pub struct MapData(pub ::std::vec::Vec<u8>);
+            ),
+            quote!(
+                pub struct MapScale(pub ::std::option::Option<U8>);
+            ),
+        ];
+        for definition in &definitions {
+            assert!(got.contains(&definition.to_string()), "{}", definition);
+        }
+        let uses = [
+            quote!(scope.insert("columns", &field_columns);),
+            quote!(deserialize_counted_with(
+                scope.count("columns")?,
+                buffer,
+                limits,
+                scope
+            )),
+            quote!(deserialize_counted_with(2, buffer, limits, scope)),
+            quote!(deserialize_prefixed_with::<Varint>(buffer, limits, scope)),
+        ];
+        for tokens in &uses {
+            assert!(got.contains(&tokens.to_string()), "{}", tokens);
+        }
+    }
+
+    #[test]
+    fn natives_the_runtime_doesnt_provide_are_reported() {
+        let compilation_unit = lower(json!({
+            "types": {
+                "varint": "native",
+                "nbt": "native",
+                "packet_nbt_query_response": [
+                    "container",
+                    [
+                        { "name": "transactionId", "type": "varint" },
+                        { "name": "nbt", "type": "nbt" },
+                    ]
+                ],
+            }
+        }));
+
+        let got = generate_rust(&compilation_unit).unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[Diagnostic::UnknownNative { name: "nbt".into() }]
+        );
+    }
+
+    #[test]
+    fn natives_can_be_given_a_rust_type() {
+        let compilation_unit = lower(json!({
+            "types": {
+                "varint": "native",
+                "nbt": "native",
+                "packet_nbt_query_response": [
+                    "container",
+                    [
+                        { "name": "transactionId", "type": "varint" },
+                        { "name": "nbt", "type": "nbt" },
+                    ]
+                ],
+            }
+        }));
+        let options = CodegenOptions::new()
+            .native_type("nbt", "crate::nbt::CompoundTag")
+            .native_type("optionalNbt", "crate::nbt::OptionalCompoundTag");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[Diagnostic::MissingName {
+                name: "optionalNbt".into(),
+                used_by: None,
+            }]
+        );
+
+        let options =
+            CodegenOptions::new().native_type("nbt", "crate::nbt::CompoundTag");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap()
+            .to_string();
+
+        assert!(got.contains(
+            &quote! {
+                use ::protodef_core::native::{Varint};
+                use crate::nbt::CompoundTag as Nbt;
+            }
+            .to_string()
+        ));
+        assert!(got.contains("pub nbt : Nbt"));
+    }
+
+    #[test]
+    fn invalid_options_are_reported() {
+        let compilation_unit = namespaced_protocol();
+        let options = CodegenOptions::new()
+            .derive("Not A Trait(")
+            .type_name("unused", "not an ident")
+            .include_type("play.toClient.packet")
+            .include_namespace("login");
+
+        let got = generate_rust_with_options(&compilation_unit, &options)
+            .unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[
+                Diagnostic::InvalidOption {
+                    option: "derive".into(),
+                    value: "Not A Trait(".into(),
+                },
+                Diagnostic::InvalidOption {
+                    option: "type_name".into(),
+                    value: "not an ident".into(),
+                },
+                Diagnostic::MissingName {
+                    name: "play.toClient.packet".into(),
+                    used_by: None,
+                },
+                Diagnostic::MissingName {
+                    name: "login".into(),
+                    used_by: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rustfmt_errors_are_reported() {
+        // a valid token stream, but not a valid Rust item
+        let tokens = quote!(fn {});
+
+        assert!(rustfmt(&tokens).is_err());
+    }
 }
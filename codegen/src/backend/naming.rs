@@ -0,0 +1,124 @@
+//! Converting names from a `protocol.json` file into valid, idiomatic Rust
+//! identifiers.
+
+use heck::{CamelCase, SnakeCase};
+use proc_macro2::{Ident, Span, TokenStream, TokenTree};
+use quote::format_ident;
+use unicode_xid::UnicodeXID;
+
+/// Keywords which can't be used as identifiers, even when written as a raw
+/// identifier.
+const NON_RAW_KEYWORDS: &[&str] = &["crate", "self", "Self", "super", "_"];
+
+/// Keywords (including reserved and edition-specific ones) which need to be
+/// escaped with `r#`.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const",
+    "continue", "do", "dyn", "else", "enum", "extern", "false", "final", "fn",
+    "for", "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move",
+    "mut", "override", "priv", "pub", "ref", "return", "static", "struct",
+    "trait", "true", "try", "type", "typeof", "union", "unsafe", "unsized",
+    "use", "virtual", "where", "while", "yield",
+];
+
+/// Get the `UpperCamelCase` identifier used for a type.
+pub(crate) fn type_name(name: &str) -> Ident {
+    to_ident(sanitise(&name.to_camel_case(), "Unnamed"))
+}
+
+/// Get the `snake_case` identifier used for a field.
+pub(crate) fn field_name(name: &str) -> Ident {
+    to_ident(sanitise(&name.to_snake_case(), "unnamed"))
+}
+
+/// Parse a user-provided identifier (e.g. a type name override).
+pub(crate) fn parse_ident(name: &str) -> Option<Ident> {
+    let tokens: TokenStream = name.parse().ok()?;
+    let mut tokens = tokens.into_iter();
+
+    match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Ident(ident)), None) => Some(ident),
+        _ => None,
+    }
+}
+
+/// Make sure a name can be used as an identifier, ignoring keywords.
+///
+/// Identifiers follow the Unicode `XID_Start`/`XID_Continue` rules, so things
+/// like `²` are replaced even though they count as alphanumeric.
+fn sanitise(name: &str, fallback: &str) -> String {
+    let mut sanitised: String = name
+        .chars()
+        .map(|c| if c.is_xid_continue() { c } else { '_' })
+        .collect();
+
+    if sanitised.is_empty() {
+        sanitised.push_str(fallback);
+    }
+
+    if !sanitised.starts_with(|c: char| c.is_xid_start() || c == '_') {
+        sanitised.insert(0, '_');
+    }
+
+    if NON_RAW_KEYWORDS.contains(&sanitised.as_str()) {
+        sanitised.push('_');
+    }
+
+    sanitised
+}
+
+fn to_ident(name: String) -> Ident {
+    if KEYWORDS.contains(&name.as_str()) {
+        format_ident!("r#{}", name)
+    } else {
+        Ident::new(&name, Span::call_site())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_names_are_upper_camel_case() {
+        let inputs = vec![
+            ("packet_set_protocol", "PacketSetProtocol"),
+            ("UUID", "Uuid"),
+            ("i8", "I8"),
+            ("entityMetadata", "EntityMetadata"),
+            ("some-dashed-name", "SomeDashedName"),
+            ("1st_packet", "_1stPacket"),
+            ("self", "Self_"),
+            ("", "Unnamed"),
+            ("²nd", "_Nd"),
+        ];
+
+        for (input, should_be) in inputs {
+            let got = type_name(input);
+            assert_eq!(got.to_string(), should_be, "{}", input);
+        }
+    }
+
+    #[test]
+    fn field_names_are_snake_case() {
+        let inputs = vec![
+            ("itemCount", "item_count"),
+            ("nbtData", "nbt_data"),
+            ("VehicleID", "vehicle_id"),
+            ("type", "r#type"),
+            ("match", "r#match"),
+            ("self", "self_"),
+            ("dashed-name", "dashed_name"),
+            ("2d", "_2d"),
+            ("$compareTo", "compare_to"),
+            ("größe", "größe"),
+            ("area²", "area__"),
+            ("٣d", "_٣d"),
+        ];
+
+        for (input, should_be) in inputs {
+            let got = field_name(input);
+            assert_eq!(got.to_string(), should_be, "{}", input);
+        }
+    }
+}
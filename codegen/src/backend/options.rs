@@ -0,0 +1,204 @@
+use indexmap::IndexMap;
+
+/// Options used to tweak the code generated by
+/// [`generate_rust_with_options()`][super::generate_rust_with_options].
+///
+/// # Examples
+///
+/// ```rust
+/// use protodef_codegen::backend::{CodegenOptions, StringType};
+///
+/// let options = CodegenOptions::new()
+///     .derive("Eq")
+///     .derive("Hash")
+///     .runtime_crate("crate::runtime")
+///     .visibility("pub(crate)")
+///     .type_name("handshaking.toServer.packet_set_protocol", "SetProtocol")
+///     .native_type("nbt", "crate::nbt::Nbt")
+///     .include_namespace("handshaking")
+///     .next_state(1, "status")
+///     .next_state(2, "login")
+///     .string_type(StringType::Borrowed)
+///     .serde(true);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodegenOptions {
+    pub(crate) derives: Vec<String>,
+    pub(crate) runtime_crate: String,
+    pub(crate) visibility: String,
+    pub(crate) type_names: IndexMap<String, String>,
+    pub(crate) native_types: IndexMap<String, String>,
+    pub(crate) namespaces: Vec<String>,
+    pub(crate) types: Vec<String>,
+    pub(crate) next_states: IndexMap<i64, String>,
+    pub(crate) string_type: StringType,
+    pub(crate) no_std: bool,
+    pub(crate) serde: bool,
+}
+
+impl CodegenOptions {
+    pub fn new() -> Self {
+        CodegenOptions {
+            derives: Vec::new(),
+            runtime_crate: String::from("::protodef_core"),
+            visibility: String::from("pub"),
+            type_names: IndexMap::new(),
+            native_types: IndexMap::new(),
+            namespaces: Vec::new(),
+            types: Vec::new(),
+            next_states: IndexMap::new(),
+            string_type: StringType::Owned,
+            no_std: false,
+            serde: false,
+        }
+    }
+
+    /// Add an extra trait to the `#[derive(...)]` attribute on every
+    /// generated type, in addition to `Debug`, `Clone`, and `PartialEq`.
+    pub fn derive(mut self, derive: impl Into<String>) -> Self {
+        self.derives.push(derive.into());
+        self
+    }
+
+    /// The path to the `protodef_core` crate (defaults to
+    /// `::protodef_core`), useful when it is re-exported by another crate.
+    pub fn runtime_crate(mut self, path: impl Into<String>) -> Self {
+        self.runtime_crate = path.into();
+        self
+    }
+
+    /// The visibility used for generated types and their fields (defaults to
+    /// `pub`).
+    pub fn visibility(mut self, visibility: impl Into<String>) -> Self {
+        self.visibility = visibility.into();
+        self
+    }
+
+    /// Use a specific Rust name for a type instead of deriving one from the
+    /// `protocol.json`.
+    ///
+    /// The type is identified by its fully qualified name (e.g.
+    /// `play.toClient.packet`).
+    pub fn type_name(
+        mut self,
+        protocol_name: impl Into<String>,
+        rust_name: impl Into<String>,
+    ) -> Self {
+        self.type_names
+            .insert(protocol_name.into(), rust_name.into());
+        self
+    }
+
+    /// Use your own Rust type for a `"native"` type which `protodef_core`
+    /// doesn't provide (e.g. `nbt`).
+    ///
+    /// The type must implement the same traits as the natives in
+    /// `protodef_core::native`. Natives which are used without being given
+    /// a path or being provided by `protodef_core` are reported as an error.
+    pub fn native_type(
+        mut self,
+        protocol_name: impl Into<String>,
+        rust_path: impl Into<String>,
+    ) -> Self {
+        self.native_types
+            .insert(protocol_name.into(), rust_path.into());
+        self
+    }
+
+    /// Only emit types from this namespace (e.g. `play.toClient`) and the
+    /// types they depend on.
+    pub fn include_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespaces.push(namespace.into());
+        self
+    }
+
+    /// Only emit this type (e.g. `play.toClient.packet`) and the types it
+    /// depends on.
+    pub fn include_type(mut self, name: impl Into<String>) -> Self {
+        self.types.push(name.into());
+        self
+    }
+
+    /// Switch to the `state` namespace (e.g. `login`) when a packet's
+    /// `nextState` field is `value`.
+    ///
+    /// A `nextState` which is a `mapper` switches to the state named by its
+    /// label, but any other `nextState` is just a number, so the states it
+    /// refers to must be given here. Values which don't refer to a state are
+    /// rejected when the packet is decoded.
+    ///
+    /// Only `nextState` fields switch states automatically. Any other
+    /// switches (e.g. from `login` to `play` once the login succeeds) need
+    /// to be made with `protodef_core::Connection::set_state()`.
+    pub fn next_state(mut self, value: i64, state: impl Into<String>) -> Self {
+        self.next_states.insert(value, state.into());
+        self
+    }
+
+    /// How strings should be represented (defaults to
+    /// [`StringType::Owned`]).
+    pub fn string_type(mut self, string_type: StringType) -> Self {
+        self.string_type = string_type;
+        self
+    }
+
+    /// Generate code for a `#![no_std]` crate, which only refers to items
+    /// from `core` and `alloc` (defaults to `false`).
+    ///
+    /// The crate must have an `extern crate alloc` and use `protodef_core`
+    /// with the `alloc` feature. Without `std`, types can't be read from a
+    /// stream, so no `ReadFrom` impls are generated.
+    pub fn no_std(mut self, no_std: bool) -> Self {
+        self.no_std = no_std;
+        self
+    }
+
+    /// Derive `serde::Serialize` and `serde::Deserialize` for every generated
+    /// type (defaults to `false`).
+    ///
+    /// Fields keep the names they were given in the `protocol.json`, a
+    /// `mapper` is written as its name, and a `bitfield` as an object. A
+    /// `switch` (or packet) is tagged with the field it compares against,
+    /// with the variant's fields alongside the tag (e.g.
+    /// `{ "name": "chat", "message": "Hi" }`). If any variant isn't an
+    /// object, the variant goes in its own field instead (`"value"` for a
+    /// `switch`, or the packet's params field, e.g.
+    /// `{ "name": "chat", "params": "Hi" }`).
+    ///
+    /// The crate needs `serde` with its `derive` feature, and
+    /// `protodef_core` with its `serde` feature.
+    ///
+    /// Types which borrow from their input (see [`StringType`]) only derive
+    /// `Serialize`, because `serde` reserves the `'de` lifetime.
+    pub fn serde(mut self, serde: bool) -> Self {
+        self.serde = serde;
+        self
+    }
+
+    /// Should everything in the [`CompilationUnit`] be emitted?
+    ///
+    /// [`CompilationUnit`]: crate::lowering::CompilationUnit
+    pub(crate) fn emit_everything(&self) -> bool {
+        self.namespaces.is_empty() && self.types.is_empty()
+    }
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self { CodegenOptions::new() }
+}
+
+/// The Rust type used for a `pstring`.
+///
+/// The borrowed representations avoid allocating for every string, but give
+/// any type containing a string (directly or indirectly) a `'de` lifetime
+/// tied to the buffer it was deserialized from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StringType {
+    /// `String`.
+    Owned,
+    /// `&'de str`.
+    Borrowed,
+    /// `Cow<'de, str>`, which is borrowed when deserializing but may also
+    /// hold an owned `String` when constructing values by hand.
+    Cow,
+}
@@ -0,0 +1,1687 @@
+//! Building blocks for generating `Serialize` and `Deserialize` impls.
+//!
+//! These are used by both the [`backend`][super] and the `#[derive(ProtoDef)]`
+//! macro from `protodef-macros`, so hand-written types are encoded exactly the
+//! same way as generated ones.
+
+use proc_macro2::{Ident, Literal, TokenStream};
+use quote::{format_ident, quote, ToTokens};
+
+/// The paths generated code uses to refer to the runtime crate and the
+/// standard library.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    /// The path to `protodef_core` (e.g. `::protodef_core`).
+    pub runtime: TokenStream,
+    /// Where items from `core` can be found (`::std` or `::core`).
+    pub core: TokenStream,
+    /// Where items from `alloc` can be found (`::std` or `::alloc`).
+    pub alloc: TokenStream,
+    /// Can the generated code use `std`? If not, it won't get `ReadFrom`
+    /// impls.
+    pub std: bool,
+    /// Does the generated code go through `protodef_core`'s re-exports
+    /// instead of naming `core`, `alloc`, and `std` directly, only getting
+    /// `ReadFrom` impls when `protodef_core` has its `std` feature?
+    pub runtime_features: bool,
+}
+
+impl Paths {
+    /// Paths for code which uses the standard library.
+    pub fn std(runtime: TokenStream) -> Self {
+        Paths {
+            runtime,
+            core: quote!(::std),
+            alloc: quote!(::std),
+            std: true,
+            runtime_features: false,
+        }
+    }
+
+    /// Paths for `#![no_std]` code, which needs `extern crate alloc`.
+    pub fn no_std(runtime: TokenStream) -> Self {
+        Paths {
+            runtime,
+            core: quote!(::core),
+            alloc: quote!(::alloc),
+            std: false,
+            runtime_features: false,
+        }
+    }
+
+    /// Paths for code which works with whichever features `protodef_core`
+    /// was compiled with (e.g. code from `#[derive(ProtoDef)]`, which can't
+    /// know whether it is in a `#![no_std]` crate).
+    pub fn runtime_features(runtime: TokenStream) -> Self {
+        Paths {
+            core: quote!(#runtime::__private::core),
+            alloc: quote!(#runtime::__private::alloc),
+            runtime,
+            std: true,
+            runtime_features: true,
+        }
+    }
+
+    /// Where items from `std` can be found.
+    fn std_path(&self) -> TokenStream {
+        let runtime = &self.runtime;
+
+        if self.runtime_features {
+            quote!(#runtime::__private::std)
+        } else {
+            quote!(::std)
+        }
+    }
+
+    /// Wrap code which needs `std` (i.e. `ReadFrom` impls) so it is only
+    /// compiled when `protodef_core` has its `std` feature, if necessary.
+    fn std_only(&self, tokens: TokenStream) -> TokenStream {
+        let runtime = &self.runtime;
+
+        if self.runtime_features && !tokens.is_empty() {
+            quote!(#runtime::__std_only! { #tokens })
+        } else {
+            tokens
+        }
+    }
+}
+
+/// A field in a struct or enum variant.
+#[derive(Debug, Clone)]
+pub struct FieldInfo {
+    /// How the field is accessed (e.g. `item_count` or `0`).
+    pub member: TokenStream,
+    /// The name used for the field when it is decoded, as used by a
+    /// [`Encoding::Switch`]'s `compare_to`.
+    pub name: String,
+    /// The field's Rust type.
+    pub ty: TokenStream,
+    pub encoding: Encoding,
+    /// Should the field be recorded in the `Context` once it is decoded, so
+    /// nested types can refer to it (e.g. as `../action`)?
+    pub context: bool,
+    /// Fields of an anonymous struct which are recorded in the `Context` as
+    /// if they belonged to this struct, as the name they are recorded under
+    /// and the path to them from this field (e.g. `.kind`).
+    pub merged: Vec<(String, TokenStream)>,
+}
+
+impl FieldInfo {
+    /// The local variable this field is deserialized into.
+    fn binding(&self) -> Ident { binding_for(&self.name) }
+
+    /// Does decoding this field add anything to the `Context`?
+    fn records(&self) -> bool { self.context || !self.merged.is_empty() }
+}
+
+/// How a value is encoded.
+#[derive(Debug, Clone)]
+pub enum Encoding {
+    /// Use the type's own `Serialize` and `Deserialize` impls.
+    Default,
+    /// Encode the value as another type (e.g. an `i32` as a `Varint`),
+    /// converting between the two using `From`.
+    As(TokenStream),
+    /// A length-prefixed string, buffer, or array where the length is encoded
+    /// as `count`.
+    LengthPrefixed { count: TokenStream },
+    /// A buffer or array which always has `length` items.
+    Fixed { length: usize },
+    /// A buffer or array where the number of items is the value of a
+    /// previously decoded field, which is looked up in the `Context` (e.g.
+    /// `columns` or `../count`).
+    Counted { count: String },
+    /// One of several variants, chosen using a previously decoded field.
+    ///
+    /// The `compare_to` is either the name of an earlier sibling or a path
+    /// which is looked up in the `Context` (e.g. `../action` or `$version`).
+    Switch { compare_to: String },
+}
+
+/// The shape of a struct or enum variant.
+#[derive(Debug, Clone)]
+pub enum Fields {
+    Named(Vec<FieldInfo>),
+    Unnamed(Vec<FieldInfo>),
+    Unit,
+}
+
+impl Fields {
+    fn fields(&self) -> &[FieldInfo] {
+        match self {
+            Fields::Named(fields) | Fields::Unnamed(fields) => fields,
+            Fields::Unit => &[],
+        }
+    }
+
+    /// Construct something with these fields, assuming each field has already
+    /// been deserialized into its binding.
+    fn constructor(&self, path: TokenStream) -> TokenStream {
+        match self {
+            Fields::Named(fields) => {
+                let members = fields.iter().map(|f| &f.member);
+                let bindings = fields.iter().map(FieldInfo::binding);
+                quote!(#path { #( #members: #bindings ),* })
+            },
+            Fields::Unnamed(fields) => {
+                let bindings = fields.iter().map(FieldInfo::binding);
+                quote!(#path( #( #bindings ),* ))
+            },
+            Fields::Unit => path,
+        }
+    }
+}
+
+/// A variant in a `switch`.
+#[derive(Debug, Clone)]
+pub struct VariantInfo {
+    pub name: Ident,
+    /// The `SwitchKey` pattern matched by this variant, or `None` for the
+    /// default variant.
+    pub key: Option<TokenStream>,
+    pub fields: Fields,
+}
+
+/// Generate `Deserialize`, `ReadFrom`, and `Serialize` impls for a struct.
+///
+/// If `borrows` is set, the struct is assumed to borrow from the buffer it
+/// was deserialized from (i.e. it is declared as `Name<'de>`). Types which
+/// borrow can't be read from a stream, so they don't get a `ReadFrom` impl,
+/// and neither does code without access to `std` (see [`Paths::std`]).
+///
+/// An `anonymous` struct (i.e. one declared with `"anon": true`) shares its
+/// parent's scope in the `Context`.
+pub fn struct_impls(
+    paths: &Paths,
+    name: &Ident,
+    borrows: bool,
+    anonymous: bool,
+    fields: &Fields,
+) -> TokenStream {
+    let Paths {
+        runtime,
+        core,
+        alloc,
+        std,
+        ..
+    } = paths;
+    let std_path = paths.std_path();
+    let lifetime = lifetime(borrows);
+    let deserialize_fields = deserialize_fields(paths, fields.fields());
+    let constructor = fields.constructor(quote!(#name));
+    let serialize_fields = fields.fields().iter().map(|f| {
+        let member = &f.member;
+        serialize_field(paths, f, quote!(&self.#member))
+    });
+    let scope = if anonymous {
+        shared_scope(fields)
+    } else {
+        scope(fields, matches!(fields, Fields::Unnamed(_)))
+    };
+    // structs without any fields (e.g. a packet with no params) never touch
+    // the reader or buffer
+    let (unused_reader, unused_buffer) = if fields.fields().is_empty() {
+        (quote!(let _ = reader;), quote!(let _ = buffer;))
+    } else {
+        (TokenStream::new(), TokenStream::new())
+    };
+    let read_from = paths.std_only(if borrows || !std {
+        TokenStream::new()
+    } else {
+        let read_fields = read_fields(paths, fields.fields());
+        quote! {
+            impl #runtime::ReadFrom for #name {
+                fn read_from<R>(
+                    reader: &mut R,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFrom>::read_from_limited(
+                        reader,
+                        &mut #runtime::DecodeLimits::new(),
+                    )
+                }
+
+                fn read_from_limited<R>(
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFrom>::read_from_with(
+                        reader,
+                        limits,
+                        &#runtime::Context::new(),
+                    )
+                }
+
+                fn read_from_with<R>(
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                    context: &#runtime::Context<'_>,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    #unused_reader
+                    limits.enter()?;
+                    #scope
+                    #( #read_fields )*
+                    limits.leave();
+                    #core::result::Result::Ok(#constructor)
+                }
+            }
+        }
+    });
+
+    quote! {
+        impl<'de> #runtime::Deserialize<'de> for #name #lifetime {
+            fn deserialize(
+                buffer: &'de [u8],
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::Deserialize<'de>>::deserialize_limited(
+                    buffer,
+                    &mut #runtime::DecodeLimits::new(),
+                )
+            }
+
+            fn deserialize_limited(
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::Deserialize<'de>>::deserialize_with(
+                    buffer,
+                    limits,
+                    &#runtime::Context::new(),
+                )
+            }
+
+            fn deserialize_with(
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+                context: &#runtime::Context<'_>,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                limits.enter()?;
+                #scope
+                #( #deserialize_fields )*
+                limits.leave();
+                #core::result::Result::Ok((#constructor, buffer))
+            }
+        }
+
+        #read_from
+
+        impl #lifetime #runtime::Serialize for #name #lifetime {
+            fn serialize(
+                &self,
+                buffer: &mut #alloc::vec::Vec<u8>,
+            ) -> #core::result::Result<(), #runtime::SerializeError> {
+                #unused_buffer
+                #( #serialize_fields )*
+                #core::result::Result::Ok(())
+            }
+        }
+    }
+}
+
+/// Generate `DeserializeSwitch`, `ReadFromSwitch`, and `Serialize` impls for
+/// an enum used as a `switch`.
+///
+/// See [`struct_impls()`] for the meaning of `borrows`.
+pub fn switch_impls(
+    paths: &Paths,
+    name: &Ident,
+    borrows: bool,
+    variants: &[VariantInfo],
+) -> TokenStream {
+    let Paths {
+        runtime,
+        core,
+        alloc,
+        std,
+        ..
+    } = paths;
+    let std_path = paths.std_path();
+    let lifetime = lifetime(borrows);
+    let mut deserialize_arms = Vec::new();
+    let mut read_arms = Vec::new();
+    let mut default_arms = None;
+
+    for variant in variants {
+        let variant_name = &variant.name;
+        let deserialize_fields =
+            deserialize_fields(paths, variant.fields.fields());
+        let read_fields = read_fields(paths, variant.fields.fields());
+        let constructor =
+            variant.fields.constructor(quote!(#name::#variant_name));
+        let transparent = matches!(variant.fields, Fields::Unnamed(_));
+        let scope = scope(&variant.fields, transparent);
+        let deserialize_body = quote! {
+            {
+                limits.enter()?;
+                #scope
+                #( #deserialize_fields )*
+                limits.leave();
+                #core::result::Result::Ok((#constructor, buffer))
+            }
+        };
+        let read_body = quote! {
+            {
+                limits.enter()?;
+                #scope
+                #( #read_fields )*
+                limits.leave();
+                #core::result::Result::Ok(#constructor)
+            }
+        };
+
+        match &variant.key {
+            Some(key) => {
+                deserialize_arms.push(quote!(#key => #deserialize_body,));
+                read_arms.push(quote!(#key => #read_body,));
+            },
+            None => {
+                default_arms = Some((
+                    quote!(_ => #deserialize_body,),
+                    quote!(_ => #read_body,),
+                ))
+            },
+        }
+    }
+
+    let (default_arm, default_read_arm) = default_arms.unwrap_or_else(|| {
+        let unknown = quote! {
+            _ => #core::result::Result::Err(
+                #runtime::DeserializeError::unknown_switch_variant(key),
+            ),
+        };
+        (unknown.clone(), unknown)
+    });
+    // an empty switch would otherwise leave its arguments unused
+    let (deserialize_switch, read_switch) = if variants.is_empty() {
+        let unknown = quote! {
+            #core::result::Result::Err(
+                #runtime::DeserializeError::unknown_switch_variant(key),
+            )
+        };
+        (
+            quote! {
+                let _ = (buffer, limits, context);
+                #unknown
+            },
+            quote! {
+                let _ = (reader, limits, context);
+                #unknown
+            },
+        )
+    } else {
+        (
+            quote! {
+                match key {
+                    #( #deserialize_arms )*
+                    #default_arm
+                }
+            },
+            quote! {
+                match key {
+                    #( #read_arms )*
+                    #default_read_arm
+                }
+            },
+        )
+    };
+    let read_from_switch = paths.std_only(if borrows || !std {
+        TokenStream::new()
+    } else {
+        quote! {
+            impl #runtime::ReadFromSwitch for #name {
+                fn read_from_switch<R>(
+                    key: #runtime::SwitchKey<'_>,
+                    reader: &mut R,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFromSwitch>::read_from_switch_limited(
+                        key,
+                        reader,
+                        &mut #runtime::DecodeLimits::new(),
+                    )
+                }
+
+                fn read_from_switch_limited<R>(
+                    key: #runtime::SwitchKey<'_>,
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFromSwitch>::read_from_switch_with(
+                        key,
+                        reader,
+                        limits,
+                        &#runtime::Context::new(),
+                    )
+                }
+
+                fn read_from_switch_with<R>(
+                    key: #runtime::SwitchKey<'_>,
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                    context: &#runtime::Context<'_>,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    #read_switch
+                }
+            }
+        }
+    });
+
+    let serialize_arms = variants.iter().map(|variant| {
+        let variant_name = &variant.name;
+        let fields = variant.fields.fields();
+        let serialize_fields = fields.iter().map(|f| {
+            serialize_field(paths, f, f.binding().into_token_stream())
+        });
+        let pattern = match &variant.fields {
+            Fields::Named(fields) => {
+                let members = fields.iter().map(|f| &f.member);
+                let bindings = fields.iter().map(FieldInfo::binding);
+                quote!(#name::#variant_name { #( #members: #bindings ),* })
+            },
+            Fields::Unnamed(fields) => {
+                let bindings = fields.iter().map(FieldInfo::binding);
+                quote!(#name::#variant_name( #( #bindings ),* ))
+            },
+            Fields::Unit => quote!(#name::#variant_name),
+        };
+
+        quote! {
+            #pattern => {
+                #( #serialize_fields )*
+            },
+        }
+    });
+    let serialize = if variants.is_empty() {
+        quote! {
+            let _ = buffer;
+            match *self {}
+        }
+    } else {
+        quote! {
+            match self {
+                #( #serialize_arms )*
+            }
+            #core::result::Result::Ok(())
+        }
+    };
+
+    quote! {
+        impl<'de> #runtime::DeserializeSwitch<'de> for #name #lifetime {
+            fn deserialize_switch(
+                key: #runtime::SwitchKey<'_>,
+                buffer: &'de [u8],
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::DeserializeSwitch<'de>>::deserialize_switch_limited(
+                    key,
+                    buffer,
+                    &mut #runtime::DecodeLimits::new(),
+                )
+            }
+
+            fn deserialize_switch_limited(
+                key: #runtime::SwitchKey<'_>,
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::DeserializeSwitch<'de>>::deserialize_switch_with(
+                    key,
+                    buffer,
+                    limits,
+                    &#runtime::Context::new(),
+                )
+            }
+
+            fn deserialize_switch_with(
+                key: #runtime::SwitchKey<'_>,
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+                context: &#runtime::Context<'_>,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                #deserialize_switch
+            }
+        }
+
+        #read_from_switch
+
+        impl #lifetime #runtime::Serialize for #name #lifetime {
+            fn serialize(
+                &self,
+                buffer: &mut #alloc::vec::Vec<u8>,
+            ) -> #core::result::Result<(), #runtime::SerializeError> {
+                #serialize
+            }
+        }
+    }
+}
+
+/// A variant in a `mapper`.
+#[derive(Debug, Clone)]
+pub struct MappingInfo {
+    pub name: Ident,
+    /// The integer this variant is encoded as.
+    pub value: i64,
+    /// An expression which creates the encoded value (e.g. `Varint(1)`).
+    pub raw: TokenStream,
+    /// The variant's name in the `protocol.json`, which is what a `switch`
+    /// compares against.
+    pub label: String,
+}
+
+/// Generate `Deserialize`, `ReadFrom`, `Serialize`, and `AsSwitchKey` impls
+/// for an enum used as a `mapper`, where each variant is encoded as a value
+/// of the `raw` type.
+pub fn mapper_impls(
+    paths: &Paths,
+    name: &Ident,
+    raw: &TokenStream,
+    variants: &[MappingInfo],
+) -> TokenStream {
+    let Paths {
+        runtime,
+        core,
+        alloc,
+        std,
+        ..
+    } = paths;
+    let std_path = paths.std_path();
+    let from_raw_arms = variants.iter().map(|v| {
+        let variant = &v.name;
+        let value = Literal::i64_unsuffixed(v.value);
+        quote! {
+            #runtime::SwitchKey::Integer(#value) => #core::result::Result::Ok(#name::#variant),
+        }
+    });
+    let to_raw_arms = variants.iter().map(|v| {
+        let variant = &v.name;
+        let raw = &v.raw;
+        quote!(#name::#variant => #raw,)
+    });
+    let label_arms = variants.iter().map(|v| {
+        let variant = &v.name;
+        let label = &v.label;
+        quote!(#name::#variant => #runtime::SwitchKey::String(#label),)
+    });
+    let serialize = if variants.is_empty() {
+        quote! {
+            let _ = buffer;
+            match *self {}
+        }
+    } else {
+        quote! {
+            let raw: #raw = match *self {
+                #( #to_raw_arms )*
+            };
+            #runtime::Serialize::serialize(&raw, buffer)
+        }
+    };
+    let read_from = paths.std_only(if *std {
+        quote! {
+            impl #runtime::ReadFrom for #name {
+                fn read_from<R>(
+                    reader: &mut R,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFrom>::read_from_limited(
+                        reader,
+                        &mut #runtime::DecodeLimits::new(),
+                    )
+                }
+
+                fn read_from_limited<R>(
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    let raw = <#raw as #runtime::ReadFrom>::read_from_limited(reader, limits)?;
+                    #name::from_raw(&raw)
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    });
+
+    quote! {
+        impl #name {
+            // a mapper without any mappings only has the fallback arm
+            #[allow(clippy::match_single_binding)]
+            fn from_raw(raw: &#raw) -> #core::result::Result<Self, #runtime::DeserializeError> {
+                match #runtime::AsSwitchKey::as_switch_key(raw) {
+                    #( #from_raw_arms )*
+                    key => #core::result::Result::Err(
+                        #runtime::DeserializeError::unknown_mapping(key),
+                    ),
+                }
+            }
+        }
+
+        impl<'de> #runtime::Deserialize<'de> for #name {
+            fn deserialize(
+                buffer: &'de [u8],
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::Deserialize<'de>>::deserialize_limited(
+                    buffer,
+                    &mut #runtime::DecodeLimits::new(),
+                )
+            }
+
+            fn deserialize_limited(
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                let (raw, buffer) = <#raw as #runtime::Deserialize<'de>>::deserialize_limited(buffer, limits)?;
+                #core::result::Result::Ok((#name::from_raw(&raw)?, buffer))
+            }
+        }
+
+        #read_from
+
+        impl #runtime::Serialize for #name {
+            fn serialize(
+                &self,
+                buffer: &mut #alloc::vec::Vec<u8>,
+            ) -> #core::result::Result<(), #runtime::SerializeError> {
+                #serialize
+            }
+        }
+
+        impl #runtime::AsSwitchKey for #name {
+            fn as_switch_key(&self) -> #runtime::SwitchKey<'_> {
+                match *self {
+                    #( #label_arms )*
+                }
+            }
+        }
+    }
+}
+
+/// A packet in a namespace's dispatch enum (see [`packet_impls()`]).
+#[derive(Debug, Clone)]
+pub struct PacketInfo {
+    /// The packet's id and name, as written by the `mapper`.
+    pub mapping: MappingInfo,
+    /// The packet's body, as selected by the `switch`.
+    pub fields: Fields,
+}
+
+/// Generate impls for the enum a namespace uses to dispatch packets, where
+/// each packet is encoded as its id (a `raw` value from a `mapper`) followed
+/// by its body.
+///
+/// While decoding a body, the packet's name is recorded in the `Context` as
+/// `tag`, the same as if the enum was a struct containing the `mapper`
+/// followed by a `switch` on it.
+///
+/// Besides the usual `Deserialize`, `ReadFrom`, and `Serialize` impls, the
+/// enum gets `packet_id()` and `name()` methods, and constructors for decoding
+/// a packet's body when the id has already been read.
+///
+/// See [`struct_impls()`] for the meaning of `borrows`.
+pub fn packet_impls(
+    paths: &Paths,
+    name: &Ident,
+    borrows: bool,
+    raw: &TokenStream,
+    tag: &str,
+    packets: &[PacketInfo],
+) -> TokenStream {
+    let Paths {
+        runtime,
+        core,
+        alloc,
+        std,
+        ..
+    } = paths;
+    let std_path = paths.std_path();
+    let lifetime = lifetime(borrows);
+    let unknown = quote! {
+        #core::result::Result::Err(
+            #runtime::DeserializeError::unknown_mapping(
+                #runtime::SwitchKey::Integer(id),
+            ),
+        )
+    };
+    let patterns: Vec<_> = packets
+        .iter()
+        .map(|p| {
+            let variant = &p.mapping.name;
+            quote!(#name::#variant { .. })
+        })
+        .collect();
+    let ids = packets
+        .iter()
+        .map(|p| Literal::i64_unsuffixed(p.mapping.value));
+    let labels = packets.iter().map(|p| &p.mapping.label);
+    let raws = packets.iter().map(|p| &p.mapping.raw);
+
+    let arms = |fields_for: &dyn Fn(&[FieldInfo]) -> Vec<TokenStream>,
+                result: &dyn Fn(TokenStream) -> TokenStream| {
+        packets
+            .iter()
+            .map(|p| {
+                let id = Literal::i64_unsuffixed(p.mapping.value);
+                let label = &p.mapping.label;
+                let variant = &p.mapping.name;
+                let fields = fields_for(p.fields.fields());
+                let value =
+                    result(p.fields.constructor(quote!(#name::#variant)));
+
+                quote! {
+                    #id => {
+                        let scope = &mut context.child();
+                        scope.insert(#tag, #label);
+                        #( #fields )*
+                        #value
+                    },
+                }
+            })
+            .collect::<Vec<_>>()
+    };
+    let deserialize_body = if packets.is_empty() {
+        quote! {
+            let _ = (buffer, limits, context);
+            #unknown
+        }
+    } else {
+        let arms = arms(
+            &|fields| deserialize_fields(paths, fields),
+            &|value| quote!((#value, buffer)),
+        );
+        quote! {
+            limits.enter()?;
+            let (packet, buffer) = match id {
+                #( #arms )*
+                _ => return #unknown,
+            };
+            limits.leave();
+            #core::result::Result::Ok((packet, buffer))
+        }
+    };
+    let read_body = if packets.is_empty() {
+        quote! {
+            let _ = (reader, limits, context);
+            #unknown
+        }
+    } else {
+        let arms = arms(&|fields| read_fields(paths, fields), &|value| value);
+        quote! {
+            limits.enter()?;
+            let packet = match id {
+                #( #arms )*
+                _ => return #unknown,
+            };
+            limits.leave();
+            #core::result::Result::Ok(packet)
+        }
+    };
+
+    let read_from = paths.std_only(if borrows || !std {
+        TokenStream::new()
+    } else {
+        quote! {
+            impl #name {
+                /// Read the body of the packet with a particular id.
+                pub fn read_from_by_id<R>(
+                    id: i64,
+                    reader: &mut R,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    #name::read_from_by_id_with(
+                        id,
+                        reader,
+                        &mut #runtime::DecodeLimits::new(),
+                        &#runtime::Context::new(),
+                    )
+                }
+
+                /// Read the body of the packet with a particular id, using
+                /// values from the surrounding `Context`.
+                pub fn read_from_by_id_with<R>(
+                    id: i64,
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                    context: &#runtime::Context<'_>,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    #read_body
+                }
+            }
+
+            impl #runtime::ReadFrom for #name {
+                fn read_from<R>(
+                    reader: &mut R,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFrom>::read_from_limited(
+                        reader,
+                        &mut #runtime::DecodeLimits::new(),
+                    )
+                }
+
+                fn read_from_limited<R>(
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFrom>::read_from_with(
+                        reader,
+                        limits,
+                        &#runtime::Context::new(),
+                    )
+                }
+
+                fn read_from_with<R>(
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                    context: &#runtime::Context<'_>,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    let raw = <#raw as #runtime::ReadFrom>::read_from_limited(reader, limits)?;
+                    #name::read_from_by_id_with(#name::id_from_raw(&raw)?, reader, limits, context)
+                }
+            }
+        }
+    });
+    let serialize_body = if packets.is_empty() {
+        quote! {
+            let _ = buffer;
+            match *self {}
+        }
+    } else {
+        let serialize_arms = packets.iter().map(|p| {
+            let variant = &p.mapping.name;
+            let fields = p.fields.fields();
+            let bindings = fields.iter().map(FieldInfo::binding);
+            let serialize_fields = fields.iter().map(|f| {
+                serialize_field(paths, f, f.binding().into_token_stream())
+            });
+            let pattern = match &p.fields {
+                Fields::Unit => quote!(#name::#variant),
+                _ => quote!(#name::#variant( #( #bindings ),* )),
+            };
+
+            quote! {
+                #pattern => {
+                    #( #serialize_fields )*
+                },
+            }
+        });
+
+        quote! {
+            let raw: #raw = match *self {
+                #( #patterns => #raws, )*
+            };
+            #runtime::Serialize::serialize(&raw, buffer)?;
+
+            match self {
+                #( #serialize_arms )*
+            }
+            #core::result::Result::Ok(())
+        }
+    };
+
+    quote! {
+        impl<'de> #name #lifetime {
+            /// The id this packet is encoded with.
+            pub fn packet_id(&self) -> i64 {
+                match *self {
+                    #( #patterns => #ids, )*
+                }
+            }
+
+            /// The packet's name, as used in the `protocol.json`.
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    #( #patterns => #labels, )*
+                }
+            }
+
+            /// Deserialize the body of the packet with a particular id.
+            pub fn deserialize_by_id(
+                id: i64,
+                buffer: &'de [u8],
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                #name::deserialize_by_id_with(
+                    id,
+                    buffer,
+                    &mut #runtime::DecodeLimits::new(),
+                    &#runtime::Context::new(),
+                )
+            }
+
+            /// Deserialize the body of the packet with a particular id, using
+            /// values from the surrounding `Context`.
+            pub fn deserialize_by_id_with(
+                id: i64,
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+                context: &#runtime::Context<'_>,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                #deserialize_body
+            }
+
+            fn id_from_raw(raw: &#raw) -> #core::result::Result<i64, #runtime::DeserializeError> {
+                match #runtime::AsSwitchKey::as_switch_key(raw) {
+                    #runtime::SwitchKey::Integer(id) => #core::result::Result::Ok(id),
+                    key => #core::result::Result::Err(
+                        #runtime::DeserializeError::unknown_mapping(key),
+                    ),
+                }
+            }
+        }
+
+        impl<'de> #runtime::Deserialize<'de> for #name #lifetime {
+            fn deserialize(
+                buffer: &'de [u8],
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::Deserialize<'de>>::deserialize_limited(
+                    buffer,
+                    &mut #runtime::DecodeLimits::new(),
+                )
+            }
+
+            fn deserialize_limited(
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::Deserialize<'de>>::deserialize_with(
+                    buffer,
+                    limits,
+                    &#runtime::Context::new(),
+                )
+            }
+
+            fn deserialize_with(
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+                context: &#runtime::Context<'_>,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                let (raw, buffer) = <#raw as #runtime::Deserialize<'de>>::deserialize_limited(buffer, limits)?;
+                #name::deserialize_by_id_with(#name::id_from_raw(&raw)?, buffer, limits, context)
+            }
+        }
+
+        #read_from
+
+        impl #lifetime #runtime::Serialize for #name #lifetime {
+            fn serialize(
+                &self,
+                buffer: &mut #alloc::vec::Vec<u8>,
+            ) -> #core::result::Result<(), #runtime::SerializeError> {
+                #serialize_body
+            }
+        }
+    }
+}
+
+/// One of the states a connection can be in (e.g. `play`), which is used
+/// for both the generated `State` enum and the packets sent in that state.
+#[derive(Debug, Clone)]
+pub struct StateInfo {
+    pub name: Ident,
+    /// The state's name in the `protocol.json`.
+    pub label: String,
+    /// The type containing the packets sent in this state, if any.
+    pub packets: Option<TokenStream>,
+    /// Packets which switch to a new state.
+    pub transitions: Vec<Transition>,
+}
+
+/// A packet with a `nextState` field, which switches the connection to a new
+/// state.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    /// The path to the packet's variant in its namespace's `Packet` enum.
+    pub packet: TokenStream,
+    /// The field containing the next state.
+    pub field: Ident,
+    /// The `SwitchKey` pattern matched by the field for each state it
+    /// switches to.
+    pub next_states: Vec<(TokenStream, Ident)>,
+}
+
+/// Generate the `ConnectionState` impl for an enum containing each of the
+/// `states`, in declaration order.
+pub fn state_impls(
+    paths: &Paths,
+    name: &Ident,
+    states: &[StateInfo],
+) -> TokenStream {
+    let runtime = &paths.runtime;
+    let variants = states.iter().map(|s| &s.name);
+    let name_arms = states.iter().map(|s| {
+        let variant = &s.name;
+        let label = &s.label;
+        quote!(#name::#variant => #label,)
+    });
+
+    quote! {
+        impl #runtime::ConnectionState for #name {
+            const STATES: &'static [Self] = &[ #( #name::#variants ),* ];
+
+            fn name(self) -> &'static str {
+                match self {
+                    #( #name_arms )*
+                }
+            }
+        }
+    }
+}
+
+/// Generate `Stateful`, `DeserializeStateful`, `ReadFromStateful`, and
+/// `Serialize` impls for an enum containing the packets sent in one
+/// `direction` (e.g. `ToClient`) during each of the `states`.
+///
+/// See [`struct_impls()`] for the meaning of `borrows`.
+pub fn stateful_impls(
+    paths: &Paths,
+    name: &Ident,
+    borrows: bool,
+    direction: &Ident,
+    state: &Ident,
+    states: &[StateInfo],
+) -> TokenStream {
+    let Paths {
+        runtime,
+        core,
+        alloc,
+        std,
+        ..
+    } = paths;
+    let std_path = paths.std_path();
+    let lifetime = lifetime(borrows);
+    let with_packets: Vec<_> = states
+        .iter()
+        .filter_map(|s| s.packets.as_ref().map(|packets| (&s.name, packets)))
+        .collect();
+    let no_packets = states.iter().filter(|s| s.packets.is_none()).map(|s| {
+        let variant = &s.name;
+        let label = &s.label;
+        quote! {
+            #state::#variant => #core::result::Result::Err(
+                #runtime::DeserializeError::NoPacketsInState { state: #label },
+            ),
+        }
+    });
+    let no_packets: Vec<_> = no_packets.collect();
+    let state_arms = with_packets
+        .iter()
+        .map(|(variant, _)| quote!(#name::#variant(..) => #state::#variant,));
+    let transitions: Vec<_> = states
+        .iter()
+        .flat_map(|s| {
+            let variant = &s.name;
+            s.transitions.iter().map(move |t| {
+                let Transition {
+                    packet,
+                    field,
+                    next_states,
+                } = t;
+                let arms = next_states.iter().map(|(pattern, next)| {
+                    quote! {
+                        #pattern => #core::result::Result::Ok(
+                            #core::option::Option::Some(#state::#next),
+                        ),
+                    }
+                });
+
+                quote! {
+                    #name::#variant(#packet(packet)) => {
+                        match #runtime::AsSwitchKey::as_switch_key(&packet.#field) {
+                            #( #arms )*
+                            key => #core::result::Result::Err(
+                                #runtime::DeserializeError::unknown_next_state(key),
+                            ),
+                        }
+                    },
+                }
+            })
+        })
+        .collect();
+    let next_state = if transitions.is_empty() {
+        quote!(#core::result::Result::Ok(#core::option::Option::None))
+    } else {
+        quote! {
+            match self {
+                #( #transitions )*
+                _ => #core::result::Result::Ok(#core::option::Option::None),
+            }
+        }
+    };
+    let deserialize_arms = with_packets.iter().map(|(variant, packets)| {
+        quote! {
+            #state::#variant => {
+                let (packet, buffer) =
+                    <#packets as #runtime::Deserialize<'de>>::deserialize_limited(buffer, limits)?;
+                #core::result::Result::Ok((#name::#variant(packet), buffer))
+            },
+        }
+    });
+    let read_from = paths.std_only(if borrows || !std {
+        TokenStream::new()
+    } else {
+        let read_arms = with_packets.iter().map(|(variant, packets)| {
+            quote! {
+                #state::#variant => {
+                    let packet = <#packets as #runtime::ReadFrom>::read_from_limited(reader, limits)?;
+                    #core::result::Result::Ok(#name::#variant(packet))
+                },
+            }
+        });
+
+        quote! {
+            impl #runtime::ReadFromStateful for #name {
+                fn read_from_stateful<R>(
+                    state: #state,
+                    reader: &mut R,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    <Self as #runtime::ReadFromStateful>::read_from_stateful_limited(
+                        state,
+                        reader,
+                        &mut #runtime::DecodeLimits::new(),
+                    )
+                }
+
+                fn read_from_stateful_limited<R>(
+                    state: #state,
+                    reader: &mut R,
+                    limits: &mut #runtime::DecodeLimits,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    match state {
+                        #( #read_arms )*
+                        #( #no_packets )*
+                    }
+                }
+            }
+        }
+    });
+    let serialize_arms = with_packets.iter().map(|(variant, _)| {
+        quote! {
+            #name::#variant(packet) => #runtime::Serialize::serialize(packet, buffer),
+        }
+    });
+
+    quote! {
+        impl #lifetime #runtime::Stateful for #name #lifetime {
+            type State = #state;
+
+            const DIRECTION: #runtime::Direction = #runtime::Direction::#direction;
+
+            fn state(&self) -> #state {
+                match *self {
+                    #( #state_arms )*
+                }
+            }
+
+            #[allow(unreachable_patterns)]
+            fn next_state(
+                &self,
+            ) -> #core::result::Result<#core::option::Option<#state>, #runtime::DeserializeError> {
+                #next_state
+            }
+        }
+
+        impl<'de> #runtime::DeserializeStateful<'de> for #name #lifetime {
+            fn deserialize_stateful(
+                state: #state,
+                buffer: &'de [u8],
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                <Self as #runtime::DeserializeStateful<'de>>::deserialize_stateful_limited(
+                    state,
+                    buffer,
+                    &mut #runtime::DecodeLimits::new(),
+                )
+            }
+
+            fn deserialize_stateful_limited(
+                state: #state,
+                buffer: &'de [u8],
+                limits: &mut #runtime::DecodeLimits,
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                match state {
+                    #( #deserialize_arms )*
+                    #( #no_packets )*
+                }
+            }
+        }
+
+        #read_from
+
+        impl #lifetime #runtime::Serialize for #name #lifetime {
+            fn serialize(
+                &self,
+                buffer: &mut #alloc::vec::Vec<u8>,
+            ) -> #core::result::Result<(), #runtime::SerializeError> {
+                match self {
+                    #( #serialize_arms )*
+                }
+            }
+        }
+    }
+}
+
+/// A field in a `bitfield`.
+#[derive(Debug, Clone)]
+pub struct BitFieldInfo {
+    pub member: Ident,
+    /// The integer type used to store the field (e.g. `i32`).
+    pub ty: TokenStream,
+    /// The number of bits used to encode the field.
+    pub size: usize,
+    pub signed: bool,
+}
+
+/// Generate `Deserialize`, `ReadFrom`, and `Serialize` impls for a struct
+/// used as a `bitfield`, where the fields are packed into a big-endian
+/// integer with the first field in the most significant bits.
+///
+/// The fields must add up to a whole number of bytes, and no more than 64
+/// bits.
+pub fn bitfield_impls(
+    paths: &Paths,
+    name: &Ident,
+    fields: &[BitFieldInfo],
+) -> TokenStream {
+    let Paths {
+        runtime,
+        core,
+        alloc,
+        std,
+        ..
+    } = paths;
+    let std_path = paths.std_path();
+    let total_bits: usize = fields.iter().map(|f| f.size).sum();
+    let bytes = Literal::usize_unsuffixed(total_bits / 8);
+    // to_bits() returns a big-endian u64, so skip the unused leading bytes
+    let skipped = Literal::usize_unsuffixed(8 - total_bits / 8);
+    let mut unpack = Vec::new();
+    let mut pack = Vec::new();
+    let mut remaining = total_bits;
+
+    for field in fields {
+        let BitFieldInfo {
+            member,
+            ty,
+            size,
+            signed,
+        } = field;
+        remaining -= size;
+        let shift = Literal::usize_unsuffixed(remaining);
+        let shifted = if remaining == 0 {
+            quote!(raw)
+        } else {
+            quote!((raw >> #shift))
+        };
+        let mask = if *size >= 64 {
+            TokenStream::new()
+        } else {
+            let mask = Literal::u64_unsuffixed((1 << size) - 1);
+            quote!(& #mask)
+        };
+
+        unpack.push(if *signed && *size < 64 {
+            // move the field's sign bit to the top so shifting back down
+            // sign-extends it
+            let unused = Literal::usize_unsuffixed(64 - size);
+            quote!(#member: ((#shifted << #unused) as i64 >> #unused) as #ty,)
+        } else {
+            quote!(#member: (#shifted #mask) as #ty,)
+        });
+        pack.push(if remaining == 0 {
+            quote!(raw |= self.#member as u64 #mask;)
+        } else {
+            quote!(raw |= (self.#member as u64 #mask) << #shift;)
+        });
+    }
+
+    let read_from = paths.std_only(if *std {
+        quote! {
+            impl #runtime::ReadFrom for #name {
+                fn read_from<R>(
+                    reader: &mut R,
+                ) -> #core::result::Result<Self, #runtime::DeserializeError>
+                where
+                    R: #std_path::io::Read + ?#core::marker::Sized,
+                {
+                    let mut bytes = [0_u8; #bytes];
+                    #runtime::read_exact(reader, &mut bytes)?;
+                    #core::result::Result::Ok(#name::from_bits(&bytes))
+                }
+            }
+        }
+    } else {
+        TokenStream::new()
+    });
+
+    quote! {
+        impl #name {
+            #[allow(clippy::unnecessary_cast)]
+            fn from_bits(bytes: &[u8]) -> Self {
+                let mut raw = 0_u64;
+                for byte in bytes {
+                    raw = raw << 8 | u64::from(*byte);
+                }
+
+                #name { #( #unpack )* }
+            }
+
+            #[allow(clippy::unnecessary_cast)]
+            fn to_bits(&self) -> [u8; 8] {
+                let mut raw = 0_u64;
+                #( #pack )*
+                raw.to_be_bytes()
+            }
+        }
+
+        impl<'de> #runtime::Deserialize<'de> for #name {
+            fn deserialize(
+                buffer: &'de [u8],
+            ) -> #core::result::Result<(Self, &'de [u8]), #runtime::DeserializeError> {
+                let (bytes, buffer) = #runtime::take(buffer, #bytes)?;
+                #core::result::Result::Ok((#name::from_bits(bytes), buffer))
+            }
+        }
+
+        #read_from
+
+        impl #runtime::Serialize for #name {
+            fn serialize(
+                &self,
+                buffer: &mut #alloc::vec::Vec<u8>,
+            ) -> #core::result::Result<(), #runtime::SerializeError> {
+                buffer.extend_from_slice(&self.to_bits()[#skipped..]);
+                #core::result::Result::Ok(())
+            }
+        }
+    }
+}
+
+/// Get the path to a native type from the runtime crate (e.g. `varint` is
+/// `::protodef_core::native::Varint`).
+pub fn native_type(runtime: &TokenStream, name: &str) -> TokenStream {
+    let name = super::naming::type_name(name);
+    quote!(#runtime::native::#name)
+}
+
+/// Get the `SwitchKey` pattern matched by a key from a `switch`'s `fields`.
+///
+/// Keys are always strings in a `protocol.json` file, so anything which looks
+/// like an integer or boolean is matched as one.
+pub fn switch_key_pattern(runtime: &TokenStream, key: &str) -> TokenStream {
+    if let Ok(integer) = key.parse::<i64>() {
+        let integer = proc_macro2::Literal::i64_unsuffixed(integer);
+        quote!(#runtime::SwitchKey::Integer(#integer))
+    } else if let Ok(boolean) = key.parse::<bool>() {
+        quote!(#runtime::SwitchKey::Bool(#boolean))
+    } else {
+        quote!(#runtime::SwitchKey::String(#key))
+    }
+}
+
+/// The generic parameters for a type which may borrow from its input.
+fn lifetime(borrows: bool) -> TokenStream {
+    if borrows {
+        quote!(<'de>)
+    } else {
+        TokenStream::new()
+    }
+}
+
+/// Create the `Context` scope a struct or variant's fields are decoded in.
+///
+/// Tuple structs and variants only wrap another type, so they are
+/// transparent (i.e. the `../` in a wrapped struct refers to the struct
+/// containing the switch, and an array's `count` is a sibling of the array)
+/// unless they need to record one of their own fields.
+fn scope(fields: &Fields, transparent: bool) -> TokenStream {
+    let fields = fields.fields();
+
+    if fields.is_empty() {
+        quote!(let _ = context;)
+    } else if fields.iter().any(FieldInfo::records) {
+        quote!(let scope = &mut context.child();)
+    } else if transparent {
+        quote!(let scope = context;)
+    } else {
+        quote!(let scope = &context.child();)
+    }
+}
+
+/// The `Context` scope an anonymous struct's fields are decoded in, which is
+/// its parent's scope (plus its own fields, if they need to be recorded).
+fn shared_scope(fields: &Fields) -> TokenStream {
+    let fields = fields.fields();
+
+    if fields.is_empty() {
+        quote!(let _ = context;)
+    } else if fields.iter().any(FieldInfo::records) {
+        quote!(let scope = &mut context.clone();)
+    } else {
+        quote!(let scope = context;)
+    }
+}
+
+/// Record a field in the `Context` after it has been decoded, if necessary.
+fn record(field: &FieldInfo) -> TokenStream {
+    let binding = field.binding();
+    let own = if field.context {
+        let name = &field.name;
+        quote!(scope.insert(#name, &#binding);)
+    } else {
+        TokenStream::new()
+    };
+    let merged = field
+        .merged
+        .iter()
+        .map(|(name, path)| quote!(scope.insert(#name, &#binding #path);));
+
+    quote! {
+        #own
+        #( #merged )*
+    }
+}
+
+/// The expression used to get the `SwitchKey` a switch compares against.
+///
+/// Earlier siblings are used directly, while anything else is looked up in
+/// the `Context`.
+fn switch_key(
+    runtime: &TokenStream,
+    compare_to: &str,
+    earlier: &[FieldInfo],
+) -> TokenStream {
+    if earlier.iter().any(|f| f.name == compare_to) {
+        let binding = binding_for(compare_to);
+        quote!(#runtime::AsSwitchKey::as_switch_key(&#binding))
+    } else {
+        quote!(scope.switch_key(#compare_to)?)
+    }
+}
+
+fn binding_for(name: &str) -> Ident {
+    format_ident!("field_{}", name.trim_start_matches("r#"))
+}
+
+fn deserialize_fields(paths: &Paths, fields: &[FieldInfo]) -> Vec<TokenStream> {
+    let Paths { runtime, core, .. } = paths;
+
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let binding = f.binding();
+            let ty = &f.ty;
+            let record = record(f);
+
+            let decode = match &f.encoding {
+                Encoding::Default => quote! {
+                    let (#binding, buffer) =
+                        <#ty as #runtime::Deserialize<'de>>::deserialize_with(buffer, limits, scope)?;
+                },
+                Encoding::As(wire) => quote! {
+                    let (#binding, buffer) =
+                        <#wire as #runtime::Deserialize<'de>>::deserialize_with(buffer, limits, scope)?;
+                    let #binding: #ty = #core::convert::From::from(#binding);
+                },
+                Encoding::LengthPrefixed { count } => quote! {
+                    let (#binding, buffer) =
+                        <#ty as #runtime::DeserializePrefixed<'de>>::deserialize_prefixed_with::<#count>(buffer, limits, scope)?;
+                },
+                Encoding::Fixed { .. } | Encoding::Counted { .. } => {
+                    let length = length(&f.encoding);
+                    quote! {
+                        let (#binding, buffer) =
+                            <#ty as #runtime::DeserializeCounted<'de>>::deserialize_counted_with(#length, buffer, limits, scope)?;
+                    }
+                },
+                Encoding::Switch { compare_to } => {
+                    let key = switch_key(runtime, compare_to, &fields[..i]);
+                    quote! {
+                        let (#binding, buffer) =
+                            <#ty as #runtime::DeserializeSwitch<'de>>::deserialize_switch_with(
+                                #key,
+                                buffer,
+                                limits,
+                                scope,
+                            )?;
+                    }
+                },
+            };
+
+            quote! {
+                #decode
+                #record
+            }
+        })
+        .collect()
+}
+
+/// The streaming equivalent of [`deserialize_fields()`].
+fn read_fields(paths: &Paths, fields: &[FieldInfo]) -> Vec<TokenStream> {
+    let Paths { runtime, core, .. } = paths;
+
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let binding = f.binding();
+            let ty = &f.ty;
+            let record = record(f);
+
+            let read = match &f.encoding {
+                Encoding::Default => quote! {
+                    let #binding = <#ty as #runtime::ReadFrom>::read_from_with(reader, limits, scope)?;
+                },
+                Encoding::As(wire) => quote! {
+                    let #binding = <#wire as #runtime::ReadFrom>::read_from_with(reader, limits, scope)?;
+                    let #binding: #ty = #core::convert::From::from(#binding);
+                },
+                Encoding::LengthPrefixed { count } => quote! {
+                    let #binding =
+                        <#ty as #runtime::ReadFromPrefixed>::read_from_prefixed_with::<#count, R>(reader, limits, scope)?;
+                },
+                Encoding::Fixed { .. } | Encoding::Counted { .. } => {
+                    let length = length(&f.encoding);
+                    quote! {
+                        let #binding =
+                            <#ty as #runtime::ReadFromCounted>::read_from_counted_with::<R>(#length, reader, limits, scope)?;
+                    }
+                },
+                Encoding::Switch { compare_to } => {
+                    let key = switch_key(runtime, compare_to, &fields[..i]);
+                    quote! {
+                        let #binding =
+                            <#ty as #runtime::ReadFromSwitch>::read_from_switch_with(
+                                #key,
+                                reader,
+                                limits,
+                                scope,
+                            )?;
+                    }
+                },
+            };
+
+            quote! {
+                #read
+                #record
+            }
+        })
+        .collect()
+}
+
+/// Serialize a field, where `value` is an expression evaluating to a reference
+/// to the field.
+fn serialize_field(
+    paths: &Paths,
+    field: &FieldInfo,
+    value: TokenStream,
+) -> TokenStream {
+    let Paths { runtime, core, .. } = paths;
+
+    match &field.encoding {
+        Encoding::Default | Encoding::Switch { .. } => quote! {
+            #runtime::Serialize::serialize(#value, buffer)?;
+        },
+        Encoding::As(wire) => quote! {
+            #runtime::Serialize::serialize(
+                &<#wire as #core::convert::From<_>>::from(#core::clone::Clone::clone(#value)),
+                buffer,
+            )?;
+        },
+        Encoding::LengthPrefixed { count } => quote! {
+            #runtime::SerializePrefixed::serialize_prefixed::<#count>(#value, buffer)?;
+        },
+        Encoding::Fixed { .. } | Encoding::Counted { .. } => quote! {
+            #runtime::SerializeCounted::serialize_counted(#value, buffer)?;
+        },
+    }
+}
+
+/// The expression giving the number of items in a [`Encoding::Fixed`] or
+/// [`Encoding::Counted`] field.
+fn length(encoding: &Encoding) -> TokenStream {
+    match encoding {
+        Encoding::Fixed { length } => {
+            let length = Literal::usize_unsuffixed(*length);
+            quote!(#length)
+        },
+        Encoding::Counted { count } => quote!(scope.count(#count)?),
+        _ => unreachable!("{:?} doesn't have a known length", encoding),
+    }
+}
@@ -0,0 +1,283 @@
+use crate::{
+    backend::{self, CodegenOptions},
+    lowering::{self, Diagnostics, LoweringOptions},
+    syntax::{self, ParseError},
+};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A helper for generating code from a `protocol.json` file inside a
+/// `build.rs` script.
+///
+/// By default, the generated code is written to `$OUT_DIR/protocol.rs` (using
+/// the protocol file's name) so it can be pulled into your crate with
+/// `include!()`.
+///
+/// # Examples
+///
+/// In your `build.rs`:
+///
+/// ```rust,no_run
+/// use protodef_codegen::{backend::CodegenOptions, Builder};
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     Builder::new("protocol.json")
+///         .codegen_options(CodegenOptions::new().derive("Eq"))
+///         .generate()?;
+///
+///     Ok(())
+/// }
+/// ```
+///
+/// Then somewhere in your crate:
+///
+/// ```rust,ignore
+/// include!(concat!(env!("OUT_DIR"), "/protocol.rs"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    protocol: PathBuf,
+    output: Option<PathBuf>,
+    lowering_options: LoweringOptions,
+    codegen_options: CodegenOptions,
+    rustfmt: bool,
+}
+
+impl Builder {
+    pub fn new(protocol: impl Into<PathBuf>) -> Self {
+        Builder {
+            protocol: protocol.into(),
+            output: None,
+            lowering_options: LoweringOptions::default(),
+            codegen_options: CodegenOptions::default(),
+            rustfmt: true,
+        }
+    }
+
+    /// Where to write the generated code.
+    ///
+    /// Relative paths are resolved relative to `$OUT_DIR`.
+    pub fn output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.output = Some(path.into());
+        self
+    }
+
+    /// Give structurally identical anonymous types a single definition (see
+    /// [`LoweringOptions::deduplicate_types`]).
+    pub fn deduplicate_types(mut self, deduplicate: bool) -> Self {
+        self.lowering_options.deduplicate_types = deduplicate;
+        self
+    }
+
+    pub fn codegen_options(mut self, options: CodegenOptions) -> Self {
+        self.codegen_options = options;
+        self
+    }
+
+    /// Should the generated code be formatted with `rustfmt` (if it is
+    /// installed)?
+    pub fn rustfmt(mut self, rustfmt: bool) -> Self {
+        self.rustfmt = rustfmt;
+        self
+    }
+
+    /// Generate the code, returning the path it was written to.
+    ///
+    /// This also tells `cargo` to re-run the build script whenever the
+    /// protocol file changes.
+    pub fn generate(&self) -> Result<PathBuf, BuildError> {
+        println!("cargo:rerun-if-changed={}", self.protocol.display());
+
+        let output = self.output_path()?;
+        let rust_code = self.generate_code()?;
+
+        if let Some(parent) = output.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| BuildError::io(parent, e))?;
+        }
+        std::fs::write(&output, rust_code)
+            .map_err(|e| BuildError::io(&output, e))?;
+
+        Ok(output)
+    }
+
+    fn generate_code(&self) -> Result<String, BuildError> {
+        let src = std::fs::read_to_string(&self.protocol)
+            .map_err(|e| BuildError::io(&self.protocol, e))?;
+        let json = serde_json::from_str(&src).map_err(BuildError::Json)?;
+
+        let (parsed, errors) = syntax::parse_with_recovery(&json);
+        if !errors.is_empty() {
+            return Err(BuildError::Parse(errors));
+        }
+
+        let analysed =
+            lowering::lower_with_options(&parsed, &self.lowering_options)
+                .map_err(BuildError::Diagnostics)?;
+        let tokens = backend::generate_rust_with_options(
+            &analysed,
+            &self.codegen_options,
+        )
+        .map_err(BuildError::Diagnostics)?;
+
+        if self.rustfmt {
+            if let Ok(formatted) = backend::rustfmt(&tokens) {
+                return Ok(formatted);
+            }
+        }
+
+        Ok(tokens.to_string())
+    }
+
+    fn output_path(&self) -> Result<PathBuf, BuildError> {
+        let filename = match &self.output {
+            Some(output) if output.is_absolute() => return Ok(output.clone()),
+            Some(output) => output.clone(),
+            None => {
+                let stem = self
+                    .protocol
+                    .file_stem()
+                    .unwrap_or_else(|| "protocol".as_ref());
+                Path::new(stem).with_extension("rs")
+            },
+        };
+
+        let out_dir =
+            std::env::var_os("OUT_DIR").ok_or(BuildError::MissingOutDir)?;
+
+        Ok(Path::new(&out_dir).join(filename))
+    }
+}
+
+/// Errors that may occur while using a [`Builder`].
+#[derive(Debug)]
+pub enum BuildError {
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Json(serde_json::Error),
+    Parse(Vec<ParseError>),
+    Diagnostics(Diagnostics),
+    /// The `$OUT_DIR` environment variable wasn't set (i.e. we aren't being
+    /// run from a build script) and no absolute output path was provided.
+    MissingOutDir,
+}
+
+impl BuildError {
+    fn io(path: &Path, error: io::Error) -> Self {
+        BuildError::Io {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Io { path, error } => {
+                write!(f, "unable to access \"{}\": {}", path.display(), error)
+            },
+            BuildError::Json(e) => write!(f, "unable to parse the JSON: {}", e),
+            BuildError::Parse(errors) => {
+                writeln!(f, "{} parse errors found:", errors.len())?;
+
+                for error in errors {
+                    writeln!(f, "  {}", error)?;
+                }
+
+                Ok(())
+            },
+            BuildError::Diagnostics(diags) => Display::fmt(diags, f),
+            BuildError::MissingOutDir => {
+                write!(f, "the OUT_DIR environment variable isn't set")
+            },
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::Io { error, .. } => Some(error),
+            BuildError::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(env!("CARGO_PKG_NAME")).join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn write_the_generated_code_to_a_file() {
+        let dir = scratch_dir("write_the_generated_code_to_a_file");
+        let protocol = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join("fixtures")
+            .join("basic.json");
+        let output = dir.join("nested").join("basic.rs");
+
+        let got = Builder::new(protocol)
+            .output(&output)
+            .rustfmt(false)
+            .generate()
+            .unwrap();
+
+        assert_eq!(got, output);
+        let generated = std::fs::read_to_string(&output).unwrap();
+        assert!(generated.contains("pub struct Items"));
+    }
+
+    #[test]
+    fn report_every_parse_error() {
+        let dir = scratch_dir("report_every_parse_error");
+        let protocol = dir.join("protocol.json");
+        let src = json!({
+            "types": {
+                "first": ["container", 42],
+                "second": ["unknown", {}],
+            }
+        });
+        std::fs::write(&protocol, src.to_string()).unwrap();
+
+        let got = Builder::new(&protocol)
+            .output(dir.join("protocol.rs"))
+            .generate()
+            .unwrap_err();
+
+        match got {
+            BuildError::Parse(errors) => assert_eq!(errors.len(), 2),
+            other => panic!("Expected a parse error, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn errors_include_the_underlying_cause() {
+        let dir = scratch_dir("errors_include_the_underlying_cause");
+        let protocol = dir.join("missing.json");
+
+        let got = Builder::new(&protocol)
+            .output(dir.join("protocol.rs"))
+            .generate()
+            .unwrap_err();
+
+        let error = std::fs::read(&protocol).unwrap_err();
+        assert!(matches!(got, BuildError::Io { .. }));
+        assert!(got.to_string().starts_with("unable to access"));
+        assert!(got.to_string().ends_with(&error.to_string()));
+    }
+}
@@ -0,0 +1,999 @@
+//! Compare two versions of a protocol to see what changed.
+//!
+//! ```rust
+//! use protodef_codegen::diff::{self, Change};
+//!
+//! let old = protodef_codegen::syntax::parse(&serde_json::json!({
+//!   "types": {
+//!     "varint": "native",
+//!     "packet_ping": ["container", [{ "name": "id", "type": "varint" }]]
+//!   }
+//! }))?;
+//! let new = protodef_codegen::syntax::parse(&serde_json::json!({
+//!   "types": {
+//!     "varint": "native",
+//!     "i64": "native",
+//!     "packet_ping": ["container", [{ "name": "id", "type": "i64" }]]
+//!   }
+//! }))?;
+//!
+//! let changes = diff::diff(&old, &new);
+//!
+//! assert_eq!(
+//!     changes,
+//!     vec![
+//!         Change::TypeAdded { name: String::from("i64") },
+//!         Change::FieldTypeChanged {
+//!             ty: String::from("packet_ping"),
+//!             field: String::from("id"),
+//!             old: String::from("varint"),
+//!             new: String::from("i64"),
+//!         },
+//!     ]
+//! );
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::syntax::{Container, Count, Mapper, Protocol, Switch, Type};
+use indexmap::IndexMap;
+use serde_json::json;
+use std::fmt::{self, Display, Formatter};
+
+/// Find everything which changed between two versions of a protocol.
+///
+/// Types are identified by their fully qualified name (e.g.
+/// `play.toClient.packet_chat`), and a type which was removed is considered
+/// to be renamed if a type with an identical definition was added to the
+/// same namespace.
+pub fn diff(old: &Protocol, new: &Protocol) -> Vec<Change> {
+    let old_types = qualified_types(old);
+    let new_types = qualified_types(new);
+    let mut changes = Vec::new();
+
+    let mut removed: Vec<&str> = old_types
+        .keys()
+        .filter(|name| !new_types.contains_key(*name))
+        .map(|name| name.as_str())
+        .collect();
+    let mut added: Vec<&str> = new_types
+        .keys()
+        .filter(|name| !old_types.contains_key(*name))
+        .map(|name| name.as_str())
+        .collect();
+
+    // look for types which were renamed
+    removed.retain(|old_name| {
+        let old_ty = old_types[*old_name];
+        let renamed_to = added.iter().position(|new_name| {
+            namespace(new_name) == namespace(old_name)
+                && new_types[*new_name] == old_ty
+        });
+
+        match renamed_to {
+            Some(index) => {
+                changes.push(Change::TypeRenamed {
+                    old: old_name.to_string(),
+                    new: added.remove(index).to_string(),
+                });
+                false
+            },
+            None => true,
+        }
+    });
+
+    changes.extend(removed.into_iter().map(|name| Change::TypeRemoved {
+        name: name.to_string(),
+    }));
+    changes.extend(added.into_iter().map(|name| Change::TypeAdded {
+        name: name.to_string(),
+    }));
+
+    for (name, old_ty) in &old_types {
+        if let Some(new_ty) = new_types.get(name) {
+            diff_type(name, None, old_ty, new_ty, &mut changes);
+        }
+    }
+
+    changes
+}
+
+/// Something which changed between two versions of a protocol.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Change {
+    TypeAdded {
+        name: String,
+    },
+    TypeRemoved {
+        name: String,
+    },
+    /// A type was removed and another type with the same definition was
+    /// added to the same namespace.
+    TypeRenamed {
+        old: String,
+        new: String,
+    },
+    /// A type's definition changed in a way that couldn't be described more
+    /// precisely.
+    TypeChanged {
+        name: String,
+        old: String,
+        new: String,
+    },
+    FieldAdded {
+        ty: String,
+        field: String,
+    },
+    FieldRemoved {
+        ty: String,
+        field: String,
+    },
+    FieldTypeChanged {
+        ty: String,
+        field: String,
+        old: String,
+        new: String,
+    },
+    /// The fields a container has in common with its previous version are
+    /// now in a different order.
+    FieldsReordered {
+        ty: String,
+        field: Option<String>,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// A `switch` gained a new case.
+    VariantAdded {
+        ty: String,
+        field: Option<String>,
+        key: String,
+    },
+    VariantRemoved {
+        ty: String,
+        field: Option<String>,
+        key: String,
+    },
+    MappingAdded {
+        ty: String,
+        field: Option<String>,
+        id: i64,
+        name: String,
+    },
+    MappingRemoved {
+        ty: String,
+        field: Option<String>,
+        id: i64,
+        name: String,
+    },
+    /// The same id now maps to a different name.
+    MappingRenamed {
+        ty: String,
+        field: Option<String>,
+        id: i64,
+        old: String,
+        new: String,
+    },
+    /// The same name now has a different id (e.g. a packet id changed).
+    MappingIdChanged {
+        ty: String,
+        field: Option<String>,
+        name: String,
+        old: i64,
+        new: i64,
+    },
+}
+
+impl Change {
+    /// Get a machine-readable version of this change.
+    ///
+    /// Each change is an object with a `kind` (e.g. `"field_added"`) and one
+    /// key for each piece of information about the change.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Change::TypeAdded { name } => {
+                json!({ "kind": "type_added", "name": name })
+            },
+            Change::TypeRemoved { name } => {
+                json!({ "kind": "type_removed", "name": name })
+            },
+            Change::TypeRenamed { old, new } => {
+                json!({ "kind": "type_renamed", "old": old, "new": new })
+            },
+            Change::TypeChanged { name, old, new } => json!({
+                "kind": "type_changed",
+                "name": name,
+                "old": old,
+                "new": new,
+            }),
+            Change::FieldAdded { ty, field } => {
+                json!({ "kind": "field_added", "type": ty, "field": field })
+            },
+            Change::FieldRemoved { ty, field } => {
+                json!({ "kind": "field_removed", "type": ty, "field": field })
+            },
+            Change::FieldTypeChanged {
+                ty,
+                field,
+                old,
+                new,
+            } => json!({
+                "kind": "field_type_changed",
+                "type": ty,
+                "field": field,
+                "old": old,
+                "new": new,
+            }),
+            Change::FieldsReordered {
+                ty,
+                field,
+                old,
+                new,
+            } => json!({
+                "kind": "fields_reordered",
+                "type": ty,
+                "field": field,
+                "old": old,
+                "new": new,
+            }),
+            Change::VariantAdded { ty, field, key } => json!({
+                "kind": "variant_added",
+                "type": ty,
+                "field": field,
+                "key": key,
+            }),
+            Change::VariantRemoved { ty, field, key } => json!({
+                "kind": "variant_removed",
+                "type": ty,
+                "field": field,
+                "key": key,
+            }),
+            Change::MappingAdded {
+                ty,
+                field,
+                id,
+                name,
+            } => json!({
+                "kind": "mapping_added",
+                "type": ty,
+                "field": field,
+                "id": id,
+                "name": name,
+            }),
+            Change::MappingRemoved {
+                ty,
+                field,
+                id,
+                name,
+            } => json!({
+                "kind": "mapping_removed",
+                "type": ty,
+                "field": field,
+                "id": id,
+                "name": name,
+            }),
+            Change::MappingRenamed {
+                ty,
+                field,
+                id,
+                old,
+                new,
+            } => json!({
+                "kind": "mapping_renamed",
+                "type": ty,
+                "field": field,
+                "id": id,
+                "old": old,
+                "new": new,
+            }),
+            Change::MappingIdChanged {
+                ty,
+                field,
+                name,
+                old,
+                new,
+            } => json!({
+                "kind": "mapping_id_changed",
+                "type": ty,
+                "field": field,
+                "name": name,
+                "old": old,
+                "new": new,
+            }),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::TypeAdded { name } => write!(f, "+ {}", name),
+            Change::TypeRemoved { name } => write!(f, "- {}", name),
+            Change::TypeRenamed { old, new } => {
+                write!(f, "~ {} renamed to {}", old, new)
+            },
+            Change::TypeChanged { name, old, new } => {
+                write!(f, "~ {} changed from {} to {}", name, old, new)
+            },
+            Change::FieldAdded { ty, field } => {
+                write!(f, "+ {}.{}", ty, field)
+            },
+            Change::FieldRemoved { ty, field } => {
+                write!(f, "- {}.{}", ty, field)
+            },
+            Change::FieldTypeChanged {
+                ty,
+                field,
+                old,
+                new,
+            } => {
+                write!(f, "~ {}.{} changed from {} to {}", ty, field, old, new)
+            },
+            Change::FieldsReordered {
+                ty,
+                field,
+                old,
+                new,
+            } => write!(
+                f,
+                "~ {} fields reordered from [{}] to [{}]",
+                Location(ty, field),
+                old.join(", "),
+                new.join(", ")
+            ),
+            Change::VariantAdded { ty, field, key } => {
+                write!(f, "+ {} case \"{}\"", Location(ty, field), key)
+            },
+            Change::VariantRemoved { ty, field, key } => {
+                write!(f, "- {} case \"{}\"", Location(ty, field), key)
+            },
+            Change::MappingAdded {
+                ty,
+                field,
+                id,
+                name,
+            } => write!(
+                f,
+                "+ {} maps {} to \"{}\"",
+                Location(ty, field),
+                id,
+                name
+            ),
+            Change::MappingRemoved {
+                ty,
+                field,
+                id,
+                name,
+            } => write!(
+                f,
+                "- {} no longer maps {} to \"{}\"",
+                Location(ty, field),
+                id,
+                name
+            ),
+            Change::MappingRenamed {
+                ty,
+                field,
+                id,
+                old,
+                new,
+            } => write!(
+                f,
+                "~ {} maps {} to \"{}\" instead of \"{}\"",
+                Location(ty, field),
+                id,
+                new,
+                old
+            ),
+            Change::MappingIdChanged {
+                ty,
+                field,
+                name,
+                old,
+                new,
+            } => write!(
+                f,
+                "~ {} id for \"{}\" changed from {} to {}",
+                Location(ty, field),
+                name,
+                old,
+                new
+            ),
+        }
+    }
+}
+
+/// A type name, optionally followed by the field inside it.
+struct Location<'a>(&'a str, &'a Option<String>);
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.1 {
+            Some(field) => write!(f, "{}.{}", self.0, field),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+fn qualified_types(protocol: &Protocol) -> IndexMap<String, &Type> {
+    fn visit<'p>(
+        protocol: &'p Protocol,
+        prefix: &str,
+        types: &mut IndexMap<String, &'p Type>,
+    ) {
+        for (name, ty) in &protocol.types {
+            types.insert(format!("{}{}", prefix, name), ty);
+        }
+
+        for (name, namespace) in &protocol.namespaces {
+            visit(namespace, &format!("{}{}.", prefix, name), types);
+        }
+    }
+
+    let mut types = IndexMap::new();
+    visit(protocol, "", &mut types);
+    types
+}
+
+/// The namespace part of a fully qualified name.
+fn namespace(qualified_name: &str) -> &str {
+    match qualified_name.rfind('.') {
+        Some(index) => &qualified_name[..index],
+        None => "",
+    }
+}
+
+fn diff_type(
+    ty: &str,
+    field: Option<&str>,
+    old: &Type,
+    new: &Type,
+    changes: &mut Vec<Change>,
+) {
+    if old == new {
+        return;
+    }
+
+    match (old, new) {
+        (Type::Container(old), Type::Container(new)) => {
+            diff_container(ty, field, old, new, changes)
+        },
+        (Type::Switch(old), Type::Switch(new))
+            if old.compare_to == new.compare_to =>
+        {
+            diff_switch(ty, field, old, new, changes)
+        },
+        (Type::Mapper(old), Type::Mapper(new)) if old.ty == new.ty => {
+            diff_mapper(ty, field, old, new, changes)
+        },
+        _ => {
+            let old = describe(old);
+            let new = describe(new);
+
+            changes.push(match field {
+                Some(field) => Change::FieldTypeChanged {
+                    ty: ty.to_string(),
+                    field: field.to_string(),
+                    old,
+                    new,
+                },
+                None => Change::TypeChanged {
+                    name: ty.to_string(),
+                    old,
+                    new,
+                },
+            });
+        },
+    }
+}
+
+fn diff_container(
+    ty: &str,
+    field: Option<&str>,
+    old: &Container,
+    new: &Container,
+    changes: &mut Vec<Change>,
+) {
+    let old_fields = field_names(old);
+    let new_fields = field_names(new);
+    let path = |name: &str| match field {
+        Some(field) => format!("{}.{}", field, name),
+        None => name.to_string(),
+    };
+
+    for name in old_fields.keys() {
+        if !new_fields.contains_key(name) {
+            changes.push(Change::FieldRemoved {
+                ty: ty.to_string(),
+                field: path(name),
+            });
+        }
+    }
+
+    for name in new_fields.keys() {
+        if !old_fields.contains_key(name) {
+            changes.push(Change::FieldAdded {
+                ty: ty.to_string(),
+                field: path(name),
+            });
+        }
+    }
+
+    let common_old: Vec<String> = old_fields
+        .keys()
+        .filter(|name| new_fields.contains_key(*name))
+        .cloned()
+        .collect();
+    let common_new: Vec<String> = new_fields
+        .keys()
+        .filter(|name| old_fields.contains_key(*name))
+        .cloned()
+        .collect();
+
+    if common_old != common_new {
+        changes.push(Change::FieldsReordered {
+            ty: ty.to_string(),
+            field: field.map(String::from),
+            old: common_old,
+            new: common_new,
+        });
+    }
+
+    for (name, old_ty) in &old_fields {
+        if let Some(new_ty) = new_fields.get(name) {
+            diff_type(ty, Some(&path(name)), old_ty, new_ty, changes);
+        }
+    }
+}
+
+/// Get a container's fields, giving anonymous fields a name based on their
+/// position among the other anonymous fields.
+fn field_names(container: &Container) -> IndexMap<String, &Type> {
+    let mut anonymous = 0;
+
+    container
+        .fields
+        .iter()
+        .map(|field| match &field.name {
+            Some(name) => (name.clone(), &field.ty),
+            None => {
+                anonymous += 1;
+                (format!("<anonymous #{}>", anonymous), &field.ty)
+            },
+        })
+        .collect()
+}
+
+fn diff_switch(
+    ty: &str,
+    field: Option<&str>,
+    old: &Switch,
+    new: &Switch,
+    changes: &mut Vec<Change>,
+) {
+    let path = |key: &str| match field {
+        Some(field) => format!("{}[{}]", field, key),
+        None => format!("[{}]", key),
+    };
+
+    for key in old.variants.keys() {
+        if !new.variants.contains_key(key) {
+            changes.push(Change::VariantRemoved {
+                ty: ty.to_string(),
+                field: field.map(String::from),
+                key: key.clone(),
+            });
+        }
+    }
+
+    for key in new.variants.keys() {
+        if !old.variants.contains_key(key) {
+            changes.push(Change::VariantAdded {
+                ty: ty.to_string(),
+                field: field.map(String::from),
+                key: key.clone(),
+            });
+        }
+    }
+
+    for (key, old_ty) in &old.variants {
+        if let Some(new_ty) = new.variants.get(key) {
+            diff_type(ty, Some(&path(key)), old_ty, new_ty, changes);
+        }
+    }
+
+    match (&old.default, &new.default) {
+        (Some(old_ty), Some(new_ty)) => {
+            diff_type(ty, Some(&path("default")), old_ty, new_ty, changes)
+        },
+        (Some(_), None) => changes.push(Change::VariantRemoved {
+            ty: ty.to_string(),
+            field: field.map(String::from),
+            key: String::from("default"),
+        }),
+        (None, Some(_)) => changes.push(Change::VariantAdded {
+            ty: ty.to_string(),
+            field: field.map(String::from),
+            key: String::from("default"),
+        }),
+        (None, None) => {},
+    }
+}
+
+fn diff_mapper(
+    ty: &str,
+    field: Option<&str>,
+    old: &Mapper,
+    new: &Mapper,
+    changes: &mut Vec<Change>,
+) {
+    let field = field.map(String::from);
+    let id_of = |mapper: &Mapper, name: &str| {
+        mapper
+            .mappings
+            .iter()
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(id, _)| *id)
+    };
+
+    for (&id, old_name) in &old.mappings {
+        let new_name = new.mappings.get(&id);
+
+        match id_of(new, old_name) {
+            Some(new_id) if new_id != id => {
+                changes.push(Change::MappingIdChanged {
+                    ty: ty.to_string(),
+                    field: field.clone(),
+                    name: old_name.clone(),
+                    old: id,
+                    new: new_id,
+                });
+            },
+            Some(_) => {},
+            None => match new_name {
+                Some(new_name) if id_of(old, new_name).is_none() => {
+                    changes.push(Change::MappingRenamed {
+                        ty: ty.to_string(),
+                        field: field.clone(),
+                        id,
+                        old: old_name.clone(),
+                        new: new_name.clone(),
+                    });
+                },
+                _ => changes.push(Change::MappingRemoved {
+                    ty: ty.to_string(),
+                    field: field.clone(),
+                    id,
+                    name: old_name.clone(),
+                }),
+            },
+        }
+    }
+
+    for (&id, new_name) in &new.mappings {
+        let old_name = old.mappings.get(&id);
+        let renamed = old_name.is_some_and(|old_name| {
+            id_of(new, old_name).is_none() && id_of(old, new_name).is_none()
+        });
+
+        if id_of(old, new_name).is_none() && !renamed {
+            changes.push(Change::MappingAdded {
+                ty: ty.to_string(),
+                field: field.clone(),
+                id,
+                name: new_name.clone(),
+            });
+        }
+    }
+}
+
+/// A short, human-readable description of a type.
+fn describe(ty: &Type) -> String {
+    match ty {
+        Type::Native => String::from("native"),
+        Type::Named(name) => name.clone(),
+        Type::Container(c) => {
+            let fields: Vec<String> = field_names(c)
+                .iter()
+                .map(|(name, ty)| format!("{}: {}", name, describe(ty)))
+                .collect();
+            format!("container {{ {} }}", fields.join(", "))
+        },
+        Type::Switch(s) => format!("switch({})", s.compare_to),
+        Type::BitFields(b) => {
+            let fields: Vec<String> = b
+                .fields
+                .iter()
+                .map(|f| {
+                    let sign = if f.signed { 'i' } else { 'u' };
+                    format!("{}: {}{}", f.name, sign, f.size)
+                })
+                .collect();
+            format!("bitfield {{ {} }}", fields.join(", "))
+        },
+        Type::LengthPrefixedString { count_type } => {
+            format!("pstring({})", describe(count_type))
+        },
+        Type::Mapper(m) => format!("mapper({})", describe(&m.ty)),
+        Type::Array(a) => {
+            format!("array({}; {})", describe(&a.ty), describe_count(&a.count))
+        },
+        Type::Buffer(b) => format!("buffer({})", describe_count(&b.count)),
+        Type::Option(inner) => format!("option({})", describe(inner)),
+    }
+}
+
+fn describe_count(count: &Count) -> String {
+    match count {
+        Count::Prefixed(ty) => describe(ty),
+        Count::Fixed(n) => n.to_string(),
+        Count::Field(field) => format!("\"{}\"", field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(doc: serde_json::Value) -> Protocol {
+        crate::syntax::parse(&doc).unwrap()
+    }
+
+    fn packets(
+        mappings: serde_json::Value,
+        types: serde_json::Value,
+    ) -> Protocol {
+        let mut namespace = types;
+        namespace["packet"] = json!([
+            "container",
+            [{
+                "name": "name",
+                "type": ["mapper", { "type": "varint", "mappings": mappings }]
+            }]
+        ]);
+
+        parse(json!({
+            "types": { "varint": "native", "string": "native", "u8": "native" },
+            "play": { "toClient": { "types": namespace } }
+        }))
+    }
+
+    #[test]
+    fn added_removed_and_renamed_packets() {
+        let old = packets(
+            json!({ "0x00": "ping", "0x01": "chat" }),
+            json!({
+                "packet_ping": ["container", [{ "name": "id", "type": "varint" }]],
+                "packet_chat": ["container", [{ "name": "message", "type": "string" }]],
+            }),
+        );
+        let new = packets(
+            json!({ "0x00": "keep_alive", "0x02": "title" }),
+            json!({
+                "packet_keep_alive": ["container", [{ "name": "id", "type": "varint" }]],
+                "packet_title": ["container", [{ "name": "text", "type": "string" }]],
+            }),
+        );
+
+        let got = diff(&old, &new);
+
+        let should_be = vec![
+            Change::TypeRenamed {
+                old: String::from("play.toClient.packet_ping"),
+                new: String::from("play.toClient.packet_keep_alive"),
+            },
+            Change::TypeRemoved {
+                name: String::from("play.toClient.packet_chat"),
+            },
+            Change::TypeAdded {
+                name: String::from("play.toClient.packet_title"),
+            },
+            Change::MappingRenamed {
+                ty: String::from("play.toClient.packet"),
+                field: Some(String::from("name")),
+                id: 0,
+                old: String::from("ping"),
+                new: String::from("keep_alive"),
+            },
+            Change::MappingRemoved {
+                ty: String::from("play.toClient.packet"),
+                field: Some(String::from("name")),
+                id: 1,
+                name: String::from("chat"),
+            },
+            Change::MappingAdded {
+                ty: String::from("play.toClient.packet"),
+                field: Some(String::from("name")),
+                id: 2,
+                name: String::from("title"),
+            },
+        ];
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn packet_ids_changed() {
+        let old = packets(json!({ "0x00": "ping", "0x01": "chat" }), json!({}));
+        let new = packets(json!({ "0x00": "ping", "0x05": "chat" }), json!({}));
+
+        let got = diff(&old, &new);
+
+        assert_eq!(
+            got,
+            vec![Change::MappingIdChanged {
+                ty: String::from("play.toClient.packet"),
+                field: Some(String::from("name")),
+                name: String::from("chat"),
+                old: 1,
+                new: 5,
+            }]
+        );
+    }
+
+    #[test]
+    fn changed_and_reordered_fields() {
+        let old = parse(json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "entity": ["container", [
+                    { "name": "id", "type": "varint" },
+                    { "name": "x", "type": "u8" },
+                    { "name": "y", "type": "u8" },
+                    { "name": "removed", "type": "u8" },
+                ]],
+            }
+        }));
+        let new = parse(json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "entity": ["container", [
+                    { "name": "id", "type": "u8" },
+                    { "name": "y", "type": "u8" },
+                    { "name": "x", "type": "u8" },
+                    { "name": "added", "type": ["option", "u8"] },
+                ]],
+            }
+        }));
+
+        let got = diff(&old, &new);
+
+        let should_be = vec![
+            Change::FieldRemoved {
+                ty: String::from("entity"),
+                field: String::from("removed"),
+            },
+            Change::FieldAdded {
+                ty: String::from("entity"),
+                field: String::from("added"),
+            },
+            Change::FieldsReordered {
+                ty: String::from("entity"),
+                field: None,
+                old: vec![
+                    String::from("id"),
+                    String::from("x"),
+                    String::from("y"),
+                ],
+                new: vec![
+                    String::from("id"),
+                    String::from("y"),
+                    String::from("x"),
+                ],
+            },
+            Change::FieldTypeChanged {
+                ty: String::from("entity"),
+                field: String::from("id"),
+                old: String::from("varint"),
+                new: String::from("u8"),
+            },
+        ];
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn nested_containers_are_compared_field_by_field() {
+        let old = parse(json!({
+            "types": {
+                "u8": "native",
+                "varint": "native",
+                "outer": ["container", [
+                    { "name": "inner", "type": ["container", [
+                        { "name": "a", "type": "u8" }
+                    ]]},
+                    { "name": "list", "type": ["array", { "countType": "varint", "type": "u8" }] },
+                ]],
+            }
+        }));
+        let new = parse(json!({
+            "types": {
+                "u8": "native",
+                "varint": "native",
+                "outer": ["container", [
+                    { "name": "inner", "type": ["container", [
+                        { "name": "a", "type": "varint" }
+                    ]]},
+                    { "name": "list", "type": ["array", { "count": 3, "type": "u8" }] },
+                ]],
+            }
+        }));
+
+        let got: Vec<String> =
+            diff(&old, &new).iter().map(|c| c.to_string()).collect();
+
+        assert_eq!(
+            got,
+            vec![
+                "~ outer.inner.a changed from u8 to varint",
+                "~ outer.list changed from array(u8; varint) to array(u8; 3)",
+            ]
+        );
+    }
+
+    #[test]
+    fn switch_cases_are_compared() {
+        let old = parse(json!({
+            "types": {
+                "u8": "native",
+                "varint": "native",
+                "packet": ["container", [
+                    { "name": "name", "type": "varint" },
+                    { "name": "params", "type": ["switch", {
+                        "compareTo": "name",
+                        "fields": { "0": "u8", "1": "u8" }
+                    }]},
+                ]],
+            }
+        }));
+        let new = parse(json!({
+            "types": {
+                "u8": "native",
+                "varint": "native",
+                "packet": ["container", [
+                    { "name": "name", "type": "varint" },
+                    { "name": "params", "type": ["switch", {
+                        "compareTo": "name",
+                        "fields": { "0": "varint", "2": "u8" }
+                    }]},
+                ]],
+            }
+        }));
+
+        let got: Vec<String> =
+            diff(&old, &new).iter().map(|c| c.to_string()).collect();
+
+        assert_eq!(
+            got,
+            vec![
+                "- packet.params case \"1\"",
+                "+ packet.params case \"2\"",
+                "~ packet.params[0] changed from u8 to varint",
+            ]
+        );
+    }
+
+    #[test]
+    fn changes_as_json() {
+        let change = Change::MappingIdChanged {
+            ty: String::from("play.toClient.packet"),
+            field: Some(String::from("name")),
+            name: String::from("chat"),
+            old: 1,
+            new: 5,
+        };
+
+        let got = change.to_json();
+
+        assert_eq!(
+            got,
+            json!({
+                "kind": "mapping_id_changed",
+                "type": "play.toClient.packet",
+                "field": "name",
+                "name": "chat",
+                "old": 1,
+                "new": 5,
+            })
+        );
+    }
+}
@@ -0,0 +1,957 @@
+use super::Interpreter;
+use crate::lowering::{
+    Array, BitFields, Buffer, Count, Enum, Mapper, Struct, Type, TypeId,
+};
+use protodef_core::{
+    native::{RestBuffer, Uuid, Varint},
+    DecodeLimits, Deserialize, DeserializeError, Value,
+};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    ops::Range,
+};
+
+pub(crate) fn decode<'a>(
+    interpreter: &Interpreter<'_>,
+    id: TypeId,
+    input: &'a [u8],
+) -> Result<(Value, &'a [u8]), DecodeError> {
+    let mut decoder = Decoder::new(interpreter, input, None);
+
+    let value = decoder.decode(id)?;
+
+    Ok((value, &input[decoder.position..]))
+}
+
+pub(crate) fn decode_with_spans<'a>(
+    interpreter: &Interpreter<'_>,
+    id: TypeId,
+    input: &'a [u8],
+) -> Result<(Value, Vec<Span>, &'a [u8]), DecodeError> {
+    let mut decoder = Decoder::new(interpreter, input, Some(Vec::new()));
+
+    let value = decoder.decode(id)?;
+    let spans = decoder.spans.unwrap_or_default();
+
+    Ok((value, spans, &input[decoder.position..]))
+}
+
+/// The bytes a particular field was decoded from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    /// The names of the fields (or array indices) leading to this field,
+    /// starting from the outermost type.
+    ///
+    /// Anonymous fields are merged into their parent, so they don't appear
+    /// in the path.
+    pub path: Vec<String>,
+    pub range: Range<usize>,
+}
+
+/// The state used while decoding a single value.
+struct Decoder<'i, 'cu, 'a> {
+    interpreter: &'i Interpreter<'cu>,
+    input: &'a [u8],
+    position: usize,
+    /// The fields decoded so far for each struct we are currently inside,
+    /// used when a `switch` or `array` refers to another field.
+    scopes: Vec<Vec<(String, Value)>>,
+    /// The path to the field currently being decoded.
+    path: Vec<String>,
+    /// Where each field was decoded from, if the caller asked for it.
+    spans: Option<Vec<Span>>,
+    limits: DecodeLimits,
+}
+
+impl<'i, 'cu, 'a> Decoder<'i, 'cu, 'a> {
+    fn new(
+        interpreter: &'i Interpreter<'cu>,
+        input: &'a [u8],
+        spans: Option<Vec<Span>>,
+    ) -> Self {
+        Decoder {
+            interpreter,
+            input,
+            position: 0,
+            scopes: Vec::new(),
+            path: Vec::new(),
+            spans,
+            limits: interpreter.limits,
+        }
+    }
+
+    fn decode(&mut self, id: TypeId) -> Result<Value, DecodeError> {
+        let ty = match self.interpreter.compilation_unit.types.get(&id) {
+            Some(ty) => ty,
+            None => {
+                return Err(self.error(DecodeErrorKind::UnknownType {
+                    name: format!("{:?}", id),
+                }))
+            },
+        };
+
+        match ty {
+            Type::Native => self.decode_native(id),
+            Type::Struct(s) => self.decode_struct(s),
+            Type::Enum(e) => self.decode_enum(e),
+            Type::LengthPrefixedString(s) => {
+                let length = self.decode_length(s.count_type)?;
+                let start = self.position;
+                let bytes = self.take(length)?;
+
+                match std::str::from_utf8(bytes) {
+                    Ok(s) => Ok(Value::from(s)),
+                    Err(e) => Err(DecodeError::new(
+                        start,
+                        DeserializeError::InvalidUtf8(e).into(),
+                    )),
+                }
+            },
+            Type::BitFields(b) => self.decode_bitfields(b),
+            Type::Mapper(m) => self.decode_mapper(m),
+            Type::Array(a) => self.decode_array(a),
+            Type::Buffer(b) => self.decode_buffer(b),
+            Type::Option(inner) => {
+                let present = self.deserialize::<bool>()?;
+
+                if present {
+                    self.decode(*inner)
+                } else {
+                    Ok(Value::Null)
+                }
+            },
+        }
+    }
+
+    /// Decode a field, recording where it came from.
+    fn decode_spanned(
+        &mut self,
+        name: impl Display,
+        id: TypeId,
+    ) -> Result<Value, DecodeError> {
+        let start = self.position;
+        self.path.push(name.to_string());
+
+        let result = self.decode(id);
+
+        if let (Ok(_), Some(spans)) = (&result, &mut self.spans) {
+            spans.push(Span {
+                path: self.path.clone(),
+                range: start..self.position,
+            });
+        }
+        self.path.pop();
+
+        result
+    }
+
+    fn decode_native(&mut self, id: TypeId) -> Result<Value, DecodeError> {
+        let name = self.interpreter.natives.get(&id).copied().unwrap_or("");
+
+        match name {
+            "i8" => self.deserialize::<i8>().map(Value::from),
+            "i16" => self.deserialize::<i16>().map(Value::from),
+            "i32" => self.deserialize::<i32>().map(Value::from),
+            "i64" => self.deserialize::<i64>().map(Value::from),
+            "u8" => self.deserialize::<u8>().map(Value::from),
+            "u16" => self.deserialize::<u16>().map(Value::from),
+            "u32" => self.deserialize::<u32>().map(Value::from),
+            "u64" => self.deserialize::<u64>().map(Value::from),
+            "f32" => self.deserialize::<f32>().map(Value::from),
+            "f64" => self.deserialize::<f64>().map(Value::from),
+            "bool" => self.deserialize::<bool>().map(Value::from),
+            "void" => Ok(Value::Null),
+            "varint" => self.deserialize::<Varint>().map(|v| Value::from(v.0)),
+            "UUID" => self
+                .deserialize::<Uuid>()
+                .map(|uuid| Value::String(format_uuid(&uuid))),
+            "restBuffer" => {
+                self.deserialize::<RestBuffer>().map(|b| Value::Bytes(b.0))
+            },
+            other => Err(self.error(DecodeErrorKind::UnsupportedNative {
+                name: other.to_string(),
+            })),
+        }
+    }
+
+    fn decode_struct(&mut self, s: &Struct) -> Result<Value, DecodeError> {
+        self.scopes.push(Vec::new());
+        let result = self.decode_fields(s);
+        let fields = self.scopes.pop().expect("pushed above");
+
+        result.map(|_| Value::Map(fields))
+    }
+
+    /// Decode a struct's fields into the current scope.
+    fn decode_fields(&mut self, s: &Struct) -> Result<(), DecodeError> {
+        for field in &s.fields {
+            let types = &self.interpreter.compilation_unit.types;
+
+            if let (true, Some(Type::Struct(inner))) =
+                (field.anonymous, types.get(&field.ty))
+            {
+                // anonymous containers share their parent's scope, so their
+                // fields can refer to the parent's fields (and vice versa)
+                self.decode_fields(inner)
+                    .map_err(|e| e.with_context(&field.name))?;
+                continue;
+            }
+
+            let value = if field.anonymous {
+                self.decode(field.ty)
+            } else {
+                self.decode_spanned(&field.name, field.ty)
+            }
+            .map_err(|e| e.with_context(&field.name))?;
+            let scope = self.scopes.last_mut().expect("inside a struct");
+
+            match value {
+                // the contents of anonymous fields are merged into their
+                // parent
+                Value::Map(fields) if field.anonymous => scope.extend(fields),
+                Value::Null if field.anonymous => {},
+                other => scope.push((field.name.clone(), other)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decode_enum(&mut self, e: &Enum) -> Result<Value, DecodeError> {
+        let key = self.lookup(&e.compare_to)?.clone();
+
+        let variant = e
+            .variants
+            .iter()
+            .find(|v| key_matches(&v.key, &key))
+            .map(|v| v.ty)
+            .or(e.default);
+
+        match variant {
+            Some(ty) => self.decode(ty),
+            // a switch without a default is treated as void
+            None => Ok(Value::Null),
+        }
+    }
+
+    fn decode_bitfields(
+        &mut self,
+        b: &BitFields,
+    ) -> Result<Value, DecodeError> {
+        if let Some(field) =
+            b.fields.iter().find(|f| !(1..=128).contains(&f.size))
+        {
+            return Err(self.error(DecodeErrorKind::UnsupportedNative {
+                name: format!("{}-bit bitfield \"{}\"", field.size, field.name),
+            }));
+        }
+
+        let total_bits: usize = b.fields.iter().map(|f| f.size).sum();
+
+        if total_bits > 128 || total_bits % 8 != 0 {
+            return Err(self.error(DecodeErrorKind::UnsupportedNative {
+                name: format!("bitfield with {} bits", total_bits),
+            }));
+        }
+
+        let bytes = self.take(total_bits / 8)?;
+        let raw = bytes
+            .iter()
+            .fold(0_u128, |acc, &byte| (acc << 8) | u128::from(byte));
+
+        let mut remaining = bytes.len() * 8;
+        let mut fields = Vec::new();
+
+        for field in &b.fields {
+            remaining -= field.size;
+            let mask = if field.size == 128 {
+                u128::MAX
+            } else {
+                (1 << field.size) - 1
+            };
+            let unsigned = (raw >> remaining) & mask;
+
+            // move the field's sign bit to the top of an i128 and back down
+            // again to sign-extend it
+            let unused = 128 - field.size;
+            let value = if field.signed {
+                ((unsigned << unused) as i128) >> unused
+            } else {
+                unsigned as i128
+            };
+
+            fields.push((field.name.clone(), Value::Integer(value)));
+        }
+
+        Ok(Value::Map(fields))
+    }
+
+    fn decode_mapper(&mut self, m: &Mapper) -> Result<Value, DecodeError> {
+        let start = self.position;
+        let value = self.decode(m.ty)?;
+        let integer = value.as_integer().ok_or_else(|| {
+            DecodeError::new(
+                start,
+                DecodeErrorKind::ExpectedInteger {
+                    found: value.kind(),
+                },
+            )
+        })?;
+
+        m.mappings
+            .iter()
+            .find(|(key, _)| i128::from(*key) == integer)
+            .map(|(_, name)| Value::from(name.as_str()))
+            .ok_or_else(|| {
+                DecodeError::new(
+                    start,
+                    DecodeErrorKind::UnknownMapping { value: integer },
+                )
+            })
+    }
+
+    fn decode_array(&mut self, a: &Array) -> Result<Value, DecodeError> {
+        let length = self.decode_count(&a.count)?;
+        self.limits
+            .array::<Value>(length)
+            .map_err(|e| self.error(e.into()))?;
+
+        // the length comes from the input, so never pre-allocate more items
+        // than there are bytes left to decode them from
+        let remaining = self.input.len().saturating_sub(self.position);
+        let mut items = Vec::with_capacity(length.min(remaining));
+
+        for i in 0..length {
+            let item = self
+                .decode_spanned(i, a.item)
+                .map_err(|e| e.with_context(i))?;
+            items.push(item);
+        }
+
+        Ok(Value::List(items))
+    }
+
+    fn decode_buffer(&mut self, b: &Buffer) -> Result<Value, DecodeError> {
+        let length = self.decode_count(&b.count)?;
+        let bytes = self.take(length)?;
+
+        Ok(Value::Bytes(bytes.to_vec()))
+    }
+
+    fn decode_count(&mut self, count: &Count) -> Result<usize, DecodeError> {
+        match count {
+            Count::Prefixed(ty) => self.decode_length(*ty),
+            Count::Fixed(n) => Ok(*n),
+            Count::Field(path) => {
+                let value = self.lookup(path)?;
+                let length = value.as_integer().ok_or_else(|| {
+                    self.error(DecodeErrorKind::ExpectedInteger {
+                        found: value.kind(),
+                    })
+                })?;
+                self.to_length(length)
+            },
+        }
+    }
+
+    /// Decode a length prefix.
+    fn decode_length(&mut self, ty: TypeId) -> Result<usize, DecodeError> {
+        let start = self.position;
+        let value = self.decode(ty)?;
+
+        match value.as_integer() {
+            Some(length) => self.to_length(length),
+            None => Err(DecodeError::new(
+                start,
+                DecodeErrorKind::ExpectedInteger {
+                    found: value.kind(),
+                },
+            )),
+        }
+    }
+
+    fn to_length(&self, length: i128) -> Result<usize, DecodeError> {
+        use std::convert::TryFrom;
+
+        usize::try_from(length).map_err(|_| {
+            self.error(DeserializeError::InvalidLength { length }.into())
+        })
+    }
+
+    /// Find a previously decoded field, where the path is relative to the
+    /// struct currently being decoded (e.g. `action`, `../action`, or
+    /// `data/kind`).
+    fn lookup(&self, path: &str) -> Result<&Value, DecodeError> {
+        let missing = || {
+            self.error(DecodeErrorKind::MissingField {
+                path: path.to_string(),
+            })
+        };
+
+        let mut segments = path.split('/').peekable();
+        let mut depth = 0;
+
+        while segments.peek() == Some(&"..") {
+            segments.next();
+            depth += 1;
+        }
+
+        let scope = self
+            .scopes
+            .len()
+            .checked_sub(depth + 1)
+            .and_then(|index| self.scopes.get(index))
+            .ok_or_else(missing)?;
+
+        let first = segments.next().ok_or_else(missing)?;
+        let mut value = scope
+            .iter()
+            .rev()
+            .find(|(name, _)| name == first)
+            .map(|(_, value)| value)
+            .ok_or_else(missing)?;
+
+        for segment in segments {
+            value = value.get(segment).ok_or_else(missing)?;
+        }
+
+        Ok(value)
+    }
+
+    fn deserialize<T>(&mut self) -> Result<T, DecodeError>
+    where
+        T: Deserialize<'a>,
+    {
+        let buffer = &self.input[self.position..];
+
+        match T::deserialize(buffer) {
+            Ok((value, rest)) => {
+                self.position += buffer.len() - rest.len();
+                Ok(value)
+            },
+            Err(e) => Err(self.error(e.into())),
+        }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], DecodeError> {
+        let (bytes, _) =
+            protodef_core::take(&self.input[self.position..], length)
+                .map_err(|e| self.error(e.into()))?;
+        self.position += length;
+
+        Ok(bytes)
+    }
+
+    fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError::new(self.position, kind)
+    }
+}
+
+/// Does a `switch` key (as written in the `protocol.json` file) match the
+/// value being compared against?
+fn key_matches(key: &str, value: &Value) -> bool {
+    match value {
+        Value::Integer(i) => key.parse::<i128>() == Ok(*i),
+        Value::String(s) => key == s,
+        Value::Bool(b) => key.parse::<bool>() == Ok(*b),
+        _ => false,
+    }
+}
+
+fn format_uuid(uuid: &Uuid) -> String {
+    let hex: String = uuid.0.iter().map(|b| format!("{:02x}", b)).collect();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// An error encountered while decoding a [`Value`].
+#[derive(Debug)]
+pub struct DecodeError {
+    /// The names of the fields being decoded when the error occurred,
+    /// starting from the outermost type.
+    pub context: Vec<String>,
+    /// Where in the input the error was encountered.
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+impl DecodeError {
+    pub(super) fn new(offset: usize, kind: DecodeErrorKind) -> Self {
+        DecodeError {
+            context: Vec::new(),
+            offset,
+            kind,
+        }
+    }
+
+    fn with_context(mut self, context: impl Display) -> Self {
+        self.context.insert(0, context.to_string());
+        self
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.context.is_empty() {
+            write!(f, "At \"{}\" ", self.context.join(" > "))?;
+        }
+
+        write!(f, "(offset {}): ", self.offset)?;
+
+        match &self.kind {
+            DecodeErrorKind::Deserialize(e) => write!(f, "{}", e),
+            DecodeErrorKind::UnknownType { name } => {
+                write!(f, "unknown type, \"{}\"", name)
+            },
+            DecodeErrorKind::UnsupportedNative { name } => {
+                write!(f, "unable to decode a \"{}\"", name)
+            },
+            DecodeErrorKind::MissingField { path } => {
+                write!(f, "unable to find the \"{}\" field", path)
+            },
+            DecodeErrorKind::ExpectedInteger { found } => {
+                write!(f, "expected an integer but found a {}", found)
+            },
+            DecodeErrorKind::UnknownMapping { value } => {
+                write!(f, "{} isn't in the mapper's mappings", value)
+            },
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            DecodeErrorKind::Deserialize(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DecodeErrorKind {
+    /// A native type couldn't be deserialized.
+    Deserialize(DeserializeError),
+    UnknownType {
+        name: String,
+    },
+    /// The interpreter doesn't know how to decode this native type.
+    UnsupportedNative {
+        name: String,
+    },
+    /// A `switch` or `array` referred to a field which doesn't exist.
+    MissingField {
+        path: String,
+    },
+    /// A length or `mapper` value wasn't an integer.
+    ExpectedInteger {
+        found: &'static str,
+    },
+    UnknownMapping {
+        value: i128,
+    },
+}
+
+impl From<DeserializeError> for DecodeErrorKind {
+    fn from(e: DeserializeError) -> Self { DecodeErrorKind::Deserialize(e) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::CompilationUnit;
+
+    fn compilation_unit(doc: serde_json::Value) -> CompilationUnit {
+        let protocol = crate::syntax::parse(&doc).unwrap();
+        crate::lowering::lower(&protocol).unwrap()
+    }
+
+    fn map(fields: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn decode_a_packet_using_a_mapper_and_switch() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "i16": "native",
+                "string": ["pstring", { "countType": "varint" }],
+            },
+            "play": {
+                "toClient": {
+                    "types": {
+                        "packet_chat": [
+                            "container",
+                            [
+                                { "name": "message", "type": "string" },
+                                { "name": "position", "type": "u8" },
+                            ]
+                        ],
+                        "packet_entity": [
+                            "container",
+                            [{ "name": "entityId", "type": "i16" }]
+                        ],
+                        "packet": [
+                            "container",
+                            [
+                                {
+                                    "name": "name",
+                                    "type": ["mapper", {
+                                        "type": "varint",
+                                        "mappings": {
+                                            "0x02": "chat",
+                                            "0x14": "entity",
+                                        }
+                                    }]
+                                },
+                                {
+                                    "name": "params",
+                                    "type": ["switch", {
+                                        "compareTo": "name",
+                                        "fields": {
+                                            "chat": "packet_chat",
+                                            "entity": "packet_entity",
+                                        }
+                                    }]
+                                },
+                            ]
+                        ]
+                    }
+                }
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+        let input = [0x02, 0x02, b'H', b'i', 0x01, 0xff];
+
+        let (got, rest) =
+            interpreter.decode("play.toClient.packet", &input).unwrap();
+
+        let should_be = map(vec![
+            ("name", Value::from("chat")),
+            (
+                "params",
+                map(vec![
+                    ("message", Value::from("Hi")),
+                    ("position", Value::from(1_u8)),
+                ]),
+            ),
+        ]);
+        assert_eq!(got, should_be);
+        assert_eq!(rest, &[0xff]);
+    }
+
+    #[test]
+    fn anonymous_fields_are_merged_into_their_parent() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "i8": "native",
+                "i16": "native",
+                "void": "native",
+                "slot": [
+                    "container",
+                    [
+                        { "name": "blockId", "type": "i16" },
+                        {
+                            "anon": true,
+                            "type": ["switch", {
+                                "compareTo": "blockId",
+                                "fields": { "-1": "void" },
+                                "default": [
+                                    "container",
+                                    [{ "name": "itemCount", "type": "i8" }]
+                                ]
+                            }]
+                        }
+                    ]
+                ]
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let (empty, _) = interpreter.decode("slot", &[0xff, 0xff]).unwrap();
+        let (full, _) =
+            interpreter.decode("slot", &[0x00, 0x01, 0x05]).unwrap();
+
+        assert_eq!(empty, map(vec![("blockId", Value::from(-1_i16))]));
+        assert_eq!(
+            full,
+            map(vec![
+                ("blockId", Value::from(1_i16)),
+                ("itemCount", Value::from(5_i8))
+            ])
+        );
+    }
+
+    #[test]
+    fn huge_arrays_of_empty_items_are_rejected() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "void": "native",
+                "voids": ["array", { "countType": "varint", "type": "void" }],
+            }
+        }));
+        let limits = DecodeLimits::new().max_array_elements(1024);
+        let interpreter = Interpreter::new(&cu).limits(limits);
+
+        let got = interpreter
+            .decode("voids", &[0xff, 0xff, 0xff, 0xff, 0x07])
+            .unwrap_err();
+
+        assert!(matches!(
+            got.kind,
+            DecodeErrorKind::Deserialize(DeserializeError::LimitExceeded {
+                limit: protodef_core::Limit::ArrayElements,
+                requested: 2147483647,
+                max: 1024,
+            })
+        ));
+    }
+
+    #[test]
+    fn huge_array_lengths_run_out_of_input() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "bytes": ["array", { "countType": "varint", "type": "u8" }],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let got = interpreter
+            .decode("bytes", &[0xff, 0xff, 0xff, 0xff, 0x07])
+            .unwrap_err();
+
+        assert!(matches!(
+            got.kind,
+            DecodeErrorKind::Deserialize(
+                DeserializeError::UnexpectedEndOfInput { .. }
+            )
+        ));
+    }
+
+    #[test]
+    fn arrays_of_empty_items() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "void": "native",
+                "voids": ["array", { "countType": "varint", "type": "void" }],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let (got, rest) = interpreter.decode("voids", &[0x03]).unwrap();
+
+        assert_eq!(got, Value::List(vec![Value::Null; 3]));
+        assert!(rest.is_empty());
+        assert_eq!(
+            interpreter
+                .encode("voids", &json!([null, null, null]))
+                .unwrap(),
+            [0x03]
+        );
+    }
+
+    #[test]
+    fn switches_can_refer_to_parent_fields() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "bool": "native",
+                "void": "native",
+                "player_info": [
+                    "container",
+                    [
+                        { "name": "action", "type": "varint" },
+                        {
+                            "name": "data",
+                            "type": ["array", {
+                                "countType": "varint",
+                                "type": ["container", [{
+                                    "name": "ping",
+                                    "type": ["switch", {
+                                        "compareTo": "../action",
+                                        "fields": { "2": "varint" },
+                                    }]
+                                }]]
+                            }]
+                        }
+                    ]
+                ]
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let (got, _) = interpreter
+            .decode("player_info", &[0x02, 0x02, 0x0a, 0x14])
+            .unwrap();
+
+        let data = got.get("data").unwrap();
+        assert_eq!(
+            data,
+            &Value::List(vec![
+                map(vec![("ping", Value::from(10))]),
+                map(vec![("ping", Value::from(20))]),
+            ])
+        );
+    }
+
+    #[test]
+    fn decode_bitfields_options_and_buffers() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "position": ["bitfield", [
+                    { "name": "x", "size": 4, "signed": true },
+                    { "name": "y", "size": 4, "signed": false },
+                ]],
+                "thing": [
+                    "container",
+                    [
+                        { "name": "position", "type": "position" },
+                        { "name": "maybe", "type": ["option", "u8"] },
+                        {
+                            "name": "data",
+                            "type": ["buffer", { "countType": "varint" }]
+                        },
+                        {
+                            "name": "pair",
+                            "type": ["array", { "count": 2, "type": "u8" }]
+                        },
+                    ]
+                ]
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+        let input = [0xf3, 0x00, 0x02, 0xca, 0xfe, 0x01, 0x02];
+
+        let (got, rest) = interpreter.decode("thing", &input).unwrap();
+
+        let should_be = map(vec![
+            (
+                "position",
+                map(vec![("x", Value::from(-1)), ("y", Value::from(3))]),
+            ),
+            ("maybe", Value::Null),
+            ("data", Value::Bytes(vec![0xca, 0xfe])),
+            (
+                "pair",
+                Value::List(vec![Value::from(1_u8), Value::from(2_u8)]),
+            ),
+        ]);
+        assert_eq!(got, should_be);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn errors_say_where_they_happened() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "nbt": "native",
+                "string": ["pstring", { "countType": "varint" }],
+                "outer": [
+                    "container",
+                    [
+                        { "name": "name", "type": "string" },
+                        { "name": "tag", "type": "nbt" },
+                    ]
+                ],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let got = interpreter.decode("outer", b"\x01a").unwrap_err();
+
+        assert_eq!(got.context, &["tag"]);
+        assert_eq!(got.offset, 2);
+        assert!(matches!(
+            got.kind,
+            DecodeErrorKind::UnsupportedNative { ref name } if name == "nbt"
+        ));
+    }
+
+    #[test]
+    fn unknown_mappings_are_an_error() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "kind": ["mapper", {
+                    "type": "varint",
+                    "mappings": { "0": "zero" }
+                }],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let got = interpreter.decode("kind", &[0x05]).unwrap_err();
+
+        assert!(matches!(
+            got.kind,
+            DecodeErrorKind::UnknownMapping { value: 5 }
+        ));
+    }
+
+    #[test]
+    fn record_where_each_field_came_from() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "u8": "native",
+                "i16": "native",
+                "entry": [
+                    "container",
+                    [
+                        { "name": "id", "type": "i16" },
+                        {
+                            "anon": true,
+                            "type": ["container", [
+                                { "name": "count", "type": "u8" }
+                            ]]
+                        },
+                        {
+                            "name": "values",
+                            "type": ["array", { "countType": "u8", "type": "u8" }]
+                        },
+                    ]
+                ]
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+        let input = [0x00, 0x01, 0x07, 0x02, 0xaa, 0xbb];
+
+        let (_, spans, _) =
+            interpreter.decode_with_spans("entry", &input).unwrap();
+
+        let got: Vec<_> = spans
+            .iter()
+            .map(|span| (span.path.join("."), span.range.clone()))
+            .collect();
+        let should_be = vec![
+            (String::from("id"), 0..2),
+            (String::from("count"), 2..3),
+            (String::from("values.0"), 4..5),
+            (String::from("values.1"), 5..6),
+            (String::from("values"), 3..6),
+        ];
+        assert_eq!(got, should_be);
+    }
+}
@@ -0,0 +1,1012 @@
+use super::Interpreter;
+use crate::lowering::{
+    Array, BitFields, Count, Enum, Mapper, Struct, Type, TypeId,
+};
+use protodef_core::{
+    native::{Uuid, Varint},
+    Serialize, SerializeError,
+};
+use serde_json::{Map, Value};
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// Used in place of a field which wasn't provided.
+static MISSING: Value = Value::Null;
+
+pub(crate) fn encode(
+    interpreter: &Interpreter<'_>,
+    id: TypeId,
+    value: &Value,
+) -> Result<Vec<u8>, EncodeError> {
+    let mut encoder = Encoder {
+        interpreter,
+        buffer: Vec::new(),
+        scopes: Vec::new(),
+    };
+
+    encoder.encode(id, value)?;
+
+    Ok(encoder.buffer)
+}
+
+/// The state used while encoding a single value.
+struct Encoder<'i, 'cu, 'j> {
+    interpreter: &'i Interpreter<'cu>,
+    buffer: Vec<u8>,
+    /// The object for each struct we are currently inside, used when a
+    /// `switch` or `array` refers to another field.
+    scopes: Vec<&'j Map<String, Value>>,
+}
+
+impl<'i, 'cu, 'j> Encoder<'i, 'cu, 'j> {
+    fn encode(
+        &mut self,
+        id: TypeId,
+        value: &'j Value,
+    ) -> Result<(), EncodeError> {
+        let ty = match self.interpreter.compilation_unit.types.get(&id) {
+            Some(ty) => ty,
+            None => {
+                return Err(EncodeError::new(EncodeErrorKind::UnknownType {
+                    name: format!("{:?}", id),
+                }))
+            },
+        };
+
+        match ty {
+            Type::Native => self.encode_native(id, value),
+            Type::Struct(s) => self.encode_struct(s, value),
+            Type::Enum(e) => self.encode_enum(e, value),
+            Type::LengthPrefixedString(s) => {
+                let string = value
+                    .as_str()
+                    .ok_or_else(|| incorrect_type("string", value))?;
+                self.encode_integer(s.count_type, string.len() as i128)?;
+                self.buffer.extend_from_slice(string.as_bytes());
+                Ok(())
+            },
+            Type::BitFields(b) => self.encode_bitfields(b, value),
+            Type::Mapper(m) => self.encode_mapper(m, value),
+            Type::Array(a) => self.encode_array(a, value),
+            Type::Buffer(b) => {
+                let bytes = bytes(value)?;
+                self.encode_count(&b.count, bytes.len())?;
+                self.buffer.extend_from_slice(&bytes);
+                Ok(())
+            },
+            Type::Option(inner) => {
+                if value.is_null() {
+                    self.buffer.push(0);
+                    Ok(())
+                } else {
+                    self.buffer.push(1);
+                    self.encode(*inner, value)
+                }
+            },
+        }
+    }
+
+    fn encode_native(
+        &mut self,
+        id: TypeId,
+        value: &Value,
+    ) -> Result<(), EncodeError> {
+        let name = self.interpreter.natives.get(&id).copied().unwrap_or("");
+
+        match name {
+            "i8" => self.serialize(&integer::<i8>(value, name)?),
+            "i16" => self.serialize(&integer::<i16>(value, name)?),
+            "i32" => self.serialize(&integer::<i32>(value, name)?),
+            "i64" => self.serialize(&integer::<i64>(value, name)?),
+            "u8" => self.serialize(&integer::<u8>(value, name)?),
+            "u16" => self.serialize(&integer::<u16>(value, name)?),
+            "u32" => self.serialize(&integer::<u32>(value, name)?),
+            "u64" => self.serialize(&integer::<u64>(value, name)?),
+            "f32" => self.serialize(&(float(value)? as f32)),
+            "f64" => self.serialize(&float(value)?),
+            "bool" => {
+                let b = value
+                    .as_bool()
+                    .ok_or_else(|| incorrect_type("bool", value))?;
+                self.serialize(&b)
+            },
+            // there's nothing to check for a void
+            "void" => Ok(()),
+            "varint" => self.serialize(&Varint(integer(value, name)?)),
+            "UUID" => self.serialize(&uuid(value)?),
+            "restBuffer" => {
+                self.buffer.extend(bytes(value)?);
+                Ok(())
+            },
+            other => {
+                Err(EncodeError::new(EncodeErrorKind::UnsupportedNative {
+                    name: other.to_string(),
+                }))
+            },
+        }
+    }
+
+    fn encode_struct(
+        &mut self,
+        s: &Struct,
+        value: &'j Value,
+    ) -> Result<(), EncodeError> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| incorrect_type("object", value))?;
+
+        self.scopes.push(object);
+        let result = self.encode_fields(s, object, value);
+        self.scopes.pop();
+
+        result
+    }
+
+    /// Encode a struct's fields from an object, which is already in scope.
+    fn encode_fields(
+        &mut self,
+        s: &Struct,
+        object: &'j Map<String, Value>,
+        value: &'j Value,
+    ) -> Result<(), EncodeError> {
+        for field in &s.fields {
+            let types = &self.interpreter.compilation_unit.types;
+
+            if let (true, Some(Type::Struct(inner))) =
+                (field.anonymous, types.get(&field.ty))
+            {
+                // anonymous containers share their parent's scope (and
+                // their fields are merged into the parent's object)
+                self.encode_fields(inner, object, value)?;
+                continue;
+            }
+
+            if field.anonymous {
+                // anonymous fields are merged into their parent
+                self.encode(field.ty, value)?;
+            } else {
+                match object.get(&field.name) {
+                    Some(value) => self
+                        .encode(field.ty, value)
+                        .map_err(|e| e.with_context(&field.name))?,
+                    None => self
+                        .encode(field.ty, &MISSING)
+                        .map_err(|e| e.in_missing_field(&field.name))?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn encode_enum(
+        &mut self,
+        e: &Enum,
+        value: &'j Value,
+    ) -> Result<(), EncodeError> {
+        let key = self.lookup(&e.compare_to)?;
+
+        let variant = e
+            .variants
+            .iter()
+            .find(|v| key_matches(&v.key, key))
+            .map(|v| v.ty)
+            .or(e.default);
+
+        match variant {
+            Some(ty) => self.encode(ty, value),
+            // a switch without a default is treated as void
+            None if value.is_null() => Ok(()),
+            None => Err(EncodeError::new(EncodeErrorKind::UnknownSwitchKey {
+                key: key.to_string(),
+            })),
+        }
+    }
+
+    fn encode_bitfields(
+        &mut self,
+        b: &BitFields,
+        value: &Value,
+    ) -> Result<(), EncodeError> {
+        if let Some(field) =
+            b.fields.iter().find(|f| !(1..=128).contains(&f.size))
+        {
+            return Err(EncodeError::new(EncodeErrorKind::UnsupportedNative {
+                name: format!("{}-bit bitfield \"{}\"", field.size, field.name),
+            }));
+        }
+
+        let total_bits: usize = b.fields.iter().map(|f| f.size).sum();
+
+        if total_bits > 128 || total_bits % 8 != 0 {
+            return Err(EncodeError::new(EncodeErrorKind::UnsupportedNative {
+                name: format!("bitfield with {} bits", total_bits),
+            }));
+        }
+
+        let object = value
+            .as_object()
+            .ok_or_else(|| incorrect_type("object", value))?;
+        let mut raw = 0_u128;
+
+        for field in &b.fields {
+            let value = match object.get(&field.name) {
+                Some(value) => bitfield(value, field)
+                    .map_err(|e| e.with_context(&field.name))?,
+                None => {
+                    return Err(EncodeError::new(
+                        EncodeErrorKind::MissingField {
+                            name: field.name.clone(),
+                        },
+                    ))
+                },
+            };
+
+            let mask = if field.size == 128 {
+                u128::MAX
+            } else {
+                (1 << field.size) - 1
+            };
+            raw = raw.checked_shl(field.size as u32).unwrap_or(0)
+                | (value as u128 & mask);
+        }
+
+        let bytes = raw.to_be_bytes();
+        self.buffer
+            .extend_from_slice(&bytes[bytes.len() - total_bits / 8..]);
+
+        Ok(())
+    }
+
+    fn encode_mapper(
+        &mut self,
+        m: &Mapper,
+        value: &Value,
+    ) -> Result<(), EncodeError> {
+        let name = value
+            .as_str()
+            .ok_or_else(|| incorrect_type("string", value))?;
+
+        let integer = m
+            .mappings
+            .iter()
+            .find(|(_, mapped)| mapped == name)
+            .map(|(integer, _)| *integer)
+            .ok_or_else(|| {
+                EncodeError::new(EncodeErrorKind::UnknownMapping {
+                    name: name.to_string(),
+                })
+            })?;
+
+        self.encode_integer(m.ty, integer.into())
+    }
+
+    fn encode_array(
+        &mut self,
+        a: &Array,
+        value: &'j Value,
+    ) -> Result<(), EncodeError> {
+        let items = value
+            .as_array()
+            .ok_or_else(|| incorrect_type("array", value))?;
+
+        self.encode_count(&a.count, items.len())?;
+
+        for (i, item) in items.iter().enumerate() {
+            self.encode(a.item, item).map_err(|e| e.with_context(i))?;
+        }
+
+        Ok(())
+    }
+
+    fn encode_count(
+        &mut self,
+        count: &Count,
+        length: usize,
+    ) -> Result<(), EncodeError> {
+        let expected = match count {
+            Count::Prefixed(ty) => {
+                return self.encode_integer(*ty, length as i128)
+            },
+            Count::Fixed(n) => *n as i128,
+            Count::Field(path) => {
+                let value = self.lookup(path)?;
+                integer::<i128>(value, "length")?
+            },
+        };
+
+        if expected == length as i128 {
+            Ok(())
+        } else {
+            Err(EncodeError::new(EncodeErrorKind::LengthMismatch {
+                expected,
+                found: length,
+            }))
+        }
+    }
+
+    /// Encode an integer (e.g. a length prefix or `mapper` value) using a
+    /// native type.
+    fn encode_integer(
+        &mut self,
+        ty: TypeId,
+        value: i128,
+    ) -> Result<(), EncodeError> {
+        let json = i64::try_from(value)
+            .map(Value::from)
+            .or_else(|_| u64::try_from(value).map(Value::from))
+            .map_err(|_| {
+                EncodeError::new(EncodeErrorKind::OutOfRange {
+                    value,
+                    ty: String::from("u64"),
+                })
+            })?;
+
+        self.encode_native(ty, &json)
+    }
+
+    /// Find a field from the object being encoded, where the path is
+    /// relative to the struct currently being encoded (e.g. `action`,
+    /// `../action`, or `data/kind`).
+    fn lookup(&self, path: &str) -> Result<&'j Value, EncodeError> {
+        let missing = || {
+            EncodeError::new(EncodeErrorKind::MissingField {
+                name: path.to_string(),
+            })
+        };
+
+        let mut segments = path.split('/').peekable();
+        let mut depth = 0;
+
+        while segments.peek() == Some(&"..") {
+            segments.next();
+            depth += 1;
+        }
+
+        let scope = self
+            .scopes
+            .len()
+            .checked_sub(depth + 1)
+            .and_then(|index| self.scopes.get(index))
+            .ok_or_else(missing)?;
+
+        let first = segments.next().ok_or_else(missing)?;
+        let mut value = scope.get(first).ok_or_else(missing)?;
+
+        for segment in segments {
+            value = value.get(segment).ok_or_else(missing)?;
+        }
+
+        Ok(value)
+    }
+
+    fn serialize<T: Serialize>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), EncodeError> {
+        value
+            .serialize(&mut self.buffer)
+            .map_err(|e| EncodeError::new(EncodeErrorKind::Serialize(e)))
+    }
+}
+
+/// Does a `switch` key (as written in the `protocol.json` file) match the
+/// value being compared against?
+fn key_matches(key: &str, value: &Value) -> bool {
+    match value {
+        Value::Number(n) => match key.parse::<i128>() {
+            Ok(key) => {
+                n.as_i64().map(i128::from) == Some(key)
+                    || n.as_u64().map(i128::from) == Some(key)
+            },
+            Err(_) => false,
+        },
+        Value::String(s) => key == s,
+        Value::Bool(b) => key.parse::<bool>() == Ok(*b),
+        _ => false,
+    }
+}
+
+fn integer<T: TryFrom<i128>>(
+    value: &Value,
+    ty: &str,
+) -> Result<T, EncodeError> {
+    let integer = value
+        .as_i64()
+        .map(i128::from)
+        .or_else(|| value.as_u64().map(i128::from))
+        .ok_or_else(|| incorrect_type("integer", value))?;
+
+    T::try_from(integer).map_err(|_| {
+        EncodeError::new(EncodeErrorKind::OutOfRange {
+            value: integer,
+            ty: ty.to_string(),
+        })
+    })
+}
+
+fn float(value: &Value) -> Result<f64, EncodeError> {
+    value
+        .as_f64()
+        .ok_or_else(|| incorrect_type("number", value))
+}
+
+fn bytes(value: &Value) -> Result<Vec<u8>, EncodeError> {
+    let items = value
+        .as_array()
+        .ok_or_else(|| incorrect_type("array of bytes", value))?;
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            integer::<u8>(item, "u8").map_err(|e| e.with_context(i))
+        })
+        .collect()
+}
+
+/// Parse a UUID, with or without hyphens.
+fn uuid(value: &Value) -> Result<Uuid, EncodeError> {
+    let invalid = || EncodeError::new(EncodeErrorKind::InvalidUuid);
+
+    let text = value
+        .as_str()
+        .ok_or_else(|| incorrect_type("string", value))?;
+    let hex: String = text.chars().filter(|c| *c != '-').collect();
+
+    if hex.len() != 32 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+
+    let mut uuid = [0; 16];
+
+    for (i, byte) in uuid.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| invalid())?;
+    }
+
+    Ok(Uuid(uuid))
+}
+
+fn bitfield(
+    value: &Value,
+    field: &crate::syntax::BitField,
+) -> Result<i128, EncodeError> {
+    let integer = integer::<i128>(value, &field.name)?;
+    let size = field.size as u32;
+    // fields are between 1 and 128 bits, so shift the extremes of an i128
+    // or u128 down to the field's size
+    let unused = 128 - size;
+
+    let (min, max) = if field.signed {
+        (i128::MIN >> unused, i128::MAX >> unused)
+    } else {
+        let max = u128::MAX >> unused;
+        (0, max.min(i128::MAX as u128) as i128)
+    };
+
+    if min <= integer && integer <= max {
+        Ok(integer)
+    } else {
+        let sign = if field.signed { "i" } else { "u" };
+
+        Err(EncodeError::new(EncodeErrorKind::OutOfRange {
+            value: integer,
+            ty: format!("{}{}", sign, size),
+        }))
+    }
+}
+
+fn incorrect_type(expected: &'static str, found: &Value) -> EncodeError {
+    let found = match found {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+
+    EncodeError::new(EncodeErrorKind::IncorrectType { expected, found })
+}
+
+/// An error encountered while encoding a JSON value.
+#[derive(Debug)]
+pub struct EncodeError {
+    /// The names of the fields (or array indices) being encoded when the
+    /// error occurred, starting from the outermost type.
+    pub context: Vec<String>,
+    pub kind: EncodeErrorKind,
+}
+
+impl EncodeError {
+    pub(super) fn new(kind: EncodeErrorKind) -> Self {
+        EncodeError {
+            context: Vec::new(),
+            kind,
+        }
+    }
+
+    fn with_context(mut self, context: impl Display) -> Self {
+        self.context.insert(0, context.to_string());
+        self
+    }
+
+    /// The location of the offending value as a JSON path (e.g.
+    /// `$.params.items[2]`).
+    ///
+    /// Any context which is made entirely of digits is assumed to be an
+    /// array index.
+    pub fn json_path(&self) -> String {
+        let mut path = String::from("$");
+
+        for segment in &self.context {
+            if !segment.is_empty()
+                && segment.chars().all(|c| c.is_ascii_digit())
+            {
+                path.push('[');
+                path.push_str(segment);
+                path.push(']');
+            } else {
+                path.push('.');
+                path.push_str(segment);
+            }
+        }
+
+        path
+    }
+
+    /// Add context to an error encountered while encoding a field that
+    /// wasn't provided, turning it into a [`EncodeErrorKind::MissingField`]
+    /// if the field was actually needed.
+    fn in_missing_field(self, name: &str) -> Self {
+        match self.kind {
+            EncodeErrorKind::IncorrectType { found: "null", .. }
+                if self.context.is_empty() =>
+            {
+                EncodeError::new(EncodeErrorKind::MissingField {
+                    name: name.to_string(),
+                })
+            },
+            _ => self.with_context(name),
+        }
+    }
+}
+
+impl Display for EncodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.context.is_empty() {
+            write!(f, "At \"{}\" ", self.context.join(" > "))?;
+        }
+
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Display for EncodeErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeErrorKind::Serialize(e) => write!(f, "{}", e),
+            EncodeErrorKind::UnknownType { name } => {
+                write!(f, "unknown type, \"{}\"", name)
+            },
+            EncodeErrorKind::UnsupportedNative { name } => {
+                write!(f, "unable to encode a \"{}\"", name)
+            },
+            EncodeErrorKind::MissingField { name } => {
+                write!(f, "missing the \"{}\" field", name)
+            },
+            EncodeErrorKind::IncorrectType { expected, found } => write!(
+                f,
+                "incorrect type, expected {} but found {}",
+                expected, found
+            ),
+            EncodeErrorKind::OutOfRange { value, ty } => {
+                write!(f, "{} is out of range for a {}", value, ty)
+            },
+            EncodeErrorKind::UnknownSwitchKey { key } => {
+                write!(f, "no switch variant matches {}", key)
+            },
+            EncodeErrorKind::UnknownMapping { name } => {
+                write!(f, "\"{}\" isn't in the mapper's mappings", name)
+            },
+            EncodeErrorKind::LengthMismatch { expected, found } => {
+                write!(f, "expected {} items but found {}", expected, found)
+            },
+            EncodeErrorKind::InvalidUuid => write!(f, "invalid UUID"),
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            EncodeErrorKind::Serialize(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum EncodeErrorKind {
+    /// A native type couldn't be serialized.
+    Serialize(SerializeError),
+    UnknownType {
+        name: String,
+    },
+    /// The interpreter doesn't know how to encode this native type.
+    UnsupportedNative {
+        name: String,
+    },
+    /// A field (or the field a `switch` or `array` refers to) wasn't
+    /// provided.
+    MissingField {
+        name: String,
+    },
+    IncorrectType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// An integer doesn't fit in the type it is encoded as.
+    OutOfRange {
+        value: i128,
+        ty: String,
+    },
+    /// None of a switch's variants match the value it is compared to.
+    UnknownSwitchKey {
+        key: String,
+    },
+    UnknownMapping {
+        name: String,
+    },
+    /// An array or buffer has a different number of items than the field
+    /// containing its length (or its fixed `count`).
+    LengthMismatch {
+        expected: i128,
+        found: usize,
+    },
+    InvalidUuid,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::CompilationUnit;
+
+    fn compilation_unit(doc: Value) -> CompilationUnit {
+        let protocol = crate::syntax::parse(&doc).unwrap();
+        crate::lowering::lower(&protocol).unwrap()
+    }
+
+    fn packet_protocol() -> CompilationUnit {
+        compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "i8": "native",
+                "i16": "native",
+                "void": "native",
+                "string": ["pstring", { "countType": "varint" }],
+                "packet_chat": [
+                    "container",
+                    [
+                        { "name": "message", "type": "string" },
+                        { "name": "position", "type": "i8" },
+                    ]
+                ],
+                "packet": [
+                    "container",
+                    [
+                        {
+                            "name": "name",
+                            "type": ["mapper", {
+                                "type": "varint",
+                                "mappings": { "0x02": "chat", "0x03": "ping" }
+                            }]
+                        },
+                        {
+                            "name": "params",
+                            "type": ["switch", {
+                                "compareTo": "name",
+                                "fields": { "chat": "packet_chat" }
+                            }]
+                        },
+                    ]
+                ],
+            }
+        }))
+    }
+
+    #[test]
+    fn round_trip_a_packet() {
+        let cu = packet_protocol();
+        let interpreter = Interpreter::new(&cu);
+        let packet = json!({
+            "name": "chat",
+            "params": { "message": "Hi", "position": -1 }
+        });
+
+        let got = interpreter.encode("packet", &packet).unwrap();
+
+        assert_eq!(got, &[0x02, 0x02, b'H', b'i', 0xff]);
+        let (decoded, _) = interpreter.decode("packet", &got).unwrap();
+        assert_eq!(
+            decoded.to_string(),
+            r#"{ name: "chat", params: { message: "Hi", position: -1 } }"#
+        );
+    }
+
+    #[test]
+    fn anonymous_containers_share_their_parents_scope() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "i8": "native",
+                "i16": "native",
+                "void": "native",
+                "slot": [
+                    "container",
+                    [
+                        { "name": "blockId", "type": "i16" },
+                        {
+                            "anon": true,
+                            "type": ["container", [
+                                {
+                                    "name": "itemCount",
+                                    "type": ["switch", {
+                                        "compareTo": "blockId",
+                                        "fields": { "-1": "void" },
+                                        "default": "i8"
+                                    }]
+                                },
+                            ]]
+                        },
+                    ]
+                ],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+        let slot = json!({ "blockId": 1, "itemCount": 5 });
+
+        let got = interpreter.encode("slot", &slot).unwrap();
+
+        assert_eq!(got, &[0, 1, 5]);
+        let (decoded, rest) = interpreter.decode("slot", &got).unwrap();
+        assert_eq!(decoded.to_string(), "{ blockId: 1, itemCount: 5 }");
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn missing_fields() {
+        let cu = packet_protocol();
+        let interpreter = Interpreter::new(&cu);
+        let packet = json!({
+            "name": "chat",
+            "params": { "message": "Hi" }
+        });
+
+        let got = interpreter.encode("packet", &packet).unwrap_err();
+
+        assert_eq!(got.context, &["params"]);
+        assert!(matches!(
+            got.kind,
+            EncodeErrorKind::MissingField { ref name } if name == "position"
+        ));
+        assert_eq!(
+            got.to_string(),
+            "At \"params\" missing the \"position\" field"
+        );
+    }
+
+    #[test]
+    fn integers_must_be_in_range() {
+        let cu = packet_protocol();
+        let interpreter = Interpreter::new(&cu);
+        let packet = json!({
+            "name": "chat",
+            "params": { "message": "Hi", "position": 300 }
+        });
+
+        let got = interpreter.encode("packet", &packet).unwrap_err();
+
+        assert_eq!(got.context, &["params", "position"]);
+        assert!(matches!(
+            got.kind,
+            EncodeErrorKind::OutOfRange { value: 300, ref ty } if ty == "i8"
+        ));
+    }
+
+    #[test]
+    fn unknown_switch_keys_and_mappings() {
+        let cu = packet_protocol();
+        let interpreter = Interpreter::new(&cu);
+
+        // "ping" is a valid mapping, but there's no switch variant for it
+        let unknown_key = json!({ "name": "ping", "params": { "x": 1 } });
+        let got = interpreter.encode("packet", &unknown_key).unwrap_err();
+        assert!(matches!(
+            got.kind,
+            EncodeErrorKind::UnknownSwitchKey { ref key } if key == "\"ping\""
+        ));
+
+        // ... although it's fine when the switch is treated as a void
+        let void = json!({ "name": "ping" });
+        assert_eq!(interpreter.encode("packet", &void).unwrap(), &[0x03]);
+
+        let unknown_mapping = json!({ "name": "pong" });
+        let got = interpreter.encode("packet", &unknown_mapping).unwrap_err();
+        assert_eq!(got.context, &["name"]);
+        assert!(matches!(
+            got.kind,
+            EncodeErrorKind::UnknownMapping { ref name } if name == "pong"
+        ));
+    }
+
+    #[test]
+    fn encode_bitfields_arrays_and_options() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "varint": "native",
+                "u8": "native",
+                "UUID": "native",
+                "thing": [
+                    "container",
+                    [
+                        {
+                            "anon": true,
+                            "type": ["bitfield", [
+                                { "name": "x", "size": 4, "signed": true },
+                                { "name": "y", "size": 4, "signed": false },
+                            ]]
+                        },
+                        { "name": "maybe", "type": ["option", "u8"] },
+                        {
+                            "name": "data",
+                            "type": ["buffer", { "countType": "varint" }]
+                        },
+                        {
+                            "name": "pair",
+                            "type": ["array", { "count": 2, "type": "u8" }]
+                        },
+                        { "name": "id", "type": "UUID" },
+                    ]
+                ]
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+        let thing = json!({
+            "x": -1,
+            "y": 3,
+            "maybe": 7,
+            "data": [202, 254],
+            "pair": [1, 2],
+            "id": "00112233-4455-6677-8899-aabbccddeeff",
+        });
+
+        let got = interpreter.encode("thing", &thing).unwrap();
+
+        let mut should_be =
+            vec![0xf3, 0x01, 0x07, 0x02, 0xca, 0xfe, 0x01, 0x02];
+        should_be.extend((0..16).map(|i| i * 0x11));
+        assert_eq!(got, should_be);
+    }
+
+    #[test]
+    fn fixed_length_arrays_are_checked() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "u8": "native",
+                "pair": ["array", { "count": 2, "type": "u8" }],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let got = interpreter.encode("pair", &json!([1, 2, 3])).unwrap_err();
+
+        assert!(matches!(
+            got.kind,
+            EncodeErrorKind::LengthMismatch {
+                expected: 2,
+                found: 3
+            }
+        ));
+    }
+
+    #[test]
+    fn bitfield_values_must_fit() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "position": ["bitfield", [
+                    { "name": "x", "size": 4, "signed": true },
+                    { "name": "y", "size": 4, "signed": false },
+                ]],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let got = interpreter
+            .encode("position", &json!({ "x": 8, "y": 0 }))
+            .unwrap_err();
+
+        assert_eq!(got.context, &["x"]);
+        assert!(matches!(
+            got.kind,
+            EncodeErrorKind::OutOfRange { value: 8, ref ty } if ty == "i4"
+        ));
+    }
+
+    #[test]
+    fn zero_width_bitfields_are_unsupported() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "flags": ["bitfield", [
+                    { "name": "empty", "size": 0, "signed": true },
+                    { "name": "x", "size": 8, "signed": true },
+                ]],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let encoded = interpreter
+            .encode("flags", &json!({ "empty": 0, "x": 1 }))
+            .unwrap_err();
+        let decoded = interpreter.decode("flags", &[0x01]).unwrap_err();
+
+        assert!(matches!(
+            encoded.kind,
+            EncodeErrorKind::UnsupportedNative { .. }
+        ));
+        assert!(matches!(
+            decoded.kind,
+            crate::interpreter::DecodeErrorKind::UnsupportedNative { .. }
+        ));
+    }
+
+    #[test]
+    fn round_trip_128_bit_bitfields() {
+        let cu = compilation_unit(json!({
+            "types": {
+                "signed": ["bitfield", [
+                    { "name": "x", "size": 128, "signed": true },
+                ]],
+                "unsigned": ["bitfield", [
+                    { "name": "x", "size": 128, "signed": false },
+                ]],
+            }
+        }));
+        let interpreter = Interpreter::new(&cu);
+
+        let values =
+            [("signed", json!(i64::MIN)), ("unsigned", json!(u64::MAX))];
+
+        for (name, x) in &values {
+            let got = interpreter.encode(name, &json!({ "x": x })).unwrap();
+
+            let x = integer::<i128>(x, "x").unwrap();
+            assert_eq!(got, x.to_be_bytes());
+            let (decoded, _) = interpreter.decode(name, &got).unwrap();
+            assert_eq!(
+                decoded.get("x"),
+                Some(&protodef_core::Value::Integer(x))
+            );
+        }
+    }
+
+    #[test]
+    fn errors_know_their_json_path() {
+        let error = EncodeError::new(EncodeErrorKind::InvalidUuid)
+            .with_context("uuid")
+            .with_context(3)
+            .with_context("players")
+            .with_context("params");
+
+        assert_eq!(error.json_path(), "$.params.players[3].uuid");
+        assert_eq!(
+            EncodeError::new(EncodeErrorKind::InvalidUuid).json_path(),
+            "$"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Decode and encode packets at runtime by walking a [`CompilationUnit`],
+//! without generating any code.
+//!
+//! This is useful for inspecting packets from protocol versions you haven't
+//! generated code for, or for turning hand-written JSON into bytes.
+//!
+//! ```rust
+//! use protodef_codegen::interpreter::Interpreter;
+//! use protodef_core::Value;
+//!
+//! let document = serde_json::json!({
+//!   "types": {
+//!     "varint": "native",
+//!     "string": ["pstring", { "countType": "varint" }],
+//!     "chat": ["container", [{ "name": "message", "type": "string" }]]
+//!   }
+//! });
+//! let protocol = protodef_codegen::syntax::parse(&document)?;
+//! let compilation_unit = protodef_codegen::lowering::lower(&protocol)?;
+//!
+//! let interpreter = Interpreter::new(&compilation_unit);
+//! let (value, rest) = interpreter.decode("chat", b"\x02Hi")?;
+//!
+//! assert_eq!(value.get("message"), Some(&Value::from("Hi")));
+//! assert!(rest.is_empty());
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+mod decode;
+mod encode;
+
+pub use decode::{DecodeError, DecodeErrorKind, Span};
+pub use encode::{EncodeError, EncodeErrorKind};
+
+use crate::lowering::{CompilationUnit, Type, TypeId};
+use protodef_core::{DecodeLimits, Value};
+use std::collections::HashMap;
+
+/// Converts between bytes and dynamically typed values using the types from
+/// a [`CompilationUnit`].
+#[derive(Debug, Clone)]
+pub struct Interpreter<'cu> {
+    compilation_unit: &'cu CompilationUnit,
+    /// The name each native type was declared with (e.g. `varint`).
+    natives: HashMap<TypeId, &'cu str>,
+    limits: DecodeLimits,
+}
+
+impl<'cu> Interpreter<'cu> {
+    pub fn new(compilation_unit: &'cu CompilationUnit) -> Self {
+        let natives = compilation_unit
+            .named_types
+            .iter()
+            .filter(|(_, id)| {
+                matches!(compilation_unit.types.get(*id), Some(Type::Native))
+            })
+            .map(|(name, id)| {
+                let unqualified = name.rsplit('.').next().unwrap_or(name);
+                (*id, unqualified)
+            })
+            .collect();
+
+        Interpreter {
+            compilation_unit,
+            natives,
+            limits: DecodeLimits::new(),
+        }
+    }
+
+    /// The limits used when decoding each value (only array lengths are
+    /// checked).
+    pub fn limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Decode a value using the type with this fully qualified name (e.g.
+    /// `play.toClient.packet`), returning the decoded value and any bytes
+    /// which weren't used.
+    pub fn decode<'a>(
+        &self,
+        type_name: &str,
+        input: &'a [u8],
+    ) -> Result<(Value, &'a [u8]), DecodeError> {
+        match self.compilation_unit.named_types.get(type_name) {
+            Some(&id) => self.decode_type(id, input),
+            None => Err(DecodeError::new(
+                0,
+                DecodeErrorKind::UnknownType {
+                    name: type_name.to_string(),
+                },
+            )),
+        }
+    }
+
+    /// Decode a value like [`Interpreter::decode()`], also recording which
+    /// bytes each field was decoded from.
+    pub fn decode_with_spans<'a>(
+        &self,
+        type_name: &str,
+        input: &'a [u8],
+    ) -> Result<(Value, Vec<Span>, &'a [u8]), DecodeError> {
+        match self.compilation_unit.named_types.get(type_name) {
+            Some(&id) => decode::decode_with_spans(self, id, input),
+            None => Err(DecodeError::new(
+                0,
+                DecodeErrorKind::UnknownType {
+                    name: type_name.to_string(),
+                },
+            )),
+        }
+    }
+
+    /// Decode a value using a particular type.
+    pub fn decode_type<'a>(
+        &self,
+        id: TypeId,
+        input: &'a [u8],
+    ) -> Result<(Value, &'a [u8]), DecodeError> {
+        decode::decode(self, id, input)
+    }
+
+    /// Encode a JSON value using the type with this fully qualified name
+    /// (e.g. `play.toServer.packet`), checking that the value matches the
+    /// type.
+    ///
+    /// Byte buffers are written as arrays of integers, and anonymous fields
+    /// are expected to be merged into their parent object (i.e. the same
+    /// shape [`Interpreter::decode()`] produces).
+    pub fn encode(
+        &self,
+        type_name: &str,
+        value: &serde_json::Value,
+    ) -> Result<Vec<u8>, EncodeError> {
+        match self.compilation_unit.named_types.get(type_name) {
+            Some(&id) => self.encode_type(id, value),
+            None => Err(EncodeError::new(EncodeErrorKind::UnknownType {
+                name: type_name.to_string(),
+            })),
+        }
+    }
+
+    /// Encode a JSON value using a particular type.
+    pub fn encode_type(
+        &self,
+        id: TypeId,
+        value: &serde_json::Value,
+    ) -> Result<Vec<u8>, EncodeError> {
+        encode::encode(self, id, value)
+    }
+}
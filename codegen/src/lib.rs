@@ -9,11 +9,18 @@
 //! let json = serde_json::from_str(src).unwrap();
 //! let parsed = protodef_codegen::syntax::parse(&json).unwrap();
 //! let analysed = protodef_codegen::lowering::lower(&parsed).unwrap();
-//! let rust_code = protodef_codegen::backend::generate_rust(&analysed);
+//! let rust_code = protodef_codegen::backend::generate_rust(&analysed).unwrap();
 //!
 //! println!("{}", rust_code);
 //! ```
 //!
+//! Inside a build script you will probably want to use the [`Builder`]
+//! instead.
+//!
+//! The [`interpreter`] can decode packets at runtime without generating any
+//! code at all, and [`diff`] shows what changed between two versions of a
+//! protocol.
+//!
 //! [proto]: https://github.com/ProtoDef-io/ProtoDef
 
 #[cfg(test)]
@@ -24,5 +31,11 @@ extern crate serde_json;
 extern crate pretty_assertions;
 
 pub mod backend;
+mod builder;
+pub mod diff;
+pub mod interpreter;
 pub mod lowering;
+pub mod report;
 pub mod syntax;
+
+pub use builder::{BuildError, Builder};
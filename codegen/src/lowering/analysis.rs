@@ -1,23 +1,41 @@
 use crate::{
     lowering::{
-        BitFields, CompilationUnit, Diagnostic, Diagnostics, Field,
-        LengthPrefixedString, Struct, Type, TypeId,
+        Array, BitFields, Buffer, CompilationUnit, Count, Diagnostic,
+        Diagnostics, Enum, Field, LengthPrefixedString, Mapper, Struct, Type,
+        TypeId, Variant,
     },
     syntax,
 };
 use indexmap::IndexMap;
+use std::collections::HashMap;
 
 /// Analyse the `protocol.json` file's AST and convert it to the corresponding
 /// Rust types.
 pub fn lower(
     protocol: &crate::syntax::Protocol,
+) -> Result<CompilationUnit, Diagnostics> {
+    lower_with_options(protocol, &LoweringOptions::default())
+}
+
+/// Options for tweaking how a `protocol.json` file is lowered.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LoweringOptions {
+    /// Give structurally identical anonymous types (e.g. the inline
+    /// `{x: i32, y: i32, z: i32}` containers used throughout a protocol) a
+    /// single shared [`TypeId`].
+    pub deduplicate_types: bool,
+}
+
+/// Analyse the `protocol.json` file's AST and convert it to the corresponding
+/// Rust types, using the provided [`LoweringOptions`].
+pub fn lower_with_options(
+    protocol: &crate::syntax::Protocol,
+    options: &LoweringOptions,
 ) -> Result<CompilationUnit, Diagnostics> {
     let mut analyser = Analyser::new();
+    analyser.deduplicate_types = options.deduplicate_types;
 
-    for (name, ty) in &protocol.types {
-        let type_id = analyser.visit_type(ty);
-        analyser.register_name(name, type_id);
-    }
+    analyser.visit_protocol(protocol);
 
     analyser.finalise()
 }
@@ -26,8 +44,20 @@ pub fn lower(
 struct Analyser {
     types: IndexMap<TypeId, Type>,
     named_types: IndexMap<String, TypeId>,
+    name_hints: IndexMap<TypeId, String>,
     last_id: TypeId,
     diagnostics: Diagnostics,
+    /// The namespace currently being visited (e.g. `["play", "toClient"]`).
+    namespace: Vec<String>,
+    /// The path to the item currently being visited (e.g. the type name
+    /// followed by a field name), used to give anonymous types a name.
+    context: Vec<String>,
+    /// How deeply nested the type being visited is, where `1` is a type
+    /// which is given a name at the top level.
+    depth: usize,
+    deduplicate_types: bool,
+    /// Anonymous types we've already seen, used when deduplicating.
+    interned: HashMap<Type, TypeId>,
 }
 
 impl Analyser {
@@ -35,38 +65,103 @@ impl Analyser {
         Analyser {
             types: IndexMap::new(),
             named_types: IndexMap::new(),
+            name_hints: IndexMap::new(),
             last_id: TypeId::ERROR,
             diagnostics: Diagnostics::default(),
+            namespace: Vec::new(),
+            context: Vec::new(),
+            depth: 0,
+            deduplicate_types: false,
+            interned: HashMap::new(),
         }
     }
 
     fn add_type(&mut self, ty: Type) -> TypeId {
+        let intern = self.deduplicate_types
+            && self.is_anonymous()
+            && !matches!(ty, Type::Native);
+
+        if intern {
+            if let Some(&existing) = self.interned.get(&ty) {
+                return existing;
+            }
+        }
+
         let id = self.last_id.next();
         self.last_id = id;
 
+        if intern {
+            self.interned.insert(ty.clone(), id);
+        }
         self.types.insert(id, ty);
 
+        let hint = if self.context.is_empty() {
+            String::from("anonymous")
+        } else {
+            self.context.join("_")
+        };
+        let hint = self.qualified_name(&hint);
+        self.name_hints.insert(id, hint);
+
         id
     }
 
-    fn register_name(&mut self, name: impl Into<String>, type_id: TypeId) {
-        self.named_types.insert(name.into(), type_id);
+    /// Are we inside another type (i.e. the type being visited won't be
+    /// given a name)?
+    fn is_anonymous(&self) -> bool { self.depth > 1 }
+
+    fn in_context<T>(
+        &mut self,
+        name: impl Into<String>,
+        visit: impl FnOnce(&mut Self) -> T,
+    ) -> T {
+        self.context.push(name.into());
+        let ret = visit(self);
+        self.context.pop();
+        ret
+    }
+
+    /// Get the fully qualified name for something in the current namespace
+    /// (e.g. `play.toClient.packet`).
+    fn qualified_name(&self, name: &str) -> String {
+        self.namespace
+            .iter()
+            .map(String::as_str)
+            .chain(std::iter::once(name))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    fn register_name(&mut self, name: &str, type_id: TypeId) {
+        let name = self.qualified_name(name);
+        self.named_types.insert(name, type_id);
     }
 
+    /// Look up a type by name, starting in the current namespace and working
+    /// outwards towards the root.
     fn lookup_by_name(&self, name: &str) -> Option<TypeId> {
-        self.named_types.get(name).copied()
+        (0..=self.namespace.len()).rev().find_map(|depth| {
+            let mut path = self.namespace[..depth].to_vec();
+            path.push(name.to_string());
+            self.named_types.get(&path.join(".")).copied()
+        })
     }
 
     fn finalise(self) -> Result<CompilationUnit, Diagnostics> {
         let Analyser {
             types,
             named_types,
+            name_hints,
             diagnostics,
             ..
         } = self;
 
         if diagnostics.all_diagnostics().is_empty() {
-            Ok(CompilationUnit { types, named_types })
+            Ok(CompilationUnit {
+                types,
+                named_types,
+                name_hints,
+            })
         } else {
             Err(diagnostics)
         }
@@ -74,14 +169,40 @@ impl Analyser {
 }
 
 impl Analyser {
+    fn visit_protocol(&mut self, protocol: &syntax::Protocol) {
+        for (name, ty) in &protocol.types {
+            let type_id = self.in_context(name, |a| a.visit_type(ty));
+            self.register_name(name, type_id);
+        }
+
+        for (name, namespace) in &protocol.namespaces {
+            self.namespace.push(name.clone());
+            self.visit_protocol(namespace);
+            self.namespace.pop();
+        }
+    }
+
     fn visit_type(&mut self, ty: &syntax::Type) -> TypeId {
+        self.depth += 1;
+        let id = self.visit_type_inner(ty);
+        self.depth -= 1;
+        id
+    }
+
+    fn visit_type_inner(&mut self, ty: &syntax::Type) -> TypeId {
         match ty {
             syntax::Type::Native => self.add_type(Type::Native),
             syntax::Type::Named(name) => match self.lookup_by_name(name) {
                 Some(id) => id,
                 None => {
-                    self.diagnostics
-                        .push(Diagnostic::MissingName { name: name.clone() });
+                    let used_by = self
+                        .context
+                        .first()
+                        .map(|parent| self.qualified_name(parent));
+                    self.diagnostics.push(Diagnostic::MissingName {
+                        name: name.clone(),
+                        used_by,
+                    });
                     TypeId::ERROR
                 },
             },
@@ -95,33 +216,66 @@ impl Analyser {
                 ))
             },
             syntax::Type::Mapper(m) => self.visit_mapper(m),
+            syntax::Type::Array(a) => self.visit_array(a),
+            syntax::Type::Buffer(b) => {
+                let count = self.visit_count(&b.count);
+                self.add_type(Type::Buffer(Buffer { count }))
+            },
+            syntax::Type::Option(inner) => {
+                let inner = self.visit_type(inner);
+                self.add_type(Type::Option(inner))
+            },
         }
     }
 
     fn visit_container(&mut self, container: &syntax::Container) -> TypeId {
         let mut fields = Vec::new();
+        let mut anonymous_fields = 0;
 
         for field in &container.fields {
-            match &field.ty {
-                syntax::Type::Switch(_) => todo!("Handle switch fields"),
-                other => {
-                    let ty = self.visit_type(other);
-
-                    match &field.name {
-                        Some(name) => fields.push(Field {
-                            name: name.clone(),
-                            ty,
-                        }),
-                        None => todo!("Handle anonymous non-switch structs by flattening them"),
-                    }
+            let (name, anonymous) = match &field.name {
+                Some(name) => (name.clone(), false),
+                None => {
+                    anonymous_fields += 1;
+                    let name = match anonymous_fields {
+                        1 => String::from("anon"),
+                        n => format!("anon{}", n),
+                    };
+                    (name, true)
                 },
-            }
+            };
+
+            let ty = self.in_context(&name, |a| a.visit_type(&field.ty));
+            fields.push(Field {
+                name,
+                ty,
+                anonymous,
+            });
         }
 
         self.add_type(Type::Struct(Struct { fields }))
     }
 
-    fn visit_switch(&mut self, _switch: &syntax::Switch) -> TypeId { todo!() }
+    fn visit_switch(&mut self, switch: &syntax::Switch) -> TypeId {
+        let variants = switch
+            .variants
+            .iter()
+            .map(|(key, ty)| Variant {
+                key: key.clone(),
+                ty: self.in_context(key, |a| a.visit_type(ty)),
+            })
+            .collect();
+        let default = switch
+            .default
+            .as_ref()
+            .map(|ty| self.in_context("default", |a| a.visit_type(ty)));
+
+        self.add_type(Type::Enum(Enum {
+            compare_to: switch.compare_to.clone(),
+            variants,
+            default,
+        }))
+    }
 
     fn visit_bitfields(&mut self, bitfields: &syntax::BitFields) -> TypeId {
         self.add_type(Type::BitFields(BitFields {
@@ -129,7 +283,31 @@ impl Analyser {
         }))
     }
 
-    fn visit_mapper(&mut self, _mapper: &syntax::Mapper) -> TypeId { todo!() }
+    fn visit_mapper(&mut self, mapper: &syntax::Mapper) -> TypeId {
+        let ty = self.visit_type(&mapper.ty);
+        let mappings = mapper
+            .mappings
+            .iter()
+            .map(|(value, name)| (*value, name.clone()))
+            .collect();
+
+        self.add_type(Type::Mapper(Mapper { ty, mappings }))
+    }
+
+    fn visit_array(&mut self, array: &syntax::Array) -> TypeId {
+        let count = self.visit_count(&array.count);
+        let item = self.visit_type(&array.ty);
+
+        self.add_type(Type::Array(Array { count, item }))
+    }
+
+    fn visit_count(&mut self, count: &syntax::Count) -> Count {
+        match count {
+            syntax::Count::Prefixed(ty) => Count::Prefixed(self.visit_type(ty)),
+            syntax::Count::Fixed(n) => Count::Fixed(*n),
+            syntax::Count::Field(name) => Count::Field(name.clone()),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -159,10 +337,12 @@ mod tests {
                 Field {
                     name: "first".into(),
                     ty: int,
+                    anonymous: false,
                 },
                 Field {
                     name: "second".into(),
                     ty: int,
+                    anonymous: false,
                 },
             ],
         });
@@ -172,4 +352,263 @@ mod tests {
         assert!(!got.is_error());
         assert_eq!(analyser.types[&got], should_be);
     }
+
+    #[test]
+    fn anonymous_types_are_named_after_their_context() {
+        let doc = json!({
+            "types": {
+                "i16": "native",
+                "slot": [
+                    "container",
+                    [{
+                        "name": "data",
+                        "type": [
+                            "container",
+                            [{ "name": "itemDamage", "type": "i16" }]
+                        ]
+                    }]
+                ],
+            }
+        });
+        let protocol = syntax::parse(&doc).unwrap();
+
+        let got = lower(&protocol).unwrap();
+
+        let data = field_types(&got, "slot")[0];
+        assert_eq!(got.name_hints[&data], "slot_data");
+        assert_eq!(got.name_hints[&got.named_types["slot"]], "slot");
+    }
+
+    #[test]
+    fn namespaced_types_can_use_types_from_parent_namespaces() {
+        let doc = json!({
+            "types": {
+                "i32": "native",
+                "packet": ["container", [{ "name": "x", "type": "i32" }]],
+            },
+            "play": {
+                "toClient": {
+                    "types": {
+                        "packet": [
+                            "container",
+                            [{ "name": "y", "type": "i32" }]
+                        ],
+                        "wrapper": [
+                            "container",
+                            [{ "name": "inner", "type": "packet" }]
+                        ],
+                    }
+                }
+            }
+        });
+        let protocol = syntax::parse(&doc).unwrap();
+
+        let got = lower(&protocol).unwrap();
+
+        let names: Vec<_> = got.named_types.keys().collect();
+        assert_eq!(
+            names,
+            &[
+                "i32",
+                "packet",
+                "play.toClient.packet",
+                "play.toClient.wrapper"
+            ]
+        );
+        let inner = field_types(&got, "play.toClient.wrapper")[0];
+        assert_eq!(inner, got.named_types["play.toClient.packet"]);
+        let y = field_types(&got, "play.toClient.packet")[0];
+        assert_eq!(y, got.named_types["i32"]);
+    }
+
+    #[test]
+    fn missing_names_record_the_type_using_them() {
+        let doc = json!({
+            "play": {
+                "toClient": {
+                    "types": {
+                        "packet": [
+                            "container",
+                            [{ "name": "y", "type": "varint" }]
+                        ],
+                    }
+                }
+            }
+        });
+        let protocol = syntax::parse(&doc).unwrap();
+
+        let got = lower(&protocol).unwrap_err();
+
+        assert_eq!(
+            got.all_diagnostics(),
+            &[Diagnostic::MissingName {
+                name: String::from("varint"),
+                used_by: Some(String::from("play.toClient.packet")),
+            }]
+        );
+    }
+
+    fn protocol_with_duplicate_anonymous_types() -> syntax::Protocol {
+        let vector = |name: &str| {
+            json!({
+                "name": name,
+                "type": [
+                    "container",
+                    [
+                        { "name": "x", "type": "i32" },
+                        { "name": "y", "type": "i32" },
+                    ]
+                ]
+            })
+        };
+        let doc = json!({
+            "types": {
+                "i32": "native",
+                "i64": "native",
+                "first": ["container", [vector("position"), vector("velocity")]],
+                "second": ["container", [vector("position")]],
+                "third": ["container", [vector("x")]],
+            }
+        });
+
+        syntax::parse(&doc).unwrap()
+    }
+
+    #[test]
+    fn deduplicate_the_items_of_top_level_arrays() {
+        let vector = json!([
+            "container",
+            [
+                { "name": "x", "type": "i32" },
+                { "name": "y", "type": "i32" },
+            ]
+        ]);
+        let doc = json!({
+            "types": {
+                "i32": "native",
+                "positions": ["array", { "countType": "i32", "type": vector }],
+                "velocities": ["array", { "countType": "i32", "type": vector }],
+            }
+        });
+        let protocol = syntax::parse(&doc).unwrap();
+        let options = LoweringOptions {
+            deduplicate_types: true,
+        };
+
+        let got = lower_with_options(&protocol, &options).unwrap();
+
+        let item = |name: &str| match &got.types[&got.named_types[name]] {
+            Type::Array(a) => a.item,
+            other => panic!("Expected an array but found {:?}", other),
+        };
+        assert_eq!(item("positions"), item("velocities"));
+        // the arrays themselves are named, so they stay separate
+        assert_ne!(got.named_types["positions"], got.named_types["velocities"]);
+    }
+
+    fn field_types(unit: &CompilationUnit, name: &str) -> Vec<TypeId> {
+        match &unit.types[&unit.named_types[name]] {
+            Type::Struct(s) => s.fields.iter().map(|f| f.ty).collect(),
+            other => panic!("Expected a struct but found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn anonymous_types_are_distinct_by_default() {
+        let protocol = protocol_with_duplicate_anonymous_types();
+
+        let got = lower(&protocol).unwrap();
+
+        let first = field_types(&got, "first");
+        let second = field_types(&got, "second");
+        assert_ne!(first[0], first[1]);
+        assert_ne!(first[0], second[0]);
+        assert_ne!(got.named_types["i32"], got.named_types["i64"]);
+    }
+
+    #[test]
+    fn deduplicate_identical_anonymous_types() {
+        let protocol = protocol_with_duplicate_anonymous_types();
+        let options = LoweringOptions {
+            deduplicate_types: true,
+        };
+
+        let got = lower_with_options(&protocol, &options).unwrap();
+
+        let first = field_types(&got, "first");
+        let second = field_types(&got, "second");
+        let third = field_types(&got, "third");
+        assert_eq!(first[0], first[1]);
+        assert_eq!(first[0], second[0]);
+        assert_eq!(first[0], third[0]);
+        // natives and named types are never merged
+        assert_ne!(got.named_types["i32"], got.named_types["i64"]);
+        assert_ne!(got.named_types["first"], got.named_types["second"]);
+        // the first declaration gives the shared type its name
+        assert_eq!(got.name_hints[&first[0]], "first_position");
+    }
+
+    #[test]
+    fn lower_a_packet_with_a_mapper_and_switch() {
+        let doc = json!({
+            "types": {
+                "varint": "native",
+                "bool": "native",
+                "packet_ping": ["container", []],
+                "packet": [
+                    "container",
+                    [
+                        {
+                            "name": "name",
+                            "type": ["mapper", {
+                                "type": "varint",
+                                "mappings": { "0x01": "ping" }
+                            }]
+                        },
+                        {
+                            "name": "params",
+                            "type": ["switch", {
+                                "compareTo": "name",
+                                "fields": { "ping": "packet_ping" },
+                                "default": ["option", "bool"]
+                            }]
+                        },
+                        { "anon": true, "type": ["container", []] },
+                    ]
+                ],
+            }
+        });
+        let protocol = crate::syntax::parse(&doc).unwrap();
+
+        let got = lower(&protocol).unwrap();
+
+        let fields = match &got.types[&got.named_types["packet"]] {
+            Type::Struct(s) => &s.fields,
+            other => panic!("Expected a struct but found {:?}", other),
+        };
+        assert_eq!(
+            got.types[&fields[0].ty],
+            Type::Mapper(Mapper {
+                ty: got.named_types["varint"],
+                mappings: vec![(1, String::from("ping"))],
+            })
+        );
+        let params = match &got.types[&fields[1].ty] {
+            Type::Enum(e) => e,
+            other => panic!("Expected an enum but found {:?}", other),
+        };
+        assert_eq!(params.compare_to, "name");
+        assert_eq!(
+            params.variants,
+            vec![Variant {
+                key: String::from("ping"),
+                ty: got.named_types["packet_ping"],
+            }]
+        );
+        let default = params.default.unwrap();
+        assert_eq!(got.name_hints[&default], "packet_params_default");
+        assert_eq!(got.types[&default], Type::Option(got.named_types["bool"]));
+        assert_eq!(fields[2].name, "anon");
+        assert!(fields[2].anonymous);
+    }
 }
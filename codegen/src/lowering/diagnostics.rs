@@ -34,15 +34,86 @@ impl Display for Diagnostics {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Diagnostic {
-    MissingName { name: String },
+    /// Something refers to a name which was never defined.
+    MissingName {
+        name: String,
+        /// The fully qualified name of the type which used the name (e.g.
+        /// `play.toClient.packet`), if it came from the `protocol.json`.
+        used_by: Option<String>,
+    },
+    /// Several names from the `protocol.json` would be converted to the same
+    /// Rust identifier.
+    NameCollision { ident: String, names: Vec<String> },
+    /// A code generation option couldn't be used.
+    InvalidOption { option: String, value: String },
+    /// A `bitfield` isn't a whole number of bytes, or is wider than 64 bits.
+    UnsupportedBitFields { name: String, bits: usize },
+    /// A `"native"` type is used, but the runtime crate doesn't provide it
+    /// and no Rust type was given for it.
+    UnknownNative { name: String },
+    /// A packet switches to the state given by its `nextState` field, but
+    /// the state for each of its values wasn't given.
+    MissingNextStates { packet: String },
+}
+
+impl Diagnostic {
+    /// A short, stable identifier for this kind of diagnostic.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Diagnostic::MissingName { .. } => "missing-name",
+            Diagnostic::NameCollision { .. } => "name-collision",
+            Diagnostic::InvalidOption { .. } => "invalid-option",
+            Diagnostic::UnsupportedBitFields { .. } => "unsupported-bitfields",
+            Diagnostic::UnknownNative { .. } => "unknown-native",
+            Diagnostic::MissingNextStates { .. } => "missing-next-states",
+        }
+    }
 }
 
 impl Display for Diagnostic {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
-            Diagnostic::MissingName { name } => {
-                writeln!(f, "missing name: {}", name)
+            Diagnostic::MissingName {
+                name,
+                used_by: Some(used_by),
+            } => {
+                writeln!(f, "missing name: {} (used by \"{}\")", name, used_by)
+            },
+            Diagnostic::MissingName {
+                name,
+                used_by: None,
+            } => writeln!(f, "missing name: {}", name),
+            Diagnostic::NameCollision { ident, names } => {
+                let names = names
+                    .iter()
+                    .map(|name| format!("\"{}\"", name))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                writeln!(f, "{} would all be named \"{}\"", names, ident)
             },
+            Diagnostic::InvalidOption { option, value } => writeln!(
+                f,
+                "\"{}\" is not a valid value for the \"{}\" option",
+                value, option
+            ),
+            Diagnostic::UnsupportedBitFields { name, bits } => writeln!(
+                f,
+                "\"{}\" has {} bits, but a bitfield must be a whole number \
+                 of bytes and no more than 64 bits",
+                name, bits
+            ),
+            Diagnostic::UnknownNative { name } => writeln!(
+                f,
+                "\"{}\" is a native type which the runtime crate doesn't \
+                 provide, so its Rust type must be given explicitly",
+                name
+            ),
+            Diagnostic::MissingNextStates { packet } => writeln!(
+                f,
+                "\"{}\" has a nextState field, so the state each of its values \
+                 switches to must be given explicitly",
+                packet
+            ),
         }
     }
 }
@@ -5,45 +5,97 @@ use std::fmt::{self, Debug, Formatter};
 pub struct CompilationUnit {
     pub types: IndexMap<TypeId, Type>,
     pub named_types: IndexMap<String, TypeId>,
+    /// A name for each type, derived from where it was declared (e.g.
+    /// `slot_data` for an inline type in the `data` field of `slot`).
+    ///
+    /// This is used when generating names for anonymous types.
+    pub name_hints: IndexMap<TypeId, String>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Type {
     Native,
     Struct(Struct),
     Enum(Enum),
     LengthPrefixedString(LengthPrefixedString),
     BitFields(BitFields),
+    Mapper(Mapper),
+    Array(Array),
+    Buffer(Buffer),
+    Option(TypeId),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Struct {
     pub fields: Vec<Field>,
 }
 
 /// A [`Struct`] field.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Field {
     pub name: String,
     pub ty: TypeId,
+    /// Was this field declared with `"anon": true`?
+    ///
+    /// Anonymous fields are given a placeholder name (e.g. `anon`), but their
+    /// contents are treated as if they were part of the parent struct when
+    /// resolving a `switch`'s `compareTo`.
+    pub anonymous: bool,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// A `switch`, where the variant is chosen based on the value of a
+/// previously decoded field.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Enum {
+    /// The path to the field being compared against (e.g. `action` or
+    /// `../action`).
     pub compare_to: String,
     pub variants: Vec<Variant>,
+    pub default: Option<TypeId>,
 }
 
 /// A [`Enum`] variant.
-#[derive(Debug, Clone, PartialEq)]
-pub enum Variant {}
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Variant {
+    /// The value which selects this variant, exactly as written in the
+    /// `protocol.json` file.
+    pub key: String,
+    pub ty: TypeId,
+}
 
-#[derive(Debug, Clone, PartialEq)]
+/// Maps the integers decoded by another type to names.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Mapper {
+    pub ty: TypeId,
+    pub mappings: Vec<(i64, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Array {
+    pub count: Count,
+    pub item: TypeId,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Buffer {
+    pub count: Count,
+}
+
+/// How the number of items in an [`Array`] or [`Buffer`] is determined.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Count {
+    Prefixed(TypeId),
+    Fixed(usize),
+    /// Use the value of a previously decoded field.
+    Field(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BitFields {
     pub fields: Vec<crate::syntax::BitField>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct LengthPrefixedString {
     pub count_type: TypeId,
 }
@@ -54,6 +106,7 @@ pub struct TypeId(u32);
 impl TypeId {
     pub const ERROR: TypeId = TypeId(0);
 
+    #[allow(clippy::match_like_matches_macro)]
     pub const fn is_error(self) -> bool {
         match self {
             TypeId::ERROR => true,
@@ -62,10 +115,6 @@ impl TypeId {
     }
 
     pub(crate) const fn next(self) -> Self { TypeId(self.0 + 1) }
-
-    pub(crate) fn unique_name(self) -> String {
-        format!("AnonymousType{}", self.0)
-    }
 }
 
 impl Debug for TypeId {
@@ -4,6 +4,6 @@ mod analysis;
 mod diagnostics;
 mod hir;
 
-pub use analysis::lower;
+pub use analysis::{lower, lower_with_options, LoweringOptions};
 pub use diagnostics::{Diagnostic, Diagnostics};
 pub use hir::*;
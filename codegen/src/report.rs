@@ -0,0 +1,446 @@
+//! Machine-readable versions of [`ParseError`]s, [`Diagnostic`]s, and the
+//! [`interpreter`][crate::interpreter]'s errors, for editor integrations and
+//! CI annotations.
+//!
+//! ```rust
+//! use protodef_codegen::report::Report;
+//!
+//! let src = r#"{
+//!   "types": {
+//!     "broken": ["container", 42]
+//!   }
+//! }"#;
+//! let document = serde_json::from_str(src)?;
+//! let (_, errors) = protodef_codegen::syntax::parse_with_recovery(&document);
+//!
+//! let report = Report::from(&errors[0]).located("protocol.json", src);
+//!
+//! assert_eq!(report.code, "incorrect-type");
+//! assert_eq!(report.line, Some(3));
+//! assert_eq!(report.column, Some(15));
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use crate::{
+    interpreter::{DecodeError, EncodeError},
+    lowering::Diagnostic,
+    syntax::ParseError,
+};
+use serde_json::json;
+use std::{
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+};
+
+/// A single problem, in a form that is easy to serialize.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// A short, stable identifier for the kind of problem (e.g.
+    /// `missing-field`).
+    pub code: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// The file the problem was found in, if known.
+    pub file: Option<PathBuf>,
+    /// The 1-based line number, if the problem could be located.
+    pub line: Option<usize>,
+    /// The 1-based column number, if the problem could be located.
+    pub column: Option<usize>,
+    /// Breadcrumbs leading to the problem, starting from the top of the
+    /// `protocol.json` file.
+    pub context: Vec<String>,
+}
+
+impl Report {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Report {
+            code,
+            severity: Severity::Error,
+            message: message.into(),
+            file: None,
+            line: None,
+            column: None,
+            context: Vec::new(),
+        }
+    }
+
+    /// Record which file this problem came from, using the file's contents
+    /// to figure out the line and column when possible.
+    ///
+    /// The position points at the most deeply nested JSON value the
+    /// [`Report::context`] could be followed to (typically the definition
+    /// of the offending type).
+    pub fn located(mut self, file: impl Into<PathBuf>, src: &str) -> Self {
+        self.file = Some(file.into());
+
+        if self.line.is_none() {
+            if let Some(offset) = locate(src, &self.context) {
+                let (line, column) = line_and_column(src, offset);
+                self.line = Some(line);
+                self.column = Some(column);
+            }
+        }
+
+        self
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "code": self.code,
+            "severity": self.severity.as_str(),
+            "message": self.message,
+            "file": self.file.as_ref().map(|f| f.display().to_string()),
+            "line": self.line,
+            "column": self.column,
+            "context": self.context,
+        })
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.severity.as_str())?;
+
+        if let Some(file) = &self.file {
+            write!(f, " in {}", file.display())?;
+
+            if let (Some(line), Some(column)) = (self.line, self.column) {
+                write!(f, ":{}:{}", line, column)?;
+            }
+        }
+
+        write!(f, ": {} [{}]", self.message, self.code)
+    }
+}
+
+impl From<&ParseError> for Report {
+    fn from(e: &ParseError) -> Self {
+        Report {
+            context: e.context.clone(),
+            ..Report::error(e.kind.code(), e.kind.to_string())
+        }
+    }
+}
+
+impl From<&Diagnostic> for Report {
+    fn from(d: &Diagnostic) -> Self {
+        let context = match d {
+            Diagnostic::NameCollision { names, .. } => names
+                .first()
+                .map(|name| json_path(name))
+                .unwrap_or_default(),
+            Diagnostic::MissingName {
+                used_by: Some(used_by),
+                ..
+            } => json_path(used_by),
+            Diagnostic::UnknownNative { name } => json_path(name),
+            Diagnostic::MissingNextStates { packet } => json_path(packet),
+            _ => Vec::new(),
+        };
+
+        Report {
+            context,
+            ..Report::error(d.code(), d.to_string().trim_end())
+        }
+    }
+}
+
+/// The [`Report::context`] is the path to the field being decoded, so the
+/// report can't be located in the `protocol.json` file.
+impl From<&DecodeError> for Report {
+    fn from(e: &DecodeError) -> Self {
+        Report {
+            context: e.context.clone(),
+            ..Report::error("decode-failed", e.to_string())
+        }
+    }
+}
+
+/// The [`Report::context`] is the path to the invalid part of the value, so
+/// the report can't be located in the `protocol.json` file.
+impl From<&EncodeError> for Report {
+    fn from(e: &EncodeError) -> Self {
+        Report {
+            context: e.context.clone(),
+            ..Report::error("invalid-value", e.kind.to_string())
+        }
+    }
+}
+
+impl From<&serde_json::Error> for Report {
+    fn from(e: &serde_json::Error) -> Self {
+        let mut report = Report::error("invalid-json", e.to_string());
+
+        if e.line() > 0 {
+            report.line = Some(e.line());
+            report.column = Some(e.column());
+        }
+
+        report
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+/// The keys leading to a type's definition (e.g. `play.toClient.packet`
+/// becomes `["play", "toClient", "types", "packet"]`).
+fn json_path(qualified_name: &str) -> Vec<String> {
+    let mut path: Vec<String> =
+        qualified_name.split('.').map(String::from).collect();
+    let name = path.pop().unwrap_or_default();
+
+    path.push(String::from("types"));
+    path.push(name);
+    path
+}
+
+fn line_and_column(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+
+    (line, column)
+}
+
+/// Follow a path of object keys through a JSON document, returning the byte
+/// offset of the deepest value that could be reached.
+///
+/// Keys which aren't present (e.g. breadcrumbs like `"container"` that
+/// don't correspond to an object key) stop the search.
+fn locate(src: &str, path: &[String]) -> Option<usize> {
+    let mut scanner = Scanner { src, position: 0 };
+    scanner.skip_whitespace();
+    let mut found = None;
+
+    for key in path {
+        match scanner.find_key(key) {
+            Some(offset) => found = Some(offset),
+            None => break,
+        }
+    }
+
+    found
+}
+
+/// Just enough of a JSON parser to find out where values are.
+struct Scanner<'a> {
+    src: &'a str,
+    position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    /// Assuming we are at the start of an object, move to the start of the
+    /// value with this key.
+    fn find_key(&mut self, key: &str) -> Option<usize> {
+        self.expect('{')?;
+
+        loop {
+            self.skip_whitespace();
+            if self.peek()? == '}' {
+                return None;
+            }
+
+            let start = self.position;
+            self.skip_string()?;
+            let name: String =
+                serde_json::from_str(&self.src[start..self.position]).ok()?;
+
+            self.skip_whitespace();
+            self.expect(':')?;
+            self.skip_whitespace();
+
+            if name == key {
+                return Some(self.position);
+            }
+
+            self.skip_value()?;
+            self.skip_whitespace();
+            if self.peek()? == ',' {
+                self.position += 1;
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        match self.peek()? {
+            '"' => self.skip_string(),
+            '{' | '[' => {
+                let mut depth = 0;
+
+                loop {
+                    match self.peek()? {
+                        '"' => {
+                            self.skip_string()?;
+                            continue;
+                        },
+                        '{' | '[' => depth += 1,
+                        '}' | ']' => depth -= 1,
+                        _ => {},
+                    }
+                    self.position += 1;
+
+                    if depth == 0 {
+                        return Some(());
+                    }
+                }
+            },
+            _ => {
+                // numbers, booleans, and null
+                while let Some(c) = self.peek() {
+                    if c == ',' || c == '}' || c == ']' || c.is_whitespace() {
+                        break;
+                    }
+                    self.position += c.len_utf8();
+                }
+                Some(())
+            },
+        }
+    }
+
+    fn skip_string(&mut self) -> Option<()> {
+        self.expect('"')?;
+
+        loop {
+            let c = self.peek()?;
+            self.position += c.len_utf8();
+
+            match c {
+                '\\' => self.position += self.peek()?.len_utf8(),
+                '"' => return Some(()),
+                _ => {},
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.position += c.len_utf8();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Option<()> {
+        if self.peek()? == expected {
+            self.position += expected.len_utf8();
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn peek(&self) -> Option<char> { self.src[self.position..].chars().next() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: &str = r#"{
+  "types": { "varint": "native" },
+  "play": {
+    "toClient": {
+      "types": {
+        "note": "a \"quoted\" string",
+        "packet": ["container", []]
+      }
+    }
+  }
+}"#;
+
+    fn path(segments: &[&str]) -> Vec<String> {
+        segments.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn locate_nested_keys() {
+        let offset = locate(
+            SRC,
+            &path(&["play", "toClient", "types", "packet", "container"]),
+        )
+        .unwrap();
+
+        assert!(SRC[offset..].starts_with(r#"["container""#));
+        assert_eq!(line_and_column(SRC, offset), (7, 19));
+    }
+
+    #[test]
+    fn unknown_keys_stop_the_search() {
+        let got = locate(SRC, &path(&["play", "toServer", "types"])).unwrap();
+
+        assert!(SRC[got..].starts_with(
+            r#"{
+    "toClient""#
+        ));
+        assert_eq!(locate(SRC, &path(&["nope"])), None);
+    }
+
+    #[test]
+    fn reports_as_json() {
+        let diagnostic = Diagnostic::NameCollision {
+            ident: String::from("Packet"),
+            names: vec![
+                String::from("play.toClient.packet"),
+                String::from("play.toClient.Packet"),
+            ],
+        };
+
+        let got = Report::from(&diagnostic).located("protocol.json", SRC);
+
+        assert_eq!(
+            got.to_json(),
+            json!({
+                "code": "name-collision",
+                "severity": "error",
+                "message": "\"play.toClient.packet\", \"play.toClient.Packet\" would all be named \"Packet\"",
+                "file": "protocol.json",
+                "line": 7,
+                "column": 19,
+                "context": ["play", "toClient", "types", "packet"],
+            })
+        );
+    }
+
+    #[test]
+    fn missing_names_point_at_the_type_using_them() {
+        let diagnostic = Diagnostic::MissingName {
+            name: String::from("varint"),
+            used_by: Some(String::from("play.toClient.packet")),
+        };
+
+        let got = Report::from(&diagnostic).located("protocol.json", SRC);
+
+        assert_eq!((got.line, got.column), (Some(7), Some(19)));
+        assert_eq!(got.context, path(&["play", "toClient", "types", "packet"]));
+        assert_eq!(
+            got.message,
+            "missing name: varint (used by \"play.toClient.packet\")"
+        );
+    }
+
+    #[test]
+    fn invalid_json_has_a_location() {
+        let e =
+            serde_json::from_str::<serde_json::Value>("{\n  oops").unwrap_err();
+
+        let got = Report::from(&e);
+
+        assert_eq!(got.code, "invalid-json");
+        assert_eq!((got.line, got.column), (Some(2), Some(3)));
+    }
+}
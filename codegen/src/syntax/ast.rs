@@ -3,6 +3,9 @@ use indexmap::IndexMap;
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct Protocol {
     pub types: IndexMap<String, Type>,
+    /// Nested namespaces (e.g. `play` and `toClient`), each of which may
+    /// declare their own types.
+    pub namespaces: IndexMap<String, Protocol>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -14,6 +17,9 @@ pub enum Type {
     BitFields(BitFields),
     LengthPrefixedString { count_type: Box<Type> },
     Mapper(Mapper),
+    Array(Array),
+    Buffer(Buffer),
+    Option(Box<Type>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -39,7 +45,12 @@ impl Field {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Switch {
     pub compare_to: String,
-    pub variants: IndexMap<i64, Type>,
+    /// The variants, keyed by the value being compared to.
+    ///
+    /// Keys are always strings in a `protocol.json` file because the value
+    /// may come from a `mapper` (e.g. `"keep_alive"`), a number (e.g. `"-1"`),
+    /// or a `bool`.
+    pub variants: IndexMap<String, Type>,
     pub default: Option<Box<Type>>,
 }
 
@@ -48,12 +59,39 @@ pub struct BitFields {
     pub fields: Vec<BitField>,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BitField {
     pub name: String,
     pub size: usize,
     pub signed: bool,
 }
 
+/// Maps the integers decoded by some type to names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapper {
+    pub ty: Box<Type>,
+    pub mappings: IndexMap<i64, String>,
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct Mapper;
+pub struct Array {
+    pub count: Count,
+    pub ty: Box<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buffer {
+    pub count: Count,
+}
+
+/// How the number of items in an `array` or `buffer` is determined.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Count {
+    /// The length is encoded as a prefix (`countType`).
+    Prefixed(Box<Type>),
+    /// A fixed number of items (e.g. `"count": 2`).
+    Fixed(usize),
+    /// The length comes from a previously decoded field (e.g.
+    /// `"count": "columns"`).
+    Field(String),
+}
@@ -14,6 +14,7 @@ pub enum ValueKind {
     Integer,
     Float,
     Bool,
+    Null,
 }
 
 impl ValueKind {
@@ -76,7 +77,13 @@ impl Display for ParseError {
             write!(f, "At \"{}\" ", breadcrumbs)?;
         }
 
-        match &self.kind {
+        write!(f, "{}", self.kind)
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
             ErrorKind::IncorrectType { expected, found } => {
                 write!(f, "incorrect type, expected ")?;
 
@@ -133,6 +140,19 @@ pub enum ErrorKind {
     },
 }
 
+impl ErrorKind {
+    /// A short, stable identifier for this kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::IncorrectType { .. } => "incorrect-type",
+            ErrorKind::UnknownFunction { .. } => "unknown-function",
+            ErrorKind::MissingField { .. } => "missing-field",
+            ErrorKind::ParseInt(_) => "invalid-integer",
+            ErrorKind::IncorrectArrayLength { .. } => "incorrect-array-length",
+        }
+    }
+}
+
 pub(crate) trait ResultExt<T> {
     fn with_context(self, context: impl Display) -> Result<T, ParseError>;
 }
@@ -217,7 +237,9 @@ impl ValueExt for Value {
             Value::Object(_) => ValueKind::Object,
             Value::String(_) => ValueKind::String,
             Value::Array(_) => ValueKind::Array,
-            _ => todo!(),
+            Value::Number(n) => ValueKind::for_number(n.clone()),
+            Value::Bool(_) => ValueKind::Bool,
+            Value::Null => ValueKind::Null,
         }
     }
 }
@@ -40,4 +40,4 @@ mod parse;
 
 pub use ast::*;
 pub use errors::{ErrorKind, ParseError};
-pub use parse::parse;
+pub use parse::{parse, parse_with_recovery};
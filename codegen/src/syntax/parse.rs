@@ -1,39 +1,103 @@
 use super::{
     errors::{Lookup, ResultExt, ValueExt, ValueKind},
-    BitField, BitFields, Container, ErrorKind, Field, Mapper, ParseError,
-    Protocol, Switch, Type,
+    Array, BitField, BitFields, Buffer, Container, Count, ErrorKind, Field,
+    Mapper, ParseError, Protocol, Switch, Type,
 };
 use indexmap::IndexMap;
 use serde_json::{Map, Value};
 
-/// Parses a JSON document into a [`Protocol`].
+/// Parses a JSON document into a [`Protocol`], bailing on the first error.
 pub fn parse(document: &Value) -> Result<Protocol, ParseError> {
-    parse_document(document)
+    let (protocol, mut errors) = parse_with_recovery(document);
+
+    if errors.is_empty() {
+        Ok(protocol)
+    } else {
+        Err(errors.remove(0))
+    }
 }
 
-fn parse_document(document: &Value) -> Result<Protocol, ParseError> {
+/// Parses a JSON document into a [`Protocol`], skipping any malformed types
+/// so every error in the document can be reported at once.
+///
+/// The returned [`Protocol`] contains only the types which were parsed
+/// successfully.
+///
+/// ```rust
+/// let document = serde_json::json!({
+///   "types": {
+///     "varint": "native",
+///     "first": ["container", 42],
+///     "second": ["not_a_function", {}]
+///   }
+/// });
+///
+/// let (protocol, errors) =
+///     protodef_codegen::syntax::parse_with_recovery(&document);
+///
+/// assert_eq!(errors.len(), 2);
+/// assert!(protocol.types.contains_key("varint"));
+/// ```
+pub fn parse_with_recovery(document: &Value) -> (Protocol, Vec<ParseError>) {
+    let mut errors = Vec::new();
+    let protocol = parse_document(document, &mut errors);
+
+    (protocol, errors)
+}
+
+fn parse_document(document: &Value, errors: &mut Vec<ParseError>) -> Protocol {
     let types = match document.get("types") {
-        Some(types) => {
-            let types = types.expect_object().with_context("types")?;
-            parse_types(types).with_context("types")?
+        Some(types) => match types.expect_object().with_context("types") {
+            Ok(types) => parse_types(types, "types", errors),
+            Err(e) => {
+                errors.push(e);
+                IndexMap::new()
+            },
         },
         None => IndexMap::new(),
     };
 
-    Ok(Protocol { types })
+    let mut namespaces = IndexMap::new();
+
+    if let Value::Object(items) = document {
+        for (name, value) in items.iter().filter(|(key, _)| *key != "types") {
+            if let Err(e) = value.expect_object().with_context(name) {
+                errors.push(e);
+                continue;
+            }
+
+            let mut namespace_errors = Vec::new();
+            let namespace = parse_document(value, &mut namespace_errors);
+            errors.extend(namespace_errors.into_iter().map(|mut e| {
+                e.context.insert(0, name.clone());
+                e
+            }));
+            namespaces.insert(name.clone(), namespace);
+        }
+    }
+
+    Protocol { types, namespaces }
 }
 
 fn parse_types(
     types: &Map<String, Value>,
-) -> Result<IndexMap<String, Type>, ParseError> {
+    context: &str,
+    errors: &mut Vec<ParseError>,
+) -> IndexMap<String, Type> {
     let mut parsed_types = IndexMap::new();
 
     for (name, ty) in types {
-        let parsed = parse_type(ty).with_context(name)?;
-        parsed_types.insert(name.clone(), parsed);
+        match parse_type(ty).with_context(name).with_context(context) {
+            Ok(parsed) => {
+                parsed_types.insert(name.clone(), parsed);
+            },
+            // skip the malformed type and keep going so we can report as
+            // many errors as possible
+            Err(e) => errors.push(e),
+        }
     }
 
-    Ok(parsed_types)
+    parsed_types
 }
 
 fn parse_type(ty: &Value) -> Result<Type, ParseError> {
@@ -64,6 +128,11 @@ fn parse_type(ty: &Value) -> Result<Type, ParseError> {
             .with_context("bitfield"),
         "pstring" => parse_length_prefixed_string(arg).with_context("pstring"),
         "mapper" => parse_mapper(arg).map(Type::Mapper).with_context("mapper"),
+        "array" => parse_array(arg).map(Type::Array).with_context("array"),
+        "buffer" => parse_buffer(arg).map(Type::Buffer).with_context("buffer"),
+        "option" => parse_type(arg)
+            .map(|ty| Type::Option(Box::new(ty)))
+            .with_context("option"),
 
         "entityMetadataLoop" => {
             // TODO: Parse "entityMetadataLoop"
@@ -109,12 +178,8 @@ fn parse_switch(arg: &Value) -> Result<Switch, ParseError> {
     let mut variants = IndexMap::new();
 
     for (key, value) in args.lookup_object("fields")? {
-        let ty = parse_type(value).with_context("fields").with_context(key)?;
-        let key = key
-            .parse::<i64>()
-            .map_err(|e| ParseError::new(ErrorKind::ParseInt(e)))?;
-
-        variants.insert(key, ty);
+        let ty = parse_type(value).with_context(key).with_context("fields")?;
+        variants.insert(key.clone(), ty);
     }
 
     let default = args
@@ -176,7 +241,84 @@ fn parse_bitfield(field: &Value) -> Result<BitField, ParseError> {
     Ok(BitField { name, size, signed })
 }
 
-fn parse_mapper(_arg: &Value) -> Result<Mapper, ParseError> { todo!() }
+fn parse_mapper(arg: &Value) -> Result<Mapper, ParseError> {
+    let args = arg.expect_object()?;
+
+    let ty = parse_type(args.lookup("type")?).with_context("type")?;
+
+    let mut mappings = IndexMap::new();
+
+    for (key, name) in args.lookup_object("mappings")? {
+        let value = parse_integer(key)
+            .with_context(key)
+            .with_context("mappings")?;
+        let name = name
+            .expect_string()
+            .with_context(key)
+            .with_context("mappings")?;
+
+        mappings.insert(value, name.clone());
+    }
+
+    Ok(Mapper {
+        ty: Box::new(ty),
+        mappings,
+    })
+}
+
+/// Parse an integer which may be written in hex (e.g. `"0x1f"`).
+fn parse_integer(s: &str) -> Result<i64, ParseError> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+
+    parsed.map_err(|e| ParseError::new(ErrorKind::ParseInt(e)))
+}
+
+fn parse_array(arg: &Value) -> Result<Array, ParseError> {
+    let args = arg.expect_object()?;
+
+    let count = parse_count(args)?;
+    let ty = parse_type(args.lookup("type")?).with_context("type")?;
+
+    Ok(Array {
+        count,
+        ty: Box::new(ty),
+    })
+}
+
+fn parse_buffer(arg: &Value) -> Result<Buffer, ParseError> {
+    let count = parse_count(arg.expect_object()?)?;
+
+    Ok(Buffer { count })
+}
+
+fn parse_count(args: &Map<String, Value>) -> Result<Count, ParseError> {
+    if let Some(count_type) = args.get("countType") {
+        let ty = parse_type(count_type).with_context("countType")?;
+        return Ok(Count::Prefixed(Box::new(ty)));
+    }
+
+    let count = args.lookup("count")?;
+
+    match count {
+        Value::String(field) => Ok(Count::Field(field.clone())),
+        Value::Number(n) => {
+            n.as_u64().map(|n| Count::Fixed(n as usize)).ok_or_else(|| {
+                ParseError::new(ErrorKind::IncorrectType {
+                    expected: vec![ValueKind::Integer],
+                    found: ValueKind::for_number(n.clone()),
+                })
+            })
+        },
+        other => Err(ParseError::new(ErrorKind::IncorrectType {
+            expected: vec![ValueKind::String, ValueKind::Integer],
+            found: other.value_kind(),
+        })),
+    }
+    .with_context("count")
+}
 
 #[track_caller]
 fn expect_length<T>(items: &[T], expected: usize) -> Result<(), ParseError> {
@@ -251,7 +393,6 @@ mod tests {
     }
 
     #[test]
-    #[ignore]
     fn parse_mapper() {
         let doc = json! {[
           "mapper",
@@ -263,7 +404,15 @@ mod tests {
             }
           }
         ]};
-        let should_be = Type::Mapper(Mapper);
+        let should_be = Type::Mapper(Mapper {
+            ty: Box::new(Type::Named("varint".into())),
+            mappings: vec![
+                (0x00, String::from("set_protocol")),
+                (0xfe, String::from("legacy_server_list_ping")),
+            ]
+            .into_iter()
+            .collect(),
+        });
 
         let got = parse_type(&doc).unwrap();
 
@@ -282,7 +431,7 @@ mod tests {
         ]};
         let should_be = Type::Switch(Switch {
             compare_to: "blockId".into(),
-            variants: vec![(-1, Type::Named("void".into()))]
+            variants: vec![("-1".into(), Type::Named("void".into()))]
                 .into_iter()
                 .collect(),
             default: Some(Box::new(Type::Container(Container {
@@ -295,6 +444,82 @@ mod tests {
         assert_eq!(got, should_be);
     }
 
+    #[test]
+    fn switch_keys_can_be_names() {
+        let doc = json! {[
+              "switch",
+              {
+                "compareTo": "name",
+                "fields": {
+                    "keep_alive": "packet_keep_alive",
+                    "true": "void"
+                }
+              }
+        ]};
+
+        let got = match parse_type(&doc).unwrap() {
+            Type::Switch(s) => s,
+            other => panic!("Expected a switch but found {:?}", other),
+        };
+
+        let keys: Vec<_> = got.variants.keys().collect();
+        assert_eq!(keys, &["keep_alive", "true"]);
+    }
+
+    #[test]
+    fn parse_arrays() {
+        let prefixed =
+            json!(["array", { "countType": "varint", "type": "i32" }]);
+        let fixed = json!(["array", { "count": 2, "type": "i32" }]);
+        let field = json!(["array", { "count": "columns", "type": "i32" }]);
+        let array = |count| {
+            Type::Array(Array {
+                count,
+                ty: Box::new(Type::Named("i32".into())),
+            })
+        };
+
+        assert_eq!(
+            parse_type(&prefixed).unwrap(),
+            array(Count::Prefixed(Box::new(Type::Named("varint".into()))))
+        );
+        assert_eq!(parse_type(&fixed).unwrap(), array(Count::Fixed(2)));
+        assert_eq!(
+            parse_type(&field).unwrap(),
+            array(Count::Field("columns".into()))
+        );
+    }
+
+    #[test]
+    fn parse_buffers_and_options() {
+        let buffer = json!(["buffer", { "countType": "varint" }]);
+        let option = json!(["option", "string"]);
+
+        assert_eq!(
+            parse_type(&buffer).unwrap(),
+            Type::Buffer(Buffer {
+                count: Count::Prefixed(Box::new(Type::Named("varint".into())))
+            })
+        );
+        assert_eq!(
+            parse_type(&option).unwrap(),
+            Type::Option(Box::new(Type::Named("string".into())))
+        );
+    }
+
+    #[test]
+    fn array_without_a_count() {
+        let doc = json!(["array", { "type": "i32" }]);
+
+        let err = parse_type(&doc).unwrap_err();
+
+        assert_eq!(err.context, &["array"]);
+        assert!(matches!(
+            err.kind,
+            ErrorKind::MissingField { ref name } if name == "count"
+        ));
+    }
+
     #[test]
     fn parse_pstring() {
         let doc = json! {[
@@ -400,6 +625,98 @@ mod tests {
         assert_eq!(got, should_be);
     }
 
+    #[test]
+    fn collect_errors_from_every_malformed_type() {
+        let doc = json!({
+            "types": {
+                "varint": "native",
+                "first": ["container", [{ "name": "x" }]],
+                "string": ["pstring", { "countType": "varint" }],
+                "second": ["bitfield", [{ "name": "y", "size": true }]],
+            }
+        });
+
+        let (protocol, errors) = parse_with_recovery(&doc);
+
+        let names: Vec<_> = protocol.types.keys().collect();
+        assert_eq!(names, &["varint", "string"]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(
+            errors[0].context,
+            &["types", "first", "container", "0", "field"]
+        );
+        assert!(matches!(
+            errors[0].kind,
+            ErrorKind::MissingField { ref name } if name == "type"
+        ));
+        assert_eq!(
+            errors[1].context,
+            &["types", "second", "bitfield", "field", "0", "size"]
+        );
+    }
+
+    #[test]
+    fn parse_stops_at_the_first_error() {
+        let doc = json!({
+            "types": {
+                "first": ["unknown", {}],
+                "second": ["container", 42],
+            }
+        });
+
+        let err = parse(&doc).unwrap_err();
+
+        assert_eq!(err.context, &["types", "first"]);
+        assert!(matches!(
+            err.kind,
+            ErrorKind::UnknownFunction { ref name } if name == "unknown"
+        ));
+    }
+
+    #[test]
+    fn parse_nested_namespaces() {
+        let doc = json!({
+            "types": { "varint": "native" },
+            "handshaking": {
+                "toServer": {
+                    "types": {
+                        "packet_ping": ["container", []],
+                        "broken": ["container", 42],
+                    }
+                }
+            },
+        });
+
+        let (protocol, errors) = parse_with_recovery(&doc);
+
+        let to_server =
+            &protocol.namespaces["handshaking"].namespaces["toServer"];
+        assert!(to_server.types.contains_key("packet_ping"));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].context,
+            &[
+                "handshaking",
+                "toServer",
+                "types",
+                "broken",
+                "container",
+                "fields"
+            ]
+        );
+    }
+
+    #[test]
+    fn top_level_types_must_be_an_object() {
+        let doc = json!({ "types": [] });
+
+        let (protocol, errors) = parse_with_recovery(&doc);
+
+        assert!(protocol.types.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].context, &["types"]);
+    }
+
     #[test]
     fn parse_anon_field() {
         let doc = json! {{
@@ -0,0 +1,78 @@
+use protodef_codegen::{interpreter::Interpreter, lowering::CompilationUnit};
+use protodef_core::Value;
+use serde_json::Value as Json;
+
+fn minecraft_protocol() -> CompilationUnit {
+    let src = include_str!("fixtures/protocol.json");
+    let doc: Json = serde_json::from_str(src).unwrap();
+    let parsed = protodef_codegen::syntax::parse(&doc).unwrap();
+
+    protodef_codegen::lowering::lower(&parsed).unwrap()
+}
+
+fn map(fields: Vec<(&str, Value)>) -> Value {
+    Value::Map(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    )
+}
+
+#[test]
+fn decode_a_chat_packet() {
+    let compilation_unit = minecraft_protocol();
+    let interpreter = Interpreter::new(&compilation_unit);
+    let input = b"\x02\x05Hello\x01";
+
+    let (got, rest) =
+        interpreter.decode("play.toClient.packet", input).unwrap();
+
+    let should_be = map(vec![
+        ("name", Value::from("chat")),
+        (
+            "params",
+            map(vec![
+                ("message", Value::from("Hello")),
+                ("position", Value::from(1_i8)),
+            ]),
+        ),
+    ]);
+    assert_eq!(got, should_be);
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn decode_an_empty_slot() {
+    let compilation_unit = minecraft_protocol();
+    let interpreter = Interpreter::new(&compilation_unit);
+    // set_slot, window 0, slot 36, no item
+    let input = [0x2f, 0x00, 0x00, 0x24, 0xff, 0xff];
+
+    let (got, _) = interpreter.decode("play.toClient.packet", &input).unwrap();
+
+    let params = got.get("params").unwrap();
+    assert_eq!(params.get("slot"), Some(&Value::from(36_i16)));
+    assert_eq!(
+        params.get("item"),
+        Some(&map(vec![("blockId", Value::from(-1_i16))]))
+    );
+}
+
+#[test]
+fn encode_a_packet_from_json() {
+    let compilation_unit = minecraft_protocol();
+    let interpreter = Interpreter::new(&compilation_unit);
+    let packet = serde_json::json!({
+        "name": "set_slot",
+        "params": {
+            "windowId": 0,
+            "slot": 36,
+            "item": { "blockId": -1 }
+        }
+    });
+
+    let got = interpreter.encode("play.toClient.packet", &packet).unwrap();
+
+    assert_eq!(got, &[0x2f, 0x00, 0x00, 0x24, 0xff, 0xff]);
+}
@@ -1,19 +1,33 @@
 #[macro_use]
 extern crate pretty_assertions;
 
-use protodef_codegen::lowering::{Field, Struct, Type};
+use protodef_codegen::{
+    backend::CodegenOptions,
+    lowering::{Field, Struct, Type},
+};
 use serde_json::Value;
 
 #[test]
-#[ignore]
 fn protocol() {
     let src = include_str!("fixtures/protocol.json");
     let doc: Value = serde_json::from_str(src).unwrap();
 
     let parsed = protodef_codegen::syntax::parse(&doc).unwrap();
     let analysed = protodef_codegen::lowering::lower(&parsed).unwrap();
-    let _rust_code =
-        protodef_codegen::backend::generate_rust(&analysed).to_string();
+    // protodef_core doesn't know about NBT or entity metadata
+    let options = CodegenOptions::new()
+        .native_type("nbt", "crate::nbt::Nbt")
+        .native_type("optionalNbt", "crate::nbt::OptionalNbt")
+        .native_type("entityMetadata", "crate::metadata::EntityMetadata")
+        // the handshake's nextState is a plain varint
+        .next_state(1, "status")
+        .next_state(2, "login")
+        .next_state(3, "login");
+    let _rust_code = protodef_codegen::backend::generate_rust_with_options(
+        &analysed, &options,
+    )
+    .unwrap()
+    .to_string();
 }
 
 #[test]
@@ -29,16 +43,29 @@ fn basic() {
             Field {
                 name: "itemCount".into(),
                 ty: analysed.named_types["i8"],
+                anonymous: false,
             },
             Field {
                 name: "itemDamage".into(),
                 ty: analysed.named_types["i16"],
+                anonymous: false,
             },
         ],
     });
     assert_eq!(analysed.types[&items_id], items_should_be);
-    let tokens = protodef_codegen::backend::generate_rust(&analysed);
+    let tokens = protodef_codegen::backend::generate_rust(&analysed).unwrap();
     let formatted = protodef_codegen::backend::rustfmt(&tokens)
         .unwrap_or_else(|_| tokens.to_string());
     println!("{}", formatted);
 }
+
+#[test]
+fn lower_the_full_protocol() {
+    let src = include_str!("fixtures/protocol.json");
+    let doc: Value = serde_json::from_str(src).unwrap();
+
+    let parsed = protodef_codegen::syntax::parse(&doc).unwrap();
+    let analysed = protodef_codegen::lowering::lower(&parsed).unwrap();
+
+    assert!(analysed.named_types.contains_key("play.toClient.packet"));
+}
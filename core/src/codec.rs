@@ -0,0 +1,286 @@
+//! A [`tokio_util::codec`] integration for sending and receiving packets
+//! which are prefixed by their length as a [`Varint`].
+//!
+//! ```rust
+//! use protodef_core::codec::PacketCodec;
+//! use tokio_util::codec::{Decoder, Encoder};
+//! use bytes::BytesMut;
+//!
+//! let mut codec = PacketCodec::<u16>::new();
+//! let mut buffer = BytesMut::new();
+//!
+//! codec.encode(0x0102, &mut buffer)?;
+//! assert_eq!(&buffer[..], &[0x02, 0x01, 0x02]);
+//!
+//! let packet = codec.decode(&mut buffer)?;
+//! assert_eq!(packet, Some(0x0102));
+//! # Ok::<(), protodef_core::codec::CodecError>(())
+//! ```
+
+use crate::{framing::Framing, DecodeLimits, Deserialize, Serialize};
+use bytes::{Buf, BufMut, BytesMut};
+use std::{
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+pub use crate::framing::DEFAULT_MAX_FRAME_LENGTH;
+
+/// Errors returned by a [`PacketCodec`], which uses the same framing as the
+/// [`crate::framing`] module.
+pub type CodecError = crate::framing::FrameError;
+
+/// Decodes and encodes `T`s, where each packet is prefixed by its length as
+/// a [`Varint`].
+///
+/// [`Varint`]: crate::native::Varint
+pub struct PacketCodec<T> {
+    framing: Framing,
+    _packet: PhantomData<fn(T) -> T>,
+}
+
+impl<T> PacketCodec<T> {
+    pub fn new() -> Self {
+        PacketCodec {
+            framing: Framing::new(),
+            _packet: PhantomData,
+        }
+    }
+
+    /// Reject any frames (or decompressed payloads) longer than this many
+    /// bytes, not including the length prefix.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.framing.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// The limits used when deserializing each packet.
+    pub fn limits(mut self, limits: DecodeLimits) -> Self {
+        self.framing.limits = limits;
+        self
+    }
+
+    /// Switch to the compressed frame format, compressing any payloads
+    /// which are at least `threshold` bytes long, or go back to uncompressed
+    /// frames with `None`.
+    ///
+    /// See [`crate::framing::FrameWriter::set_compression_threshold()`].
+    #[cfg(feature = "zlib")]
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.framing.compression_threshold = threshold;
+    }
+}
+
+impl<T> Default for PacketCodec<T> {
+    fn default() -> Self { PacketCodec::new() }
+}
+
+impl<T> Debug for PacketCodec<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketCodec")
+            .field("max_frame_length", &self.framing.max_frame_length)
+            .field("compression_threshold", &self.framing.compression_threshold)
+            .field("limits", &self.framing.limits)
+            .finish()
+    }
+}
+
+impl<T> Clone for PacketCodec<T> {
+    fn clone(&self) -> Self {
+        PacketCodec {
+            framing: self.framing,
+            _packet: PhantomData,
+        }
+    }
+}
+
+impl<T> Decoder for PacketCodec<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    type Error = CodecError;
+    type Item = T;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>, CodecError> {
+        let (header, length) = match self.framing.frame_length(&src[..])? {
+            Some(pair) => pair,
+            None => return Ok(None),
+        };
+
+        if src.len() < header + length {
+            // make room for the rest of the frame so it can arrive in one go
+            src.reserve(header + length - src.len());
+            return Ok(None);
+        }
+
+        src.advance(header);
+        let frame = src.split_to(length);
+        let payload = self.framing.unpack(&frame)?;
+
+        self.framing.deserialize(&payload).map(Some)
+    }
+}
+
+impl<T> Encoder<T> for PacketCodec<T>
+where
+    T: Serialize,
+{
+    type Error = CodecError;
+
+    fn encode(
+        &mut self,
+        item: T,
+        dst: &mut BytesMut,
+    ) -> Result<(), CodecError> {
+        let mut payload = Vec::new();
+        item.serialize(&mut payload)?;
+
+        let mut frame = Vec::new();
+        self.framing.pack(&payload, &mut frame)?;
+        dst.put_slice(&frame);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{native::Varint, DeserializeError};
+
+    #[test]
+    fn wait_for_the_whole_frame() {
+        let mut codec = PacketCodec::<u32>::new();
+        let mut buffer = BytesMut::new();
+
+        buffer.put_slice(&[0x04, 0x00]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+        buffer.put_slice(&[0x00, 0x01, 0x00, 0x03]);
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(0x0000_0100));
+        assert_eq!(&buffer[..], &[0x03]);
+        assert!(codec.decode(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn round_trip_several_packets() {
+        let mut codec = PacketCodec::<Varint>::new();
+        let mut buffer = BytesMut::new();
+
+        codec.encode(Varint(300), &mut buffer).unwrap();
+        codec.encode(Varint(1), &mut buffer).unwrap();
+
+        assert_eq!(&buffer[..], &[0x02, 0xac, 0x02, 0x01, 0x01]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Varint(300)));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Varint(1)));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn frames_which_are_too_long_are_rejected() {
+        let mut codec = PacketCodec::<u8>::new().max_frame_length(2);
+        let mut buffer = BytesMut::from(&[0x03, 0x00][..]);
+
+        let got = codec.decode(&mut buffer).unwrap_err();
+
+        assert!(matches!(
+            got,
+            CodecError::FrameTooLong { length: 3, max: 2 }
+        ));
+    }
+
+    #[test]
+    fn packets_must_fill_their_frame() {
+        let mut codec = PacketCodec::<u8>::new();
+        let mut buffer = BytesMut::from(&[0x02, 0x01, 0x02][..]);
+
+        let got = codec.decode(&mut buffer).unwrap_err();
+
+        assert!(matches!(got, CodecError::TrailingBytes { count: 1 }));
+    }
+
+    /// A packet which is just the rest of its frame.
+    #[derive(Debug, Clone, PartialEq)]
+    struct Blob(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for Blob {
+        fn deserialize(
+            buffer: &'de [u8],
+        ) -> Result<(Self, &'de [u8]), DeserializeError> {
+            Ok((Blob(buffer.to_vec()), &[]))
+        }
+
+        fn deserialize_limited(
+            buffer: &'de [u8],
+            limits: &mut DecodeLimits,
+        ) -> Result<(Self, &'de [u8]), DeserializeError> {
+            limits.array::<u8>(buffer.len())?;
+            Self::deserialize(buffer)
+        }
+    }
+
+    impl Serialize for Blob {
+        fn serialize(
+            &self,
+            buffer: &mut Vec<u8>,
+        ) -> Result<(), crate::SerializeError> {
+            buffer.extend_from_slice(&self.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn packets_are_decoded_within_the_limits() {
+        let limits = DecodeLimits::new().max_allocation(2);
+        let mut codec = PacketCodec::<Blob>::new().limits(limits);
+        let mut buffer = BytesMut::from(&[0x02, 0x01, 0x02, 0x03, 1, 2, 3][..]);
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Blob(vec![1, 2])));
+        let got = codec.decode(&mut buffer).unwrap_err();
+
+        assert!(matches!(
+            got,
+            CodecError::Deserialize(DeserializeError::LimitExceeded {
+                limit: crate::Limit::Allocation,
+                requested: 3,
+                max: 2,
+            })
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "zlib")]
+    fn compressed_frames_round_trip() {
+        let mut codec = PacketCodec::<Blob>::new();
+        codec.set_compression_threshold(Some(64));
+        let mut buffer = BytesMut::new();
+        let small = Blob(vec![0x2a; 3]);
+        let large = Blob(vec![0x2a; 256]);
+
+        codec.encode(small.clone(), &mut buffer).unwrap();
+        // payloads below the threshold are sent as-is after a data length
+        // of zero
+        assert_eq!(&buffer[..], &[0x04, 0x00, 0x2a, 0x2a, 0x2a]);
+        codec.encode(large.clone(), &mut buffer).unwrap();
+        assert!(buffer.len() < 6 + large.0.len());
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(small));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(large));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "zlib")]
+    fn codec_frames_can_be_read_by_a_frame_reader() {
+        let mut codec = PacketCodec::<u16>::new();
+        codec.set_compression_threshold(Some(1));
+        let mut buffer = BytesMut::new();
+        codec.encode(0x0102, &mut buffer).unwrap();
+
+        let mut reader = crate::framing::FrameReader::new(&buffer[..]);
+        reader.set_compression_threshold(Some(1));
+
+        assert_eq!(reader.read_packet::<u16>().unwrap(), Some(0x0102));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{AsSwitchKey, DeserializeError, SwitchKey, Value};
+use alloc::{string::String, vec::Vec};
+use core::convert::TryFrom;
+
+/// Previously decoded values which later parts of a packet may depend on,
+/// such as the field a `switch` compares against or the arguments passed to
+/// a parametrized type.
+///
+/// Each struct being deserialized gets its own scope (see
+/// [`Context::child()`]), so paths are resolved the same way as a `switch`'s
+/// `compareTo` in a `protocol.json` file:
+///
+/// - `action` is a field from the current scope
+/// - `../action` is a field from the parent's scope
+/// - `$action` is a parameter, looked up from the innermost scope outwards
+///
+/// ```rust
+/// use protodef_core::{Context, SwitchKey};
+///
+/// let root = Context::new().with_param("version", 754);
+/// let mut packet = root.child();
+/// packet.insert("action", &2_u8);
+/// let body = packet.child();
+///
+/// assert_eq!(body.switch_key("../action")?, SwitchKey::Integer(2));
+/// assert_eq!(body.switch_key("$version")?, SwitchKey::Integer(754));
+/// assert!(body.switch_key("action").is_err());
+/// # Ok::<(), protodef_core::DeserializeError>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context<'a> {
+    parent: Option<&'a Context<'a>>,
+    fields: Vec<(&'static str, Value)>,
+    params: Vec<(&'static str, Value)>,
+}
+
+impl<'a> Context<'a> {
+    /// Create a new, empty context.
+    pub fn new() -> Self {
+        Context {
+            parent: None,
+            fields: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Add a parameter which can be referred to as `$name`.
+    pub fn with_param(
+        mut self,
+        name: &'static str,
+        value: impl Into<Value>,
+    ) -> Self {
+        self.params.push((name, value.into()));
+        self
+    }
+
+    /// Create the scope for a nested struct.
+    pub fn child(&'a self) -> Context<'a> {
+        Context {
+            parent: Some(self),
+            fields: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Record a decoded field so nested types can refer to it.
+    pub fn insert<K>(&mut self, name: &'static str, value: &K)
+    where
+        K: AsSwitchKey + ?Sized,
+    {
+        self.fields.push((name, Value::from(value.as_switch_key())));
+    }
+
+    /// Resolve a path (e.g. `../action` or `$version`) to a value.
+    pub fn get(&self, path: &str) -> Option<&Value> {
+        if let Some(param) = path.strip_prefix('$') {
+            return self.param(param);
+        }
+
+        let mut scope = self;
+        let mut name = path;
+
+        while let Some(rest) = name.strip_prefix("../") {
+            scope = scope.parent?;
+            name = rest;
+        }
+
+        scope
+            .fields
+            .iter()
+            .rev()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Resolve a path to the [`SwitchKey`] a `switch` should use.
+    pub fn switch_key(
+        &self,
+        path: &str,
+    ) -> Result<SwitchKey<'_>, DeserializeError> {
+        self.get(path)
+            .and_then(Value::as_switch_key)
+            .ok_or_else(|| DeserializeError::MissingContextValue {
+                path: String::from(path),
+            })
+    }
+
+    /// Resolve a path to the number of items in an array or buffer (e.g. a
+    /// `count` of `"columns"`).
+    pub fn count(&self, path: &str) -> Result<usize, DeserializeError> {
+        let count =
+            self.get(path).and_then(Value::as_integer).ok_or_else(|| {
+                DeserializeError::MissingContextValue {
+                    path: String::from(path),
+                }
+            })?;
+
+        usize::try_from(count)
+            .map_err(|_| DeserializeError::InvalidLength { length: count })
+    }
+
+    fn param(&self, name: &str) -> Option<&Value> {
+        let mut scope = Some(self);
+
+        while let Some(current) = scope {
+            if let Some((_, value)) =
+                current.params.iter().find(|(param, _)| *param == name)
+            {
+                return Some(value);
+            }
+            scope = current.parent;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn walk_up_several_scopes() {
+        let mut root = Context::new();
+        root.insert("kind", "chat");
+        let mut middle = root.child();
+        middle.insert("kind", &true);
+        let leaf = middle.child();
+
+        assert_eq!(leaf.switch_key("../kind").unwrap(), SwitchKey::Bool(true));
+        assert_eq!(
+            leaf.switch_key("../../kind").unwrap(),
+            SwitchKey::String("chat")
+        );
+        assert!(leaf.get("../../../kind").is_none());
+    }
+
+    #[test]
+    fn missing_values_are_an_error() {
+        let got = Context::new().switch_key("../action").unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::MissingContextValue { path } if path == "../action"
+        ));
+    }
+
+    #[test]
+    fn counts_must_be_non_negative_integers() {
+        let mut scope = Context::new();
+        scope.insert("columns", &3_u8);
+        scope.insert("delta", &-1_i8);
+        scope.insert("name", "chat");
+
+        assert_eq!(scope.count("columns").unwrap(), 3);
+        assert!(matches!(
+            scope.count("delta").unwrap_err(),
+            DeserializeError::InvalidLength { length: -1 }
+        ));
+        assert!(matches!(
+            scope.count("name").unwrap_err(),
+            DeserializeError::MissingContextValue { .. }
+        ));
+    }
+
+    #[test]
+    fn inner_params_shadow_outer_ones() {
+        let root = Context::new().with_param("x", 1_u8);
+        let child = root.child().with_param("x", 2_u8);
+
+        assert_eq!(child.switch_key("$x").unwrap(), SwitchKey::Integer(2));
+        assert_eq!(root.switch_key("$x").unwrap(), SwitchKey::Integer(1));
+    }
+}
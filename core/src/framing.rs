@@ -0,0 +1,614 @@
+//! Splitting a stream into frames, where each frame is prefixed by its
+//! length as a [`Varint`].
+//!
+//! This is the framing used by the Minecraft protocol. Once a compression
+//! threshold is set (requires the `zlib` feature), each frame also starts
+//! with the uncompressed length of its payload, and payloads at least as
+//! long as the threshold are compressed with zlib.
+//!
+//! ```rust
+//! use protodef_core::framing::{FrameReader, FrameWriter};
+//!
+//! let mut writer = FrameWriter::new(Vec::new());
+//! writer.write_packet(&0x0102_u16)?;
+//! writer.write_frame(b"Hello")?;
+//! let bytes = writer.into_inner();
+//!
+//! assert_eq!(bytes, b"\x02\x01\x02\x05Hello");
+//!
+//! let mut reader = FrameReader::new(&bytes[..]);
+//! assert_eq!(reader.read_packet::<u16>()?, Some(0x0102));
+//! assert_eq!(reader.read_frame()?, Some(b"Hello".to_vec()));
+//! assert_eq!(reader.read_frame()?, None);
+//! # Ok::<(), protodef_core::framing::FrameError>(())
+//! ```
+
+use crate::{
+    native::Varint, read_exact, DecodeLimits, Deserialize, DeserializeError,
+    Length, Serialize, SerializeError,
+};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    io::{self, Read, Write},
+};
+
+/// The largest frame accepted by default (2 MiB).
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 2 * 1024 * 1024;
+
+/// How frames are laid out and the packets inside them decoded, shared by
+/// everything which reads or writes frames (i.e. [`FrameReader`],
+/// [`FrameWriter`], and the `tokio` codec).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Framing {
+    pub(crate) max_frame_length: usize,
+    pub(crate) compression_threshold: Option<usize>,
+    /// A template for the limits used when deserializing each packet.
+    pub(crate) limits: DecodeLimits,
+}
+
+impl Framing {
+    pub(crate) const fn new() -> Self {
+        Framing {
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+            compression_threshold: None,
+            limits: DecodeLimits::new(),
+        }
+    }
+
+    /// Parse the length prefix at the start of a buffer, returning the
+    /// length of the prefix and of the frame which follows it, or `None` if
+    /// the prefix is incomplete.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn frame_length(
+        &self,
+        buffer: &[u8],
+    ) -> Result<Option<(usize, usize)>, FrameError> {
+        match Varint::deserialize(buffer) {
+            Ok((length, rest)) => {
+                let length = self.check_length(length.to_length()?)?;
+                Ok(Some((buffer.len() - rest.len(), length)))
+            },
+            Err(DeserializeError::UnexpectedEndOfInput { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn check_length(&self, length: usize) -> Result<usize, FrameError> {
+        if length > self.max_frame_length {
+            Err(FrameError::FrameTooLong {
+                length,
+                max: self.max_frame_length,
+            })
+        } else {
+            Ok(length)
+        }
+    }
+
+    /// Get the payload from a frame, decompressing it if necessary.
+    pub(crate) fn unpack<'a>(
+        &self,
+        frame: &'a [u8],
+    ) -> Result<Cow<'a, [u8]>, FrameError> {
+        if self.compression_threshold.is_none() {
+            return Ok(Cow::Borrowed(frame));
+        }
+
+        let (data_length, compressed) = Varint::deserialize(frame)?;
+        let data_length = data_length.to_length()?;
+
+        if data_length == 0 {
+            // the payload was below the threshold, so it was sent as-is
+            return Ok(Cow::Borrowed(compressed));
+        }
+
+        self.check_length(data_length)?;
+        zlib::decompress(compressed, data_length).map(Cow::Owned)
+    }
+
+    /// Write a payload as a single frame, including its length prefix.
+    pub(crate) fn pack(
+        &self,
+        payload: &[u8],
+        dst: &mut Vec<u8>,
+    ) -> Result<(), FrameError> {
+        let mut frame = Vec::new();
+
+        match self.compression_threshold {
+            None => frame.extend_from_slice(payload),
+            // a data length of 0 means "uncompressed", so empty payloads
+            // can never be compressed
+            Some(threshold)
+                if payload.len() < threshold || payload.is_empty() =>
+            {
+                Varint(0).serialize(&mut frame)?;
+                frame.extend_from_slice(payload);
+            },
+            Some(_) => {
+                Varint::from_length(payload.len())?.serialize(&mut frame)?;
+                zlib::compress(payload, &mut frame)?;
+            },
+        }
+
+        self.check_length(frame.len())?;
+        Varint::from_length(frame.len())?.serialize(dst)?;
+        dst.extend_from_slice(&frame);
+
+        Ok(())
+    }
+
+    /// Deserialize a packet which should take up its entire payload.
+    pub(crate) fn deserialize<'de, T>(
+        &self,
+        payload: &'de [u8],
+    ) -> Result<T, FrameError>
+    where
+        T: Deserialize<'de>,
+    {
+        let (packet, rest) =
+            T::deserialize_limited(payload, &mut self.limits.clone())?;
+
+        if rest.is_empty() {
+            Ok(packet)
+        } else {
+            Err(FrameError::TrailingBytes { count: rest.len() })
+        }
+    }
+}
+
+impl Default for Framing {
+    fn default() -> Self { Framing::new() }
+}
+
+/// Reads length-prefixed frames from a [`Read`]er.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    reader: R,
+    framing: Framing,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        FrameReader {
+            reader,
+            framing: Framing::new(),
+        }
+    }
+
+    /// Reject any frames (or decompressed payloads) longer than this many
+    /// bytes.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.framing.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// The limits used when deserializing each packet with
+    /// [`FrameReader::read_packet()`].
+    pub fn limits(mut self, limits: DecodeLimits) -> Self {
+        self.framing.limits = limits;
+        self
+    }
+
+    /// Expect frames to use the compressed format from now on, or go back
+    /// to uncompressed frames with `None`.
+    ///
+    /// This mirrors [`FrameWriter::set_compression_threshold()`]. Only the
+    /// writer needs the actual threshold, because each compressed frame says
+    /// whether its payload was compressed.
+    #[cfg(feature = "zlib")]
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.framing.compression_threshold = threshold;
+    }
+
+    /// Read the next frame's payload, decompressing it if necessary.
+    ///
+    /// Returns `None` if the stream ended cleanly before the next frame.
+    pub fn read_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let length = match read_frame_length(&mut self.reader)? {
+            Some(length) => self.framing.check_length(length)?,
+            None => return Ok(None),
+        };
+
+        let mut frame = vec![0; length];
+        read_exact(&mut self.reader, &mut frame)?;
+
+        // avoid copying payloads which weren't compressed
+        let start = match self.framing.unpack(&frame)? {
+            Cow::Owned(payload) => return Ok(Some(payload)),
+            Cow::Borrowed(payload) => frame.len() - payload.len(),
+        };
+        frame.drain(..start);
+
+        Ok(Some(frame))
+    }
+
+    /// Read the next frame and deserialize it as a `T`.
+    pub fn read_packet<T>(&mut self) -> Result<Option<T>, FrameError>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        match self.read_frame()? {
+            Some(frame) => self.framing.deserialize(&frame).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R { &self.reader }
+
+    pub fn get_mut(&mut self) -> &mut R { &mut self.reader }
+
+    pub fn into_inner(self) -> R { self.reader }
+}
+
+/// Writes length-prefixed frames to a [`Write`]r.
+#[derive(Debug)]
+pub struct FrameWriter<W> {
+    writer: W,
+    framing: Framing,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(writer: W) -> Self {
+        FrameWriter {
+            writer,
+            framing: Framing::new(),
+        }
+    }
+
+    /// Refuse to write frames longer than this many bytes.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.framing.max_frame_length = max_frame_length;
+        self
+    }
+
+    /// Switch to the compressed frame format, compressing any non-empty
+    /// payloads which are at least `threshold` bytes long, or go back to
+    /// uncompressed frames with `None`.
+    #[cfg(feature = "zlib")]
+    pub fn set_compression_threshold(&mut self, threshold: Option<usize>) {
+        self.framing.compression_threshold = threshold;
+    }
+
+    /// Write a payload as a single frame.
+    pub fn write_frame(&mut self, payload: &[u8]) -> Result<(), FrameError> {
+        let mut bytes = Vec::new();
+        self.framing.pack(payload, &mut bytes)?;
+        self.writer.write_all(&bytes)?;
+
+        Ok(())
+    }
+
+    /// Serialize a packet and write it as a single frame.
+    pub fn write_packet<T>(&mut self, packet: &T) -> Result<(), FrameError>
+    where
+        T: Serialize + ?Sized,
+    {
+        let mut payload = Vec::new();
+        packet.serialize(&mut payload)?;
+        self.write_frame(&payload)
+    }
+
+    pub fn flush(&mut self) -> Result<(), FrameError> {
+        self.writer.flush().map_err(FrameError::from)
+    }
+
+    pub fn get_ref(&self) -> &W { &self.writer }
+
+    pub fn get_mut(&mut self) -> &mut W { &mut self.writer }
+
+    pub fn into_inner(self) -> W { self.writer }
+}
+
+/// Read the varint at the start of a frame, returning `None` if the stream
+/// ends before it starts.
+fn read_frame_length<R: Read>(
+    reader: &mut R,
+) -> Result<Option<usize>, FrameError> {
+    let mut first = [0];
+
+    loop {
+        match reader.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut header = vec![first[0]];
+
+    while header.len() < 5 && header[header.len() - 1] & 0x80 != 0 {
+        let mut byte = [0];
+        read_exact(reader, &mut byte)?;
+        header.push(byte[0]);
+    }
+
+    let (length, _) = Varint::deserialize(&header)?;
+    length.to_length().map(Some).map_err(FrameError::from)
+}
+
+#[cfg(feature = "zlib")]
+mod zlib {
+    use super::FrameError;
+    use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+    use std::io::{Read, Write};
+
+    pub(super) fn compress(
+        payload: &[u8],
+        frame: &mut Vec<u8>,
+    ) -> Result<(), FrameError> {
+        let mut encoder = ZlibEncoder::new(frame, Compression::default());
+        encoder.write_all(payload)?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    pub(super) fn decompress(
+        compressed: &[u8],
+        length: usize,
+    ) -> Result<Vec<u8>, FrameError> {
+        let mut payload = Vec::with_capacity(length);
+        // read one byte more than expected so we can tell when the payload
+        // is too long, without letting it use up all our memory
+        ZlibDecoder::new(compressed)
+            .take(length as u64 + 1)
+            .read_to_end(&mut payload)?;
+
+        if payload.len() == length {
+            Ok(payload)
+        } else {
+            Err(FrameError::IncorrectUncompressedLength {
+                expected: length,
+                actual: payload.len(),
+            })
+        }
+    }
+}
+
+#[cfg(not(feature = "zlib"))]
+mod zlib {
+    //! Without the `zlib` feature there is no way to set a compression
+    //! threshold, so these are never called.
+
+    use super::FrameError;
+
+    pub(super) fn compress(
+        _payload: &[u8],
+        _frame: &mut Vec<u8>,
+    ) -> Result<(), FrameError> {
+        unreachable!("compression requires the \"zlib\" feature")
+    }
+
+    pub(super) fn decompress(
+        _compressed: &[u8],
+        _length: usize,
+    ) -> Result<Vec<u8>, FrameError> {
+        unreachable!("compression requires the \"zlib\" feature")
+    }
+}
+
+/// Errors returned by a [`FrameReader`], [`FrameWriter`], or the `tokio`
+/// codec.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum FrameError {
+    Io(io::Error),
+    Deserialize(DeserializeError),
+    Serialize(SerializeError),
+    /// A frame was longer than the maximum frame length allows.
+    FrameTooLong {
+        length: usize,
+        max: usize,
+    },
+    /// A packet didn't use up all the bytes in its frame.
+    TrailingBytes {
+        count: usize,
+    },
+    /// A compressed payload didn't decompress to the length it said it
+    /// would.
+    IncorrectUncompressedLength {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(_) => write!(f, "unable to read or write a frame"),
+            FrameError::Deserialize(_) => {
+                write!(f, "unable to deserialize a packet")
+            },
+            FrameError::Serialize(_) => {
+                write!(f, "unable to serialize a packet")
+            },
+            FrameError::FrameTooLong { length, max } => write!(
+                f,
+                "a frame of {} bytes is longer than the maximum of {}",
+                length, max
+            ),
+            FrameError::TrailingBytes { count } => write!(
+                f,
+                "{} bytes were left over after deserializing a packet",
+                count
+            ),
+            FrameError::IncorrectUncompressedLength { expected, actual } => {
+                write!(
+                    f,
+                    "expected the payload to decompress to {} bytes, but got {}",
+                    expected, actual
+                )
+            },
+        }
+    }
+}
+
+impl Error for FrameError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FrameError::Io(inner) => Some(inner),
+            FrameError::Deserialize(inner) => Some(inner),
+            FrameError::Serialize(inner) => Some(inner),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self { FrameError::Io(e) }
+}
+
+impl From<DeserializeError> for FrameError {
+    fn from(e: DeserializeError) -> Self { FrameError::Deserialize(e) }
+}
+
+impl From<SerializeError> for FrameError {
+    fn from(e: SerializeError) -> Self { FrameError::Serialize(e) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_prefixed_by_their_length() {
+        let payload = vec![0xab; 300];
+        let mut writer = FrameWriter::new(Vec::new());
+
+        writer.write_frame(&payload).unwrap();
+        writer.write_frame(&[]).unwrap();
+
+        let bytes = writer.into_inner();
+        assert_eq!(&bytes[..2], &[0xac, 0x02]);
+        assert_eq!(bytes.len(), 2 + 300 + 1);
+        let mut reader = FrameReader::new(&bytes[..]);
+        assert_eq!(reader.read_frame().unwrap(), Some(payload));
+        assert_eq!(reader.read_frame().unwrap(), Some(Vec::new()));
+        assert_eq!(reader.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn truncated_frames_are_an_error() {
+        let mut reader = FrameReader::new(&[0x05, b'H', b'i'][..]);
+
+        let got = reader.read_frame().unwrap_err();
+
+        assert!(matches!(
+            got,
+            FrameError::Deserialize(DeserializeError::UnexpectedEndOfInput {
+                needed: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn the_maximum_frame_length_is_enforced() {
+        let mut reader =
+            FrameReader::new(&[0x05, 0, 0, 0, 0, 0][..]).max_frame_length(4);
+        let mut writer = FrameWriter::new(Vec::new()).max_frame_length(4);
+
+        let read = reader.read_frame().unwrap_err();
+        let written = writer.write_frame(&[0; 5]).unwrap_err();
+
+        assert!(matches!(
+            read,
+            FrameError::FrameTooLong { length: 5, max: 4 }
+        ));
+        assert!(matches!(
+            written,
+            FrameError::FrameTooLong { length: 5, max: 4 }
+        ));
+        assert!(writer.get_ref().is_empty());
+    }
+
+    #[test]
+    fn packets_must_fill_their_frame() {
+        let mut reader = FrameReader::new(&[0x02, 0x01, 0x02][..]);
+
+        let got = reader.read_packet::<u8>().unwrap_err();
+
+        assert!(matches!(got, FrameError::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn packets_are_decoded_within_the_limits() {
+        let limits = DecodeLimits::new().max_allocation(2);
+        let mut reader =
+            FrameReader::new(&[0x03, 0x01, 0x02, 0x03][..]).limits(limits);
+
+        let got = reader
+            .read_packet::<crate::native::RestBuffer>()
+            .unwrap_err();
+
+        assert!(matches!(
+            got,
+            FrameError::Deserialize(DeserializeError::LimitExceeded {
+                limit: crate::Limit::Allocation,
+                requested: 3,
+                max: 2,
+            })
+        ));
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn payloads_over_the_threshold_are_compressed() {
+        let small = b"tiny".to_vec();
+        let big = vec![b'a'; 256];
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.set_compression_threshold(Some(64));
+
+        writer.write_frame(&small).unwrap();
+        writer.write_frame(&big).unwrap();
+
+        let bytes = writer.into_inner();
+        // small payloads get a data length of 0 and are sent as-is
+        assert_eq!(&bytes[..6], b"\x05\x00tiny");
+        // big ones record their uncompressed length and shrink
+        assert_eq!(&bytes[7..9], &[0x80, 0x02]);
+        assert!(bytes.len() < 6 + big.len());
+        let mut reader = FrameReader::new(&bytes[..]);
+        reader.set_compression_threshold(Some(64));
+        assert_eq!(reader.read_frame().unwrap(), Some(small));
+        assert_eq!(reader.read_frame().unwrap(), Some(big));
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn empty_payloads_are_never_compressed() {
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.set_compression_threshold(Some(0));
+
+        writer.write_frame(b"").unwrap();
+
+        let bytes = writer.into_inner();
+        assert_eq!(bytes, &[0x01, 0x00]);
+        let mut reader = FrameReader::new(&bytes[..]);
+        reader.set_compression_threshold(Some(0));
+        assert_eq!(reader.read_frame().unwrap(), Some(Vec::new()));
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn lying_about_the_uncompressed_length() {
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.set_compression_threshold(Some(0));
+        writer.write_frame(b"Hello, World!").unwrap();
+        let mut bytes = writer.into_inner();
+        // claim the payload is 12 bytes instead of 13
+        bytes[1] = 12;
+
+        let mut reader = FrameReader::new(&bytes[..]);
+        reader.set_compression_threshold(Some(0));
+        let got = reader.read_frame().unwrap_err();
+
+        assert!(matches!(
+            got,
+            FrameError::IncorrectUncompressedLength {
+                expected: 12,
+                actual: 13
+            }
+        ));
+    }
+}
@@ -1,35 +1,296 @@
 //! Core abstractions and types used by ProtoDef-generated code.
+//!
+//! # Feature Flags
+//!
+//! This crate is `no_std` when the default `std` feature is disabled.
+//!
+//! - `std` (default) - streaming with [`ReadFrom`], the [`framing`] module, and
+//!   [`std::error::Error`] impls
+//! - `alloc` - anything which needs to allocate, including [`Serialize`],
+//!   strings, arrays, and [`Value`]. Without it, only fixed-size types can be
+//!   deserialized
+//! - `zlib` - compressed frames in the [`framing`] module
+//! - `tokio` - a [`tokio_util::codec`] integration
+//! - `serde` - `Serialize` and `Deserialize` impls for the [`native`] types, so
+//!   generated code can use `#[derive(serde::Serialize)]`
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "tokio")]
+pub mod codec;
+#[cfg(feature = "alloc")]
+mod context;
+#[cfg(feature = "std")]
+pub mod framing;
+mod limits;
 pub mod native;
+mod prefixed;
+#[cfg(feature = "serde")]
+mod serde_impls;
+mod state;
+#[cfg(feature = "std")]
+mod stream;
+mod switch;
+#[cfg(feature = "alloc")]
+mod value;
+
+#[cfg(feature = "alloc")]
+pub use context::Context;
+pub use limits::{DecodeLimits, Limit};
+#[cfg(feature = "alloc")]
+pub use prefixed::{DeserializeCounted, SerializeCounted, SerializePrefixed};
+pub use prefixed::{DeserializePrefixed, Length};
+#[cfg(feature = "std")]
+pub use state::ReadFromStateful;
+pub use state::{
+    Connection, ConnectionState, DeserializeStateful, Direction, Stateful,
+};
+#[cfg(feature = "std")]
+pub use stream::{
+    read_buffered, read_buffered_limited, read_exact, ReadFrom,
+    ReadFromCounted, ReadFromPrefixed, ReadFromSwitch,
+};
+pub use switch::{AsSwitchKey, DeserializeSwitch, SwitchKey, UnknownKey};
+#[cfg(feature = "alloc")]
+pub use value::Value;
 
-use std::{
-    error::Error,
+#[cfg(feature = "alloc")]
+use alloc::{string::String, vec::Vec};
+use core::{
     fmt::{self, Display, Formatter},
+    str::Utf8Error,
 };
+#[cfg(feature = "std")]
+use std::{error::Error, io};
 
 /// Deserialize something from its binary form.
 pub trait Deserialize<'de>: Sized {
     fn deserialize(
         buffer: &'de [u8],
     ) -> Result<(Self, &'de [u8]), DeserializeError>;
+
+    /// Deserialize something, failing with a
+    /// [`DeserializeError::LimitExceeded`] instead of allocating more than
+    /// the [`DecodeLimits`] allow.
+    ///
+    /// The default implementation ignores the limits, which is only
+    /// appropriate for types that never allocate.
+    fn deserialize_limited(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let _ = limits;
+        Self::deserialize(buffer)
+    }
+
+    /// Deserialize something which may depend on values decoded earlier
+    /// (e.g. a `switch` comparing against `../action`).
+    ///
+    /// The default implementation ignores the [`Context`], which is only
+    /// appropriate for types that never contain a `switch` or parameter.
+    #[cfg(feature = "alloc")]
+    fn deserialize_with(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let _ = context;
+        Self::deserialize_limited(buffer, limits)
+    }
+}
+
+/// Serialize something to its binary form.
+#[cfg(feature = "alloc")]
+pub trait Serialize {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> Result<(), SerializeError>;
 }
 
 /// Any errors that can occur in [`Deserialize::deserialize()`].
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum DeserializeError {
+    /// We reached the end of the input while there were still bytes left to
+    /// read.
+    UnexpectedEndOfInput {
+        needed: usize,
+    },
+    /// A [`native::Varint`] was longer than 5 bytes.
+    VarintTooLong,
+    /// A length prefix was negative or didn't fit in a `usize`.
+    InvalidLength {
+        length: i128,
+    },
+    InvalidUtf8(Utf8Error),
+    /// Deserializing would have gone past one of the [`DecodeLimits`].
+    LimitExceeded {
+        limit: Limit,
+        requested: usize,
+        max: usize,
+    },
+    /// A value the [`Context`] was asked for was never decoded, or isn't
+    /// the kind of value that was needed (e.g. a count which isn't an
+    /// integer).
+    #[cfg(feature = "alloc")]
+    MissingContextValue {
+        path: String,
+    },
+    /// A `mapper` doesn't have a name for this value.
+    UnknownMapping {
+        value: i64,
+    },
+    /// A connection's current state doesn't have any packets which can be
+    /// sent in this direction.
+    NoPacketsInState {
+        state: &'static str,
+    },
+    /// None of a switch's variants matched the value it was compared to.
+    UnknownSwitchVariant {
+        key: UnknownKey,
+    },
+    /// A packet's `nextState` field doesn't refer to any of the connection's
+    /// states.
+    UnknownNextState {
+        key: UnknownKey,
+    },
+    /// Reading from the underlying stream failed (see [`ReadFrom`]).
+    #[cfg(feature = "std")]
+    Io(io::Error),
+    #[cfg(feature = "std")]
     Custom(Box<dyn Error>),
 }
 
 impl Display for DeserializeError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "Custom error")
+        match self {
+            DeserializeError::UnexpectedEndOfInput { needed } => write!(
+                f,
+                "reached the end of the input while {} more bytes were needed",
+                needed
+            ),
+            DeserializeError::VarintTooLong => write!(f, "varint too long"),
+            DeserializeError::InvalidLength { length } => {
+                write!(f, "{} is not a valid length", length)
+            },
+            DeserializeError::InvalidUtf8(_) => write!(f, "invalid UTF-8"),
+            DeserializeError::LimitExceeded {
+                limit,
+                requested,
+                max,
+            } => write!(f, "{} is over the {} of {}", requested, limit, max),
+            #[cfg(feature = "alloc")]
+            DeserializeError::MissingContextValue { path } => {
+                write!(f, "\"{}\" wasn't decoded before it was needed", path)
+            },
+            DeserializeError::UnknownMapping { value } => {
+                write!(f, "{} isn't one of the mapper's values", value)
+            },
+            DeserializeError::NoPacketsInState { state } => write!(
+                f,
+                "no packets can be sent this way in the \"{}\" state",
+                state
+            ),
+            DeserializeError::UnknownSwitchVariant { key } => {
+                write!(f, "no switch variant matches {}", key)
+            },
+            DeserializeError::UnknownNextState { key } => {
+                write!(f, "no connection state matches {}", key)
+            },
+            #[cfg(feature = "std")]
+            DeserializeError::Io(_) => write!(f, "unable to read the input"),
+            #[cfg(feature = "std")]
+            DeserializeError::Custom(_) => write!(f, "Custom error"),
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl Error for DeserializeError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
+            DeserializeError::InvalidUtf8(inner) => Some(inner),
+            DeserializeError::Io(inner) => Some(inner),
             DeserializeError::Custom(inner) => Some(&**inner),
+            _ => None,
+        }
+    }
+}
+
+/// Any errors that can occur in [`Serialize::serialize()`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SerializeError {
+    /// A length can't be represented by the length prefix's type.
+    LengthOutOfRange { length: usize },
+    #[cfg(feature = "std")]
+    Custom(Box<dyn Error>),
+}
+
+impl Display for SerializeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializeError::LengthOutOfRange { length } => write!(
+                f,
+                "a length of {} can't be stored in the length prefix",
+                length
+            ),
+            #[cfg(feature = "std")]
+            SerializeError::Custom(_) => write!(f, "Custom error"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for SerializeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SerializeError::Custom(inner) => Some(&**inner),
+            _ => None,
         }
     }
 }
+
+/// Split a certain number of bytes off the front of the buffer.
+pub fn take(
+    buffer: &[u8],
+    len: usize,
+) -> Result<(&[u8], &[u8]), DeserializeError> {
+    if buffer.len() >= len {
+        Ok(buffer.split_at(len))
+    } else {
+        Err(DeserializeError::UnexpectedEndOfInput {
+            needed: len - buffer.len(),
+        })
+    }
+}
+
+/// Items used by code from `#[derive(ProtoDef)]`, which can't know which
+/// features this crate was compiled with.
+#[doc(hidden)]
+pub mod __private {
+    #[cfg(feature = "alloc")]
+    pub extern crate alloc;
+    pub extern crate core;
+    #[cfg(feature = "std")]
+    pub extern crate std;
+}
+
+/// Keep the tokens when the `std` feature is enabled, and drop them
+/// otherwise.
+#[cfg(feature = "std")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __std_only {
+    ($($tokens:tt)*) => { $($tokens)* };
+}
+
+/// Keep the tokens when the `std` feature is enabled, and drop them
+/// otherwise.
+#[cfg(not(feature = "std"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __std_only {
+    ($($tokens:tt)*) => {};
+}
@@ -0,0 +1,224 @@
+use crate::DeserializeError;
+use core::fmt::{self, Display, Formatter};
+
+/// Don't pre-allocate more than this many items when the length of an array
+/// comes from the input.
+#[cfg(feature = "alloc")]
+pub(crate) const MAX_PREALLOCATED_ITEMS: usize = 1024;
+
+/// Limits used to protect against malicious input when deserializing (e.g.
+/// a length prefix claiming a string is several gigabytes long).
+///
+/// The limits are checked by [`Deserialize::deserialize_limited()`] and
+/// friends. Usage is tracked as a value is deserialized, so create a fresh
+/// set of limits (or copy a template) for every packet.
+///
+/// ```rust
+/// use protodef_core::{
+///     native::Varint, DecodeLimits, DeserializeError, DeserializePrefixed,
+///     Limit,
+/// };
+///
+/// let limits = DecodeLimits::new().max_string_length(4);
+///
+/// let got = <&str>::deserialize_prefixed_limited::<Varint>(
+///     b"\x05Hello",
+///     &mut limits.clone(),
+/// );
+///
+/// assert!(matches!(
+///     got,
+///     Err(DeserializeError::LimitExceeded {
+///         limit: Limit::StringLength,
+///         requested: 5,
+///         max: 4,
+///     })
+/// ));
+/// ```
+///
+/// [`Deserialize::deserialize_limited()`]: crate::Deserialize::deserialize_limited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    max_string_length: usize,
+    max_array_elements: usize,
+    max_depth: usize,
+    max_allocation: usize,
+    depth: usize,
+    allocated: usize,
+}
+
+impl DecodeLimits {
+    /// Create a new set of limits, where nothing is limited.
+    pub const fn new() -> Self {
+        DecodeLimits {
+            max_string_length: usize::MAX,
+            max_array_elements: usize::MAX,
+            max_depth: usize::MAX,
+            max_allocation: usize::MAX,
+            depth: 0,
+            allocated: 0,
+        }
+    }
+
+    /// The longest string (in bytes) that may be deserialized.
+    pub fn max_string_length(mut self, max: usize) -> Self {
+        self.max_string_length = max;
+        self
+    }
+
+    /// The most elements an array (or bytes a buffer) may contain.
+    pub fn max_array_elements(mut self, max: usize) -> Self {
+        self.max_array_elements = max;
+        self
+    }
+
+    /// How deeply structs and switches may be nested.
+    pub fn max_depth(mut self, max: usize) -> Self {
+        self.max_depth = max;
+        self
+    }
+
+    /// The most bytes that may be allocated while deserializing a value,
+    /// across all of its strings, buffers, and arrays.
+    pub fn max_allocation(mut self, max: usize) -> Self {
+        self.max_allocation = max;
+        self
+    }
+
+    /// The number of bytes allocated so far.
+    pub const fn allocated(&self) -> usize { self.allocated }
+
+    /// Check a string's length before it is deserialized, recording the
+    /// allocation if the string is going to be copied.
+    pub fn string(
+        &mut self,
+        length: usize,
+        copied: bool,
+    ) -> Result<(), DeserializeError> {
+        check(Limit::StringLength, length, self.max_string_length)?;
+
+        if copied {
+            self.allocate(length)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check an array's length before it is deserialized, recording the
+    /// memory needed to store its elements.
+    ///
+    /// Zero-sized elements (e.g. `()`) are counted as a byte each, because
+    /// they don't use any input and would otherwise let a length prefix make
+    /// us loop for as long as it likes.
+    pub fn array<T>(&mut self, length: usize) -> Result<(), DeserializeError> {
+        check(Limit::ArrayElements, length, self.max_array_elements)?;
+        let element = core::cmp::max(core::mem::size_of::<T>(), 1);
+        self.allocate(length.saturating_mul(element))
+    }
+
+    /// Record that some bytes are about to be allocated.
+    pub fn allocate(&mut self, bytes: usize) -> Result<(), DeserializeError> {
+        let total = self.allocated.saturating_add(bytes);
+        check(Limit::Allocation, total, self.max_allocation)?;
+        self.allocated = total;
+        Ok(())
+    }
+
+    /// Start deserializing a nested struct or switch.
+    pub fn enter(&mut self) -> Result<(), DeserializeError> {
+        check(Limit::Depth, self.depth + 1, self.max_depth)?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Finish deserializing something started with [`DecodeLimits::enter()`].
+    pub fn leave(&mut self) { self.depth = self.depth.saturating_sub(1); }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self { DecodeLimits::new() }
+}
+
+fn check(
+    limit: Limit,
+    requested: usize,
+    max: usize,
+) -> Result<(), DeserializeError> {
+    if requested <= max {
+        Ok(())
+    } else {
+        Err(DeserializeError::LimitExceeded {
+            limit,
+            requested,
+            max,
+        })
+    }
+}
+
+/// The [`DecodeLimits`] that were exceeded in a
+/// [`DeserializeError::LimitExceeded`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Limit {
+    StringLength,
+    ArrayElements,
+    Depth,
+    Allocation,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::StringLength => write!(f, "maximum string length"),
+            Limit::ArrayElements => write!(f, "maximum array length"),
+            Limit::Depth => write!(f, "maximum nesting depth"),
+            Limit::Allocation => write!(f, "maximum allocation"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_add_up() {
+        let mut limits = DecodeLimits::new().max_allocation(10);
+
+        limits.string(4, true).unwrap();
+        limits.string(100, false).unwrap();
+        limits.array::<u16>(3).unwrap();
+        let got = limits.allocate(1).unwrap_err();
+
+        assert_eq!(limits.allocated(), 10);
+        assert!(matches!(
+            got,
+            DeserializeError::LimitExceeded {
+                limit: Limit::Allocation,
+                requested: 11,
+                max: 10,
+            }
+        ));
+    }
+
+    #[test]
+    fn zero_sized_elements_are_counted() {
+        let mut limits = DecodeLimits::new().max_allocation(10);
+
+        limits.array::<()>(10).unwrap();
+
+        assert_eq!(limits.allocated(), 10);
+        assert!(limits.array::<()>(1).is_err());
+    }
+
+    #[test]
+    fn nesting_depth() {
+        let mut limits = DecodeLimits::new().max_depth(2);
+
+        limits.enter().unwrap();
+        limits.enter().unwrap();
+        assert!(limits.enter().is_err());
+        limits.leave();
+        limits.enter().unwrap();
+    }
+}
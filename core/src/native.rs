@@ -1 +1,335 @@
 //! Builtin types which get special treatment by the codegen tool.
+//!
+//! Each `"native"` type from a `protocol.json` file is referred to by its
+//! `UpperCamelCase` name (e.g. `varint` becomes [`Varint`] and `UUID` becomes
+//! [`Uuid`]).
+
+use crate::{take, DecodeLimits, Deserialize, DeserializeError};
+#[cfg(feature = "alloc")]
+use crate::{Context, Serialize, SerializeError};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+
+pub type I8 = i8;
+pub type I16 = i16;
+pub type I32 = i32;
+pub type I64 = i64;
+pub type U8 = u8;
+pub type U16 = u16;
+pub type U32 = u32;
+pub type U64 = u64;
+pub type F32 = f32;
+pub type F64 = f64;
+pub type Bool = bool;
+
+/// A type with no content.
+pub type Void = ();
+
+/// A variable-length integer, as used by Google's Protocol Buffers.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Varint(pub i32);
+
+impl From<i32> for Varint {
+    fn from(value: i32) -> Self { Varint(value) }
+}
+
+impl From<Varint> for i32 {
+    fn from(value: Varint) -> Self { value.0 }
+}
+
+/// A 128-bit universally unique identifier.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uuid(pub [u8; 16]);
+
+/// Uses the hyphenated form (e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`).
+impl Display for Uuid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// All remaining bytes in the input.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct RestBuffer(pub Vec<u8>);
+
+macro_rules! impl_big_endian {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl<'de> Deserialize<'de> for $ty {
+                fn deserialize(
+                    buffer: &'de [u8],
+                ) -> Result<(Self, &'de [u8]), DeserializeError> {
+                    let (bytes, rest) =
+                        take(buffer, core::mem::size_of::<$ty>())?;
+                    let mut raw = [0; core::mem::size_of::<$ty>()];
+                    raw.copy_from_slice(bytes);
+
+                    Ok((<$ty>::from_be_bytes(raw), rest))
+                }
+            }
+
+            #[cfg(feature = "alloc")]
+            impl Serialize for $ty {
+                fn serialize(
+                    &self,
+                    buffer: &mut Vec<u8>,
+                ) -> Result<(), SerializeError> {
+                    buffer.extend_from_slice(&self.to_be_bytes());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_big_endian!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+impl<'de> Deserialize<'de> for bool {
+    fn deserialize(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let (byte, rest) = u8::deserialize(buffer)?;
+        Ok((byte != 0, rest))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Serialize for bool {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> Result<(), SerializeError> {
+        buffer.push(*self as u8);
+        Ok(())
+    }
+}
+
+/// An `option` is a `bool` saying whether the value is present, followed by
+/// the value itself.
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Option<T> {
+    fn deserialize(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        Self::deserialize_limited(buffer, &mut DecodeLimits::new())
+    }
+
+    fn deserialize_limited(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let (present, rest) = bool::deserialize(buffer)?;
+
+        if present {
+            let (value, rest) = T::deserialize_limited(rest, limits)?;
+            Ok((Some(value), rest))
+        } else {
+            Ok((None, rest))
+        }
+    }
+
+    #[cfg(feature = "alloc")]
+    fn deserialize_with(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let (present, rest) = bool::deserialize(buffer)?;
+
+        if present {
+            let (value, rest) = T::deserialize_with(rest, limits, context)?;
+            Ok((Some(value), rest))
+        } else {
+            Ok((None, rest))
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Serialize> Serialize for Option<T> {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> Result<(), SerializeError> {
+        match self {
+            Some(value) => {
+                true.serialize(buffer)?;
+                value.serialize(buffer)
+            },
+            None => false.serialize(buffer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for () {
+    fn deserialize(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        Ok(((), buffer))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Serialize for () {
+    fn serialize(&self, _buffer: &mut Vec<u8>) -> Result<(), SerializeError> {
+        Ok(())
+    }
+}
+
+impl<'de> Deserialize<'de> for Varint {
+    fn deserialize(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let mut value: u32 = 0;
+
+        for (i, byte) in buffer.iter().enumerate().take(5) {
+            value |= u32::from(byte & 0x7f) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return Ok((Varint(value as i32), &buffer[i + 1..]));
+            }
+        }
+
+        if buffer.len() >= 5 {
+            Err(DeserializeError::VarintTooLong)
+        } else {
+            Err(DeserializeError::UnexpectedEndOfInput { needed: 1 })
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Serialize for Varint {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> Result<(), SerializeError> {
+        let mut value = self.0 as u32;
+
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                buffer.push(byte);
+                return Ok(());
+            }
+
+            buffer.push(byte | 0x80);
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Uuid {
+    fn deserialize(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let (bytes, rest) = take(buffer, 16)?;
+        let mut uuid = [0; 16];
+        uuid.copy_from_slice(bytes);
+
+        Ok((Uuid(uuid), rest))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Serialize for Uuid {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> Result<(), SerializeError> {
+        buffer.extend_from_slice(&self.0);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'de> Deserialize<'de> for RestBuffer {
+    fn deserialize(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        Ok((RestBuffer(buffer.to_vec()), &[]))
+    }
+
+    fn deserialize_limited(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        limits.array::<u8>(buffer.len())?;
+        Self::deserialize(buffer)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Serialize for RestBuffer {
+    fn serialize(&self, buffer: &mut Vec<u8>) -> Result<(), SerializeError> {
+        buffer.extend_from_slice(&self.0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "alloc")]
+    fn round_trip<T>(value: T, bytes: &[u8])
+    where
+        T: for<'de> Deserialize<'de> + Serialize + PartialEq + core::fmt::Debug,
+    {
+        let mut buffer = Vec::new();
+        value.serialize(&mut buffer).unwrap();
+        assert_eq!(buffer, bytes);
+
+        let (got, rest) = T::deserialize(bytes).unwrap();
+        assert_eq!(got, value);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn integers_are_big_endian() {
+        round_trip(0x0102_i16, &[0x01, 0x02]);
+        round_trip(-2_i32, &[0xff, 0xff, 0xff, 0xfe]);
+        round_trip(1.0_f32, &[0x3f, 0x80, 0x00, 0x00]);
+        round_trip(true, &[0x01]);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn options_are_prefixed_with_a_bool() {
+        round_trip(Some(0x0102_u16), &[0x01, 0x01, 0x02]);
+        round_trip(None::<u16>, &[0x00]);
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn varints() {
+        round_trip(Varint(0), &[0x00]);
+        round_trip(Varint(1), &[0x01]);
+        round_trip(Varint(300), &[0xac, 0x02]);
+        round_trip(Varint(2147483647), &[0xff, 0xff, 0xff, 0xff, 0x07]);
+        round_trip(Varint(-1), &[0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    #[test]
+    fn malformed_varints() {
+        let too_long = [0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(matches!(
+            Varint::deserialize(&too_long),
+            Err(DeserializeError::VarintTooLong)
+        ));
+
+        let truncated = [0xff, 0xff];
+        assert!(matches!(
+            Varint::deserialize(&truncated),
+            Err(DeserializeError::UnexpectedEndOfInput { .. })
+        ));
+    }
+
+    #[test]
+    fn not_enough_input() {
+        let got = i32::deserialize(&[0x00, 0x01]).unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::UnexpectedEndOfInput { needed: 2 }
+        ));
+    }
+}
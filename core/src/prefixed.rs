@@ -0,0 +1,557 @@
+use crate::{
+    native::Varint, take, DecodeLimits, Deserialize, DeserializeError,
+    SerializeError,
+};
+#[cfg(feature = "alloc")]
+use crate::{limits::MAX_PREALLOCATED_ITEMS, Context, Serialize};
+#[cfg(feature = "alloc")]
+use alloc::{borrow::Cow, string::String, vec::Vec};
+
+/// A type which may be used as a length prefix (e.g. the `countType` of a
+/// `pstring` or `array`).
+pub trait Length: Sized {
+    fn to_length(&self) -> Result<usize, DeserializeError>;
+    fn from_length(length: usize) -> Result<Self, SerializeError>;
+}
+
+macro_rules! impl_length {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl Length for $ty {
+                fn to_length(&self) -> Result<usize, DeserializeError> {
+                    use core::convert::TryFrom;
+
+                    usize::try_from(*self).map_err(|_| {
+                        DeserializeError::InvalidLength {
+                            length: *self as i128,
+                        }
+                    })
+                }
+
+                fn from_length(length: usize) -> Result<Self, SerializeError> {
+                    use core::convert::TryFrom;
+
+                    <$ty>::try_from(length)
+                        .map_err(|_| SerializeError::LengthOutOfRange { length })
+                }
+            }
+        )*
+    };
+}
+
+impl_length!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Length for Varint {
+    fn to_length(&self) -> Result<usize, DeserializeError> {
+        self.0.to_length()
+    }
+
+    fn from_length(length: usize) -> Result<Self, SerializeError> {
+        i32::from_length(length).map(Varint)
+    }
+}
+
+/// Something encoded as a length prefix followed by its contents (i.e. a
+/// `pstring`, `buffer`, or `array`).
+pub trait DeserializePrefixed<'de>: Sized {
+    fn deserialize_prefixed<L>(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length;
+
+    /// The [`DeserializePrefixed`] equivalent of
+    /// [`Deserialize::deserialize_limited()`].
+    fn deserialize_prefixed_limited<L>(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        let _ = limits;
+        Self::deserialize_prefixed::<L>(buffer)
+    }
+
+    /// The [`DeserializePrefixed`] equivalent of
+    /// [`Deserialize::deserialize_with()`].
+    #[cfg(feature = "alloc")]
+    fn deserialize_prefixed_with<L>(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        let _ = context;
+        Self::deserialize_prefixed_limited::<L>(buffer, limits)
+    }
+}
+
+/// Something encoded as a number of items which is known ahead of time,
+/// without a length prefix (i.e. a `buffer` or `array` whose `count` is a
+/// number or the value of another field).
+#[cfg(feature = "alloc")]
+pub trait DeserializeCounted<'de>: Sized {
+    fn deserialize_counted_with(
+        length: usize,
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>;
+}
+
+/// The [`Serialize`] equivalent of [`DeserializeCounted`], which writes the
+/// items without a length prefix.
+#[cfg(feature = "alloc")]
+pub trait SerializeCounted {
+    fn serialize_counted(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError>;
+}
+
+/// The [`Serialize`] equivalent of [`DeserializePrefixed`].
+#[cfg(feature = "alloc")]
+pub trait SerializePrefixed {
+    fn serialize_prefixed<L>(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError>
+    where
+        L: Serialize + Length;
+}
+
+fn deserialize_length<'de, L>(
+    buffer: &'de [u8],
+    limits: &mut DecodeLimits,
+) -> Result<(usize, &'de [u8]), DeserializeError>
+where
+    L: Deserialize<'de> + Length,
+{
+    let (length, rest) = L::deserialize_limited(buffer, limits)?;
+    Ok((length.to_length()?, rest))
+}
+
+#[cfg(feature = "alloc")]
+fn serialize_length<L>(
+    length: usize,
+    buffer: &mut Vec<u8>,
+) -> Result<(), SerializeError>
+where
+    L: Serialize + Length,
+{
+    L::from_length(length)?.serialize(buffer)
+}
+
+/// Deserialize a string, where `copied` says whether it will be copied into
+/// a new allocation.
+fn deserialize_str<'de, L>(
+    buffer: &'de [u8],
+    limits: &mut DecodeLimits,
+    copied: bool,
+) -> Result<(&'de str, &'de [u8]), DeserializeError>
+where
+    L: Deserialize<'de> + Length,
+{
+    let (length, rest) = deserialize_length::<L>(buffer, limits)?;
+    limits.string(length, copied)?;
+    let (bytes, rest) = take(rest, length)?;
+    let s =
+        core::str::from_utf8(bytes).map_err(DeserializeError::InvalidUtf8)?;
+
+    Ok((s, rest))
+}
+
+#[cfg(feature = "alloc")]
+fn serialize_str<L>(s: &str, buffer: &mut Vec<u8>) -> Result<(), SerializeError>
+where
+    L: Serialize + Length,
+{
+    serialize_length::<L>(s.len(), buffer)?;
+    buffer.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+#[cfg(feature = "alloc")]
+impl<'de> DeserializePrefixed<'de> for String {
+    fn deserialize_prefixed<L>(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        Self::deserialize_prefixed_limited::<L>(
+            buffer,
+            &mut DecodeLimits::new(),
+        )
+    }
+
+    fn deserialize_prefixed_limited<L>(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        let (s, rest) = deserialize_str::<L>(buffer, limits, true)?;
+        Ok((String::from(s), rest))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SerializePrefixed for String {
+    fn serialize_prefixed<L>(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError>
+    where
+        L: Serialize + Length,
+    {
+        serialize_str::<L>(self, buffer)
+    }
+}
+
+/// Borrow the string directly from the input instead of copying it.
+impl<'de> DeserializePrefixed<'de> for &'de str {
+    fn deserialize_prefixed<L>(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        deserialize_str::<L>(buffer, &mut DecodeLimits::new(), false)
+    }
+
+    fn deserialize_prefixed_limited<L>(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        deserialize_str::<L>(buffer, limits, false)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl SerializePrefixed for &str {
+    fn serialize_prefixed<L>(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError>
+    where
+        L: Serialize + Length,
+    {
+        serialize_str::<L>(self, buffer)
+    }
+}
+
+/// Strings are always deserialized as [`Cow::Borrowed`].
+#[cfg(feature = "alloc")]
+impl<'de> DeserializePrefixed<'de> for Cow<'de, str> {
+    fn deserialize_prefixed<L>(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        Self::deserialize_prefixed_limited::<L>(
+            buffer,
+            &mut DecodeLimits::new(),
+        )
+    }
+
+    fn deserialize_prefixed_limited<L>(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        let (s, rest) = deserialize_str::<L>(buffer, limits, false)?;
+        Ok((Cow::Borrowed(s), rest))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> SerializePrefixed for Cow<'a, str> {
+    fn serialize_prefixed<L>(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError>
+    where
+        L: Serialize + Length,
+    {
+        serialize_str::<L>(self, buffer)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'de, T: Deserialize<'de>> DeserializePrefixed<'de> for Vec<T> {
+    fn deserialize_prefixed<L>(
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        Self::deserialize_prefixed_limited::<L>(
+            buffer,
+            &mut DecodeLimits::new(),
+        )
+    }
+
+    fn deserialize_prefixed_limited<L>(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        Self::deserialize_prefixed_with::<L>(buffer, limits, &Context::new())
+    }
+
+    fn deserialize_prefixed_with<L>(
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<(Self, &'de [u8]), DeserializeError>
+    where
+        L: Deserialize<'de> + Length,
+    {
+        let (length, rest) = deserialize_length::<L>(buffer, limits)?;
+        Self::deserialize_counted_with(length, rest, limits, context)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'de, T: Deserialize<'de>> DeserializeCounted<'de> for Vec<T> {
+    fn deserialize_counted_with(
+        length: usize,
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        limits.array::<T>(length)?;
+
+        let mut items = Vec::with_capacity(length.min(MAX_PREALLOCATED_ITEMS));
+        let mut rest = buffer;
+
+        for _ in 0..length {
+            let (item, remainder) = T::deserialize_with(rest, limits, context)?;
+            items.push(item);
+            rest = remainder;
+        }
+
+        Ok((items, rest))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Serialize> SerializePrefixed for Vec<T> {
+    fn serialize_prefixed<L>(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError>
+    where
+        L: Serialize + Length,
+    {
+        serialize_length::<L>(self.len(), buffer)?;
+        self.serialize_counted(buffer)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Serialize> SerializeCounted for Vec<T> {
+    fn serialize_counted(
+        &self,
+        buffer: &mut Vec<u8>,
+    ) -> Result<(), SerializeError> {
+        for item in self {
+            item.serialize(buffer)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn round_trip_a_string() {
+        let mut buffer = Vec::new();
+
+        String::from("Hello")
+            .serialize_prefixed::<Varint>(&mut buffer)
+            .unwrap();
+        buffer.push(0xff);
+
+        assert_eq!(buffer, b"\x05Hello\xff");
+        let (got, rest) =
+            String::deserialize_prefixed::<Varint>(&buffer).unwrap();
+        assert_eq!(got, "Hello");
+        assert_eq!(rest, &[0xff]);
+    }
+
+    #[test]
+    fn borrow_strings_from_the_input() {
+        let buffer = b"\x02Hi\xff";
+
+        let (borrowed, rest) =
+            <&str>::deserialize_prefixed::<Varint>(buffer).unwrap();
+        let (cow, _) =
+            Cow::<str>::deserialize_prefixed::<Varint>(buffer).unwrap();
+
+        assert_eq!(borrowed, "Hi");
+        assert_eq!(rest, &[0xff]);
+        assert!(matches!(cow, Cow::Borrowed("Hi")));
+        let mut round_tripped = Vec::new();
+        borrowed
+            .serialize_prefixed::<Varint>(&mut round_tripped)
+            .unwrap();
+        cow.serialize_prefixed::<Varint>(&mut round_tripped)
+            .unwrap();
+        assert_eq!(round_tripped, b"\x02Hi\x02Hi");
+    }
+
+    #[test]
+    fn limit_array_lengths() {
+        let buffer = [3, 0, 1, 0, 2, 0, 3];
+        let mut limits = DecodeLimits::new().max_array_elements(2);
+
+        let got = Vec::<u16>::deserialize_prefixed_limited::<u8>(
+            &buffer,
+            &mut limits,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::LimitExceeded {
+                limit: crate::Limit::ArrayElements,
+                requested: 3,
+                max: 2,
+            }
+        ));
+    }
+
+    #[test]
+    fn huge_array_lengths_run_out_of_input() {
+        let buffer = [0xff, 0xff, 0xff, 0xff, 0x07];
+
+        let got =
+            Vec::<u64>::deserialize_prefixed::<Varint>(&buffer).unwrap_err();
+
+        assert!(matches!(got, DeserializeError::UnexpectedEndOfInput { .. }));
+    }
+
+    #[test]
+    fn borrowed_strings_dont_count_as_allocations() {
+        let buffer = b"\x02Hi";
+        let mut limits = DecodeLimits::new().max_allocation(1);
+
+        <&str>::deserialize_prefixed_limited::<u8>(buffer, &mut limits)
+            .unwrap();
+        let got =
+            String::deserialize_prefixed_limited::<u8>(buffer, &mut limits);
+
+        assert!(got.is_err());
+    }
+
+    #[test]
+    fn round_trip_an_array() {
+        let items: Vec<u16> = vec![1, 0x0203];
+        let mut buffer = Vec::new();
+
+        items.serialize_prefixed::<i8>(&mut buffer).unwrap();
+
+        assert_eq!(buffer, &[2, 0, 1, 2, 3]);
+        let (got, rest) =
+            Vec::<u16>::deserialize_prefixed::<i8>(&buffer).unwrap();
+        assert_eq!(got, items);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn round_trip_a_counted_array() {
+        let items: Vec<u16> = vec![1, 0x0203];
+        let mut buffer = Vec::new();
+
+        items.serialize_counted(&mut buffer).unwrap();
+        buffer.push(0xff);
+
+        assert_eq!(buffer, &[0, 1, 2, 3, 0xff]);
+        let (got, rest) = Vec::<u16>::deserialize_counted_with(
+            2,
+            &buffer,
+            &mut DecodeLimits::new(),
+            &Context::new(),
+        )
+        .unwrap();
+        assert_eq!(got, items);
+        assert_eq!(rest, &[0xff]);
+    }
+
+    #[test]
+    fn huge_arrays_of_zero_sized_items_are_rejected() {
+        let buffer = [0xff, 0xff, 0xff, 0xff, 0x07];
+        let mut limits = DecodeLimits::new().max_allocation(1024);
+
+        let got = Vec::<()>::deserialize_prefixed_limited::<Varint>(
+            &buffer,
+            &mut limits,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::LimitExceeded {
+                limit: crate::Limit::Allocation,
+                requested: 2147483647,
+                max: 1024,
+            }
+        ));
+    }
+
+    #[test]
+    fn arrays_of_zero_sized_items_round_trip() {
+        let mut buffer = Vec::new();
+        vec![(); 3].serialize_prefixed::<u8>(&mut buffer).unwrap();
+        assert_eq!(buffer, [3]);
+
+        let (got, rest) =
+            Vec::<()>::deserialize_prefixed::<u8>(&buffer).unwrap();
+
+        assert_eq!(got, vec![(); 3]);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn negative_lengths_are_rejected() {
+        let buffer = [0xff, 0x00];
+
+        let got = Vec::<u8>::deserialize_prefixed::<i8>(&buffer).unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::InvalidLength { length: -1 }
+        ));
+    }
+
+    #[test]
+    fn lengths_which_are_too_big_for_the_prefix() {
+        let items = vec![0_u8; 300];
+        let mut buffer = Vec::new();
+
+        let got = items.serialize_prefixed::<u8>(&mut buffer).unwrap_err();
+
+        assert!(matches!(
+            got,
+            SerializeError::LengthOutOfRange { length: 300 }
+        ));
+    }
+}
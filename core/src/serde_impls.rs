@@ -0,0 +1,235 @@
+//! [`serde`] impls for the [`native`][crate::native] wrapper types.
+//!
+//! The wrappers use the representation you would expect for the value they
+//! contain, so a [`Varint`] is a plain integer and a [`Uuid`] is written as a
+//! hyphenated string in human-readable formats (e.g. JSON) and as 16 bytes
+//! everywhere else.
+
+#[cfg(feature = "alloc")]
+use crate::native::RestBuffer;
+use crate::native::{Uuid, Varint};
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::fmt::{self, Formatter};
+use serde::{
+    de::{Error, SeqAccess, Unexpected, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+impl Serialize for Varint {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Varint {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        i32::deserialize(deserializer).map(Varint)
+    }
+}
+
+impl Serialize for Uuid {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Uuid {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(UuidVisitor)
+        } else {
+            deserializer.deserialize_bytes(UuidVisitor)
+        }
+    }
+}
+
+struct UuidVisitor;
+
+impl<'de> Visitor<'de> for UuidVisitor {
+    type Value = Uuid;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "a UUID")
+    }
+
+    fn visit_str<E: Error>(self, s: &str) -> Result<Self::Value, E> {
+        let mut uuid = [0; 16];
+        let mut digits = s.chars().filter(|&c| c != '-');
+
+        for byte in &mut uuid {
+            let high = digits.next().and_then(|c| c.to_digit(16));
+            let low = digits.next().and_then(|c| c.to_digit(16));
+
+            match (high, low) {
+                (Some(high), Some(low)) => *byte = (high << 4 | low) as u8,
+                _ => return Err(E::invalid_value(Unexpected::Str(s), &self)),
+            }
+        }
+
+        if digits.next().is_some() {
+            return Err(E::invalid_value(Unexpected::Str(s), &self));
+        }
+
+        Ok(Uuid(uuid))
+    }
+
+    fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+        let mut uuid = [0; 16];
+
+        if bytes.len() != uuid.len() {
+            return Err(E::invalid_length(bytes.len(), &self));
+        }
+        uuid.copy_from_slice(bytes);
+
+        Ok(Uuid(uuid))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut uuid = [0; 16];
+
+        for (i, byte) in uuid.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| A::Error::invalid_length(i, &self))?;
+        }
+
+        Ok(Uuid(uuid))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Serialize for RestBuffer {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'de> Deserialize<'de> for RestBuffer {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+#[cfg(feature = "alloc")]
+struct BytesVisitor;
+
+#[cfg(feature = "alloc")]
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = RestBuffer;
+
+    fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "a sequence of bytes")
+    }
+
+    fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+        Ok(RestBuffer(bytes.to_vec()))
+    }
+
+    fn visit_byte_buf<E: Error>(
+        self,
+        bytes: Vec<u8>,
+    ) -> Result<Self::Value, E> {
+        Ok(RestBuffer(bytes))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::new();
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+
+        Ok(RestBuffer(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "alloc")]
+    use alloc::vec;
+
+    #[test]
+    fn varints_are_plain_integers() {
+        let json = serde_json::to_string(&Varint(-300)).unwrap();
+
+        assert_eq!(json, "-300");
+        assert_eq!(
+            serde_json::from_str::<Varint>(&json).unwrap(),
+            Varint(-300)
+        );
+    }
+
+    #[test]
+    fn uuids_are_hyphenated_strings() {
+        let uuid = Uuid([
+            0x67, 0xe5, 0x50, 0x44, 0x10, 0xb1, 0x42, 0x6f, 0x92, 0x47, 0xbb,
+            0x68, 0x0e, 0x5f, 0xe0, 0xc8,
+        ]);
+
+        let json = serde_json::to_string(&uuid).unwrap();
+
+        assert_eq!(json, r#""67e55044-10b1-426f-9247-bb680e5fe0c8""#);
+        assert_eq!(serde_json::from_str::<Uuid>(&json).unwrap(), uuid);
+        // the hyphens are optional
+        assert_eq!(
+            serde_json::from_str::<Uuid>(
+                r#""67e5504410b1426f9247bb680e5fe0c8""#
+            )
+            .unwrap(),
+            uuid
+        );
+    }
+
+    #[test]
+    fn malformed_uuids_are_rejected() {
+        for bad in &[
+            r#""67e55044""#,
+            r#""67e55044-10b1-426f-9247-bb680e5fe0c8ff""#,
+            r#""not a uuid""#,
+        ] {
+            assert!(serde_json::from_str::<Uuid>(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "alloc")]
+    fn rest_buffers_are_bytes() {
+        let buffer = RestBuffer(vec![0xca, 0xfe]);
+
+        let json = serde_json::to_string(&buffer).unwrap();
+
+        assert_eq!(json, "[202,254]");
+        assert_eq!(serde_json::from_str::<RestBuffer>(&json).unwrap(), buffer);
+    }
+}
@@ -0,0 +1,432 @@
+//! Connections which move between several states (e.g. handshaking, status,
+//! login, and play), where each state has its own set of packets.
+
+use crate::{DecodeLimits, DeserializeError, SwitchKey};
+use core::fmt::Debug;
+#[cfg(feature = "std")]
+use std::io::Read;
+
+/// The direction a packet is sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ToClient,
+    ToServer,
+}
+
+impl Direction {
+    /// The direction replies are sent in.
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::ToClient => Direction::ToServer,
+            Direction::ToServer => Direction::ToClient,
+        }
+    }
+}
+
+/// One of the states a connection can be in.
+pub trait ConnectionState: Copy + PartialEq + Debug + 'static {
+    /// Every state, with the state a connection starts in first.
+    const STATES: &'static [Self];
+
+    /// The state's name (e.g. `"login"`).
+    fn name(self) -> &'static str;
+}
+
+/// A packet which is decoded differently depending on the state of the
+/// connection it was sent over.
+pub trait Stateful {
+    type State: ConnectionState;
+    /// The direction these packets are sent in.
+    const DIRECTION: Direction;
+
+    /// The state this packet was sent in.
+    fn state(&self) -> Self::State;
+
+    /// The state the connection switches to after this packet, if any.
+    ///
+    /// This fails with [`DeserializeError::UnknownNextState`] when the
+    /// packet asks for a state which doesn't exist.
+    fn next_state(&self) -> Result<Option<Self::State>, DeserializeError>;
+}
+
+/// Deserialize a [`Stateful`] packet using the packets from a particular
+/// state.
+pub trait DeserializeStateful<'de>: Stateful + Sized {
+    fn deserialize_stateful(
+        state: Self::State,
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError>;
+
+    /// The [`DeserializeStateful`] equivalent of
+    /// [`Deserialize::deserialize_limited()`].
+    ///
+    /// [`Deserialize::deserialize_limited()`]: crate::Deserialize::deserialize_limited
+    fn deserialize_stateful_limited(
+        state: Self::State,
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let _ = limits;
+        Self::deserialize_stateful(state, buffer)
+    }
+}
+
+/// The streaming equivalent of [`DeserializeStateful`].
+#[cfg(feature = "std")]
+pub trait ReadFromStateful: Stateful + Sized {
+    fn read_from_stateful<R>(
+        state: Self::State,
+        reader: &mut R,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized;
+
+    /// The [`ReadFromStateful`] equivalent of
+    /// [`ReadFrom::read_from_limited()`].
+    ///
+    /// [`ReadFrom::read_from_limited()`]: crate::ReadFrom::read_from_limited
+    fn read_from_stateful_limited<R>(
+        state: Self::State,
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let _ = limits;
+        Self::read_from_stateful(state, reader)
+    }
+}
+
+/// Keeps track of the state a connection is in, so each packet is decoded
+/// using the packets for the current state.
+///
+/// The state changes automatically when a packet has a
+/// [`Stateful::next_state()`]. Switches which aren't described by the packets
+/// themselves (e.g. from login to play after a successful login) need to be
+/// made with [`Connection::set_state()`].
+///
+/// Packets which are sent over the connection should be passed to
+/// [`Connection::update()`] so both ends agree on the current state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Connection<S> {
+    state: S,
+    limits: DecodeLimits,
+}
+
+impl<S: ConnectionState> Connection<S> {
+    /// Create a connection in the initial state.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if [`ConnectionState::STATES`] is empty.
+    pub fn new() -> Self { Connection::in_state(S::STATES[0]) }
+
+    /// Create a connection which is already in a particular state.
+    pub fn in_state(state: S) -> Self {
+        Connection {
+            state,
+            limits: DecodeLimits::new(),
+        }
+    }
+
+    /// The limits [`Connection::deserialize()`] and
+    /// [`Connection::read_from()`] use for each packet.
+    pub fn limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn state(&self) -> S { self.state }
+
+    pub fn set_state(&mut self, state: S) { self.state = state; }
+
+    /// Switch to the next state if a packet, either sent or received,
+    /// requires it.
+    ///
+    /// The state is left alone if the packet asks for a state which doesn't
+    /// exist.
+    pub fn update<P>(&mut self, packet: &P) -> Result<(), DeserializeError>
+    where
+        P: Stateful<State = S>,
+    {
+        if let Some(next) = packet.next_state()? {
+            self.state = next;
+        }
+
+        Ok(())
+    }
+
+    /// Deserialize a packet sent in the current state, using a fresh copy of
+    /// the connection's [`DecodeLimits`].
+    pub fn deserialize<'de, P>(
+        &mut self,
+        buffer: &'de [u8],
+    ) -> Result<(P, &'de [u8]), DeserializeError>
+    where
+        P: Stateful<State = S> + DeserializeStateful<'de>,
+    {
+        let mut limits = self.limits;
+        self.deserialize_limited(buffer, &mut limits)
+    }
+
+    /// Deserialize a packet sent in the current state, without going past
+    /// the [`DecodeLimits`].
+    pub fn deserialize_limited<'de, P>(
+        &mut self,
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(P, &'de [u8]), DeserializeError>
+    where
+        P: Stateful<State = S> + DeserializeStateful<'de>,
+    {
+        let (packet, rest) =
+            P::deserialize_stateful_limited(self.state, buffer, limits)?;
+        self.update(&packet)?;
+
+        Ok((packet, rest))
+    }
+
+    /// Read a packet sent in the current state, using a fresh copy of the
+    /// connection's [`DecodeLimits`].
+    #[cfg(feature = "std")]
+    pub fn read_from<P, R>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<P, DeserializeError>
+    where
+        P: Stateful<State = S> + ReadFromStateful,
+        R: Read + ?Sized,
+    {
+        let mut limits = self.limits;
+        self.read_from_limited(reader, &mut limits)
+    }
+
+    /// Read a packet sent in the current state, without going past the
+    /// [`DecodeLimits`].
+    #[cfg(feature = "std")]
+    pub fn read_from_limited<P, R>(
+        &mut self,
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<P, DeserializeError>
+    where
+        P: Stateful<State = S> + ReadFromStateful,
+        R: Read + ?Sized,
+    {
+        let packet = P::read_from_stateful_limited(self.state, reader, limits)?;
+        self.update(&packet)?;
+
+        Ok(packet)
+    }
+}
+
+impl<S: ConnectionState> Default for Connection<S> {
+    fn default() -> Self { Connection::new() }
+}
+
+impl DeserializeError {
+    /// Create a [`DeserializeError::UnknownNextState`] for the value of a
+    /// packet's `nextState` field.
+    pub fn unknown_next_state(key: SwitchKey<'_>) -> Self {
+        DeserializeError::UnknownNextState { key: key.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Deserialize;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum State {
+        Handshaking,
+        Status,
+        Login,
+    }
+
+    impl ConnectionState for State {
+        const STATES: &'static [Self] =
+            &[State::Handshaking, State::Status, State::Login];
+
+        fn name(self) -> &'static str {
+            match self {
+                State::Handshaking => "handshaking",
+                State::Status => "status",
+                State::Login => "login",
+            }
+        }
+    }
+
+    /// A handshake is a `nextState` byte, and everything else is a single
+    /// byte.
+    #[derive(Debug, PartialEq)]
+    enum ToServer {
+        Handshake { next_state: u8 },
+        Other(State, u8),
+    }
+
+    impl Stateful for ToServer {
+        type State = State;
+
+        const DIRECTION: Direction = Direction::ToServer;
+
+        fn state(&self) -> State {
+            match *self {
+                ToServer::Handshake { .. } => State::Handshaking,
+                ToServer::Other(state, _) => state,
+            }
+        }
+
+        fn next_state(&self) -> Result<Option<State>, DeserializeError> {
+            match *self {
+                ToServer::Handshake { next_state: 1 } => {
+                    Ok(Some(State::Status))
+                },
+                ToServer::Handshake { next_state: 2 } => Ok(Some(State::Login)),
+                ToServer::Handshake { next_state } => {
+                    Err(DeserializeError::unknown_next_state(
+                        SwitchKey::Integer(i64::from(next_state)),
+                    ))
+                },
+                ToServer::Other(..) => Ok(None),
+            }
+        }
+    }
+
+    impl<'de> DeserializeStateful<'de> for ToServer {
+        fn deserialize_stateful(
+            state: State,
+            buffer: &'de [u8],
+        ) -> Result<(Self, &'de [u8]), DeserializeError> {
+            Self::deserialize_stateful_limited(
+                state,
+                buffer,
+                &mut DecodeLimits::new(),
+            )
+        }
+
+        fn deserialize_stateful_limited(
+            state: State,
+            buffer: &'de [u8],
+            limits: &mut DecodeLimits,
+        ) -> Result<(Self, &'de [u8]), DeserializeError> {
+            limits.enter()?;
+            let (value, rest) = u8::deserialize_limited(buffer, limits)?;
+            limits.leave();
+
+            let packet = match state {
+                State::Handshaking => ToServer::Handshake { next_state: value },
+                other => ToServer::Other(other, value),
+            };
+
+            Ok((packet, rest))
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl ReadFromStateful for ToServer {
+        fn read_from_stateful<R>(
+            state: State,
+            reader: &mut R,
+        ) -> Result<Self, DeserializeError>
+        where
+            R: Read + ?Sized,
+        {
+            Self::read_from_stateful_limited(
+                state,
+                reader,
+                &mut DecodeLimits::new(),
+            )
+        }
+
+        fn read_from_stateful_limited<R>(
+            state: State,
+            reader: &mut R,
+            limits: &mut DecodeLimits,
+        ) -> Result<Self, DeserializeError>
+        where
+            R: Read + ?Sized,
+        {
+            limits.enter()?;
+            let value =
+                <u8 as crate::ReadFrom>::read_from_limited(reader, limits)?;
+            limits.leave();
+
+            let packet = match state {
+                State::Handshaking => ToServer::Handshake { next_state: value },
+                other => ToServer::Other(other, value),
+            };
+
+            Ok(packet)
+        }
+    }
+
+    #[test]
+    fn follow_the_next_state() {
+        let mut connection = Connection::<State>::new();
+
+        let (got, _): (ToServer, _) = connection.deserialize(&[2]).unwrap();
+
+        assert_eq!(got, ToServer::Handshake { next_state: 2 });
+        assert_eq!(connection.state(), State::Login);
+        let (got, _): (ToServer, _) = connection.deserialize(&[2]).unwrap();
+        assert_eq!(got, ToServer::Other(State::Login, 2));
+        assert_eq!(connection.state(), State::Login);
+    }
+
+    #[test]
+    fn unknown_next_states_are_rejected() {
+        let mut connection = Connection::<State>::new();
+
+        let got = connection.deserialize::<ToServer>(&[0]).unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::UnknownNextState {
+                key: crate::UnknownKey::Integer(0),
+            }
+        ));
+        assert_eq!(connection.state(), State::Handshaking);
+    }
+
+    #[test]
+    fn packets_are_decoded_within_the_connections_limits() {
+        let limits = DecodeLimits::new().max_depth(0);
+        let mut connection = Connection::<State>::new().limits(limits);
+
+        let got = connection.deserialize::<ToServer>(&[2]).unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::LimitExceeded {
+                limit: crate::Limit::Depth,
+                ..
+            }
+        ));
+        assert_eq!(connection.state(), State::Handshaking);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn packets_are_read_within_the_connections_limits() {
+        let limits = DecodeLimits::new().max_depth(0);
+        let mut connection = Connection::<State>::new().limits(limits);
+
+        let got = connection
+            .read_from::<ToServer, _>(&mut &[2][..])
+            .unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::LimitExceeded {
+                limit: crate::Limit::Depth,
+                ..
+            }
+        ));
+        assert_eq!(connection.state(), State::Handshaking);
+        let got: ToServer =
+            Connection::<State>::new().read_from(&mut &[2][..]).unwrap();
+        assert_eq!(got, ToServer::Handshake { next_state: 2 });
+    }
+}
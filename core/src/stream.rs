@@ -0,0 +1,570 @@
+//! Deserializing from a [`Read`]er instead of an in-memory buffer.
+
+use crate::{
+    limits::MAX_PREALLOCATED_ITEMS,
+    native::{RestBuffer, Uuid, Varint},
+    Context, DecodeLimits, Deserialize, DeserializeError, Length, SwitchKey,
+};
+use std::io::{BufRead, ErrorKind, Read};
+
+/// Deserialize something by reading it from a stream.
+///
+/// This is the streaming equivalent of [`Deserialize`], and only reads as
+/// many bytes as are needed. Types which borrow from their input can't be
+/// read from a stream.
+pub trait ReadFrom: Sized {
+    fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized;
+
+    /// The streaming equivalent of [`Deserialize::deserialize_limited()`].
+    ///
+    /// The default implementation ignores the limits, which is only
+    /// appropriate for types that never allocate.
+    fn read_from_limited<R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let _ = limits;
+        Self::read_from(reader)
+    }
+
+    /// The streaming equivalent of
+    /// [`Deserialize::deserialize_with()`].
+    fn read_from_with<R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let _ = context;
+        Self::read_from_limited(reader, limits)
+    }
+}
+
+/// The streaming equivalent of
+/// [`DeserializePrefixed`][crate::DeserializePrefixed].
+pub trait ReadFromPrefixed: Sized {
+    fn read_from_prefixed<L, R>(
+        reader: &mut R,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized;
+
+    /// The streaming equivalent of
+    /// [`DeserializePrefixed::deserialize_prefixed_limited()`][crate::DeserializePrefixed::deserialize_prefixed_limited].
+    fn read_from_prefixed_limited<L, R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized,
+    {
+        let _ = limits;
+        Self::read_from_prefixed::<L, R>(reader)
+    }
+
+    /// The streaming equivalent of
+    /// [`DeserializePrefixed::deserialize_prefixed_with()`][crate::DeserializePrefixed::deserialize_prefixed_with].
+    fn read_from_prefixed_with<L, R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized,
+    {
+        let _ = context;
+        Self::read_from_prefixed_limited::<L, R>(reader, limits)
+    }
+}
+
+/// The streaming equivalent of
+/// [`DeserializeCounted`][crate::DeserializeCounted].
+pub trait ReadFromCounted: Sized {
+    fn read_from_counted_with<R>(
+        length: usize,
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized;
+}
+
+/// The streaming equivalent of [`DeserializeSwitch`][crate::DeserializeSwitch].
+pub trait ReadFromSwitch: Sized {
+    fn read_from_switch<R>(
+        key: SwitchKey<'_>,
+        reader: &mut R,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized;
+
+    /// The streaming equivalent of
+    /// [`DeserializeSwitch::deserialize_switch_limited()`][crate::DeserializeSwitch::deserialize_switch_limited].
+    fn read_from_switch_limited<R>(
+        key: SwitchKey<'_>,
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let _ = limits;
+        Self::read_from_switch(key, reader)
+    }
+
+    /// The streaming equivalent of
+    /// [`DeserializeSwitch::deserialize_switch_with()`][crate::DeserializeSwitch::deserialize_switch_with].
+    fn read_from_switch_with<R>(
+        key: SwitchKey<'_>,
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let _ = context;
+        Self::read_from_switch_limited(key, reader, limits)
+    }
+}
+
+/// Read a value from a [`BufRead`]er, deserializing straight from its
+/// internal buffer when the whole value is already there.
+///
+/// If the buffered bytes don't contain the entire value, this falls back to
+/// [`ReadFrom::read_from()`]. The same happens when a value uses up every
+/// buffered byte, because things like a [`RestBuffer`] need to keep reading
+/// until the end of the stream.
+pub fn read_buffered<T, R>(reader: &mut R) -> Result<T, DeserializeError>
+where
+    T: for<'de> Deserialize<'de> + ReadFrom,
+    R: BufRead + ?Sized,
+{
+    read_buffered_limited(reader, &mut DecodeLimits::new())
+}
+
+/// The [`read_buffered()`] equivalent of [`ReadFrom::read_from_limited()`].
+pub fn read_buffered_limited<T, R>(
+    reader: &mut R,
+    limits: &mut DecodeLimits,
+) -> Result<T, DeserializeError>
+where
+    T: for<'de> Deserialize<'de> + ReadFrom,
+    R: BufRead + ?Sized,
+{
+    let buffer = reader.fill_buf().map_err(DeserializeError::Io)?;
+    // don't count anything twice if we need to fall back to the reader
+    let mut attempt = *limits;
+
+    match T::deserialize_limited(buffer, &mut attempt) {
+        Ok((value, rest)) if !rest.is_empty() => {
+            let consumed = buffer.len() - rest.len();
+            reader.consume(consumed);
+            *limits = attempt;
+            Ok(value)
+        },
+        Ok(_) | Err(DeserializeError::UnexpectedEndOfInput { .. }) => {
+            T::read_from_limited(reader, limits)
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Fill the buffer, reporting a [`DeserializeError::UnexpectedEndOfInput`]
+/// if the stream ends first.
+pub fn read_exact<R>(
+    reader: &mut R,
+    buffer: &mut [u8],
+) -> Result<(), DeserializeError>
+where
+    R: Read + ?Sized,
+{
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => {
+                return Err(DeserializeError::UnexpectedEndOfInput {
+                    needed: buffer.len() - filled,
+                })
+            },
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(e) => return Err(DeserializeError::Io(e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Read exactly `length` bytes, without trusting `length` when allocating.
+fn read_bytes<R>(
+    reader: &mut R,
+    length: usize,
+) -> Result<Vec<u8>, DeserializeError>
+where
+    R: Read + ?Sized,
+{
+    let mut bytes = Vec::new();
+    reader
+        .take(length as u64)
+        .read_to_end(&mut bytes)
+        .map_err(DeserializeError::Io)?;
+
+    if bytes.len() == length {
+        Ok(bytes)
+    } else {
+        Err(DeserializeError::UnexpectedEndOfInput {
+            needed: length - bytes.len(),
+        })
+    }
+}
+
+fn read_length<L, R>(
+    reader: &mut R,
+    limits: &mut DecodeLimits,
+) -> Result<usize, DeserializeError>
+where
+    L: ReadFrom + Length,
+    R: Read + ?Sized,
+{
+    L::read_from_limited(reader, limits)?.to_length()
+}
+
+macro_rules! impl_big_endian {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl ReadFrom for $ty {
+                fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+                where
+                    R: Read + ?Sized,
+                {
+                    let mut raw = [0; std::mem::size_of::<$ty>()];
+                    read_exact(reader, &mut raw)?;
+
+                    Ok(<$ty>::from_be_bytes(raw))
+                }
+            }
+        )*
+    };
+}
+
+impl_big_endian!(i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+impl ReadFrom for bool {
+    fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        Ok(u8::read_from(reader)? != 0)
+    }
+}
+
+impl ReadFrom for () {
+    fn read_from<R>(_reader: &mut R) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        Ok(())
+    }
+}
+
+impl ReadFrom for Varint {
+    fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let mut value: u32 = 0;
+
+        for i in 0..5 {
+            let byte = u8::read_from(reader)?;
+            value |= u32::from(byte & 0x7f) << (7 * i);
+
+            if byte & 0x80 == 0 {
+                return Ok(Varint(value as i32));
+            }
+        }
+
+        Err(DeserializeError::VarintTooLong)
+    }
+}
+
+impl ReadFrom for Uuid {
+    fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let mut uuid = [0; 16];
+        read_exact(reader, &mut uuid)?;
+
+        Ok(Uuid(uuid))
+    }
+}
+
+/// Reads until the end of the stream.
+impl ReadFrom for RestBuffer {
+    fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        Self::read_from_limited(reader, &mut DecodeLimits::new())
+    }
+
+    fn read_from_limited<R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(DeserializeError::Io)?;
+        limits.array::<u8>(bytes.len())?;
+
+        Ok(RestBuffer(bytes))
+    }
+}
+
+impl ReadFromPrefixed for String {
+    fn read_from_prefixed<L, R>(
+        reader: &mut R,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized,
+    {
+        Self::read_from_prefixed_limited::<L, R>(
+            reader,
+            &mut DecodeLimits::new(),
+        )
+    }
+
+    fn read_from_prefixed_limited<L, R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized,
+    {
+        let length = read_length::<L, R>(reader, limits)?;
+        limits.string(length, true)?;
+        let bytes = read_bytes(reader, length)?;
+
+        String::from_utf8(bytes)
+            .map_err(|e| DeserializeError::InvalidUtf8(e.utf8_error()))
+    }
+}
+
+impl<T: ReadFrom> ReadFromPrefixed for Vec<T> {
+    fn read_from_prefixed<L, R>(
+        reader: &mut R,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized,
+    {
+        Self::read_from_prefixed_limited::<L, R>(
+            reader,
+            &mut DecodeLimits::new(),
+        )
+    }
+
+    fn read_from_prefixed_limited<L, R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized,
+    {
+        Self::read_from_prefixed_with::<L, R>(reader, limits, &Context::new())
+    }
+
+    fn read_from_prefixed_with<L, R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<Self, DeserializeError>
+    where
+        L: ReadFrom + Length,
+        R: Read + ?Sized,
+    {
+        let length = read_length::<L, R>(reader, limits)?;
+        Self::read_from_counted_with(length, reader, limits, context)
+    }
+}
+
+impl<T: ReadFrom> ReadFromCounted for Vec<T> {
+    fn read_from_counted_with<R>(
+        length: usize,
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        limits.array::<T>(length)?;
+
+        let mut items = Vec::with_capacity(length.min(MAX_PREALLOCATED_ITEMS));
+
+        for _ in 0..length {
+            items.push(T::read_from_with(reader, limits, context)?);
+        }
+
+        Ok(items)
+    }
+}
+
+impl<T: ReadFrom> ReadFrom for Option<T> {
+    fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        Self::read_from_limited(reader, &mut DecodeLimits::new())
+    }
+
+    fn read_from_limited<R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        if bool::read_from(reader)? {
+            T::read_from_limited(reader, limits).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn read_from_with<R>(
+        reader: &mut R,
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<Self, DeserializeError>
+    where
+        R: Read + ?Sized,
+    {
+        if bool::read_from(reader)? {
+            T::read_from_with(reader, limits, context).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    /// A reader which hands out one byte at a time, like a slow socket.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((first, rest)), Some(slot)) => {
+                    *slot = *first;
+                    self.0 = rest;
+                    Ok(1)
+                },
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn read_natives_from_a_stream() {
+        let mut reader = Trickle(&[0xac, 0x02, 0x01, 0x02, 0x01, 0xff]);
+
+        assert_eq!(Varint::read_from(&mut reader).unwrap(), Varint(300));
+        assert_eq!(i16::read_from(&mut reader).unwrap(), 0x0102);
+        assert!(bool::read_from(&mut reader).unwrap());
+        assert_eq!(
+            RestBuffer::read_from(&mut reader).unwrap(),
+            RestBuffer(vec![0xff])
+        );
+    }
+
+    #[test]
+    fn read_prefixed_values() {
+        let mut reader = Trickle(b"\x05Hello\x02\x00\x01\x02\x03");
+
+        let s = String::read_from_prefixed::<Varint, _>(&mut reader).unwrap();
+        let items =
+            Vec::<u16>::read_from_prefixed::<u8, _>(&mut reader).unwrap();
+
+        assert_eq!(s, "Hello");
+        assert_eq!(items, vec![1, 0x0203]);
+    }
+
+    #[test]
+    fn the_stream_ends_early() {
+        let got =
+            String::read_from_prefixed::<Varint, _>(&mut Trickle(b"\x05He"))
+                .unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::UnexpectedEndOfInput { needed: 3 }
+        ));
+    }
+
+    #[test]
+    fn limits_apply_to_streams() {
+        let mut limits = DecodeLimits::new().max_string_length(4);
+
+        let got = String::read_from_prefixed_limited::<Varint, _>(
+            &mut Trickle(b"\x05Hello"),
+            &mut limits,
+        )
+        .unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::LimitExceeded {
+                limit: crate::Limit::StringLength,
+                requested: 5,
+                max: 4,
+            }
+        ));
+    }
+
+    #[test]
+    fn huge_array_lengths_run_out_of_input() {
+        let mut reader = Trickle(&[0xff, 0xff, 0xff, 0xff, 0x07]);
+
+        let got = Vec::<u64>::read_from_prefixed::<Varint, _>(&mut reader)
+            .unwrap_err();
+
+        assert!(matches!(got, DeserializeError::UnexpectedEndOfInput { .. }));
+    }
+
+    #[test]
+    fn buffered_reads_fall_back_when_the_value_is_split() {
+        // a tiny buffer means the second value straddles two fills
+        let mut reader =
+            BufReader::with_capacity(3, &[0x00, 0x01, 0x02, 0x03, 0x04][..]);
+
+        assert_eq!(read_buffered::<u16, _>(&mut reader).unwrap(), 0x0001);
+        assert_eq!(read_buffered::<u16, _>(&mut reader).unwrap(), 0x0203);
+        assert_eq!(read_buffered::<u8, _>(&mut reader).unwrap(), 0x04);
+        assert!(read_buffered::<u8, _>(&mut reader).is_err());
+    }
+}
@@ -0,0 +1,212 @@
+#[cfg(feature = "alloc")]
+use crate::Context;
+use crate::{native::Varint, DecodeLimits, DeserializeError};
+#[cfg(feature = "alloc")]
+use alloc::{borrow::Cow, string::String};
+use core::fmt::{self, Display, Formatter};
+
+/// The value a `switch` compares against when deciding which variant to use.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SwitchKey<'a> {
+    Integer(i64),
+    /// An unsigned integer too large for [`SwitchKey::Integer`], which never
+    /// matches a case.
+    LargeInteger(u64),
+    String(&'a str),
+    Bool(bool),
+}
+
+impl<'a> Display for SwitchKey<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SwitchKey::Integer(i) => write!(f, "{}", i),
+            SwitchKey::LargeInteger(u) => write!(f, "{}", u),
+            SwitchKey::String(s) => write!(f, "\"{}\"", s),
+            SwitchKey::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// The key a [`DeserializeError::UnknownSwitchVariant`] didn't match.
+///
+/// Unlike [`SwitchKey`] this doesn't borrow from the input, so strings are
+/// only recorded as being strings.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnknownKey {
+    Integer(i64),
+    LargeInteger(u64),
+    String,
+    Bool(bool),
+}
+
+impl<'a> From<SwitchKey<'a>> for UnknownKey {
+    fn from(key: SwitchKey<'a>) -> Self {
+        match key {
+            SwitchKey::Integer(i) => UnknownKey::Integer(i),
+            SwitchKey::LargeInteger(u) => UnknownKey::LargeInteger(u),
+            SwitchKey::String(_) => UnknownKey::String,
+            SwitchKey::Bool(b) => UnknownKey::Bool(b),
+        }
+    }
+}
+
+impl Display for UnknownKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownKey::Integer(i) => write!(f, "{}", i),
+            UnknownKey::LargeInteger(u) => write!(f, "{}", u),
+            UnknownKey::String => write!(f, "the string"),
+            UnknownKey::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// Something which may be used as a switch's `compareTo` field.
+pub trait AsSwitchKey {
+    fn as_switch_key(&self) -> SwitchKey<'_>;
+}
+
+macro_rules! impl_as_switch_key_for_integers {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl AsSwitchKey for $ty {
+                fn as_switch_key(&self) -> SwitchKey<'_> {
+                    SwitchKey::Integer(*self as i64)
+                }
+            }
+        )*
+    };
+}
+
+impl_as_switch_key_for_integers!(u8, u16, u32, i8, i16, i32, i64);
+
+impl AsSwitchKey for u64 {
+    fn as_switch_key(&self) -> SwitchKey<'_> {
+        use core::convert::TryFrom;
+
+        match i64::try_from(*self) {
+            Ok(i) => SwitchKey::Integer(i),
+            Err(_) => SwitchKey::LargeInteger(*self),
+        }
+    }
+}
+
+impl AsSwitchKey for Varint {
+    fn as_switch_key(&self) -> SwitchKey<'_> { self.0.as_switch_key() }
+}
+
+impl AsSwitchKey for bool {
+    fn as_switch_key(&self) -> SwitchKey<'_> { SwitchKey::Bool(*self) }
+}
+
+impl AsSwitchKey for str {
+    fn as_switch_key(&self) -> SwitchKey<'_> { SwitchKey::String(self) }
+}
+
+#[cfg(feature = "alloc")]
+impl AsSwitchKey for String {
+    fn as_switch_key(&self) -> SwitchKey<'_> { SwitchKey::String(self) }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a> AsSwitchKey for Cow<'a, str> {
+    fn as_switch_key(&self) -> SwitchKey<'_> { SwitchKey::String(self) }
+}
+
+impl<T: AsSwitchKey + ?Sized> AsSwitchKey for &T {
+    fn as_switch_key(&self) -> SwitchKey<'_> { (**self).as_switch_key() }
+}
+
+/// Deserialize one of a `switch`'s variants, using a previously decoded
+/// value to decide which one.
+pub trait DeserializeSwitch<'de>: Sized {
+    fn deserialize_switch(
+        key: SwitchKey<'_>,
+        buffer: &'de [u8],
+    ) -> Result<(Self, &'de [u8]), DeserializeError>;
+
+    /// The [`DeserializeSwitch`] equivalent of
+    /// [`Deserialize::deserialize_limited()`].
+    ///
+    /// [`Deserialize::deserialize_limited()`]: crate::Deserialize::deserialize_limited
+    fn deserialize_switch_limited(
+        key: SwitchKey<'_>,
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let _ = limits;
+        Self::deserialize_switch(key, buffer)
+    }
+
+    /// The [`DeserializeSwitch`] equivalent of
+    /// [`Deserialize::deserialize_with()`].
+    ///
+    /// [`Deserialize::deserialize_with()`]: crate::Deserialize::deserialize_with
+    #[cfg(feature = "alloc")]
+    fn deserialize_switch_with(
+        key: SwitchKey<'_>,
+        buffer: &'de [u8],
+        limits: &mut DecodeLimits,
+        context: &Context<'_>,
+    ) -> Result<(Self, &'de [u8]), DeserializeError> {
+        let _ = context;
+        Self::deserialize_switch_limited(key, buffer, limits)
+    }
+}
+
+impl DeserializeError {
+    /// Create a [`DeserializeError::UnknownSwitchVariant`].
+    pub fn unknown_switch_variant(key: SwitchKey<'_>) -> Self {
+        DeserializeError::UnknownSwitchVariant { key: key.into() }
+    }
+
+    /// Create a [`DeserializeError::UnknownMapping`] for a `mapper`'s raw
+    /// value.
+    pub fn unknown_mapping(key: SwitchKey<'_>) -> Self {
+        match key {
+            SwitchKey::Integer(value) => {
+                DeserializeError::UnknownMapping { value }
+            },
+            // mappers only ever map integers
+            other => DeserializeError::unknown_switch_variant(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn huge_u64s_dont_wrap_around() {
+        assert_eq!(
+            (i64::MAX as u64).as_switch_key(),
+            SwitchKey::Integer(i64::MAX)
+        );
+        assert_eq!(u64::MAX.as_switch_key(), SwitchKey::LargeInteger(u64::MAX));
+
+        let got =
+            DeserializeError::unknown_switch_variant(u64::MAX.as_switch_key());
+
+        assert!(matches!(
+            got,
+            DeserializeError::UnknownSwitchVariant {
+                key: UnknownKey::LargeInteger(u64::MAX),
+            }
+        ));
+    }
+
+    #[test]
+    fn unknown_keys_dont_borrow_the_input() {
+        let got = DeserializeError::unknown_switch_variant(SwitchKey::String(
+            "missing",
+        ));
+
+        assert!(matches!(
+            got,
+            DeserializeError::UnknownSwitchVariant {
+                key: UnknownKey::String
+            }
+        ));
+    }
+}
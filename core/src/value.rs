@@ -0,0 +1,207 @@
+use crate::SwitchKey;
+use alloc::{string::String, vec::Vec};
+use core::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+};
+
+/// A dynamically typed value, used when working with packets that don't
+/// have any generated code (e.g. when interpreting a `protocol.json` at
+/// runtime).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// The absence of a value (e.g. a `void` or an empty `option`).
+    Null,
+    Bool(bool),
+    /// Any integer, wide enough to hold both an `i64` and a `u64`.
+    Integer(i128),
+    Float(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<Value>),
+    /// Named fields, in the order they were declared.
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Look up a field by name, if this is a [`Value::Map`].
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(fields) => {
+                fields.iter().find(|(name, _)| name == key).map(|(_, v)| v)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i128> {
+        match *self {
+            Value::Integer(i) => Some(i),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Convert this value into something a `switch` can compare against.
+    pub fn as_switch_key(&self) -> Option<SwitchKey<'_>> {
+        match *self {
+            Value::Bool(b) => Some(SwitchKey::Bool(b)),
+            Value::Integer(i) => match i64::try_from(i) {
+                Ok(i) => Some(SwitchKey::Integer(i)),
+                Err(_) => u64::try_from(i).ok().map(SwitchKey::LargeInteger),
+            },
+            Value::String(ref s) => Some(SwitchKey::String(s)),
+            _ => None,
+        }
+    }
+
+    /// A short description of the kind of value this is, for use in error
+    /// messages.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "bool",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Bytes(_) => "bytes",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Bytes(bytes) => {
+                write!(f, "<")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                write!(f, ">")
+            },
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Value::Map(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {}: {}", name, value)?;
+                }
+                if !fields.is_empty() {
+                    write!(f, " ")?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+macro_rules! impl_from_integer {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl From<$ty> for Value {
+                fn from(value: $ty) -> Self { Value::Integer(value.into()) }
+            }
+        )*
+    };
+}
+
+impl_from_integer!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self { Value::Bool(value) }
+}
+
+impl From<f32> for Value {
+    fn from(value: f32) -> Self { Value::Float(value.into()) }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self { Value::Float(value) }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self { Value::String(value) }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self { Value::String(String::from(value)) }
+}
+
+impl From<SwitchKey<'_>> for Value {
+    fn from(value: SwitchKey<'_>) -> Self {
+        match value {
+            SwitchKey::Integer(i) => Value::from(i),
+            SwitchKey::LargeInteger(u) => Value::from(u),
+            SwitchKey::String(s) => Value::from(s),
+            SwitchKey::Bool(b) => Value::from(b),
+        }
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self { Value::List(value) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::ToString, vec};
+
+    #[test]
+    fn display_nested_values() {
+        let value = Value::Map(vec![
+            (String::from("name"), Value::from("chat")),
+            (
+                String::from("params"),
+                Value::Map(vec![
+                    (String::from("position"), Value::from(1_u8)),
+                    (String::from("data"), Value::Bytes(vec![0xca, 0xfe])),
+                    (
+                        String::from("items"),
+                        Value::from(vec![Value::from(true), Value::Null]),
+                    ),
+                ]),
+            ),
+        ]);
+
+        let got = value.to_string();
+
+        assert_eq!(
+            got,
+            r#"{ name: "chat", params: { position: 1, data: <cafe>, items: [true, null] } }"#
+        );
+    }
+
+    #[test]
+    fn look_up_fields() {
+        let value = Value::Map(vec![(String::from("x"), Value::from(42_i32))]);
+
+        assert_eq!(value.get("x").and_then(Value::as_integer), Some(42));
+        assert_eq!(value.get("y"), None);
+        assert_eq!(Value::Null.get("x"), None);
+    }
+}
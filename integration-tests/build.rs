@@ -0,0 +1,19 @@
+use protodef_codegen::{backend::CodegenOptions, Builder};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    Builder::new("protocols/chat.json")
+        .codegen_options(CodegenOptions::new().serde(true))
+        .generate()?;
+    Builder::new("protocols/handshake.json")
+        .codegen_options(
+            CodegenOptions::new()
+                .next_state(1, "status")
+                .next_state(2, "login")
+                // a transfer from another server also goes to login
+                .next_state(3, "login"),
+        )
+        .generate()?;
+    Builder::new("protocols/anonymous.json").generate()?;
+
+    Ok(())
+}
@@ -0,0 +1,20 @@
+//! Code generated from the protocols in `protocols/`, so the tests can check
+//! that it compiles and behaves correctly.
+
+/// A `play` namespace whose types derive `serde::Serialize` and
+/// `serde::Deserialize`.
+pub mod chat {
+    include!(concat!(env!("OUT_DIR"), "/chat.rs"));
+}
+
+/// The handshaking, status, and login states, for following a connection
+/// from one state to the next.
+pub mod handshake {
+    include!(concat!(env!("OUT_DIR"), "/handshake.rs"));
+}
+
+/// Types with `"anon": true` fields, whose contents are treated as part of
+/// the parent when a `switch` looks up its `compareTo`.
+pub mod anonymous {
+    include!(concat!(env!("OUT_DIR"), "/anonymous.rs"));
+}
@@ -0,0 +1,44 @@
+use protodef_core::{Deserialize, ReadFrom, Serialize};
+use protodef_integration_tests::anonymous::{
+    BlockChange, BlockChangeAnon, BlockChangeData, EntityAction,
+    EntityActionAnon, EntityActionAnonTarget,
+};
+
+#[test]
+fn switches_in_anonymous_containers_see_their_parents_fields() {
+    let bytes = [0x01, 0x00, 0x00, 0x00, 0x2a];
+
+    let (got, rest) = EntityAction::deserialize(&bytes).unwrap();
+
+    assert!(rest.is_empty());
+    assert_eq!(
+        got,
+        EntityAction {
+            kind: 1.into(),
+            anon: EntityActionAnon {
+                target: EntityActionAnonTarget::Case1(42),
+            },
+        }
+    );
+    assert_eq!(EntityAction::read_from(&mut &bytes[..]).unwrap(), got);
+    let mut buffer = Vec::new();
+    got.serialize(&mut buffer).unwrap();
+    assert_eq!(buffer, bytes);
+}
+
+#[test]
+fn fields_of_anonymous_containers_belong_to_the_parent() {
+    let bytes = [0x00, 0x00, 0x00, 0x00, 0x2a];
+
+    let (got, rest) = BlockChange::deserialize(&bytes).unwrap();
+
+    assert!(rest.is_empty());
+    assert_eq!(
+        got,
+        BlockChange {
+            anon: BlockChangeAnon { mode: 0 },
+            data: BlockChangeData::Case0(42),
+        }
+    );
+    assert_eq!(BlockChange::read_from(&mut &bytes[..]).unwrap(), got);
+}
@@ -0,0 +1,98 @@
+use protodef_core::{Connection, DeserializeError, Serialize, UnknownKey};
+use protodef_integration_tests::handshake::{
+    handshaking, login, status, State, String, ToServerPacket,
+};
+
+fn set_protocol(next_state: i32) -> Vec<u8> {
+    let packet = handshaking::to_server::Packet::SetProtocol(
+        handshaking::to_server::PacketSetProtocol {
+            protocol_version: 340.into(),
+            server_host: String("localhost".into()),
+            server_port: 25565,
+            next_state: next_state.into(),
+        },
+    );
+    let mut buffer = Vec::new();
+    packet.serialize(&mut buffer).unwrap();
+
+    buffer
+}
+
+#[test]
+fn a_handshake_switches_to_the_status_state() {
+    let mut connection = Connection::<State>::new();
+    let handshake = set_protocol(1);
+
+    assert_eq!(&handshake[..3], &[0x00, 0xd4, 0x02]);
+    let (packet, rest): (ToServerPacket, _) =
+        connection.deserialize(&handshake).unwrap();
+
+    assert!(rest.is_empty());
+    assert!(matches!(
+        packet,
+        ToServerPacket::Handshaking(
+            handshaking::to_server::Packet::SetProtocol(_)
+        )
+    ));
+    assert_eq!(connection.state(), State::Status);
+
+    // the same bytes now mean something else
+    let (packet, _): (ToServerPacket, _) =
+        connection.deserialize(&[0x00]).unwrap();
+    assert_eq!(
+        packet,
+        ToServerPacket::Status(status::to_server::Packet::PingStart(
+            status::to_server::PacketPingStart {}
+        ))
+    );
+    assert_eq!(connection.state(), State::Status);
+}
+
+#[test]
+fn a_handshake_switches_to_the_login_state() {
+    let mut connection = Connection::<State>::new();
+
+    let _: (ToServerPacket, _) =
+        connection.deserialize(&set_protocol(2)).unwrap();
+    let (packet, _): (ToServerPacket, _) =
+        connection.deserialize(b"\x00\x02me").unwrap();
+
+    assert_eq!(connection.state(), State::Login);
+    assert_eq!(
+        packet,
+        ToServerPacket::Login(login::to_server::Packet::LoginStart(
+            login::to_server::PacketLoginStart {
+                username: String("me".into()),
+            }
+        ))
+    );
+}
+
+#[test]
+fn a_transfer_switches_to_the_login_state() {
+    let mut connection = Connection::<State>::new();
+
+    let _: (ToServerPacket, _) =
+        connection.deserialize(&set_protocol(3)).unwrap();
+
+    assert_eq!(connection.state(), State::Login);
+}
+
+#[test]
+fn a_handshake_cant_switch_to_a_state_which_wasnt_given() {
+    for next_state in &[0, 4, 7, -1] {
+        let mut connection = Connection::<State>::new();
+
+        let got = connection
+            .deserialize::<ToServerPacket>(&set_protocol(*next_state))
+            .unwrap_err();
+
+        assert!(matches!(
+            got,
+            DeserializeError::UnknownNextState {
+                key: UnknownKey::Integer(n),
+            } if n == i64::from(*next_state)
+        ));
+        assert_eq!(connection.state(), State::Handshaking);
+    }
+}
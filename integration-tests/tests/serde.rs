@@ -0,0 +1,40 @@
+use protodef_core::Deserialize;
+use protodef_integration_tests::chat::{
+    play::to_client::{Packet, PacketChat, PacketKeepAlive},
+    String,
+};
+use serde_json::json;
+
+#[test]
+fn packets_are_tagged_with_their_name() {
+    let packet = Packet::Chat(PacketChat {
+        message: String("Hi".into()),
+        position: 1,
+    });
+
+    let got = serde_json::to_value(&packet).unwrap();
+
+    assert_eq!(
+        got,
+        json!({ "name": "chat", "message": "Hi", "position": 1 })
+    );
+    let round_tripped: Packet = serde_json::from_value(got).unwrap();
+    assert_eq!(round_tripped, packet);
+}
+
+#[test]
+fn decoded_packets_can_be_written_as_json() {
+    let bytes = [0x1f, 0, 0, 0, 0, 0, 0, 0x01, 0x02];
+
+    let (packet, rest) = Packet::deserialize(&bytes).unwrap();
+
+    assert!(rest.is_empty());
+    assert_eq!(
+        packet,
+        Packet::KeepAlive(PacketKeepAlive {
+            keep_alive_id: 0x0102
+        })
+    );
+    let got = serde_json::to_value(&packet).unwrap();
+    assert_eq!(got, json!({ "name": "keep_alive", "keepAliveId": 258 }));
+}
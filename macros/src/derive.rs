@@ -0,0 +1,420 @@
+//! The implementation of `#[derive(ProtoDef)]`.
+
+use proc_macro2::{Group, Ident, Spacing, TokenStream, TokenTree};
+use protodef_codegen::backend::serialization::{
+    self, Encoding, FieldInfo, Fields, Paths, VariantInfo,
+};
+use quote::{quote, ToTokens};
+use syn::{
+    spanned::Spanned, Attribute, Data, DataEnum, DataStruct, DeriveInput,
+    Error, GenericParam, Generics, Lit, Meta, NestedMeta,
+};
+
+pub(crate) fn expand(input: &DeriveInput) -> Result<TokenStream, Error> {
+    let lifetime = lifetime(&input.generics)?;
+    let borrows = lifetime.is_some();
+    let runtime = quote!(::protodef_core);
+    // derived impls may end up in a #![no_std] crate, so they go through
+    // protodef_core's re-exports instead of naming std directly
+    let paths = Paths::runtime_features(runtime.clone());
+
+    match &input.data {
+        Data::Struct(DataStruct { fields, .. }) => {
+            let fields = fields_info(&runtime, lifetime, fields)?;
+            Ok(serialization::struct_impls(
+                &paths,
+                &input.ident,
+                borrows,
+                false,
+                &fields,
+            ))
+        },
+        Data::Enum(DataEnum { variants, .. }) => {
+            let variants = variants
+                .iter()
+                .map(|v| variant_info(&runtime, lifetime, v))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(serialization::switch_impls(
+                &paths,
+                &input.ident,
+                borrows,
+                &variants,
+            ))
+        },
+        Data::Union(u) => Err(Error::new(
+            u.union_token.span(),
+            "#[derive(ProtoDef)] doesn't support unions",
+        )),
+    }
+}
+
+/// Get the lifetime a type borrows from its input with, if it has one.
+///
+/// A type can have at most one lifetime, because it can only borrow from the
+/// buffer it was deserialized from.
+fn lifetime(generics: &Generics) -> Result<Option<&Ident>, Error> {
+    let mut lifetime = None;
+
+    for param in &generics.params {
+        match param {
+            GenericParam::Lifetime(def) if lifetime.is_none() => {
+                lifetime = Some(&def.lifetime.ident);
+            },
+            GenericParam::Lifetime(def) => {
+                return Err(Error::new(
+                    def.span(),
+                    "#[derive(ProtoDef)] only supports a single lifetime, \
+                     which is the lifetime of the buffer being deserialized",
+                ));
+            },
+            other => {
+                return Err(Error::new(
+                    other.span(),
+                    "#[derive(ProtoDef)] doesn't support generic types",
+                ));
+            },
+        }
+    }
+
+    Ok(lifetime)
+}
+
+/// Rename a type's lifetime to the `'de` used by the generated impls (e.g.
+/// `&'a str` becomes `&'de str`).
+fn rename_lifetime(
+    tokens: TokenStream,
+    lifetime: Option<&Ident>,
+) -> TokenStream {
+    let lifetime = match lifetime {
+        Some(lifetime) if lifetime != "de" => lifetime,
+        _ => return tokens,
+    };
+    let mut after_apostrophe = false;
+
+    tokens
+        .into_iter()
+        .map(|tt| {
+            let renamed = match tt {
+                TokenTree::Group(g) => {
+                    let mut group = Group::new(
+                        g.delimiter(),
+                        rename_lifetime(g.stream(), Some(lifetime)),
+                    );
+                    group.set_span(g.span());
+                    TokenTree::Group(group)
+                },
+                TokenTree::Ident(ident)
+                    if after_apostrophe && ident == *lifetime =>
+                {
+                    TokenTree::Ident(Ident::new("de", ident.span()))
+                },
+                other => other,
+            };
+            after_apostrophe = matches!(
+                &renamed,
+                TokenTree::Punct(p) if p.as_char() == '\'' && p.spacing() == Spacing::Joint
+            );
+            renamed
+        })
+        .collect()
+}
+
+fn fields_info(
+    runtime: &TokenStream,
+    lifetime: Option<&Ident>,
+    fields: &syn::Fields,
+) -> Result<Fields, Error> {
+    let mut infos: Vec<FieldInfo> = Vec::new();
+
+    for (i, field) in fields.iter().enumerate() {
+        let (member, name) = match &field.ident {
+            Some(ident) => {
+                let name = ident.to_string();
+                (ident.to_token_stream(), name)
+            },
+            None => {
+                let index = syn::Index::from(i);
+                (index.to_token_stream(), i.to_string())
+            },
+        };
+        let (encoding, context) = field_options(runtime, &field.attrs)?;
+
+        if let (Encoding::Switch { compare_to }, Some(attr)) =
+            (&encoding, protodef_attribute(&field.attrs))
+        {
+            // paths like "../kind" and "$version" come from the Context
+            let is_sibling =
+                !compare_to.contains('/') && !compare_to.starts_with('$');
+
+            if is_sibling && !infos.iter().any(|f| f.name == *compare_to) {
+                let msg = format!(
+                    "a switch can only compare to a field declared before it, \
+                     but there is no \"{}\" field",
+                    compare_to
+                );
+                return Err(Error::new(attr.span(), msg));
+            }
+        }
+
+        infos.push(FieldInfo {
+            member,
+            name,
+            ty: rename_lifetime(field.ty.to_token_stream(), lifetime),
+            encoding,
+            context,
+            merged: Vec::new(),
+        });
+    }
+
+    Ok(match fields {
+        syn::Fields::Named(_) => Fields::Named(infos),
+        syn::Fields::Unnamed(_) => Fields::Unnamed(infos),
+        syn::Fields::Unit => Fields::Unit,
+    })
+}
+
+fn variant_info(
+    runtime: &TokenStream,
+    lifetime: Option<&Ident>,
+    variant: &syn::Variant,
+) -> Result<VariantInfo, Error> {
+    let mut key = None;
+    let mut is_default = false;
+
+    for item in protodef_items(&variant.attrs)? {
+        match &item {
+            NestedMeta::Meta(Meta::NameValue(nv))
+                if nv.path.is_ident("case") =>
+            {
+                let pattern = match &nv.lit {
+                    Lit::Int(i) => i.base10_digits().to_string(),
+                    Lit::Str(s) => s.value(),
+                    Lit::Bool(b) => b.value.to_string(),
+                    other => {
+                        return Err(Error::new(
+                            other.span(),
+                            "a case must be an integer, string, or boolean",
+                        ))
+                    },
+                };
+                key =
+                    Some(serialization::switch_key_pattern(runtime, &pattern));
+            },
+            NestedMeta::Meta(Meta::Path(p)) if p.is_ident("default") => {
+                is_default = true;
+            },
+            other => {
+                return Err(Error::new(
+                    other.span(),
+                    "expected `case = ...` or `default`",
+                ))
+            },
+        }
+    }
+
+    if key.is_none() && !is_default {
+        return Err(Error::new(
+            variant.span(),
+            "each variant needs either #[protodef(case = ...)] or \
+             #[protodef(default)]",
+        ));
+    }
+    if key.is_some() && is_default {
+        return Err(Error::new(
+            variant.span(),
+            "a variant can't have both a case and be the default",
+        ));
+    }
+
+    Ok(VariantInfo {
+        name: variant.ident.clone(),
+        key,
+        fields: fields_info(runtime, lifetime, &variant.fields)?,
+    })
+}
+
+/// Get a field's [`Encoding`] and whether it should be recorded in the
+/// `Context`.
+fn field_options(
+    runtime: &TokenStream,
+    attrs: &[Attribute],
+) -> Result<(Encoding, bool), Error> {
+    let mut encoding = Encoding::Default;
+    let mut context = false;
+
+    for item in protodef_items(attrs)? {
+        let new_encoding = match &item {
+            NestedMeta::Meta(Meta::Path(p)) if p.is_ident("context") => {
+                context = true;
+                continue;
+            },
+            NestedMeta::Meta(Meta::Path(p)) if p.is_ident("varint") => {
+                Encoding::As(serialization::native_type(runtime, "varint"))
+            },
+            NestedMeta::Meta(Meta::NameValue(nv))
+                if nv.path.is_ident("count") =>
+            {
+                let count = string_value(&nv.lit)?;
+                Encoding::LengthPrefixed {
+                    count: serialization::native_type(runtime, &count),
+                }
+            },
+            NestedMeta::Meta(Meta::NameValue(nv))
+                if nv.path.is_ident("switch") =>
+            {
+                Encoding::Switch {
+                    compare_to: string_value(&nv.lit)?,
+                }
+            },
+            other => return Err(Error::new(
+                other.span(),
+                "expected `varint`, `count = \"...\"`, `switch = \"...\"`, or `context`",
+            )),
+        };
+
+        if !matches!(encoding, Encoding::Default) {
+            return Err(Error::new(
+                item.span(),
+                "a field can only have one encoding",
+            ));
+        }
+        encoding = new_encoding;
+    }
+
+    Ok((encoding, context))
+}
+
+fn protodef_attribute(attrs: &[Attribute]) -> Option<&Attribute> {
+    attrs.iter().find(|a| a.path.is_ident("protodef"))
+}
+
+/// Get the items from every `#[protodef(...)]` attribute.
+fn protodef_items(attrs: &[Attribute]) -> Result<Vec<NestedMeta>, Error> {
+    let mut items = Vec::new();
+
+    for attr in attrs.iter().filter(|a| a.path.is_ident("protodef")) {
+        match attr.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            other => {
+                return Err(Error::new(
+                    other.span(),
+                    "expected #[protodef(...)]",
+                ))
+            },
+        }
+    }
+
+    Ok(items)
+}
+
+fn string_value(lit: &Lit) -> Result<String, Error> {
+    match lit {
+        Lit::Str(s) => Ok(s.value()),
+        other => Err(Error::new(other.span(), "expected a string")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switches_must_refer_to_an_earlier_field() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Packet {
+                #[protodef(switch = "kind")]
+                body: Body,
+                kind: u8,
+            }
+        };
+
+        let got = expand(&input).unwrap_err();
+
+        assert!(got.to_string().contains("no \"kind\" field"));
+    }
+
+    #[test]
+    fn switches_can_refer_to_the_context() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Body {
+                #[protodef(switch = "../kind")]
+                first: First,
+                #[protodef(switch = "$version")]
+                second: Second,
+            }
+        };
+
+        let got = expand(&input).unwrap().to_string();
+
+        assert!(got.contains(r#"scope . switch_key ("../kind") ?"#));
+        assert!(got.contains(r#"scope . switch_key ("$version") ?"#));
+    }
+
+    #[test]
+    fn generics_are_rejected() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Wrapper<T>(T);
+        };
+
+        let got = expand(&input).unwrap_err();
+
+        assert!(got.to_string().contains("generic"));
+    }
+
+    #[test]
+    fn only_one_lifetime_is_allowed() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Names<'a, 'b>(&'a str, &'b str);
+        };
+
+        let got = expand(&input).unwrap_err();
+
+        assert!(got.to_string().contains("single lifetime"));
+    }
+
+    #[test]
+    fn lifetimes_are_renamed_to_de() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Name<'a>(#[protodef(count = "varint")] &'a str);
+        };
+
+        let got = expand(&input).unwrap().to_string();
+
+        assert!(got.contains("for Name < 'de >"));
+        assert!(got.contains("& 'de str"));
+        assert!(!got.contains("'a"));
+        // borrowed types can't be read from a stream
+        assert!(!got.contains("ReadFrom"));
+    }
+
+    #[test]
+    fn std_is_only_used_through_the_runtime() {
+        let input: DeriveInput = syn::parse_quote! {
+            struct Position {
+                x: i16,
+                y: i16,
+            }
+        };
+
+        let got = expand(&input).unwrap().to_string();
+
+        let without_reexports = got.replace("__private :: std", "");
+        assert!(!without_reexports.contains(":: std ::"));
+        assert!(got.contains(":: protodef_core :: __std_only !"));
+    }
+
+    #[test]
+    fn variants_need_a_case() {
+        let input: DeriveInput = syn::parse_quote! {
+            enum Body {
+                #[protodef(case = 0)]
+                Empty,
+                Missing(u8),
+            }
+        };
+
+        let got = expand(&input).unwrap_err();
+
+        assert!(got.to_string().contains("case"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Procedural macros for generating ProtoDef types at compile time.
+
+extern crate proc_macro;
+
+mod derive;
+mod options;
+
+use options::Input;
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use protodef_codegen::{backend, lowering, syntax};
+use quote::quote;
+use std::{fmt::Display, path::PathBuf};
+use syn::{parse_macro_input, DeriveInput, Error};
+
+/// Generate Rust types for a `protocol.json` file at compile time.
+///
+/// The path is resolved relative to the directory containing the current
+/// crate's `Cargo.toml`, and any parse errors or diagnostics are reported as
+/// compile errors.
+///
+/// The filename may be followed by options, which mirror the
+/// `CodegenOptions` and `LoweringOptions` from `protodef-codegen`:
+///
+/// - `deduplicate_types`, `serde`, and `no_std` - turn the option on
+/// - `native_type = "nbt=crate::nbt::Nbt"` - the Rust type for a native
+/// - `next_state = "2=login"` - the state a `nextState` value switches to
+/// - `type_name = "handshaking.toServer.packet_set_protocol=SetProtocol"` -
+///   the Rust name for a type
+/// - `string_type = "owned"`, `"borrowed"`, or `"cow"` - how strings are
+///   represented
+/// - `derive = "Eq"`, `runtime_crate = "..."`, `visibility = "pub(crate)"`,
+///   `include_namespace = "play"`, and `include_type = "..."`
+///
+/// Options which can be given several times (e.g. `native_type`) may be
+/// repeated.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// mod protocol {
+///     protodef_macros::include_protocol!("protocol.json");
+/// }
+///
+/// mod minecraft {
+///     protodef_macros::include_protocol!(
+///         "minecraft.json",
+///         deduplicate_types,
+///         native_type = "nbt=crate::nbt::Nbt",
+///         next_state = "2=login",
+///     );
+/// }
+/// ```
+#[proc_macro]
+pub fn include_protocol(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as Input);
+
+    expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Implement `protodef_core::Serialize` and `protodef_core::Deserialize` for a
+/// hand-written type, using the same encoding as generated code.
+///
+/// Fields can be customised with a `#[protodef(...)]` attribute:
+///
+/// - `#[protodef(varint)]` - encode an integer as a `varint`
+/// - `#[protodef(count = "varint")]` - a length-prefixed `String` or `Vec`,
+///   where the length is encoded using the named native type
+/// - `#[protodef(switch = "kind")]` - a `switch` which uses the previously
+///   decoded `kind` field to decide which variant to deserialize. Paths like
+///   `"../kind"` (a field from the containing struct) and `"$version"` (a
+///   parameter) are looked up in the `protodef_core::Context`
+/// - `#[protodef(context)]` - record the field in the `Context` so nested types
+///   can refer to it (e.g. as `"../kind"`)
+///
+/// An `enum` used as a `switch` must mark each variant with either
+/// `#[protodef(case = ...)]` or `#[protodef(default)]`.
+///
+/// A type may have a single lifetime parameter for borrowing from the buffer
+/// it is deserialized from (e.g. a `&'a str` or `Cow<'a, str>` field), like
+/// the types generated with `string_type = "borrowed"`. Types which borrow
+/// can't be read from a stream, and no type gets a `ReadFrom` impl unless
+/// `protodef_core` has its `std` feature.
+///
+/// # Examples
+///
+/// ```rust,ignore
+/// use protodef_macros::ProtoDef;
+///
+/// #[derive(ProtoDef)]
+/// struct Chat {
+///     #[protodef(varint)]
+///     kind: i32,
+///     #[protodef(switch = "kind")]
+///     body: Body,
+/// }
+///
+/// #[derive(ProtoDef)]
+/// enum Body {
+///     #[protodef(case = 0)]
+///     Message(#[protodef(count = "varint")] String),
+///     #[protodef(default)]
+///     Empty,
+/// }
+/// ```
+#[proc_macro_derive(ProtoDef, attributes(protodef))]
+pub fn derive_protodef(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    derive::expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+fn expand(input: &Input) -> Result<TokenStream2, Error> {
+    let Input {
+        filename,
+        lowering,
+        codegen,
+    } = input;
+    let span = filename.span();
+    let path = resolve(&filename.value());
+
+    let src = std::fs::read_to_string(&path).map_err(|e| {
+        let msg = format!("Unable to read \"{}\": {}", path.display(), e);
+        Error::new(span, msg)
+    })?;
+    let json = serde_json::from_str(&src).map_err(|e| {
+        let msg = format!("Unable to parse \"{}\": {}", path.display(), e);
+        Error::new(span, msg)
+    })?;
+
+    let (parsed, errors) = syntax::parse_with_recovery(&json);
+    if let Some(e) = combine_errors(&errors, span) {
+        return Err(e);
+    }
+
+    let analysed =
+        lowering::lower_with_options(&parsed, lowering).map_err(|diags| {
+            combine_errors(diags.all_diagnostics(), span)
+                .unwrap_or_else(|| Error::new(span, diags))
+        })?;
+    let tokens = backend::generate_rust_with_options(&analysed, codegen)
+        .map_err(|diags| {
+            combine_errors(diags.all_diagnostics(), span)
+                .unwrap_or_else(|| Error::new(span, diags))
+        })?;
+
+    let path = path.display().to_string();
+
+    Ok(quote! {
+        // make sure the crate is recompiled whenever the protocol changes
+        const _: &[u8] = include_bytes!(#path);
+
+        #tokens
+    })
+}
+
+fn resolve(filename: &str) -> PathBuf {
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_default();
+
+    manifest_dir.join(filename)
+}
+
+/// Turn each error into its own `compile_error!()`.
+fn combine_errors<E: Display>(errors: &[E], span: Span) -> Option<Error> {
+    errors
+        .iter()
+        .map(|e| Error::new(span, e.to_string().trim_end()))
+        .fold(None, |combined, error| match combined {
+            Some(mut combined) => {
+                Error::combine(&mut combined, error);
+                Some(combined)
+            },
+            None => Some(error),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_error_gets_its_own_compile_error() {
+        let errors = ["first problem\n", "second problem\n"];
+
+        let got = combine_errors(&errors, Span::call_site()).unwrap();
+
+        let messages: Vec<_> = got.into_iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, ["first problem", "second problem"]);
+    }
+
+    #[test]
+    fn no_errors_to_combine() {
+        let errors: [&str; 0] = [];
+
+        let got = combine_errors(&errors, Span::call_site());
+
+        assert!(got.is_none());
+    }
+
+    #[test]
+    fn missing_file() {
+        let input: Input = syn::parse_quote!("this/does/not/exist.json");
+
+        let got = expand(&input).unwrap_err();
+
+        assert!(got.to_string().starts_with("Unable to read"));
+    }
+}
@@ -0,0 +1,228 @@
+//! Parsing the arguments passed to `include_protocol!()`.
+
+use protodef_codegen::{
+    backend::{CodegenOptions, StringType},
+    lowering::LoweringOptions,
+};
+use syn::{
+    parse::{Parse, ParseStream},
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Error, Lit, LitStr, Meta, NestedMeta, Token,
+};
+
+/// The `protocol.json` file to generate code for, and the options to use
+/// when generating it.
+pub(crate) struct Input {
+    pub(crate) filename: LitStr,
+    pub(crate) lowering: LoweringOptions,
+    pub(crate) codegen: CodegenOptions,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream<'_>) -> Result<Self, Error> {
+        let filename: LitStr = input.parse()?;
+        let mut lowering = LoweringOptions::default();
+        let mut codegen = CodegenOptions::new();
+
+        if !input.is_empty() {
+            input.parse::<Token![,]>()?;
+        }
+
+        let items: Punctuated<NestedMeta, Token![,]> =
+            Punctuated::parse_terminated(input)?;
+
+        for item in items {
+            codegen = apply(&item, &mut lowering, codegen)?;
+        }
+
+        Ok(Input {
+            filename,
+            lowering,
+            codegen,
+        })
+    }
+}
+
+/// Apply a single option (e.g. `deduplicate_types` or
+/// `native_type = "nbt=crate::nbt::Nbt"`).
+fn apply(
+    item: &NestedMeta,
+    lowering: &mut LoweringOptions,
+    codegen: CodegenOptions,
+) -> Result<CodegenOptions, Error> {
+    let (path, value) = match item {
+        NestedMeta::Meta(Meta::Path(path)) => (path, None),
+        NestedMeta::Meta(Meta::NameValue(nv)) => (&nv.path, Some(&nv.lit)),
+        other => return Err(unknown_option(other)),
+    };
+    let name = match path.get_ident() {
+        Some(ident) => ident.to_string(),
+        None => return Err(unknown_option(item)),
+    };
+
+    let codegen = match (name.as_str(), value) {
+        ("deduplicate_types", None) => {
+            lowering.deduplicate_types = true;
+            codegen
+        },
+        ("serde", None) => codegen.serde(true),
+        ("no_std", None) => codegen.no_std(true),
+        ("derive", Some(lit)) => codegen.derive(string_value(lit)?),
+        ("runtime_crate", Some(lit)) => {
+            codegen.runtime_crate(string_value(lit)?)
+        },
+        ("visibility", Some(lit)) => codegen.visibility(string_value(lit)?),
+        ("include_namespace", Some(lit)) => {
+            codegen.include_namespace(string_value(lit)?)
+        },
+        ("include_type", Some(lit)) => codegen.include_type(string_value(lit)?),
+        ("type_name", Some(lit)) => {
+            let (protocol_name, rust_name) =
+                key_value(lit, "NAME=RUST_NAME", "packet=Packet")?;
+            codegen.type_name(protocol_name, rust_name)
+        },
+        ("native_type", Some(lit)) => {
+            let (name, path) =
+                key_value(lit, "NAME=PATH", "nbt=crate::nbt::Nbt")?;
+            codegen.native_type(name, path)
+        },
+        ("next_state", Some(lit)) => {
+            let (value, state) = key_value(lit, "VALUE=STATE", "2=login")?;
+            let value = value.parse().map_err(|_| {
+                Error::new(
+                    lit.span(),
+                    format!("\"{}\" is not a valid nextState value", value),
+                )
+            })?;
+            codegen.next_state(value, state)
+        },
+        ("string_type", Some(lit)) => {
+            let string_type = match string_value(lit)?.as_str() {
+                "owned" => StringType::Owned,
+                "borrowed" => StringType::Borrowed,
+                "cow" => StringType::Cow,
+                other => {
+                    return Err(Error::new(
+                        lit.span(),
+                        format!(
+                            "Expected \"owned\", \"borrowed\", or \"cow\", \
+                             found \"{}\"",
+                            other
+                        ),
+                    ))
+                },
+            };
+            codegen.string_type(string_type)
+        },
+        ("deduplicate_types" | "serde" | "no_std", Some(_)) => {
+            let msg = format!("`{}` doesn't take a value", name);
+            return Err(Error::new(item.span(), msg));
+        },
+        (
+            "derive" | "runtime_crate" | "visibility" | "include_namespace"
+            | "include_type" | "type_name" | "native_type" | "next_state"
+            | "string_type",
+            None,
+        ) => {
+            let msg =
+                format!("`{}` needs a value (e.g. `{} = \"...\"`)", name, name);
+            return Err(Error::new(item.span(), msg));
+        },
+        _ => return Err(unknown_option(item)),
+    };
+
+    Ok(codegen)
+}
+
+fn unknown_option(item: &NestedMeta) -> Error {
+    Error::new(
+        item.span(),
+        "expected one of `deduplicate_types`, `serde`, `no_std`, `derive`, \
+         `runtime_crate`, `visibility`, `include_namespace`, `include_type`, \
+         `type_name`, `native_type`, `next_state`, or `string_type`",
+    )
+}
+
+fn string_value(lit: &Lit) -> Result<String, Error> {
+    match lit {
+        Lit::Str(s) => Ok(s.value()),
+        other => Err(Error::new(other.span(), "expected a string")),
+    }
+}
+
+/// Split a `"KEY=VALUE"` string (e.g. `"nbt=crate::nbt::Nbt"`) in two.
+fn key_value(
+    lit: &Lit,
+    format: &str,
+    example: &str,
+) -> Result<(String, String), Error> {
+    let value = string_value(lit)?;
+
+    match value.find('=') {
+        Some(ix) => Ok((value[..ix].to_string(), value[ix + 1..].to_string())),
+        None => Err(Error::new(
+            lit.span(),
+            format!(
+                "Expected \"{}\" (e.g. \"{}\"), found \"{}\"",
+                format, example, value
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn just_a_filename() {
+        let got: Input = syn::parse_quote!("protocol.json");
+
+        assert_eq!(got.filename.value(), "protocol.json");
+        assert_eq!(got.codegen, CodegenOptions::new());
+        assert!(!got.lowering.deduplicate_types);
+    }
+
+    #[test]
+    fn options_follow_the_filename() {
+        let got: Input = syn::parse_quote!(
+            "protocol.json",
+            deduplicate_types,
+            native_type = "nbt=crate::nbt::Nbt",
+            next_state = "2=login",
+            string_type = "cow",
+            serde,
+        );
+
+        assert!(got.lowering.deduplicate_types);
+        assert_eq!(
+            got.codegen,
+            CodegenOptions::new()
+                .native_type("nbt", "crate::nbt::Nbt")
+                .next_state(2, "login")
+                .string_type(StringType::Cow)
+                .serde(true)
+        );
+    }
+
+    #[test]
+    fn unknown_options_are_an_error() {
+        let got = syn::parse2::<Input>(quote::quote!(
+            "protocol.json",
+            explode = "yes"
+        ));
+
+        assert!(got.is_err());
+    }
+
+    #[test]
+    fn next_states_must_be_numbers() {
+        let got = syn::parse2::<Input>(quote::quote!(
+            "protocol.json",
+            next_state = "two=login"
+        ));
+
+        assert!(got.is_err());
+    }
+}
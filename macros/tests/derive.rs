@@ -0,0 +1,267 @@
+use protodef_core::{Deserialize, ReadFrom, Serialize};
+use protodef_macros::ProtoDef;
+use std::{borrow::Cow, fmt::Debug};
+
+#[derive(Debug, Clone, PartialEq, ProtoDef)]
+struct Chat {
+    #[protodef(varint)]
+    kind: i32,
+    #[protodef(switch = "kind")]
+    body: Body,
+    #[protodef(count = "u8")]
+    recipients: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, ProtoDef)]
+enum Body {
+    #[protodef(case = 0)]
+    Message(#[protodef(count = "varint")] String),
+    #[protodef(case = 1)]
+    Position { x: i16, y: i16 },
+    #[protodef(default)]
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq, ProtoDef)]
+struct Flagged(bool, #[protodef(switch = "0")] Optional);
+
+#[derive(Debug, Clone, PartialEq, ProtoDef)]
+enum Optional {
+    #[protodef(case = true)]
+    Present(u8),
+    #[protodef(case = false)]
+    Absent,
+}
+
+fn round_trip<T>(value: T, bytes: &[u8])
+where
+    T: for<'de> Deserialize<'de> + ReadFrom + Serialize + PartialEq + Debug,
+{
+    let mut buffer = Vec::new();
+    value.serialize(&mut buffer).unwrap();
+    assert_eq!(buffer, bytes);
+
+    let (got, rest) = T::deserialize(bytes).unwrap();
+    assert_eq!(got, value);
+    assert!(rest.is_empty());
+
+    let mut reader = bytes;
+    let got = T::read_from(&mut reader).unwrap();
+    assert_eq!(got, value);
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn round_trip_a_struct_with_a_switch() {
+    round_trip(
+        Chat {
+            kind: 0,
+            body: Body::Message(String::from("Hi")),
+            recipients: vec![1],
+        },
+        &[0x00, 0x02, b'H', b'i', 0x01, 0x00, 0x01],
+    );
+    round_trip(
+        Chat {
+            kind: 1,
+            body: Body::Position { x: 1, y: -1 },
+            recipients: Vec::new(),
+        },
+        &[0x01, 0x00, 0x01, 0xff, 0xff, 0x00],
+    );
+    round_trip(
+        Chat {
+            kind: 300,
+            body: Body::Empty,
+            recipients: Vec::new(),
+        },
+        &[0xac, 0x02, 0x00],
+    );
+}
+
+#[test]
+fn round_trip_a_tuple_struct() {
+    round_trip(Flagged(true, Optional::Present(42)), &[0x01, 42]);
+    round_trip(Flagged(false, Optional::Absent), &[0x00]);
+}
+
+#[test]
+fn unknown_switch_variants_are_an_error() {
+    #[derive(Debug, ProtoDef)]
+    struct Packet {
+        kind: u8,
+        #[protodef(switch = "kind")]
+        body: Strict,
+    }
+
+    #[derive(Debug, ProtoDef)]
+    enum Strict {
+        #[protodef(case = 0)]
+        Zero,
+    }
+
+    let got = Packet::deserialize(&[0x05]).unwrap_err();
+
+    assert_eq!(got.to_string(), "no switch variant matches 5");
+}
+
+#[test]
+fn read_several_values_from_a_buffered_stream() {
+    let bytes = [
+        0x01, 0x00, 0x01, 0xff, 0xff, 0x00, 0x00, 0x02, b'H', b'i', 0x00,
+    ];
+    // small enough that the second value is split across two reads
+    let mut reader = std::io::BufReader::with_capacity(8, &bytes[..]);
+
+    let first: Chat = protodef_core::read_buffered(&mut reader).unwrap();
+    let second: Chat = protodef_core::read_buffered(&mut reader).unwrap();
+
+    assert_eq!(first.body, Body::Position { x: 1, y: -1 });
+    assert_eq!(second.body, Body::Message(String::from("Hi")));
+    assert!(reader.buffer().is_empty());
+}
+
+#[test]
+fn decode_limits_are_applied_to_nested_fields() {
+    use protodef_core::{DecodeLimits, DeserializeError, Limit};
+
+    let bytes = [0x00, 0x05, b'H', b'e', b'l', b'l', b'o', 0x00];
+    let limits = DecodeLimits::new().max_string_length(4);
+
+    let got = Chat::deserialize_limited(&bytes, &mut limits.clone());
+
+    assert!(matches!(
+        got,
+        Err(DeserializeError::LimitExceeded {
+            limit: Limit::StringLength,
+            requested: 5,
+            max: 4,
+        })
+    ));
+    // a Chat contains a Body, so it needs two levels of nesting
+    let too_shallow = DecodeLimits::new().max_depth(1);
+    assert!(
+        Chat::deserialize_limited(&bytes, &mut too_shallow.clone()).is_err()
+    );
+    let deep_enough = DecodeLimits::new().max_depth(2);
+    assert!(Chat::deserialize_limited(&bytes, &mut deep_enough.clone()).is_ok());
+}
+
+#[test]
+fn decode_limits_are_applied_when_streaming() {
+    use protodef_core::{DecodeLimits, DeserializeError, Limit};
+
+    let bytes = [0x00, 0x05, b'H', b'e', b'l', b'l', b'o', 0x00];
+    let limits = DecodeLimits::new().max_string_length(4);
+
+    let got = Chat::read_from_limited(&mut &bytes[..], &mut limits.clone());
+
+    assert!(matches!(
+        got,
+        Err(DeserializeError::LimitExceeded {
+            limit: Limit::StringLength,
+            requested: 5,
+            max: 4,
+        })
+    ));
+    let too_shallow = DecodeLimits::new().max_depth(1);
+    assert!(
+        Chat::read_from_limited(&mut &bytes[..], &mut too_shallow.clone())
+            .is_err()
+    );
+    let deep_enough = DecodeLimits::new().max_depth(2);
+    assert!(
+        Chat::read_from_limited(&mut &bytes[..], &mut deep_enough.clone())
+            .is_ok()
+    );
+}
+
+#[derive(Debug, Clone, PartialEq, ProtoDef)]
+struct Entity {
+    #[protodef(context)]
+    action: u8,
+    metadata: Metadata,
+}
+
+#[derive(Debug, Clone, PartialEq, ProtoDef)]
+struct Metadata {
+    #[protodef(switch = "../action")]
+    body: Body,
+}
+
+#[test]
+fn switch_on_a_field_from_the_parent() {
+    round_trip(
+        Entity {
+            action: 1,
+            metadata: Metadata {
+                body: Body::Position { x: 1, y: 2 },
+            },
+        },
+        &[0x01, 0x00, 0x01, 0x00, 0x02],
+    );
+}
+
+#[test]
+fn values_missing_from_the_context_are_an_error() {
+    use protodef_core::DeserializeError;
+
+    let got = Metadata::deserialize(&[0x00]).unwrap_err();
+
+    assert!(matches!(
+        got,
+        DeserializeError::MissingContextValue { ref path } if path == "../action"
+    ));
+}
+
+#[test]
+fn switch_on_a_parameter() {
+    use protodef_core::{Context, DecodeLimits};
+
+    #[derive(Debug, ProtoDef)]
+    struct Versioned {
+        #[protodef(switch = "$version")]
+        body: Body,
+    }
+
+    let context = Context::new().with_param("version", 0);
+    let bytes = [0x02, b'H', b'i'];
+
+    let (got, _) =
+        Versioned::deserialize_with(&bytes, &mut DecodeLimits::new(), &context)
+            .unwrap();
+    assert_eq!(got.body, Body::Message(String::from("Hi")));
+
+    let got = Versioned::read_from_with(
+        &mut &bytes[..],
+        &mut DecodeLimits::new(),
+        &context,
+    )
+    .unwrap();
+    assert_eq!(got.body, Body::Message(String::from("Hi")));
+}
+
+#[derive(Debug, Clone, PartialEq, ProtoDef)]
+struct Login<'a> {
+    #[protodef(count = "varint")]
+    username: &'a str,
+    #[protodef(count = "u8")]
+    server: Cow<'a, str>,
+}
+
+#[test]
+fn borrow_strings_from_the_input() {
+    let login = Login {
+        username: "me",
+        server: Cow::Borrowed("hi"),
+    };
+    let mut buffer = Vec::new();
+
+    login.serialize(&mut buffer).unwrap();
+
+    assert_eq!(buffer, &[0x02, b'm', b'e', 0x02, b'h', b'i']);
+    let (got, rest) = Login::deserialize(&buffer).unwrap();
+    assert_eq!(got, login);
+    assert!(matches!(got.server, Cow::Borrowed(_)));
+    assert!(rest.is_empty());
+}
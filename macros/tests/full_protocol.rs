@@ -0,0 +1,74 @@
+//! Make sure the code generated for a full Minecraft protocol compiles.
+
+mod nbt {
+    //! Just enough of an NBT implementation for the generated code to
+    //! compile (an NBT value is always an empty compound here).
+
+    use protodef_core::{
+        Deserialize, DeserializeError, ReadFrom, Serialize, SerializeError,
+    };
+    use std::io::Read;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Nbt;
+
+    pub type OptionalNbt = Option<Nbt>;
+
+    impl<'de> Deserialize<'de> for Nbt {
+        fn deserialize(
+            buffer: &'de [u8],
+        ) -> Result<(Self, &'de [u8]), DeserializeError> {
+            let (_, rest) = protodef_core::take(buffer, 1)?;
+            Ok((Nbt, rest))
+        }
+    }
+
+    impl Serialize for Nbt {
+        fn serialize(
+            &self,
+            buffer: &mut Vec<u8>,
+        ) -> Result<(), SerializeError> {
+            buffer.push(0);
+            Ok(())
+        }
+    }
+
+    impl ReadFrom for Nbt {
+        fn read_from<R>(reader: &mut R) -> Result<Self, DeserializeError>
+        where
+            R: Read + ?Sized,
+        {
+            protodef_core::read_exact(reader, &mut [0])?;
+            Ok(Nbt)
+        }
+    }
+}
+
+mod minecraft {
+    protodef_macros::include_protocol!(
+        "../codegen/tests/fixtures/protocol.json",
+        deduplicate_types,
+        native_type = "nbt=crate::nbt::Nbt",
+        native_type = "optionalNbt=crate::nbt::OptionalNbt",
+        // none of the tests decode entity metadata, so a stub will do
+        native_type = "entityMetadata=crate::nbt::Nbt",
+        next_state = "1=status",
+        next_state = "2=login",
+    );
+}
+
+use minecraft::{State, ToServerPacket};
+use protodef_core::Connection;
+
+#[test]
+fn a_handshake_switches_to_the_login_state() {
+    let mut connection = Connection::<State>::new();
+    // packet_set_protocol, protocol version 340, "", port 25565, login
+    let packet = [0x00, 0xd4, 0x02, 0x00, 0x63, 0xdd, 0x02];
+
+    let (_, rest): (ToServerPacket, _) =
+        connection.deserialize(&packet).unwrap();
+
+    assert!(rest.is_empty());
+    assert_eq!(connection.state(), State::Login);
+}
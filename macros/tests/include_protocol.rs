@@ -0,0 +1,78 @@
+mod basic {
+    protodef_macros::include_protocol!("../codegen/tests/fixtures/basic.json");
+}
+
+mod borrowed {
+    protodef_macros::include_protocol!(
+        "../codegen/tests/fixtures/basic.json",
+        string_type = "borrowed",
+    );
+}
+
+use basic::{BulkItems, Items};
+use protodef_core::{Deserialize, Serialize};
+use protodef_macros::ProtoDef;
+
+#[test]
+fn generated_types_are_usable() {
+    let items = Items {
+        item_count: 1,
+        item_damage: -2,
+    };
+
+    let bulk = BulkItems {
+        items: items.clone(),
+    };
+
+    assert_eq!(bulk.items, items);
+    assert_eq!(basic::String("hello".into()).0, "hello");
+}
+
+#[derive(Debug, PartialEq, ProtoDef)]
+struct Inventory {
+    #[protodef(count = "varint")]
+    slots: Vec<Items>,
+    owner: basic::String,
+}
+
+#[test]
+fn hand_written_types_can_contain_generated_ones() {
+    let inventory = Inventory {
+        slots: vec![Items {
+            item_count: 3,
+            item_damage: 0x0102,
+        }],
+        owner: basic::String("me".into()),
+    };
+    let mut buffer = Vec::new();
+
+    inventory.serialize(&mut buffer).unwrap();
+
+    assert_eq!(buffer, &[0x01, 0x03, 0x01, 0x02, 0x02, b'm', b'e']);
+    let (got, rest) = Inventory::deserialize(&buffer).unwrap();
+    assert_eq!(got, inventory);
+    assert!(rest.is_empty());
+}
+
+#[derive(Debug, PartialEq, ProtoDef)]
+struct Greeting<'a> {
+    from: borrowed::String<'a>,
+    #[protodef(count = "varint")]
+    message: &'a str,
+}
+
+#[test]
+fn hand_written_types_can_borrow_like_generated_ones() {
+    let buffer = [0x02, b'm', b'e', 0x02, b'h', b'i'];
+
+    let (got, rest) = Greeting::deserialize(&buffer).unwrap();
+
+    assert_eq!(
+        got,
+        Greeting {
+            from: borrowed::String("me"),
+            message: "hi",
+        }
+    );
+    assert!(rest.is_empty());
+}